@@ -105,16 +105,23 @@ async function main() {
       programId
     )
 
-    // Verificar registro
-    try {
-      const userAccount = await program.account.userAccount.fetch(userPda)
-      if (userAccount.isRegistered) {
+    // Verificar registro - contas no layout Borsh antigo têm outro tamanho e não
+    // podem ser decodificadas com o IDL zero-copy
+    const userAccountInfo = await connection.getAccountInfo(userPda)
+    if (userAccountInfo) {
+      const expectedSize = program.account.userAccount.size
+      if (userAccountInfo.data.length !== expectedSize) {
+        console.log(`❌ Conta de usuário no layout antigo (${userAccountInfo.data.length} bytes, esperado ${expectedSize})`)
+        console.log("   Execute migrate_user_account (layout Borsh) ou resize_user_account antes de continuar")
+        return
+      }
+      const userAccount = program.coder.accounts.decode("UserAccount", userAccountInfo.data)
+      if (userAccount.isRegistered !== 0) {
         console.log("❌ Usuário já está registrado!")
         return
       }
-    } catch {
-      console.log("✅ Usuário não registrado")
     }
+    console.log("✅ Usuário não registrado")
 
     // Derivar ATAs
     const userWsolAccount = getAssociatedTokenAddress(
@@ -165,6 +172,11 @@ async function main() {
         systemProgram: SystemProgram.programId,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         rent: SYSVAR_RENT_PUBKEY,
+        eventAuthority: PublicKey.findProgramAddressSync(
+          [Buffer.from("__event_authority")],
+          program.programId
+        )[0],
+        program: program.programId,
       })
       .remainingAccounts([
         {
@@ -258,8 +270,9 @@ async function main() {
       // Verificar resultado
       console.log("\n🔍 Verificando registro...")
       const userInfo = await program.account.userAccount.fetch(userPda)
-      console.log("✅ Registrado:", userInfo.isRegistered)
+      console.log("✅ Registrado:", userInfo.isRegistered !== 0)
       console.log("🔢 Upline ID:", userInfo.upline.id.toString())
+      console.log("🔢 Uplines:", userInfo.upline.count)
       console.log("🔢 Chain ID:", userInfo.chain.id.toString())
 
     } catch (error) {
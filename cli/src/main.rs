@@ -0,0 +1,962 @@
+// matrix-cli - operator tool for the matrix-system program. Replaces ini.js, base.js
+// and view_vault.js: initializes the program, registers base users, inspects the
+// program state, users, the SOL vault, referral trees and pending reserves, and exports
+// the whole referral graph.
+
+mod rpc;
+mod view;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use matrix_system::{
+    admin_addresses, verified_addresses, AllowlistMode, ReferralCode, MAX_BURN_RETRY_LAMPORTS, MAX_BURN_RETRY_SLIPPAGE_BPS, MAX_PROTOCOL_FEE_BPS, RESERVE_SOL, USER_FLAG_COUNTS_REFERRALS, USER_FLAG_HAS_REFERRAL_CODE,
+};
+use matrix_system_client::{
+    airdrop_start_timestamp, pda, rpc::RpcFetcher, ClaimReferralCode, CloseCampaign, CloseUserAccount, ConfigureDepositToken, ExtendCampaign, Initialize,
+    MigrateUserWallet, RefreshUserReferences, ReconcileVault, RegisterWithoutReferrer, ScheduleCampaign, SetAllowlist, SetPayoutAddress,
+    RetryPendingBurns, SetBurnEscrow, SetProtocolFee, SetReferrerLimits, SweepVaultSurplus,
+};
+use serde::Serialize;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    compute_budget::ComputeBudgetInstruction,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair},
+    signer::Signer,
+};
+
+use crate::view::*;
+
+pub type CliResult<T> = Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Output {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Allowlist {
+    Open,
+    Merkle,
+    Invite,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Json,
+    Csv,
+    Dot,
+}
+
+#[derive(Parser)]
+#[command(name = "matrix-cli", about = "Operator tool for the matrix-system program")]
+struct Cli {
+    /// RPC endpoint
+    #[arg(long, short = 'u', global = true, default_value = "https://api.devnet.solana.com")]
+    url: String,
+
+    /// Keypair file of the operator (fee payer, program owner)
+    #[arg(long, short = 'k', global = true, default_value = "~/.config/solana/id.json")]
+    keypair: String,
+
+    /// Program state address - read from the config file when omitted
+    #[arg(long, global = true)]
+    state: Option<Pubkey>,
+
+    /// Config file written by init (same format as ini.js)
+    #[arg(long, global = true, default_value = "matriz-config.json")]
+    config: PathBuf,
+
+    /// Output format
+    #[arg(long, short = 'o', global = true, value_enum, default_value = "text")]
+    output: Output,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Meteora pool accounts of a deposit token
+#[derive(Args)]
+struct DepositPool {
+    /// Meteora pool of DONUT and the token
+    #[arg(long)]
+    pool: Pubkey,
+    #[arg(long)]
+    b_vault: Pubkey,
+    #[arg(long)]
+    b_token_vault: Pubkey,
+    #[arg(long)]
+    b_vault_lp_mint: Pubkey,
+    #[arg(long)]
+    b_vault_lp: Pubkey,
+    /// Pool fee account of the token
+    #[arg(long)]
+    protocol_token_fee: Pubkey,
+    /// The pool's LP token account of the shared DONUT vault A
+    #[arg(long)]
+    a_vault_lp: Pubkey,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create the program state and write the config file
+    Init,
+    /// Register a base user (no referrer) - signed by the owner and the user wallet
+    RegisterBase {
+        /// Keypair file of the user wallet, e.g. carteiras/carteira1.json (default: the operator)
+        #[arg(long)]
+        wallet: Option<String>,
+        /// Deposit in lamports
+        #[arg(long, default_value_t = 100_000_000)]
+        deposit: u64,
+    },
+    /// Close an unused user account and return its rent - signed by the user wallet
+    CloseUser {
+        /// Keypair file of the user wallet (default: the operator)
+        #[arg(long)]
+        wallet: Option<String>,
+    },
+    /// Move a user's position to a new wallet - signed by the current and the new wallet
+    MigrateWallet {
+        /// Keypair file of the new wallet, which pays for the new account
+        #[arg(long)]
+        new_wallet: String,
+        /// Keypair file of the current wallet (default: the operator)
+        #[arg(long)]
+        wallet: Option<String>,
+    },
+    /// Send a user's reserve payments to another wallet - signed by the user wallet
+    SetPayout {
+        /// System wallet that receives the payments
+        #[arg(required_unless_present = "clear", conflicts_with = "clear")]
+        address: Option<Pubkey>,
+        /// Pay the user wallet again
+        #[arg(long)]
+        clear: bool,
+        /// Keypair file of the user wallet (default: the operator)
+        #[arg(long)]
+        wallet: Option<String>,
+    },
+    /// Claim a short referral code for a registered user - signed by the user wallet
+    ClaimCode {
+        /// 3-16 letters, digits, '-' or '_' - stored in lowercase
+        code: String,
+        /// Keypair file of the user wallet (default: the operator)
+        #[arg(long)]
+        wallet: Option<String>,
+    },
+    /// Show the referrer wallet a referral code resolves to
+    ResolveCode { code: String },
+    /// Point a user's referrer, slots and upline at the new accounts of migrated wallets
+    RefreshReferences { wallet: Pubkey },
+    /// Show the program state
+    ShowState,
+    /// Show a user's account, matrix and upline
+    ShowUser { wallet: Pubkey },
+    /// Compare the SOL vault balance with the reserved SOL of every user
+    ShowVault,
+    /// Move the SOL vault surplus over the reserved lamports to the multisig treasury
+    SweepVault,
+    /// Raise the program's tracked reserved total to the sum over every user
+    ReconcileVault,
+    /// Restrict who may register under a referrer - signed by the owner
+    SetAllowlist {
+        #[arg(value_enum)]
+        mode: Allowlist,
+        /// File with one allowed wallet per line - its Merkle root is stored (merkle)
+        #[arg(long, required_if_eq("mode", "merkle"))]
+        wallets: Option<PathBuf>,
+        /// Key that signs the invites (invite)
+        #[arg(long, required_if_eq("mode", "invite"))]
+        invite_signer: Option<Pubkey>,
+    },
+    /// Open registrations for a time window - signed by the owner
+    ScheduleCampaign {
+        /// Unix timestamp of the start
+        #[arg(long)]
+        start: i64,
+        /// Unix timestamp of the end (exclusive)
+        #[arg(long)]
+        end: i64,
+        /// Registration cap, 0 for none
+        #[arg(long, default_value_t = 0)]
+        max_registrations: u32,
+    },
+    /// Move the end of the current campaign and raise its cap - signed by the owner
+    ExtendCampaign {
+        #[arg(long)]
+        end: i64,
+        #[arg(long, default_value_t = 0)]
+        max_registrations: u32,
+    },
+    /// End the current campaign now - signed by the owner
+    CloseCampaign,
+    /// Throttle matrix completions per referrer, 0 disables a limit - signed by the owner
+    SetReferrerLimits {
+        #[arg(long, default_value_t = 0)]
+        max_cycles_per_week: u32,
+        /// Seconds between two completions of a referrer's matrix
+        #[arg(long, default_value_t = 0)]
+        min_cycle_interval: i64,
+        /// Unix timestamp of the start of week 1, by default the airdrop's start
+        #[arg(long)]
+        week_start: Option<i64>,
+    },
+    /// Send a share of each SOL deposit to the multisig treasury - signed by the owner
+    SetProtocolFee {
+        /// Basis points of the deposit, 0 disables the fee
+        fee_bps: u16,
+    },
+    /// Escrow SOL burns while the Meteora pool cannot quote the swap - signed by the owner
+    SetBurnEscrow {
+        /// Revert registrations again instead of escrowing their burns
+        #[arg(long)]
+        disable: bool,
+        /// Basis points a retried burn may swap below the pool's quote
+        #[arg(long, default_value_t = 100)]
+        retry_slippage_bps: u16,
+    },
+    /// Swap and burn the escrowed deposits once the pool is healthy - permissionless
+    RetryPendingBurns,
+    /// Accept a token deposit through its DONUT pool and vault - signed by the owner
+    ConfigureDepositToken {
+        /// Deposit token id (0 is SOL)
+        token: u8,
+        #[arg(long)]
+        mint: Pubkey,
+        /// Minimum deposit in base units of the token
+        #[arg(long)]
+        min_deposit: u64,
+        #[command(flatten)]
+        pool: Box<DepositPool>,
+    },
+    /// Show a user's upline and referrals
+    Tree {
+        wallet: Pubkey,
+        /// Referral levels to show
+        #[arg(long, default_value_t = 3)]
+        depth: usize,
+    },
+    /// List users holding a second-slot reserve
+    PendingReserves,
+    /// Export the referral tree of every user (or below one user)
+    Export {
+        #[arg(long, value_enum, default_value = "json")]
+        format: ExportFormat,
+        /// Only export this user and their referrals
+        #[arg(long)]
+        root: Option<Pubkey>,
+        /// Write to a file instead of stdout
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(err) = run(&cli) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn print<T: Serialize + std::fmt::Display>(output: Output, value: &T) -> CliResult<()> {
+    match output {
+        Output::Text => println!("{}", value),
+        Output::Json => println!("{}", serde_json::to_string_pretty(value)?),
+    }
+    Ok(())
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn load_keypair(path: &str) -> CliResult<Keypair> {
+    read_keypair_file(expand_home(path)).map_err(|err| format!("failed to read keypair {}: {}", path, err).into())
+}
+
+fn state_address(cli: &Cli) -> CliResult<Pubkey> {
+    if let Some(state) = cli.state {
+        return Ok(state);
+    }
+    let config: serde_json::Value = serde_json::from_str(&fs::read_to_string(&cli.config)?)?;
+    let address = config["stateAddress"]
+        .as_str()
+        .ok_or_else(|| format!("{} has no stateAddress - pass --state", cli.config.display()))?;
+    Ok(address.parse()?)
+}
+
+fn run(cli: &Cli) -> CliResult<()> {
+    let rpc = RpcClient::new_with_commitment(cli.url.clone(), CommitmentConfig::confirmed());
+
+    match &cli.command {
+        Command::Init => init(cli, &rpc),
+        Command::RegisterBase { wallet, deposit } => register_base(cli, &rpc, wallet.as_deref(), *deposit),
+        Command::CloseUser { wallet } => close_user(cli, &rpc, wallet.as_deref()),
+        Command::MigrateWallet { new_wallet, wallet } => migrate_wallet(cli, &rpc, new_wallet, wallet.as_deref()),
+        Command::SetPayout { address, wallet, .. } => set_payout(cli, &rpc, *address, wallet.as_deref()),
+        Command::ClaimCode { code, wallet } => claim_code(cli, &rpc, code, wallet.as_deref()),
+        Command::ResolveCode { code } => {
+            let referrer_wallet = matrix_system_client::resolve_referral_code(&RpcFetcher(&rpc), code)?;
+            let view = ReferralCodeView {
+                code: code.trim().to_ascii_lowercase(),
+                referrer_wallet: referrer_wallet.to_string(),
+                user_account: pda::user_account(&referrer_wallet).to_string(),
+            };
+            print(cli.output, &view)
+        }
+        Command::RefreshReferences { wallet } => refresh_references(cli, &rpc, wallet),
+        Command::ShowState => {
+            let address = state_address(cli)?;
+            print(cli.output, &StateView::new(&address, &rpc::program_state(&rpc, &address)?))
+        }
+        Command::ShowUser { wallet } => {
+            let address = pda::user_account(wallet);
+            let account = rpc::user_account(&rpc, &address)?.ok_or(format!("{} is not registered", wallet))?;
+            print(cli.output, &UserView::new(&address, &account))
+        }
+        Command::ShowVault => show_vault(cli, &rpc),
+        Command::SweepVault => sweep_vault(cli, &rpc),
+        Command::ReconcileVault => reconcile_vault(cli, &rpc),
+        Command::SetAllowlist { mode, wallets, invite_signer } => set_allowlist(cli, &rpc, *mode, wallets.as_deref(), *invite_signer),
+        Command::ScheduleCampaign { start, end, max_registrations } => {
+            let (state, owner) = (state_address(cli)?, load_keypair(&cli.keypair)?);
+            let instruction = ScheduleCampaign {
+                state,
+                owner: owner.pubkey(),
+                start_timestamp: *start,
+                end_timestamp: *end,
+                max_registrations: *max_registrations,
+            };
+            send_campaign(cli, &rpc, &owner, instruction.instruction(), "schedule_campaign")
+        }
+        Command::ExtendCampaign { end, max_registrations } => {
+            let (state, owner) = (state_address(cli)?, load_keypair(&cli.keypair)?);
+            let instruction =
+                ExtendCampaign { state, owner: owner.pubkey(), end_timestamp: *end, max_registrations: *max_registrations };
+            send_campaign(cli, &rpc, &owner, instruction.instruction(), "extend_campaign")
+        }
+        Command::CloseCampaign => {
+            let (state, owner) = (state_address(cli)?, load_keypair(&cli.keypair)?);
+            let instruction = CloseCampaign { state, owner: owner.pubkey() };
+            send_campaign(cli, &rpc, &owner, instruction.instruction(), "close_campaign")
+        }
+        Command::SetReferrerLimits { max_cycles_per_week, min_cycle_interval, week_start } => {
+            set_referrer_limits(cli, &rpc, *max_cycles_per_week, *min_cycle_interval, *week_start)
+        }
+        Command::SetProtocolFee { fee_bps } => {
+            if *fee_bps > MAX_PROTOCOL_FEE_BPS {
+                return Err(format!("the fee is at most {} bps", MAX_PROTOCOL_FEE_BPS).into());
+            }
+            let (state, owner) = (state_address(cli)?, load_keypair(&cli.keypair)?);
+            let instruction = SetProtocolFee { state, owner: owner.pubkey(), fee_bps: *fee_bps }.instruction();
+            let signature = rpc::send(&rpc, &[instruction], &owner, &[])?;
+            print(
+                cli.output,
+                &TransactionView {
+                    action: "set_protocol_fee",
+                    signature: signature.to_string(),
+                    accounts: vec![("state", state.to_string()), ("fee", format!("{} bps", fee_bps))],
+                },
+            )
+        }
+        Command::SetBurnEscrow { disable, retry_slippage_bps } => {
+            if *retry_slippage_bps > MAX_BURN_RETRY_SLIPPAGE_BPS {
+                return Err(format!("the retry slippage is at most {} bps", MAX_BURN_RETRY_SLIPPAGE_BPS).into());
+            }
+            let (state, owner) = (state_address(cli)?, load_keypair(&cli.keypair)?);
+            let instruction = SetBurnEscrow {
+                state,
+                owner: owner.pubkey(),
+                enabled: !disable,
+                retry_slippage_bps: *retry_slippage_bps,
+            }
+            .instruction();
+            let signature = rpc::send(&rpc, &[instruction], &owner, &[])?;
+            print(
+                cli.output,
+                &TransactionView {
+                    action: "set_burn_escrow",
+                    signature: signature.to_string(),
+                    accounts: vec![
+                        ("state", state.to_string()),
+                        ("burn escrow", pda::burn_escrow().to_string()),
+                        ("retry slippage", format!("{} bps", retry_slippage_bps)),
+                    ],
+                },
+            )
+        }
+        Command::RetryPendingBurns => retry_pending_burns(cli, &rpc),
+        Command::ConfigureDepositToken { token, mint, min_deposit, pool } => {
+            let owner = load_keypair(&cli.keypair)?;
+            let instruction = ConfigureDepositToken {
+                state: state_address(cli)?,
+                owner: owner.pubkey(),
+                token: *token,
+                mint: *mint,
+                min_deposit: *min_deposit,
+                pool: pool.pool,
+                b_vault: pool.b_vault,
+                b_token_vault: pool.b_token_vault,
+                b_vault_lp_mint: pool.b_vault_lp_mint,
+                b_vault_lp: pool.b_vault_lp,
+                protocol_token_fee: pool.protocol_token_fee,
+                a_vault_lp: pool.a_vault_lp,
+            }
+            .instruction();
+            configure_deposit_token(cli, &rpc, &owner, instruction, *token)
+        }
+        Command::Tree { wallet, depth } => tree(cli, &rpc, wallet, *depth),
+        Command::PendingReserves => pending_reserves(cli, &rpc),
+        Command::Export { format, root, out } => export(&rpc, *format, root.as_ref(), out.as_deref()),
+    }
+}
+
+fn init(cli: &Cli, rpc: &RpcClient) -> CliResult<()> {
+    let owner = load_keypair(&cli.keypair)?;
+    if owner.pubkey() != admin_addresses::AUTHORIZED_INITIALIZER {
+        return Err(format!("{} is not the authorized initializer {}", owner.pubkey(), admin_addresses::AUTHORIZED_INITIALIZER).into());
+    }
+
+    let state = Keypair::new();
+    let instruction = Initialize { state: state.pubkey(), owner: owner.pubkey() }.instruction();
+    let signature = rpc::send(rpc, &[instruction], &owner, &[&state])?;
+
+    // Same keys as the config written by ini.js
+    let (program_sol_vault, vault_bump) = Pubkey::find_program_address(&[b"program_sol_vault"], &matrix_system::ID);
+    let config = serde_json::json!({
+        "programId": matrix_system::ID.to_string(),
+        "stateAddress": state.pubkey().to_string(),
+        "statePrivateKey": state.to_bytes().to_vec(),
+        "tokenMint": verified_addresses::TOKEN_MINT.to_string(),
+        "programSolVault": program_sol_vault.to_string(),
+        "programSolVaultBump": vault_bump,
+        "ownerWallet": owner.pubkey().to_string(),
+        "multisigTreasury": admin_addresses::MULTISIG_TREASURY.to_string(),
+        "meteoraAmmProgram": verified_addresses::METEORA_AMM_PROGRAM.to_string(),
+        "meteoraVaultProgram": verified_addresses::METEORA_VAULT_PROGRAM.to_string(),
+    });
+    fs::write(&cli.config, serde_json::to_string_pretty(&config)?)?;
+
+    print(
+        cli.output,
+        &TransactionView {
+            action: "initialize",
+            signature: signature.to_string(),
+            accounts: vec![
+                ("state", state.pubkey().to_string()),
+                ("owner", owner.pubkey().to_string()),
+                ("sol vault", program_sol_vault.to_string()),
+                ("config", cli.config.display().to_string()),
+            ],
+        },
+    )
+}
+
+fn close_user(cli: &Cli, rpc: &RpcClient, wallet: Option<&str>) -> CliResult<()> {
+    let operator = load_keypair(&cli.keypair)?;
+    let user_wallet = match wallet {
+        Some(path) => load_keypair(path)?,
+        None => operator.insecure_clone(),
+    };
+
+    let user = pda::user_account(&user_wallet.pubkey());
+    let account = rpc::user_account(rpc, &user)?.ok_or(format!("{} is not registered", user_wallet.pubkey()))?;
+    if !account.is_closable() {
+        return Err(format!(
+            "{} cannot be closed: {} filled slots, {} reserved lamports, {} referrals{}",
+            user_wallet.pubkey(),
+            account.chain.filled_slots,
+            account.reserved_sol,
+            account.referral_count,
+            if account.flags & USER_FLAG_COUNTS_REFERRALS == 0 { " (not tracked)" } else { "" }
+        )
+        .into());
+    }
+
+    let instruction = CloseUserAccount { owner_wallet: user_wallet.pubkey() }.instruction();
+    let signature = rpc::send(rpc, &[instruction], &operator, &[&user_wallet])?;
+    print(
+        cli.output,
+        &TransactionView {
+            action: "close_user_account",
+            signature: signature.to_string(),
+            accounts: vec![("user", user.to_string()), ("wallet", user_wallet.pubkey().to_string())],
+        },
+    )
+}
+
+fn migrate_wallet(cli: &Cli, rpc: &RpcClient, new_wallet: &str, wallet: Option<&str>) -> CliResult<()> {
+    let operator = load_keypair(&cli.keypair)?;
+    let user_wallet = match wallet {
+        Some(path) => load_keypair(path)?,
+        None => operator.insecure_clone(),
+    };
+    let new_wallet = load_keypair(new_wallet)?;
+    let state = state_address(cli)?;
+
+    let user = pda::user_account(&user_wallet.pubkey());
+    rpc::user_account(rpc, &user)?.ok_or(format!("{} is not registered", user_wallet.pubkey()))?;
+    let new_user = pda::user_account(&new_wallet.pubkey());
+    if rpc::account_exists(rpc, &new_user) {
+        return Err(format!("{} already has a user account", new_wallet.pubkey()).into());
+    }
+
+    let instruction = MigrateUserWallet { state, owner_wallet: user_wallet.pubkey(), new_wallet: new_wallet.pubkey() }.instruction();
+    let signature = rpc::send(rpc, &[instruction], &operator, &[&user_wallet, &new_wallet])?;
+    print(
+        cli.output,
+        &TransactionView {
+            action: "migrate_user_wallet",
+            signature: signature.to_string(),
+            accounts: vec![("redirect", user.to_string()), ("new user", new_user.to_string()), ("new wallet", new_wallet.pubkey().to_string())],
+        },
+    )
+}
+
+fn set_payout(cli: &Cli, rpc: &RpcClient, address: Option<Pubkey>, wallet: Option<&str>) -> CliResult<()> {
+    let operator = load_keypair(&cli.keypair)?;
+    let user_wallet = match wallet {
+        Some(path) => load_keypair(path)?,
+        None => operator.insecure_clone(),
+    };
+
+    let user = pda::user_account(&user_wallet.pubkey());
+    rpc::user_account(rpc, &user)?.ok_or(format!("{} is not registered", user_wallet.pubkey()))?;
+    // The user wallet itself clears the payout address
+    let payout_address = address.unwrap_or(user_wallet.pubkey());
+
+    let instruction = SetPayoutAddress { owner_wallet: user_wallet.pubkey(), payout_address }.instruction();
+    let signature = rpc::send(rpc, &[instruction], &operator, &[&user_wallet])?;
+    print(
+        cli.output,
+        &TransactionView {
+            action: "set_payout_address",
+            signature: signature.to_string(),
+            accounts: vec![("user", user.to_string()), ("payout", payout_address.to_string())],
+        },
+    )
+}
+
+fn claim_code(cli: &Cli, rpc: &RpcClient, code: &str, wallet: Option<&str>) -> CliResult<()> {
+    let operator = load_keypair(&cli.keypair)?;
+    let user_wallet = match wallet {
+        Some(path) => load_keypair(path)?,
+        None => operator.insecure_clone(),
+    };
+
+    let user = pda::user_account(&user_wallet.pubkey());
+    let account = rpc::user_account(rpc, &user)?.ok_or(format!("{} is not registered", user_wallet.pubkey()))?;
+    if account.flags & USER_FLAG_HAS_REFERRAL_CODE != 0 {
+        return Err(format!("{} already claimed a referral code", user_wallet.pubkey()).into());
+    }
+    let code = code.trim().to_ascii_lowercase();
+    if !ReferralCode::is_valid(&code) {
+        return Err(format!("'{}' is not a valid referral code", code).into());
+    }
+    if rpc::account_exists(rpc, &pda::referral_code(&code)) {
+        return Err(format!("referral code '{}' is taken", code).into());
+    }
+
+    // The user wallet pays the ReferralCode rent
+    let instruction = ClaimReferralCode { owner_wallet: user_wallet.pubkey(), code: code.clone() }.instruction();
+    let signature = rpc::send(rpc, &[instruction], &operator, &[&user_wallet])?;
+    print(
+        cli.output,
+        &TransactionView {
+            action: "claim_referral_code",
+            signature: signature.to_string(),
+            accounts: vec![("referral code", pda::referral_code(&code).to_string()), ("user", user.to_string())],
+        },
+    )
+}
+
+fn refresh_references(cli: &Cli, rpc: &RpcClient, wallet: &Pubkey) -> CliResult<()> {
+    let operator = load_keypair(&cli.keypair)?;
+    let user = pda::user_account(wallet);
+    let mut account = rpc::user_account(rpc, &user)?.ok_or(format!("{} is not registered", wallet))?;
+
+    // Follow the redirects on a local copy - a migrated position can move again
+    let mut redirects = Vec::new();
+    loop {
+        let mut references: Vec<Pubkey> = account.referrer().into_iter().collect();
+        references.extend(&account.chain.slots[..account.chain.filled_slots as usize]);
+        references.extend(account.upline.entries().iter().map(|entry| entry.pda));
+
+        let stale = references
+            .into_iter()
+            .filter(|reference| !redirects.contains(reference))
+            .find_map(|reference| rpc::user_account_redirect(rpc, &reference).map(|redirect| (reference, redirect)));
+        let Some((old_user, redirect)) = stale else { break };
+        account.redirect(&old_user, &redirect.entry());
+        redirects.push(old_user);
+    }
+    if redirects.is_empty() {
+        return Err(format!("{} has no reference to a migrated account", user).into());
+    }
+
+    let instruction = RefreshUserReferences { user, redirects: redirects.clone() }.instruction();
+    let signature = rpc::send(rpc, &[instruction], &operator, &[])?;
+    let mut accounts = vec![("user", user.to_string())];
+    accounts.extend(redirects.iter().map(|redirect| ("redirect", redirect.to_string())));
+    print(cli.output, &TransactionView { action: "refresh_user_references", signature: signature.to_string(), accounts })
+}
+
+fn register_base(cli: &Cli, rpc: &RpcClient, wallet: Option<&str>, deposit: u64) -> CliResult<()> {
+    let owner = load_keypair(&cli.keypair)?;
+    let user_wallet = match wallet {
+        Some(path) => load_keypair(path)?,
+        None => owner.insecure_clone(),
+    };
+    let state = state_address(cli)?;
+
+    let user = pda::user_account(&user_wallet.pubkey());
+    if rpc::user_account(rpc, &user)?.is_some() {
+        return Err(format!("{} is already registered", user_wallet.pubkey()).into());
+    }
+
+    // The swap runs through the user's WSOL and DONUT ATAs - create the missing ones
+    let mut instructions = vec![
+        ComputeBudgetInstruction::request_heap_frame(256 * 1024),
+        ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+    ];
+    for (address, mint) in [
+        (pda::wsol_account(&user_wallet.pubkey()), verified_addresses::WSOL_MINT),
+        (pda::donut_account(&user_wallet.pubkey()), verified_addresses::TOKEN_MINT),
+    ] {
+        if !rpc::account_exists(rpc, &address) {
+            instructions.push(spl_associated_token_account::instruction::create_associated_token_account(
+                &user_wallet.pubkey(),
+                &user_wallet.pubkey(),
+                &mint,
+                &spl_token::ID,
+            ));
+        }
+    }
+    instructions.push(
+        RegisterWithoutReferrer { state, owner: owner.pubkey(), user_wallet: user_wallet.pubkey(), deposit_amount: deposit }
+            .instruction(),
+    );
+
+    let signature = rpc::send(rpc, &instructions, &owner, &[&user_wallet])?;
+    print(
+        cli.output,
+        &TransactionView {
+            action: "register_without_referrer",
+            signature: signature.to_string(),
+            accounts: vec![("wallet", user_wallet.pubkey().to_string()), ("user account", user.to_string())],
+        },
+    )
+}
+
+fn show_vault(cli: &Cli, rpc: &RpcClient) -> CliResult<()> {
+    let address = pda::program_sol_vault();
+    let balance = rpc.get_balance(&address)?;
+    let rent_exempt_minimum = rpc.get_minimum_balance_for_rent_exemption(0)?;
+
+    // Deposit token reserves sit in their token vaults, not in the SOL vault
+    let users: Vec<_> = rpc::all_user_accounts(rpc)?.into_iter().filter(|(_, user)| user.reserve_token == RESERVE_SOL).collect();
+    let reserved_total: u64 = users.iter().map(|(_, user)| user.reserved_sol).sum();
+    // Without a config or --state the vault is still shown, minus the tracked total
+    let tracked_reserved = match state_address(cli) {
+        Ok(state) => Some(rpc::program_state(rpc, &state)?.total_reserved_lamports),
+        Err(_) => None,
+    };
+
+    print(
+        cli.output,
+        &VaultView {
+            address: address.to_string(),
+            balance,
+            rent_exempt_minimum,
+            reserved_total,
+            tracked_reserved,
+            users_with_reserve: users.iter().filter(|(_, user)| user.reserved_sol > 0).count(),
+            surplus: balance as i128 - rent_exempt_minimum as i128 - reserved_total as i128,
+        },
+    )
+}
+
+fn retry_pending_burns(cli: &Cli, rpc: &RpcClient) -> CliResult<()> {
+    let cranker = load_keypair(&cli.keypair)?;
+    let state = state_address(cli)?;
+    let pending_burn_lamports = rpc::program_state(rpc, &state)?.pending_burn_lamports;
+    if pending_burn_lamports == 0 {
+        return Err("no escrowed burns to retry".into());
+    }
+    let burned = pending_burn_lamports.min(MAX_BURN_RETRY_LAMPORTS);
+
+    // The escrow swaps through its own WSOL and DONUT ATAs - create the missing ones
+    let escrow = pda::burn_escrow();
+    let mut instructions = vec![ComputeBudgetInstruction::set_compute_unit_limit(400_000)];
+    for (address, mint) in [
+        (pda::wsol_account(&escrow), verified_addresses::WSOL_MINT),
+        (pda::donut_account(&escrow), verified_addresses::TOKEN_MINT),
+    ] {
+        if !rpc::account_exists(rpc, &address) {
+            instructions.push(spl_associated_token_account::instruction::create_associated_token_account(
+                &cranker.pubkey(),
+                &escrow,
+                &mint,
+                &spl_token::ID,
+            ));
+        }
+    }
+    instructions.push(RetryPendingBurns { state, cranker: cranker.pubkey() }.instruction());
+
+    let signature = rpc::send(rpc, &instructions, &cranker, &[])?;
+    print(
+        cli.output,
+        &TransactionView {
+            action: "retry_pending_burns",
+            signature: signature.to_string(),
+            accounts: vec![
+                ("burn escrow", escrow.to_string()),
+                ("burned", format!("{} lamports", burned)),
+                ("still escrowed", format!("{} lamports", pending_burn_lamports - burned)),
+            ],
+        },
+    )
+}
+
+fn sweep_vault(cli: &Cli, rpc: &RpcClient) -> CliResult<()> {
+    let owner = load_keypair(&cli.keypair)?;
+    let state = state_address(cli)?;
+    let multisig_treasury = rpc::program_state(rpc, &state)?.multisig_treasury;
+
+    let instruction = SweepVaultSurplus { state, owner: owner.pubkey(), multisig_treasury }.instruction();
+    let signature = rpc::send(rpc, &[instruction], &owner, &[])?;
+    print(
+        cli.output,
+        &TransactionView {
+            action: "sweep_vault_surplus",
+            signature: signature.to_string(),
+            accounts: vec![("sol vault", pda::program_sol_vault().to_string()), ("treasury", multisig_treasury.to_string())],
+        },
+    )
+}
+
+fn reconcile_vault(cli: &Cli, rpc: &RpcClient) -> CliResult<()> {
+    let owner = load_keypair(&cli.keypair)?;
+    let state = state_address(cli)?;
+    let total_reserved_lamports: u64 = rpc::all_user_accounts(rpc)?
+        .iter()
+        .filter(|(_, user)| user.reserve_token == RESERVE_SOL)
+        .map(|(_, user)| user.reserved_sol)
+        .sum();
+
+    let instruction = ReconcileVault { state, owner: owner.pubkey(), total_reserved_lamports }.instruction();
+    let signature = rpc::send(rpc, &[instruction], &owner, &[])?;
+    print(
+        cli.output,
+        &TransactionView {
+            action: "reconcile_vault",
+            signature: signature.to_string(),
+            accounts: vec![("state", state.to_string()), ("reserved total", sol(total_reserved_lamports))],
+        },
+    )
+}
+
+fn set_allowlist(cli: &Cli, rpc: &RpcClient, mode: Allowlist, wallets: Option<&Path>, invite_signer: Option<Pubkey>) -> CliResult<()> {
+    let owner = load_keypair(&cli.keypair)?;
+    let state = state_address(cli)?;
+    // The root and the signer not being replaced stay as they are
+    let current = rpc::program_state(rpc, &state)?;
+
+    let root = match wallets {
+        Some(path) => {
+            let wallets = fs::read_to_string(path)?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(|line| line.parse::<Pubkey>().map_err(|err| format!("{}: {}", line, err)))
+                .collect::<Result<Vec<_>, _>>()?;
+            eprintln!("{} wallets in the allowlist", wallets.len());
+            matrix_system_client::allowlist::merkle_root(&wallets).ok_or(format!("{} has no wallets", path.display()))?
+        }
+        None => current.allowlist_root,
+    };
+    let mode = match mode {
+        Allowlist::Open => AllowlistMode::Open,
+        Allowlist::Merkle => AllowlistMode::MerkleRoot,
+        Allowlist::Invite => AllowlistMode::InviteSigner,
+    };
+
+    let instruction = SetAllowlist {
+        state,
+        owner: owner.pubkey(),
+        mode,
+        root,
+        invite_signer: invite_signer.unwrap_or(current.invite_signer),
+    }
+    .instruction();
+    let signature = rpc::send(rpc, &[instruction], &owner, &[])?;
+    print(
+        cli.output,
+        &TransactionView { action: "set_allowlist", signature: signature.to_string(), accounts: vec![("state", state.to_string())] },
+    )
+}
+
+fn set_referrer_limits(
+    cli: &Cli,
+    rpc: &RpcClient,
+    max_cycles_per_week: u32,
+    min_cycle_interval: i64,
+    week_start: Option<i64>,
+) -> CliResult<()> {
+    let owner = load_keypair(&cli.keypair)?;
+    let state = state_address(cli)?;
+    let week_start = match week_start {
+        Some(week_start) => week_start,
+        None => {
+            let data = rpc.get_account_data(&pda::airdrop_program_state())?;
+            airdrop_start_timestamp(&data).ok_or("airdrop program state is too small")?
+        }
+    };
+
+    let instruction =
+        SetReferrerLimits { state, owner: owner.pubkey(), max_cycles_per_week, min_cycle_interval, week_start }.instruction();
+    let signature = rpc::send(rpc, &[instruction], &owner, &[])?;
+    print(
+        cli.output,
+        &TransactionView {
+            action: "set_referrer_limits",
+            signature: signature.to_string(),
+            accounts: vec![("state", state.to_string()), ("week start", week_start.to_string())],
+        },
+    )
+}
+
+fn send_campaign(cli: &Cli, rpc: &RpcClient, owner: &Keypair, instruction: Instruction, action: &'static str) -> CliResult<()> {
+    let state = instruction.accounts[0].pubkey;
+    let signature = rpc::send(rpc, &[instruction], owner, &[])?;
+    let campaign = rpc::program_state(rpc, &state)?.campaign;
+    print(
+        cli.output,
+        &TransactionView {
+            action,
+            signature: signature.to_string(),
+            accounts: vec![
+                ("state", state.to_string()),
+                ("campaign", campaign.id.to_string()),
+                ("window", format!("{} - {}", campaign.start_timestamp, campaign.end_timestamp)),
+            ],
+        },
+    )
+}
+
+fn configure_deposit_token(cli: &Cli, rpc: &RpcClient, owner: &Keypair, instruction: Instruction, token: u8) -> CliResult<()> {
+    if token == RESERVE_SOL {
+        return Err("token 0 is SOL and cannot be configured".into());
+    }
+    let signature = rpc::send(rpc, &[instruction], owner, &[])?;
+    print(
+        cli.output,
+        &TransactionView {
+            action: "configure_deposit_token",
+            signature: signature.to_string(),
+            accounts: vec![
+                ("deposit config", pda::token_deposit_config(token).to_string()),
+                ("reserve vault", pda::token_reserve_vault(token).to_string()),
+            ],
+        },
+    )
+}
+
+fn tree(cli: &Cli, rpc: &RpcClient, wallet: &Pubkey, depth: usize) -> CliResult<()> {
+    let root = pda::user_account(wallet);
+    let users: HashMap<Pubkey, matrix_system::UserAccount> = rpc::all_user_accounts(rpc)?.into_iter().collect();
+    let account = users.get(&root).ok_or(format!("{} is not registered", wallet))?;
+
+    let mut referrals: HashMap<Pubkey, Vec<Pubkey>> = HashMap::new();
+    for (address, user) in &users {
+        if let Some(referrer) = user.referrer() {
+            referrals.entry(referrer).or_default().push(*address);
+        }
+    }
+    for children in referrals.values_mut() {
+        children.sort_by_key(|address| users[address].upline.id);
+    }
+
+    fn node(
+        address: &Pubkey,
+        users: &HashMap<Pubkey, matrix_system::UserAccount>,
+        referrals: &HashMap<Pubkey, Vec<Pubkey>>,
+        depth: usize,
+    ) -> TreeNode {
+        let user = &users[address];
+        let children = referrals.get(address).map(Vec::as_slice).unwrap_or(&[]);
+        TreeNode {
+            wallet: user.owner_wallet.to_string(),
+            pda: address.to_string(),
+            chain_id: user.chain.id,
+            filled_slots: user.chain.filled_slots,
+            reserved_sol: user.reserved_sol,
+            reserve_token: user.reserve_token,
+            referrals: if depth == 0 { Vec::new() } else { children.iter().map(|child| node(child, users, referrals, depth - 1)).collect() },
+            truncated: depth == 0 && !children.is_empty(),
+        }
+    }
+
+    let view = TreeView {
+        upline: UserView::new(&root, account).upline,
+        root: node(&root, &users, &referrals, depth),
+    };
+    print(cli.output, &view)
+}
+
+fn pending_reserves(cli: &Cli, rpc: &RpcClient) -> CliResult<()> {
+    let mut reserves: Vec<PendingReserve> = rpc::all_user_accounts(rpc)?
+        .into_iter()
+        .filter(|(_, user)| user.reserved_sol > 0)
+        .map(|(address, user)| PendingReserve {
+            wallet: user.owner_wallet.to_string(),
+            pda: address.to_string(),
+            chain_id: user.chain.id,
+            reserved_sol: user.reserved_sol,
+            reserve_token: user.reserve_token,
+        })
+        .collect();
+    reserves.sort_by_key(|reserve| std::cmp::Reverse(reserve.reserved_sol));
+
+    let total = reserves.iter().filter(|r| r.reserve_token == RESERVE_SOL).map(|r| r.reserved_sol).sum();
+    print(cli.output, &PendingReservesView { total, reserves })
+}
+
+fn export(rpc: &RpcClient, format: ExportFormat, root: Option<&Pubkey>, out: Option<&Path>) -> CliResult<()> {
+    let mut graph = matrix_system_client::rpc::referral_graph(rpc)?;
+    if let Some(wallet) = root {
+        let pda = pda::user_account(wallet);
+        if !graph.users.iter().any(|user| user.pda == pda) {
+            return Err(format!("{} is not registered", wallet).into());
+        }
+        graph = graph.subtree(&pda);
+    }
+    if !graph.missing_referrers.is_empty() {
+        eprintln!("warning: {} referrers are not in the current UserAccount layout", graph.missing_referrers.len());
+    }
+
+    let rendered = match format {
+        ExportFormat::Json => graph.to_json()?,
+        ExportFormat::Csv => graph.to_csv(),
+        ExportFormat::Dot => graph.to_dot(),
+    };
+    match out {
+        Some(path) => {
+            fs::write(path, rendered)?;
+            eprintln!("{} users written to {}", graph.users.len(), path.display());
+        }
+        None => print!("{}", rendered),
+    }
+    Ok(())
+}
@@ -0,0 +1,42 @@
+// RPC helpers on top of matrix_system_client::rpc: typed account reads and sending
+// transactions signed by the operator.
+
+use anchor_lang::AccountDeserialize;
+use matrix_system::{ProgramState, UserAccount, UserAccountRedirect};
+use matrix_system_client::{decode_user_account, decode_user_account_redirect, rpc::RpcFetcher, AccountFetcher};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey, signature::Signature, signer::Signer, transaction::Transaction};
+
+use crate::CliResult;
+
+pub use matrix_system_client::rpc::all_user_accounts;
+
+pub fn program_state(rpc: &RpcClient, address: &Pubkey) -> CliResult<ProgramState> {
+    let data = rpc.get_account_data(address)?;
+    Ok(ProgramState::try_deserialize(&mut data.as_slice())?)
+}
+
+pub fn account_exists(rpc: &RpcClient, address: &Pubkey) -> bool {
+    RpcFetcher(rpc).account_data(address).is_some()
+}
+
+pub fn user_account(rpc: &RpcClient, address: &Pubkey) -> CliResult<Option<UserAccount>> {
+    Ok(RpcFetcher(rpc)
+        .account_data(address)
+        .map(|data| decode_user_account(&data).ok_or(format!("{} is not a UserAccount in the current layout", address)))
+        .transpose()?)
+}
+
+/// Redirect left at `address` by migrate_user_wallet, None for any other account
+pub fn user_account_redirect(rpc: &RpcClient, address: &Pubkey) -> Option<UserAccountRedirect> {
+    RpcFetcher(rpc).account_data(address).and_then(|data| decode_user_account_redirect(&data))
+}
+
+pub fn send(rpc: &RpcClient, instructions: &[Instruction], payer: &dyn Signer, signers: &[&dyn Signer]) -> CliResult<Signature> {
+    let blockhash = rpc.get_latest_blockhash()?;
+    let mut all_signers = vec![payer];
+    all_signers.extend_from_slice(signers);
+
+    let transaction = Transaction::new_signed_with_payer(instructions, Some(&payer.pubkey()), &all_signers, blockhash);
+    Ok(rpc.send_and_confirm_transaction(&transaction)?)
+}
@@ -0,0 +1,388 @@
+// Output of each subcommand - serialized as JSON with --output json, printed with
+// Display otherwise.
+
+use std::fmt;
+
+use matrix_system::{ProgramState, UserAccount, RESERVE_SOL, USER_FLAG_COUNTS_REFERRALS};
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+
+// Lamports as SOL with every decimal
+pub fn sol(lamports: u64) -> String {
+    format!("{}.{:09}", lamports / 1_000_000_000, lamports % 1_000_000_000)
+}
+
+// A reserve in the currency it was deposited in - deposit tokens in base units
+pub fn reserve(amount: u64, token: u8) -> String {
+    match token {
+        RESERVE_SOL => format!("{} SOL", sol(amount)),
+        token => format!("{} of deposit token {}", amount, token),
+    }
+}
+
+fn signed_sol(lamports: i128) -> String {
+    let sign = if lamports < 0 { "-" } else { "" };
+    format!("{}{}", sign, sol(lamports.unsigned_abs() as u64))
+}
+
+#[derive(Serialize)]
+pub struct StateView {
+    pub address: String,
+    pub owner: String,
+    pub multisig_treasury: String,
+    pub next_upline_id: u32,
+    pub next_chain_id: u32,
+    pub airdrop_active: bool,
+    pub airdrop_end_timestamp: i64,
+    pub max_upline_depth: u8,
+    pub depth_overflow_policy: String,
+    pub total_reserved_lamports: u64,
+    pub allowlist_mode: String,
+    pub allowlist_root: String,      // Hex, all zeros when never set
+    pub invite_signer: String,
+    pub campaign: Option<CampaignView>, // None - no campaign was ever scheduled
+    pub max_cycles_per_week: u32,    // 0 = no cap
+    pub min_cycle_interval: i64,     // Seconds, 0 = none
+    pub cycle_week_start: i64,
+    pub protocol_fee_bps: u16,
+    pub burn_escrow_enabled: bool,
+    pub pending_burn_lamports: u64,  // Held in the burn escrow until retry_pending_burns
+    pub burn_retry_slippage_bps: u16,
+    pub reserves_reconciled: bool,   // false after resize_program_state until reconcile_vault
+}
+
+#[derive(Serialize)]
+pub struct CampaignView {
+    pub id: u32,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+    pub registrations: u32,
+    pub max_registrations: u32, // 0 = no cap
+    pub closed: bool,
+}
+
+impl StateView {
+    pub fn new(address: &Pubkey, state: &ProgramState) -> Self {
+        Self {
+            address: address.to_string(),
+            owner: state.owner.to_string(),
+            multisig_treasury: state.multisig_treasury.to_string(),
+            next_upline_id: state.next_upline_id,
+            next_chain_id: state.next_chain_id,
+            airdrop_active: state.airdrop_active,
+            airdrop_end_timestamp: state.airdrop_end_timestamp,
+            max_upline_depth: state.upline_depth() as u8,
+            depth_overflow_policy: format!("{:?}", state.depth_overflow_policy),
+            total_reserved_lamports: state.total_reserved_lamports,
+            allowlist_mode: format!("{:?}", state.allowlist_mode),
+            allowlist_root: state.allowlist_root.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            invite_signer: state.invite_signer.to_string(),
+            campaign: (state.campaign.id != 0).then(|| CampaignView {
+                id: state.campaign.id,
+                start_timestamp: state.campaign.start_timestamp,
+                end_timestamp: state.campaign.end_timestamp,
+                registrations: state.campaign.registrations(state.next_upline_id),
+                max_registrations: state.campaign.max_registrations,
+                closed: state.campaign.closed,
+            }),
+            max_cycles_per_week: state.referrer_limits.max_cycles_per_week,
+            min_cycle_interval: state.referrer_limits.min_cycle_interval,
+            cycle_week_start: state.referrer_limits.week_start,
+            protocol_fee_bps: state.protocol_fee_bps,
+            burn_escrow_enabled: state.burn_escrow_enabled,
+            pending_burn_lamports: state.pending_burn_lamports,
+            burn_retry_slippage_bps: state.burn_retry_slippage_bps,
+            reserves_reconciled: state.reserves_reconciled,
+        }
+    }
+}
+
+impl fmt::Display for StateView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Program state      {}", self.address)?;
+        writeln!(f, "  owner            {}", self.owner)?;
+        writeln!(f, "  treasury         {}", self.multisig_treasury)?;
+        writeln!(f, "  next upline id   {}", self.next_upline_id)?;
+        writeln!(f, "  next chain id    {}", self.next_chain_id)?;
+        writeln!(f, "  airdrop active   {}", self.airdrop_active)?;
+        if !self.airdrop_active {
+            writeln!(f, "  airdrop ended    {}", self.airdrop_end_timestamp)?;
+        }
+        writeln!(f, "  upline depth     {}", self.max_upline_depth)?;
+        writeln!(f, "  overflow policy  {}", self.depth_overflow_policy)?;
+        writeln!(
+            f,
+            "  reserved total   {} SOL{}",
+            sol(self.total_reserved_lamports),
+            if self.reserves_reconciled { "" } else { " (not reconciled - run reconcile-vault)" }
+        )?;
+        match self.allowlist_mode.as_str() {
+            "MerkleRoot" => writeln!(f, "  allowlist        Merkle root {}", self.allowlist_root)?,
+            "InviteSigner" => writeln!(f, "  allowlist        invites signed by {}", self.invite_signer)?,
+            _ => writeln!(f, "  allowlist        open")?,
+        }
+        match &self.campaign {
+            Some(campaign) => {
+                let cap = match campaign.max_registrations {
+                    0 => "no cap".to_string(),
+                    max => format!("{}/{} registrations", campaign.registrations, max),
+                };
+                writeln!(
+                    f,
+                    "  campaign         {} - {} to {}, {}{}",
+                    campaign.id,
+                    campaign.start_timestamp,
+                    campaign.end_timestamp,
+                    cap,
+                    if campaign.closed { " (closed)" } else { "" }
+                )?
+            }
+            None => writeln!(f, "  campaign         none (always open)")?,
+        }
+        match (self.max_cycles_per_week, self.min_cycle_interval) {
+            (0, 0) => writeln!(f, "  referrer limits  none")?,
+            (max, interval) => writeln!(
+                f,
+                "  referrer limits  {} cycles per week from {}, {}s between cycles",
+                if max == 0 { "unlimited".to_string() } else { max.to_string() },
+                self.cycle_week_start,
+                interval
+            )?,
+        }
+        writeln!(f, "  protocol fee     {} bps to the treasury", self.protocol_fee_bps)?;
+        write!(
+            f,
+            "  burn escrow      {}, {} lamports pending, retries at most {} bps below the quote",
+            if self.burn_escrow_enabled { "enabled" } else { "disabled" },
+            self.pending_burn_lamports,
+            self.burn_retry_slippage_bps
+        )
+    }
+}
+
+#[derive(Serialize)]
+pub struct UplineView {
+    pub pda: String,
+    pub wallet: String,
+}
+
+#[derive(Serialize)]
+pub struct UserView {
+    pub wallet: String,
+    pub pda: String,
+    pub registered: bool,
+    pub referrer: Option<String>,
+    pub upline_id: u32,
+    pub upline_depth: u8,
+    pub chain_id: u32,
+    pub filled_slots: u8,
+    pub slots: Vec<String>,
+    pub reserved_sol: u64,
+    pub reserve_token: u8, // RESERVE_SOL or a configured deposit token
+    pub referral_count: Option<u32>, // None for accounts registered before referrals were counted
+    pub payout_address: Option<String>, // None - payments go to the wallet
+    pub last_cycle_at: i64,          // 0 - the matrix never completed since cycles were tracked
+    pub cycle_week: u32,
+    pub cycles_in_week: u32,
+    pub upline: Vec<UplineView>, // Root first, direct referrer last
+}
+
+impl UserView {
+    pub fn new(pda: &Pubkey, account: &UserAccount) -> Self {
+        Self {
+            wallet: account.owner_wallet.to_string(),
+            pda: pda.to_string(),
+            registered: account.is_registered(),
+            referrer: account.referrer().map(|referrer| referrer.to_string()),
+            upline_id: account.upline.id,
+            upline_depth: account.upline.depth,
+            chain_id: account.chain.id,
+            filled_slots: account.chain.filled_slots,
+            slots: account.chain.slots[..account.chain.filled_slots as usize].iter().map(|slot| slot.to_string()).collect(),
+            reserved_sol: account.reserved_sol,
+            reserve_token: account.reserve_token,
+            referral_count: (account.flags & USER_FLAG_COUNTS_REFERRALS != 0).then_some(account.referral_count),
+            payout_address: account.payout_address().map(|payout| payout.to_string()),
+            last_cycle_at: account.cycles.last_cycle_at,
+            cycle_week: account.cycles.week,
+            cycles_in_week: account.cycles.count,
+            upline: account
+                .upline
+                .entries()
+                .iter()
+                .map(|entry| UplineView { pda: entry.pda.to_string(), wallet: entry.wallet.to_string() })
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for UserView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "User               {}", self.wallet)?;
+        writeln!(f, "  account          {}", self.pda)?;
+        writeln!(f, "  registered       {}", self.registered)?;
+        writeln!(f, "  referrer         {}", self.referrer.as_deref().unwrap_or("none (base user)"))?;
+        writeln!(f, "  upline id        {} (depth {})", self.upline_id, self.upline_depth)?;
+        writeln!(f, "  matrix           chain {} - {}/3 slots", self.chain_id, self.filled_slots)?;
+        for (i, slot) in self.slots.iter().enumerate() {
+            writeln!(f, "    slot {}         {}", i + 1, slot)?;
+        }
+        writeln!(f, "  reserved         {}", reserve(self.reserved_sol, self.reserve_token))?;
+        writeln!(f, "  payout           {}", self.payout_address.as_deref().unwrap_or("wallet"))?;
+        match self.referral_count {
+            Some(count) => writeln!(f, "  referrals        {}", count)?,
+            None => writeln!(f, "  referrals        not tracked")?,
+        }
+        match self.last_cycle_at {
+            0 => writeln!(f, "  cycles           none")?,
+            at => writeln!(f, "  cycles           {} in week {}, last at {}", self.cycles_in_week, self.cycle_week, at)?,
+        }
+        write!(f, "  upline           {} entries", self.upline.len())?;
+        for (i, entry) in self.upline.iter().enumerate() {
+            write!(f, "\n    [{}] {} (account {})", i, entry.wallet, entry.pda)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct VaultView {
+    pub address: String,
+    pub balance: u64,
+    pub rent_exempt_minimum: u64,
+    pub reserved_total: u64,         // Sum of the SOL reserves over every UserAccount
+    pub tracked_reserved: Option<u64>, // ProgramState::total_reserved_lamports
+    pub users_with_reserve: usize,
+    pub surplus: i128,               // balance - rent minimum - reserved total
+}
+
+impl fmt::Display for VaultView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Program SOL vault  {}", self.address)?;
+        writeln!(f, "  balance          {} SOL", sol(self.balance))?;
+        writeln!(f, "  rent minimum     {} SOL", sol(self.rent_exempt_minimum))?;
+        writeln!(f, "  reserved         {} SOL across {} users", sol(self.reserved_total), self.users_with_reserve)?;
+        if let Some(tracked) = self.tracked_reserved {
+            writeln!(f, "  tracked          {} SOL (program state)", sol(tracked))?;
+        }
+        write!(f, "  surplus          {} SOL", signed_sol(self.surplus))?;
+        if self.surplus < 0 {
+            write!(f, "\n  WARNING: the vault holds less than the reserved SOL")?;
+        }
+        if self.tracked_reserved.is_some_and(|tracked| tracked != self.reserved_total) {
+            write!(f, "\n  WARNING: the tracked total differs from the accounts - run reconcile-vault")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct TreeNode {
+    pub wallet: String,
+    pub pda: String,
+    pub chain_id: u32,
+    pub filled_slots: u8,
+    pub reserved_sol: u64,
+    pub reserve_token: u8,
+    pub referrals: Vec<TreeNode>, // Empty past the requested depth
+    pub truncated: bool,          // Has referrals below the requested depth
+}
+
+impl TreeNode {
+    fn write(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        write!(
+            f,
+            "\n{:indent$}{} chain {} {}/3 reserved {}",
+            "",
+            self.wallet,
+            self.chain_id,
+            self.filled_slots,
+            reserve(self.reserved_sol, self.reserve_token),
+            indent = indent
+        )?;
+        if self.truncated {
+            write!(f, " ...")?;
+        }
+        for referral in &self.referrals {
+            referral.write(f, indent + 2)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct TreeView {
+    pub upline: Vec<UplineView>, // Root first
+    pub root: TreeNode,
+}
+
+impl fmt::Display for TreeView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Upline")?;
+        if self.upline.is_empty() {
+            write!(f, "\n  none (base user)")?;
+        }
+        for entry in &self.upline {
+            write!(f, "\n  {}", entry.wallet)?;
+        }
+        write!(f, "\nReferrals")?;
+        self.root.write(f, 2)
+    }
+}
+
+#[derive(Serialize)]
+pub struct PendingReserve {
+    pub wallet: String,
+    pub pda: String,
+    pub chain_id: u32,
+    pub reserved_sol: u64,
+    pub reserve_token: u8,
+}
+
+#[derive(Serialize)]
+pub struct PendingReservesView {
+    pub total: u64, // SOL reserves only
+    pub reserves: Vec<PendingReserve>, // Largest first
+}
+
+impl fmt::Display for PendingReservesView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pending reserves   {} SOL across {} users", sol(self.total), self.reserves.len())?;
+        for reserve in &self.reserves {
+            write!(f, "\n  {} chain {} - {}", reserve.wallet, reserve.chain_id, self::reserve(reserve.reserved_sol, reserve.reserve_token))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct ReferralCodeView {
+    pub code: String,
+    pub referrer_wallet: String, // Current wallet, after any migration
+    pub user_account: String,
+}
+
+impl fmt::Display for ReferralCodeView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Referral code      {}", self.code)?;
+        writeln!(f, "  referrer wallet  {}", self.referrer_wallet)?;
+        write!(f, "  user account     {}", self.user_account)
+    }
+}
+
+#[derive(Serialize)]
+pub struct TransactionView {
+    pub action: &'static str,
+    pub signature: String,
+    pub accounts: Vec<(&'static str, String)>,
+}
+
+impl fmt::Display for TransactionView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} confirmed: {}", self.action, self.signature)?;
+        for (name, address) in &self.accounts {
+            write!(f, "\n  {:<16} {}", name, address)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+// Allowlist helpers for the invite-only registration phase: the Merkle tree of allowed
+// wallets whose root set_allowlist stores, the proof each registration passes, and the
+// Ed25519 program instruction that carries an admin invite.
+
+use matrix_system::allowlist::{invite_message, leaf, node};
+use solana_program::{ed25519_program, instruction::Instruction, pubkey::Pubkey};
+
+// Ed25519 instruction layout: count and padding, one offsets record, then the data
+const OFFSETS_START: usize = 2;
+const DATA_START: usize = OFFSETS_START + 14;
+const CURRENT_INSTRUCTION: u16 = u16::MAX;
+
+// Levels of the tree from the sorted leaves up to the root. A node without a sibling
+// moves up unchanged, so its proof simply skips that level.
+fn levels(wallets: &[Pubkey]) -> Vec<Vec<[u8; 32]>> {
+    let mut leaves: Vec<[u8; 32]> = wallets.iter().map(leaf).collect();
+    leaves.sort_unstable();
+    leaves.dedup();
+
+    let mut levels = vec![leaves];
+    while levels.last().is_some_and(|level| level.len() > 1) {
+        let next = levels
+            .last()
+            .unwrap()
+            .chunks(2)
+            .map(|pair| match pair {
+                [a, b] => node(a, b),
+                [a] => *a,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+/// Root to pass to set_allowlist, None for an empty list
+pub fn merkle_root(wallets: &[Pubkey]) -> Option<[u8; 32]> {
+    levels(wallets).last().and_then(|level| level.first().copied())
+}
+
+/// Proof of `wallet` against merkle_root(wallets), None if it is not in the list
+pub fn merkle_proof(wallets: &[Pubkey], wallet: &Pubkey) -> Option<Vec<[u8; 32]>> {
+    let levels = levels(wallets);
+    let mut index = levels[0].iter().position(|hash| *hash == leaf(wallet))?;
+
+    let mut proof = Vec::new();
+    for level in &levels[..levels.len() - 1] {
+        if let Some(sibling) = level.get(index ^ 1) {
+            proof.push(*sibling);
+        }
+        index /= 2;
+    }
+    Some(proof)
+}
+
+/// Ed25519 program instruction verifying `signature` of the invite of `wallet` by
+/// `invite_signer` - a signature of matrix_system::allowlist::invite_message(wallet).
+/// It must come before the registration in the same transaction.
+pub fn invite_instruction(invite_signer: &Pubkey, signature: &[u8; 64], wallet: &Pubkey) -> Instruction {
+    let message = invite_message(wallet);
+    let public_key_offset = DATA_START;
+    let signature_offset = public_key_offset + 32;
+    let message_offset = signature_offset + 64;
+
+    let mut data = vec![1, 0];
+    for value in [
+        signature_offset as u16,
+        CURRENT_INSTRUCTION,
+        public_key_offset as u16,
+        CURRENT_INSTRUCTION,
+        message_offset as u16,
+        message.len() as u16,
+        CURRENT_INSTRUCTION,
+    ] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(invite_signer.as_ref());
+    data.extend_from_slice(signature);
+    data.extend_from_slice(&message);
+
+    Instruction { program_id: ed25519_program::ID, accounts: vec![], data }
+}
@@ -0,0 +1,187 @@
+// Referral tree export. Rebuilds who referred whom from every UserAccount - the edge
+// comes from `referrer`, the referrer's wallet from the last entry of `upline` - and
+// renders it as JSON, CSV or Graphviz DOT annotated with each user's current matrix.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+use matrix_system::UserAccount;
+use serde::{Serialize, Serializer};
+use solana_program::pubkey::Pubkey;
+
+fn base58<S: Serializer>(key: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(key)
+}
+
+fn base58_opt<S: Serializer>(key: &Option<Pubkey>, serializer: S) -> Result<S::Ok, S::Error> {
+    match key {
+        Some(key) => serializer.collect_str(key),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn base58_vec<S: Serializer>(keys: &[Pubkey], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(keys.iter().map(|key| key.to_string()))
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ReferralNode {
+    #[serde(serialize_with = "base58")]
+    pub pda: Pubkey,
+    #[serde(serialize_with = "base58")]
+    pub wallet: Pubkey,
+    #[serde(serialize_with = "base58_opt")]
+    pub referrer: Option<Pubkey>,        // None for base users
+    #[serde(serialize_with = "base58_opt")]
+    pub referrer_wallet: Option<Pubkey>, // None if the upline does not end with the referrer
+    pub upline_id: u32,
+    pub depth: u8,
+    pub chain_id: u32,
+    pub filled_slots: u8,
+    pub reserved_sol: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ReferralGraph {
+    pub users: Vec<ReferralNode>, // Registration order (upline id)
+    #[serde(serialize_with = "base58_vec")]
+    pub missing_referrers: Vec<Pubkey>, // Referrers absent from the accounts, e.g. not migrated
+}
+
+impl ReferralGraph {
+    pub fn new<I: IntoIterator<Item = (Pubkey, UserAccount)>>(accounts: I) -> Self {
+        let mut users: Vec<ReferralNode> = accounts
+            .into_iter()
+            .map(|(pda, account)| {
+                let referrer = account.referrer();
+                let referrer_wallet = account
+                    .upline
+                    .entries()
+                    .last()
+                    .filter(|entry| Some(entry.pda) == referrer)
+                    .map(|entry| entry.wallet);
+
+                ReferralNode {
+                    pda,
+                    wallet: account.owner_wallet,
+                    referrer,
+                    referrer_wallet,
+                    upline_id: account.upline.id,
+                    depth: account.upline.depth,
+                    chain_id: account.chain.id,
+                    filled_slots: account.chain.filled_slots,
+                    reserved_sol: account.reserved_sol,
+                }
+            })
+            .collect();
+        users.sort_by_key(|user| (user.upline_id, user.pda));
+
+        let known: HashSet<Pubkey> = users.iter().map(|user| user.pda).collect();
+        let mut missing_referrers: Vec<Pubkey> = users
+            .iter()
+            .filter_map(|user| user.referrer)
+            .filter(|referrer| !known.contains(referrer))
+            .collect();
+        missing_referrers.sort();
+        missing_referrers.dedup();
+
+        Self { users, missing_referrers }
+    }
+
+    /// Users without a referrer
+    pub fn roots(&self) -> impl Iterator<Item = &ReferralNode> {
+        self.users.iter().filter(|user| user.referrer.is_none())
+    }
+
+    /// Users directly referred by `pda`, in registration order
+    pub fn referrals(&self, pda: &Pubkey) -> impl Iterator<Item = &ReferralNode> {
+        let pda = *pda;
+        self.users.iter().filter(move |user| user.referrer == Some(pda))
+    }
+
+    /// `root` and everyone below it
+    pub fn subtree(&self, root: &Pubkey) -> Self {
+        let mut children: HashMap<Pubkey, Vec<Pubkey>> = HashMap::new();
+        for user in &self.users {
+            if let Some(referrer) = user.referrer {
+                children.entry(referrer).or_default().push(user.pda);
+            }
+        }
+
+        let mut included = HashSet::new();
+        let mut queue = VecDeque::from([*root]);
+        while let Some(pda) = queue.pop_front() {
+            if included.insert(pda) {
+                queue.extend(children.get(&pda).into_iter().flatten());
+            }
+        }
+
+        Self {
+            users: self.users.iter().filter(|user| included.contains(&user.pda)).cloned().collect(),
+            missing_referrers: Vec::new(),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// One row per user; empty referrer columns for base users
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("pda,wallet,referrer,referrer_wallet,upline_id,depth,chain_id,filled_slots,reserved_sol\n");
+        for user in &self.users {
+            let referrer = user.referrer.map(|key| key.to_string()).unwrap_or_default();
+            let referrer_wallet = user.referrer_wallet.map(|key| key.to_string()).unwrap_or_default();
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{},{}",
+                user.pda,
+                user.wallet,
+                referrer,
+                referrer_wallet,
+                user.upline_id,
+                user.depth,
+                user.chain_id,
+                user.filled_slots,
+                user.reserved_sol
+            );
+        }
+        csv
+    }
+
+    /// Directed graph referrer -> referral. Base users have a double border, users
+    /// holding a reserve are filled, missing referrers are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph referrals {\n    rankdir=TB;\n    node [shape=box, fontname=\"monospace\"];\n");
+
+        for user in &self.users {
+            let wallet = user.wallet.to_string();
+            let mut attributes = format!(
+                "label=\"{}..{}\\nchain {} | {}/3\\nreserved {} lamports\"",
+                &wallet[..4],
+                &wallet[wallet.len() - 4..],
+                user.chain_id,
+                user.filled_slots,
+                user.reserved_sol
+            );
+            if user.referrer.is_none() {
+                attributes.push_str(", peripheries=2");
+            }
+            if user.reserved_sol > 0 {
+                attributes.push_str(", style=filled, fillcolor=lightyellow");
+            }
+            let _ = writeln!(dot, "    \"{}\" [{}];", user.pda, attributes);
+        }
+        for missing in &self.missing_referrers {
+            let _ = writeln!(dot, "    \"{}\" [label=\"missing\", style=dashed];", missing);
+        }
+        for user in &self.users {
+            if let Some(referrer) = user.referrer {
+                let _ = writeln!(dot, "    \"{}\" -> \"{}\";", referrer, user.pda);
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
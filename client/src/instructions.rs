@@ -0,0 +1,632 @@
+// Typed instruction builders. Each struct holds the keys and arguments the caller
+// chooses; fixed addresses, PDAs and token accounts are filled in from the program's
+// verified addresses.
+
+use anchor_lang::{InstructionData, ToAccountMetas};
+use matrix_system::{accounts, instruction, verified_addresses::*, AllowlistMode, TokenDepositConfig, UserAccount};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
+
+use crate::{pda, resolver};
+
+/// initialize - creates the program state. `state` is a fresh keypair that signs
+/// along with `owner`, which must be the authorized initializer.
+#[derive(Clone, Copy, Debug)]
+pub struct Initialize {
+    pub state: Pubkey,
+    pub owner: Pubkey,
+}
+
+impl Initialize {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::Initialize {
+                state: self.state,
+                owner: self.owner,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::Initialize {}.data(),
+        }
+    }
+}
+
+/// register_without_referrer - registers a base user. Signed by the program owner
+/// and by `user_wallet`.
+#[derive(Clone, Copy, Debug)]
+pub struct RegisterWithoutReferrer {
+    pub state: Pubkey,
+    pub owner: Pubkey,
+    pub user_wallet: Pubkey,
+    pub deposit_amount: u64,
+}
+
+impl RegisterWithoutReferrer {
+    pub fn instruction(&self) -> Instruction {
+        let mut accounts = accounts::RegisterWithoutReferrerDeposit {
+            state: self.state,
+            owner: self.owner,
+            user_wallet: self.user_wallet,
+            user: pda::user_account(&self.user_wallet),
+            user_wsol_account: pda::wsol_account(&self.user_wallet),
+            user_donut_account: pda::donut_account(&self.user_wallet),
+            wsol_mint: WSOL_MINT,
+            pool: POOL_ADDRESS,
+            b_vault: B_VAULT,
+            b_token_vault: B_TOKEN_VAULT,
+            b_vault_lp_mint: B_VAULT_LP_MINT,
+            b_vault_lp: B_VAULT_LP,
+            vault_program: METEORA_VAULT_PROGRAM,
+            token_mint: TOKEN_MINT,
+            protocol_token_fee: PROTOCOL_TOKEN_B_FEE,
+            amm_program: METEORA_AMM_PROGRAM,
+            token_program: anchor_spl::token::ID,
+            system_program: system_program::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            rent: sysvar::rent::ID,
+            event_authority: pda::event_authority(),
+            program: matrix_system::ID,
+        }
+        .to_account_metas(None);
+        accounts.extend(resolver::vault_a_accounts());
+
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts,
+            data: instruction::RegisterWithoutReferrer { deposit_amount: self.deposit_amount }.data(),
+        }
+    }
+}
+
+/// register_with_sol_deposit - registers `user_wallet` under the user owned by
+/// `referrer_wallet`. Signed by `user_wallet`. `allowlist_proof` is the Merkle proof of
+/// `user_wallet` in MerkleRoot allowlist mode, empty otherwise.
+#[derive(Clone, Debug)]
+pub struct RegisterWithSolDeposit {
+    pub state: Pubkey,
+    pub user_wallet: Pubkey,
+    pub referrer_wallet: Pubkey,
+    pub deposit_amount: u64,
+    pub allowlist_proof: Vec<[u8; 32]>,
+}
+
+impl RegisterWithSolDeposit {
+    /// Build the instruction from the referrer's current UserAccount and airdrop weeks.
+    /// The upline accounts depend on the referrer's matrix, so the instruction must be
+    /// rebuilt if the referrer changes before it lands.
+    pub fn instruction(&self, referrer: &UserAccount, weeks: resolver::AirdropWeeks) -> Instruction {
+        self.instruction_with_remaining_accounts(resolver::register_remaining_accounts(
+            &self.referrer_wallet,
+            referrer,
+            weeks,
+        ))
+    }
+
+    /// Build the instruction with an explicit remaining-accounts list
+    pub fn instruction_with_remaining_accounts(&self, remaining_accounts: Vec<AccountMeta>) -> Instruction {
+        let mut accounts = accounts::RegisterWithSolDeposit {
+            state: self.state,
+            user_wallet: self.user_wallet,
+            referrer: pda::user_account(&self.referrer_wallet),
+            referrer_wallet: self.referrer_wallet,
+            user: pda::user_account(&self.user_wallet),
+            user_wsol_account: pda::wsol_account(&self.user_wallet),
+            user_donut_account: pda::donut_account(&self.user_wallet),
+            wsol_mint: WSOL_MINT,
+            pool: POOL_ADDRESS,
+            b_vault: B_VAULT,
+            b_token_vault: B_TOKEN_VAULT,
+            b_vault_lp_mint: B_VAULT_LP_MINT,
+            b_vault_lp: B_VAULT_LP,
+            vault_program: METEORA_VAULT_PROGRAM,
+            program_sol_vault: pda::program_sol_vault(),
+            token_mint: TOKEN_MINT,
+            protocol_token_fee: PROTOCOL_TOKEN_B_FEE,
+            amm_program: METEORA_AMM_PROGRAM,
+            token_program: anchor_spl::token::ID,
+            system_program: system_program::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            rent: sysvar::rent::ID,
+            event_authority: pda::event_authority(),
+            program: matrix_system::ID,
+        }
+        .to_account_metas(None);
+        accounts.extend(remaining_accounts);
+
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts,
+            data: instruction::RegisterWithSolDeposit {
+                deposit_amount: self.deposit_amount,
+                allowlist_proof: self.allowlist_proof.clone(),
+            }
+            .data(),
+        }
+    }
+}
+
+/// audit_vault - read-only; simulate it and decode the VaultAudit return data
+#[derive(Clone, Copy, Debug)]
+pub struct AuditVault {
+    pub state: Pubkey,
+}
+
+impl AuditVault {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::AuditVault { state: self.state, program_sol_vault: pda::program_sol_vault() }.to_account_metas(None),
+            data: instruction::AuditVault {}.data(),
+        }
+    }
+}
+
+/// sweep_vault_surplus - moves the vault surplus to `multisig_treasury`, which must be
+/// the treasury stored in the program state. Signed by the program owner.
+#[derive(Clone, Copy, Debug)]
+pub struct SweepVaultSurplus {
+    pub state: Pubkey,
+    pub owner: Pubkey,
+    pub multisig_treasury: Pubkey,
+}
+
+impl SweepVaultSurplus {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::SweepVaultSurplus {
+                state: self.state,
+                owner: self.owner,
+                program_sol_vault: pda::program_sol_vault(),
+                multisig_treasury: self.multisig_treasury,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::SweepVaultSurplus {}.data(),
+        }
+    }
+}
+
+/// reconcile_vault - raises the tracked reserved total to `total_reserved_lamports`.
+/// Signed by the program owner.
+#[derive(Clone, Copy, Debug)]
+pub struct ReconcileVault {
+    pub state: Pubkey,
+    pub owner: Pubkey,
+    pub total_reserved_lamports: u64,
+}
+
+impl ReconcileVault {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::ReconcileVault { state: self.state, owner: self.owner, program_sol_vault: pda::program_sol_vault() }
+                .to_account_metas(None),
+            data: instruction::ReconcileVault { total_reserved_lamports: self.total_reserved_lamports }.data(),
+        }
+    }
+}
+
+/// close_user_account - replaces the UserAccount of `owner_wallet` with a tombstone and
+/// returns the rent difference. Signed by `owner_wallet`.
+#[derive(Clone, Copy, Debug)]
+pub struct CloseUserAccount {
+    pub owner_wallet: Pubkey,
+}
+
+impl CloseUserAccount {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::CloseUserAccount {
+                user: pda::user_account(&self.owner_wallet),
+                owner_wallet: self.owner_wallet,
+                pending_registration: pda::pending_registration(&self.owner_wallet),
+            }
+            .to_account_metas(None),
+            data: instruction::CloseUserAccount {}.data(),
+        }
+    }
+}
+
+/// migrate_user_wallet - moves the position of `owner_wallet` to `new_wallet`, which
+/// pays for the new UserAccount. Signed by both wallets.
+#[derive(Clone, Copy, Debug)]
+pub struct MigrateUserWallet {
+    pub state: Pubkey,
+    pub owner_wallet: Pubkey,
+    pub new_wallet: Pubkey,
+}
+
+impl MigrateUserWallet {
+    pub fn instruction(&self) -> Instruction {
+        let mut accounts = accounts::MigrateUserWallet {
+            state: self.state,
+            user: pda::user_account(&self.owner_wallet),
+            owner_wallet: self.owner_wallet,
+            new_wallet: self.new_wallet,
+            new_user: pda::user_account(&self.new_wallet),
+            pending_registration: pda::pending_registration(&self.owner_wallet),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None);
+        // Checked while the airdrop is active
+        accounts.push(AccountMeta::new_readonly(pda::airdrop_user_account(&self.new_wallet), false));
+
+        Instruction { program_id: matrix_system::ID, accounts, data: instruction::MigrateUserWallet {}.data() }
+    }
+}
+
+/// refresh_user_references - points the references of `user` to each of the migrated
+/// `redirects` (old UserAccount PDAs) at their new positions. Needs no signer.
+#[derive(Clone, Debug)]
+pub struct RefreshUserReferences {
+    pub user: Pubkey,
+    pub redirects: Vec<Pubkey>,
+}
+
+impl RefreshUserReferences {
+    pub fn instruction(&self) -> Instruction {
+        let mut accounts = accounts::RefreshUserReferences { user: self.user }.to_account_metas(None);
+        accounts.extend(self.redirects.iter().map(|redirect| AccountMeta::new_readonly(*redirect, false)));
+
+        Instruction { program_id: matrix_system::ID, accounts, data: instruction::RefreshUserReferences {}.data() }
+    }
+}
+
+/// set_payout_address - sends the reserve payments of `owner_wallet` to `payout_address`,
+/// which must be a system account. `owner_wallet` itself clears it. Signed by `owner_wallet`.
+#[derive(Clone, Copy, Debug)]
+pub struct SetPayoutAddress {
+    pub owner_wallet: Pubkey,
+    pub payout_address: Pubkey,
+}
+
+impl SetPayoutAddress {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::SetPayoutAddress {
+                user: pda::user_account(&self.owner_wallet),
+                owner_wallet: self.owner_wallet,
+                payout_address: self.payout_address,
+            }
+            .to_account_metas(None),
+            data: instruction::SetPayoutAddress {}.data(),
+        }
+    }
+}
+
+/// set_allowlist - restricts registrations under a referrer to the wallets of the Merkle
+/// `root` or to wallets invited by `invite_signer`, depending on `mode`. Signed by the
+/// program owner.
+#[derive(Clone, Copy, Debug)]
+pub struct SetAllowlist {
+    pub state: Pubkey,
+    pub owner: Pubkey,
+    pub mode: AllowlistMode,
+    pub root: [u8; 32],
+    pub invite_signer: Pubkey,
+}
+
+impl SetAllowlist {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::SetAllowlist { state: self.state, owner: self.owner }.to_account_metas(None),
+            data: instruction::SetAllowlist { mode: self.mode, root: self.root, invite_signer: self.invite_signer }.data(),
+        }
+    }
+}
+
+/// schedule_campaign - opens registrations under a referrer from `start_timestamp` until
+/// `end_timestamp` for at most `max_registrations` users (0 = no cap). Signed by the
+/// program owner.
+#[derive(Clone, Copy, Debug)]
+pub struct ScheduleCampaign {
+    pub state: Pubkey,
+    pub owner: Pubkey,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+    pub max_registrations: u32,
+}
+
+impl ScheduleCampaign {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::ManageCampaign { state: self.state, owner: self.owner }.to_account_metas(None),
+            data: instruction::ScheduleCampaign {
+                start_timestamp: self.start_timestamp,
+                end_timestamp: self.end_timestamp,
+                max_registrations: self.max_registrations,
+            }
+            .data(),
+        }
+    }
+}
+
+/// extend_campaign - moves the end of the current campaign to `end_timestamp` and sets
+/// its cap to `max_registrations`; neither may shrink. Signed by the program owner.
+#[derive(Clone, Copy, Debug)]
+pub struct ExtendCampaign {
+    pub state: Pubkey,
+    pub owner: Pubkey,
+    pub end_timestamp: i64,
+    pub max_registrations: u32,
+}
+
+impl ExtendCampaign {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::ManageCampaign { state: self.state, owner: self.owner }.to_account_metas(None),
+            data: instruction::ExtendCampaign { end_timestamp: self.end_timestamp, max_registrations: self.max_registrations }.data(),
+        }
+    }
+}
+
+/// close_campaign - ends the current campaign now. Signed by the program owner.
+#[derive(Clone, Copy, Debug)]
+pub struct CloseCampaign {
+    pub state: Pubkey,
+    pub owner: Pubkey,
+}
+
+impl CloseCampaign {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::ManageCampaign { state: self.state, owner: self.owner }.to_account_metas(None),
+            data: instruction::CloseCampaign {}.data(),
+        }
+    }
+}
+
+/// set_referrer_limits - throttles how often a referrer's matrix may complete: at most
+/// `max_cycles_per_week` per airdrop week counted from `week_start`, and
+/// `min_cycle_interval` seconds apart. Zero disables a limit. Signed by the program owner.
+#[derive(Clone, Copy, Debug)]
+pub struct SetReferrerLimits {
+    pub state: Pubkey,
+    pub owner: Pubkey,
+    pub max_cycles_per_week: u32,
+    pub min_cycle_interval: i64,
+    pub week_start: i64,
+}
+
+impl SetReferrerLimits {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::SetReferrerLimits { state: self.state, owner: self.owner }.to_account_metas(None),
+            data: instruction::SetReferrerLimits {
+                max_cycles_per_week: self.max_cycles_per_week,
+                min_cycle_interval: self.min_cycle_interval,
+                week_start: self.week_start,
+            }
+            .data(),
+        }
+    }
+}
+
+/// set_protocol_fee - share of each SOL deposit, in basis points, sent to the multisig
+/// treasury before routing. Signed by the program owner.
+#[derive(Clone, Copy, Debug)]
+pub struct SetProtocolFee {
+    pub state: Pubkey,
+    pub owner: Pubkey,
+    pub fee_bps: u16,
+}
+
+impl SetProtocolFee {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::SetProtocolFee { state: self.state, owner: self.owner }.to_account_metas(None),
+            data: instruction::SetProtocolFee { fee_bps: self.fee_bps }.data(),
+        }
+    }
+}
+
+/// set_burn_escrow - holds SOL burns in the burn escrow instead of reverting the
+/// registration while the Meteora pool cannot quote the swap, and bounds how far below
+/// the pool's quote retry_pending_burns may swap. Signed by the program owner.
+#[derive(Clone, Copy, Debug)]
+pub struct SetBurnEscrow {
+    pub state: Pubkey,
+    pub owner: Pubkey,
+    pub enabled: bool,
+    pub retry_slippage_bps: u16,
+}
+
+impl SetBurnEscrow {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::SetBurnEscrow { state: self.state, owner: self.owner }.to_account_metas(None),
+            data: instruction::SetBurnEscrow { enabled: self.enabled, retry_slippage_bps: self.retry_slippage_bps }.data(),
+        }
+    }
+}
+
+/// retry_pending_burns - swaps and burns up to MAX_BURN_RETRY_LAMPORTS of the escrowed
+/// deposits, failing below the pool's quote less the configured slippage. Permissionless,
+/// signed by `cranker`. The WSOL and DONUT token accounts of pda::burn_escrow() must exist.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPendingBurns {
+    pub state: Pubkey,
+    pub cranker: Pubkey,
+}
+
+impl RetryPendingBurns {
+    pub fn instruction(&self) -> Instruction {
+        let escrow = pda::burn_escrow();
+        let mut accounts = accounts::RetryPendingBurns {
+            state: self.state,
+            cranker: self.cranker,
+            burn_escrow: escrow,
+            escrow_wsol_account: pda::wsol_account(&escrow),
+            escrow_donut_account: pda::donut_account(&escrow),
+            pool: POOL_ADDRESS,
+            b_vault: B_VAULT,
+            b_token_vault: B_TOKEN_VAULT,
+            b_vault_lp_mint: B_VAULT_LP_MINT,
+            b_vault_lp: B_VAULT_LP,
+            vault_program: METEORA_VAULT_PROGRAM,
+            token_mint: TOKEN_MINT,
+            protocol_token_fee: PROTOCOL_TOKEN_B_FEE,
+            amm_program: METEORA_AMM_PROGRAM,
+            token_program: anchor_spl::token::ID,
+            system_program: system_program::ID,
+            event_authority: pda::event_authority(),
+            program: matrix_system::ID,
+        }
+        .to_account_metas(None);
+        accounts.extend(resolver::vault_a_accounts());
+
+        Instruction { program_id: matrix_system::ID, accounts, data: instruction::RetryPendingBurns {}.data() }
+    }
+}
+
+/// claim_referral_code - maps `code` to the UserAccount of `owner_wallet`, which pays
+/// the ReferralCode rent. Signed by `owner_wallet`.
+#[derive(Clone, Debug)]
+pub struct ClaimReferralCode {
+    pub owner_wallet: Pubkey,
+    pub code: String,
+}
+
+impl ClaimReferralCode {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::ClaimReferralCode {
+                user: pda::user_account(&self.owner_wallet),
+                owner_wallet: self.owner_wallet,
+                referral_code: pda::referral_code(&self.code),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::ClaimReferralCode { code: self.code.clone() }.data(),
+        }
+    }
+}
+
+/// configure_deposit_token - accepts `mint` as deposit token `token` with a minimum of
+/// `min_deposit` base units, swapped through the given Meteora DONUT/token pool. Calling
+/// it again updates the minimum and the pool. Signed by the program owner.
+#[derive(Clone, Copy, Debug)]
+pub struct ConfigureDepositToken {
+    pub state: Pubkey,
+    pub owner: Pubkey,
+    pub token: u8,
+    pub mint: Pubkey,
+    pub min_deposit: u64,
+    pub pool: Pubkey,
+    pub b_vault: Pubkey,
+    pub b_token_vault: Pubkey,
+    pub b_vault_lp_mint: Pubkey,
+    pub b_vault_lp: Pubkey,
+    pub protocol_token_fee: Pubkey,
+    pub a_vault_lp: Pubkey, // The pool's LP token account of the shared DONUT vault A
+}
+
+impl ConfigureDepositToken {
+    pub fn instruction(&self) -> Instruction {
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts: accounts::ConfigureDepositToken {
+                state: self.state,
+                owner: self.owner,
+                deposit_config: pda::token_deposit_config(self.token),
+                token_reserve_vault: pda::token_reserve_vault(self.token),
+                mint: self.mint,
+                pool: self.pool,
+                b_vault: self.b_vault,
+                b_token_vault: self.b_token_vault,
+                b_vault_lp_mint: self.b_vault_lp_mint,
+                b_vault_lp: self.b_vault_lp,
+                protocol_token_fee: self.protocol_token_fee,
+                a_vault_lp: self.a_vault_lp,
+                token_program: anchor_spl::token::ID,
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data: instruction::ConfigureDepositToken { token: self.token, min_deposit: self.min_deposit }.data(),
+        }
+    }
+}
+
+/// register_with_token_deposit - registers `user_wallet` under the user owned by
+/// `referrer_wallet`, paying `deposit_amount` base units of deposit token `token` from
+/// its associated token account. Signed by `user_wallet`. `allowlist_proof` as in
+/// RegisterWithSolDeposit.
+#[derive(Clone, Debug)]
+pub struct RegisterWithTokenDeposit {
+    pub state: Pubkey,
+    pub user_wallet: Pubkey,
+    pub referrer_wallet: Pubkey,
+    pub deposit_amount: u64,
+    pub token: u8,
+    pub allowlist_proof: Vec<[u8; 32]>,
+}
+
+impl RegisterWithTokenDeposit {
+    /// Build the instruction from the token's config, the referrer's current UserAccount
+    /// and the airdrop weeks. Reserves paid by the cascade need their payout accounts
+    /// appended - use instruction_with_remaining_accounts.
+    pub fn instruction(&self, config: &TokenDepositConfig, referrer: &UserAccount, weeks: resolver::AirdropWeeks) -> Instruction {
+        self.instruction_with_remaining_accounts(
+            config,
+            resolver::register_token_remaining_accounts(&self.referrer_wallet, referrer, config, weeks),
+        )
+    }
+
+    /// Build the instruction with an explicit remaining-accounts list
+    pub fn instruction_with_remaining_accounts(&self, config: &TokenDepositConfig, remaining_accounts: Vec<AccountMeta>) -> Instruction {
+        let mut accounts = accounts::RegisterWithTokenDeposit {
+            state: self.state,
+            user_wallet: self.user_wallet,
+            referrer: pda::user_account(&self.referrer_wallet),
+            referrer_wallet: self.referrer_wallet,
+            user: pda::user_account(&self.user_wallet),
+            deposit_config: pda::token_deposit_config(self.token),
+            user_token_account: pda::token_account(&self.user_wallet, &config.mint),
+            token_reserve_vault: pda::token_reserve_vault(self.token),
+            user_donut_account: pda::donut_account(&self.user_wallet),
+            pool: config.pool,
+            b_vault: config.b_vault,
+            b_token_vault: config.b_token_vault,
+            b_vault_lp_mint: config.b_vault_lp_mint,
+            b_vault_lp: config.b_vault_lp,
+            vault_program: METEORA_VAULT_PROGRAM,
+            program_sol_vault: pda::program_sol_vault(),
+            token_mint: TOKEN_MINT,
+            protocol_token_fee: config.protocol_token_fee,
+            amm_program: METEORA_AMM_PROGRAM,
+            token_program: anchor_spl::token::ID,
+            system_program: system_program::ID,
+            event_authority: pda::event_authority(),
+            program: matrix_system::ID,
+        }
+        .to_account_metas(None);
+        accounts.extend(remaining_accounts);
+
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts,
+            data: instruction::RegisterWithTokenDeposit {
+                deposit_amount: self.deposit_amount,
+                token: self.token,
+                allowlist_proof: self.allowlist_proof.clone(),
+            }
+            .data(),
+        }
+    }
+}
@@ -0,0 +1,29 @@
+// Rust client for the matrix-system program: PDA derivation, account decoding, the
+// remaining-accounts layout expected by the registration instructions and typed
+// instruction builders. Apart from the optional `rpc` module nothing here talks to an
+// RPC node - callers fetch the account data and pass it in, so the same code runs in
+// bots, scripts and program tests.
+
+pub mod allowlist;
+pub mod export;
+pub mod instructions;
+pub mod pda;
+pub mod planner;
+pub mod resolver;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+
+pub use export::{ReferralGraph, ReferralNode};
+pub use instructions::{
+    AuditVault, ClaimReferralCode, CloseCampaign, CloseUserAccount, ConfigureDepositToken, ExtendCampaign, Initialize,
+    MigrateUserWallet, ReconcileVault, RefreshUserReferences, RegisterWithSolDeposit, RegisterWithTokenDeposit,
+    RegisterWithoutReferrer, RetryPendingBurns, ScheduleCampaign, SetAllowlist, SetBurnEscrow, SetPayoutAddress,
+    SetProtocolFee, SetReferrerLimits, SweepVaultSurplus,
+};
+pub use planner::{plan_registration, resolve_referral_code, AccountFetcher, PlanError, RegistrationPlan};
+pub use resolver::{
+    airdrop_start_timestamp, decode_referral_code, decode_user_account, decode_user_account_redirect, register_remaining_accounts,
+    register_token_remaining_accounts, vault_a_accounts, AirdropWeeks,
+};
+
+pub use matrix_system::ID as PROGRAM_ID;
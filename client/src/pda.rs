@@ -0,0 +1,76 @@
+// PDA derivation for the matrix-system program and the airdrop program it notifies
+
+use anchor_spl::associated_token::get_associated_token_address;
+use matrix_system::{airdrop_addresses::AIRDROP_ACCOUNT, verified_addresses};
+use solana_program::pubkey::Pubkey;
+
+/// UserAccount PDA of `wallet`
+pub fn user_account(wallet: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"user_account", wallet.as_ref()], &matrix_system::ID).0
+}
+
+/// Vault holding the SOL reserved from second slots
+pub fn program_sol_vault() -> Pubkey {
+    Pubkey::find_program_address(&[b"program_sol_vault"], &matrix_system::ID).0
+}
+
+/// Escrow holding SOL burns while the Meteora pool cannot quote the swap
+pub fn burn_escrow() -> Pubkey {
+    Pubkey::find_program_address(&[b"burn_escrow"], &matrix_system::ID).0
+}
+
+/// Pending registration of `wallet` (begin_registration / advance_registration)
+pub fn pending_registration(wallet: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"pending_registration", wallet.as_ref()], &matrix_system::ID).0
+}
+
+/// Authority used by emit_cpi! events
+pub fn event_authority() -> Pubkey {
+    Pubkey::find_program_address(&[b"__event_authority"], &matrix_system::ID).0
+}
+
+/// Airdrop program state - holds the current week at offset 72
+pub fn airdrop_program_state() -> Pubkey {
+    Pubkey::find_program_address(&[b"program_state"], &AIRDROP_ACCOUNT).0
+}
+
+/// Airdrop user account of `wallet`
+pub fn airdrop_user_account(wallet: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"user_account", wallet.as_ref()], &AIRDROP_ACCOUNT).0
+}
+
+/// Airdrop weekly data for `week` (1-36)
+pub fn airdrop_weekly_data(week: u8) -> Pubkey {
+    Pubkey::find_program_address(&[b"weekly_data", &week.to_le_bytes()], &AIRDROP_ACCOUNT).0
+}
+
+/// WSOL associated token account of `wallet`
+pub fn wsol_account(wallet: &Pubkey) -> Pubkey {
+    get_associated_token_address(wallet, &verified_addresses::WSOL_MINT)
+}
+
+/// DONUT associated token account of `wallet`
+pub fn donut_account(wallet: &Pubkey) -> Pubkey {
+    get_associated_token_address(wallet, &verified_addresses::TOKEN_MINT)
+}
+
+/// ReferralCode of `code` - codes are claimed in lowercase
+pub fn referral_code(code: &str) -> Pubkey {
+    Pubkey::find_program_address(&[b"referral_code", code.as_bytes()], &matrix_system::ID).0
+}
+
+/// TokenDepositConfig of deposit token `token`
+pub fn token_deposit_config(token: u8) -> Pubkey {
+    Pubkey::find_program_address(&[b"token_deposit", &[token]], &matrix_system::ID).0
+}
+
+/// Vault holding the reserves of deposit token `token`
+pub fn token_reserve_vault(token: u8) -> Pubkey {
+    Pubkey::find_program_address(&[b"token_reserve_vault", &[token]], &matrix_system::ID).0
+}
+
+/// Associated token account of `wallet` for a deposit token `mint` - where token
+/// reserves are paid
+pub fn token_account(wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+    get_associated_token_address(wallet, mint)
+}
@@ -0,0 +1,454 @@
+// Registration planner - reads the referrer, its stored upline and the program state
+// through an AccountFetcher and runs the program's own matrix engine offline, so a
+// client knows before sending which slot the registration lands in, how far the
+// deposit cascades, the exact remaining accounts, the CPIs the handler will make, a
+// compute-unit estimate and whether the transaction needs an address lookup table.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use anchor_lang::AccountDeserialize;
+use matrix_system::{
+    airdrop_addresses::AIRDROP_ACCOUNT,
+    matrix::{self, Effect, Outcome, UserRecord},
+    verified_addresses::*,
+    allowlist, AllowlistMode, DepthOverflowPolicy, ErrorCode, ProgramState, ReferralCode, TokenDepositConfig, RESERVE_SOL,
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    pubkey::Pubkey,
+    system_program,
+};
+
+use crate::{pda, resolver, RefreshUserReferences, RegisterWithSolDeposit};
+
+// Compute budget program - set_compute_unit_limit / set_compute_unit_price
+pub const COMPUTE_BUDGET_PROGRAM: Pubkey = solana_program::pubkey!("ComputeBudget111111111111111111111111111111");
+
+// Largest serialized transaction accepted by the cluster
+pub const PACKET_DATA_SIZE: usize = 1232;
+
+// Accounts a single transaction may lock
+pub const MAX_TX_ACCOUNT_LOCKS: usize = 64;
+
+// Highest compute-unit limit a transaction may request
+pub const MAX_COMPUTE_UNITS: u32 = 1_400_000;
+
+// Compute-unit estimates. Account validation, user creation and the Chainlink read
+// cost the same for every registration; the rest depends on the effects.
+const CU_BASE: u32 = 90_000;
+const CU_PER_UPLINE: u32 = 12_000; // PDA checks and zero-copy load/store
+const CU_MARGIN_PERCENT: u32 = 20;
+const CU_PER_REDIRECT: u32 = 4_000; // refresh_user_references, per redirect account
+
+/// Account data source - an RPC client, a program-test bank or a map in tests
+pub trait AccountFetcher {
+    /// Data of the account at `address`, None if it does not exist
+    fn account_data(&self, address: &Pubkey) -> Option<Vec<u8>>;
+}
+
+impl AccountFetcher for HashMap<Pubkey, Vec<u8>> {
+    fn account_data(&self, address: &Pubkey) -> Option<Vec<u8>> {
+        self.get(address).cloned()
+    }
+}
+
+#[derive(Debug)]
+pub enum PlanError {
+    StateNotFound(Pubkey),
+    UserAlreadyRegistered(Pubkey),
+    NotAllowlisted(Pubkey),
+    Campaign(anchor_lang::error::Error),
+    ReferrerNotFound(Pubkey),
+    ReferrerMigrated { user: Pubkey, new_wallet: Pubkey },
+    ReferrerNotRegistered(Pubkey),
+    ReferrerNotInAirdrop(Pubkey),
+    AirdropStateNotFound,
+    UplineNotFound(Pubkey),
+    DepositTokenNotFound(u8),
+    InvalidReferralCode(String),
+    ReferralCodeNotFound(String),
+    Engine(anchor_lang::error::Error),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::StateNotFound(key) => write!(f, "program state {} not found", key),
+            PlanError::UserAlreadyRegistered(key) => write!(f, "user account {} already exists", key),
+            PlanError::NotAllowlisted(key) => write!(f, "wallet {} is not on the allowlist - check the Merkle proof", key),
+            PlanError::Campaign(err) => write!(f, "registration is outside the campaign: {}", err),
+            PlanError::ReferrerNotFound(key) => write!(f, "referrer account {} not found", key),
+            PlanError::ReferrerMigrated { user, new_wallet } => {
+                write!(f, "referrer account {} moved to wallet {} - register under the new wallet", user, new_wallet)
+            }
+            PlanError::ReferrerNotRegistered(key) => write!(f, "referrer account {} is not registered", key),
+            PlanError::ReferrerNotInAirdrop(key) => write!(f, "referrer wallet {} is not registered in the airdrop", key),
+            PlanError::AirdropStateNotFound => write!(f, "airdrop program state not found"),
+            PlanError::UplineNotFound(key) => write!(f, "upline account {} not found", key),
+            PlanError::DepositTokenNotFound(token) => write!(f, "deposit token {} is not configured", token),
+            PlanError::InvalidReferralCode(code) => write!(f, "'{}' is not a valid referral code", code),
+            PlanError::ReferralCodeNotFound(code) => write!(f, "referral code '{}' is not claimed", code),
+            PlanError::Engine(err) => write!(f, "matrix engine rejected the registration: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+/// Cross-program invocation the handler is expected to make
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cpi {
+    CreateUserAccount, // System program - init of the new UserAccount
+    ChainlinkRead,     // Chainlink store - latest round data and decimals
+    WrapSol,           // System transfer of the deposit into the WSOL account
+    SyncNative,        // Token program sync_native of the WSOL account
+    MeteoraSwap,       // Meteora AMM swap WSOL -> DONUT
+    BurnDonut,         // Token program burn of the swapped DONUT
+    CloseWsol,         // Token program close of the empty WSOL account
+    ReserveSol,        // System transfer into program_sol_vault
+    PayReserve,        // Signed system transfer out of program_sol_vault
+    PayTokenReserve,   // Signed token transfer out of a deposit token's reserve vault
+    AirdropNotify,     // Airdrop program notify_matrix_completion
+    TreasuryTransfer,  // System transfer to the multisig treasury
+    Event,             // emit_cpi! self-invocation
+}
+
+impl Cpi {
+    pub fn program(&self) -> Pubkey {
+        match self {
+            Cpi::CreateUserAccount | Cpi::WrapSol | Cpi::ReserveSol | Cpi::PayReserve | Cpi::TreasuryTransfer => system_program::ID,
+            Cpi::SyncNative | Cpi::BurnDonut | Cpi::CloseWsol | Cpi::PayTokenReserve => anchor_spl::token::ID,
+            Cpi::ChainlinkRead => CHAINLINK_PROGRAM,
+            Cpi::MeteoraSwap => METEORA_AMM_PROGRAM,
+            Cpi::AirdropNotify => AIRDROP_ACCOUNT,
+            Cpi::Event => matrix_system::ID,
+        }
+    }
+
+    // Estimated cost including the inner instructions it triggers
+    pub fn compute_units(&self) -> u32 {
+        match self {
+            Cpi::CreateUserAccount => 8_000,
+            Cpi::ChainlinkRead => 20_000,
+            Cpi::WrapSol => 3_000,
+            Cpi::SyncNative => 4_000,
+            Cpi::MeteoraSwap => 110_000,
+            Cpi::BurnDonut => 6_000,
+            Cpi::CloseWsol => 5_000,
+            Cpi::ReserveSol => 3_000,
+            Cpi::PayReserve => 4_000,
+            Cpi::PayTokenReserve => 6_000,
+            Cpi::AirdropNotify => 45_000,
+            Cpi::TreasuryTransfer => 3_000,
+            Cpi::Event => 6_000,
+        }
+    }
+}
+
+/// Everything a client needs to send a register_with_sol_deposit
+pub struct RegistrationPlan {
+    pub slot: u8,                      // Referrer slot the user lands in (0, 1, 2)
+    pub cascade_depth: usize,          // Uplines the deposit reaches
+    pub protocol_fee: u64,             // Sent to the treasury - the engine routes the rest
+    pub airdrop_weeks: resolver::AirdropWeeks,
+    pub outcome: Outcome,              // Engine result - effects, updated records, next_chain_id
+    pub remaining_accounts: Vec<AccountMeta>,
+    pub refresh: Option<RefreshUserReferences>, // Stale upline entries of the referrer, sent first
+    pub cpis: Vec<Cpi>,
+    pub compute_units: u32,            // Estimate with margin, capped at MAX_COMPUTE_UNITS
+    pub fits_compute_limit: bool,      // False - use begin_registration / advance_registration
+    pub missing_token_accounts: Vec<Pubkey>, // WSOL / DONUT ATAs to create first
+    pub instruction: Instruction,
+    pub legacy_size: usize,            // Serialized legacy transaction size
+    pub account_count: usize,          // Distinct accounts locked by the transaction
+    pub needs_lookup_table: bool,
+}
+
+impl RegistrationPlan {
+    /// Addresses to store in the lookup table - every non-signer account of the
+    /// instruction
+    pub fn lookup_table_addresses(&self) -> Vec<Pubkey> {
+        let mut addresses = Vec::new();
+        for meta in self.instruction.accounts.iter().filter(|meta| !meta.is_signer) {
+            if !addresses.contains(&meta.pubkey) {
+                addresses.push(meta.pubkey);
+            }
+        }
+        addresses
+    }
+
+    /// set_compute_unit_limit, the referrer's refresh when needed and the registration
+    pub fn instructions(&self) -> Vec<Instruction> {
+        let mut instructions = vec![set_compute_unit_limit(self.compute_units)];
+        instructions.extend(self.refresh.as_ref().map(RefreshUserReferences::instruction));
+        instructions.push(self.instruction.clone());
+        instructions
+    }
+}
+
+fn push_writable(accounts: &mut Vec<AccountMeta>, pubkey: Pubkey) {
+    if !accounts.iter().any(|meta| meta.pubkey == pubkey) {
+        accounts.push(AccountMeta::new(pubkey, false));
+    }
+}
+
+pub fn set_compute_unit_limit(units: u32) -> Instruction {
+    let mut data = vec![2];
+    data.extend_from_slice(&units.to_le_bytes());
+    Instruction { program_id: COMPUTE_BUDGET_PROGRAM, accounts: vec![], data }
+}
+
+pub fn set_compute_unit_price(micro_lamports: u64) -> Instruction {
+    let mut data = vec![3];
+    data.extend_from_slice(&micro_lamports.to_le_bytes());
+    Instruction { program_id: COMPUTE_BUDGET_PROGRAM, accounts: vec![], data }
+}
+
+/// Referrer wallet of referral `code`, to pass as `referrer_wallet` of a registration.
+/// Codes are case-insensitive; a code claimed before its user migrated resolves to the
+/// new wallet.
+pub fn resolve_referral_code<F: AccountFetcher>(fetcher: &F, code: &str) -> Result<Pubkey, PlanError> {
+    let code = code.trim().to_ascii_lowercase();
+    if !ReferralCode::is_valid(&code) {
+        return Err(PlanError::InvalidReferralCode(code));
+    }
+    let referral_code = fetcher
+        .account_data(&pda::referral_code(&code))
+        .and_then(|data| resolver::decode_referral_code(&data))
+        .ok_or(PlanError::ReferralCodeNotFound(code))?;
+
+    let mut user = referral_code.user;
+    loop {
+        let data = fetcher.account_data(&user).ok_or(PlanError::ReferrerNotFound(user))?;
+        if let Some(redirect) = resolver::decode_user_account_redirect(&data) {
+            user = redirect.new_user;
+            continue;
+        }
+        let account = resolver::decode_user_account(&data).ok_or(PlanError::ReferrerNotFound(user))?;
+        if !account.is_registered() {
+            return Err(PlanError::ReferrerNotRegistered(user));
+        }
+        return Ok(account.owner_wallet);
+    }
+}
+
+/// Plan `request` against the accounts returned by `fetcher`, at `unix_timestamp`
+/// (the cluster clock - it selects the airdrop's actual week).
+pub fn plan_registration<F: AccountFetcher>(
+    fetcher: &F,
+    request: &RegisterWithSolDeposit,
+    unix_timestamp: i64,
+) -> Result<RegistrationPlan, PlanError> {
+    let state_data = fetcher.account_data(&request.state).ok_or(PlanError::StateNotFound(request.state))?;
+    let state = ProgramState::try_deserialize(&mut state_data.as_slice()).map_err(PlanError::Engine)?;
+
+    let user = pda::user_account(&request.user_wallet);
+    if fetcher.account_data(&user).is_some() {
+        return Err(PlanError::UserAlreadyRegistered(user));
+    }
+
+    state.campaign.check_registration(unix_timestamp, state.next_upline_id).map_err(PlanError::Campaign)?;
+
+    // Invites are checked on-chain only - the signature is in another instruction
+    if state.allowlist_mode == AllowlistMode::MerkleRoot
+        && !allowlist::verify_proof(&state.allowlist_root, &request.user_wallet, &request.allowlist_proof)
+    {
+        return Err(PlanError::NotAllowlisted(request.user_wallet));
+    }
+
+    let referrer_key = pda::user_account(&request.referrer_wallet);
+    let referrer_data = fetcher.account_data(&referrer_key).ok_or(PlanError::ReferrerNotFound(referrer_key))?;
+    if let Some(redirect) = resolver::decode_user_account_redirect(&referrer_data) {
+        return Err(PlanError::ReferrerMigrated { user: referrer_key, new_wallet: redirect.new_wallet });
+    }
+    let mut referrer = resolver::decode_user_account(&referrer_data).ok_or(PlanError::ReferrerNotFound(referrer_key))?;
+    if !referrer.is_registered() {
+        return Err(PlanError::ReferrerNotRegistered(referrer_key));
+    }
+
+    // The PDAs stay in the layout after the airdrop ends - the program skips them
+    let airdrop_weeks = if state.airdrop_active {
+        if fetcher.account_data(&pda::airdrop_user_account(&request.referrer_wallet)).is_none() {
+            return Err(PlanError::ReferrerNotInAirdrop(request.referrer_wallet));
+        }
+        fetcher
+            .account_data(&pda::airdrop_program_state())
+            .and_then(|data| resolver::AirdropWeeks::from_program_state(&data, unix_timestamp))
+            .ok_or(PlanError::AirdropStateNotFound)?
+    } else {
+        resolver::AirdropWeeks { current: 36, actual: 36 }
+    };
+
+    // Same inputs as the handler: next_chain_id was already taken by the new user
+    let upline_depth = state.upline_depth();
+    let cascades = referrer.chain.filled_slots == 2 && referrer.referrer().is_some();
+
+    // Stored upline entries of migrated users are refreshed in the same transaction, so
+    // the uplines stay plain pairs - the program only follows a single redirect.
+    // Redirects can chain, so follow each one.
+    let mut redirects = Vec::new();
+    if cascades {
+        while let Some((old_user, redirect)) = referrer.upline.entries().iter().find_map(|entry| {
+            let data = fetcher.account_data(&entry.pda)?;
+            resolver::decode_user_account_redirect(&data).map(|redirect| (entry.pda, redirect))
+        }) {
+            if redirects.contains(&old_user) {
+                break;
+            }
+            referrer.redirect(&old_user, &redirect.entry());
+            redirects.push(old_user);
+        }
+    }
+    let refresh = (!redirects.is_empty()).then_some(RefreshUserReferences { user: referrer_key, redirects });
+    let uplines = if cascades { referrer.upline.entries() } else { &[] };
+    let mut missing_upline = None;
+
+    let protocol_fee = state.protocol_fee(request.deposit_amount);
+    let outcome = matrix::register(
+        user,
+        request.deposit_amount - protocol_fee,
+        RESERVE_SOL,
+        UserRecord::new(referrer_key, request.referrer_wallet, &referrer),
+        uplines.len().min(upline_depth),
+        |i| {
+            let entry = &uplines[i];
+            match fetcher.account_data(&entry.pda).and_then(|data| resolver::decode_user_account(&data)) {
+                Some(account) => Ok(UserRecord::new(entry.pda, entry.wallet, &account)),
+                None => {
+                    missing_upline = Some(entry.pda);
+                    Err(anchor_lang::error!(ErrorCode::MissingUplineAccount))
+                }
+            }
+        },
+        state.next_chain_id + 1,
+        state.depth_overflow_policy,
+        &state.throttle(unix_timestamp),
+    );
+    let outcome = match (outcome, missing_upline) {
+        (_, Some(pda)) => return Err(PlanError::UplineNotFound(pda)),
+        (result, None) => result.map_err(PlanError::Engine)?,
+    };
+
+    let mut remaining_accounts = resolver::register_remaining_accounts(&request.referrer_wallet, &referrer, airdrop_weeks);
+    // Payout addresses follow the upline pairs, the program finds them by key. A reserve
+    // held in a deposit token is paid from its vault to the payout's token account.
+    for effect in &outcome.effects {
+        match *effect {
+            Effect::Pay { wallet, token: RESERVE_SOL, .. } => push_writable(&mut remaining_accounts, wallet),
+            Effect::Pay { wallet, token, .. } => {
+                let config = fetcher
+                    .account_data(&pda::token_deposit_config(token))
+                    .and_then(|data| TokenDepositConfig::try_deserialize(&mut data.as_slice()).ok())
+                    .ok_or(PlanError::DepositTokenNotFound(token))?;
+                push_writable(&mut remaining_accounts, pda::token_deposit_config(token));
+                push_writable(&mut remaining_accounts, pda::token_reserve_vault(token));
+                push_writable(&mut remaining_accounts, pda::token_account(&wallet, &config.mint));
+            }
+            _ => {}
+        }
+    }
+    let pays_treasury = outcome
+        .effects
+        .iter()
+        .any(|effect| matches!(effect, Effect::Overflow { policy: DepthOverflowPolicy::Treasury, .. }));
+    if pays_treasury || protocol_fee > 0 {
+        remaining_accounts.push(AccountMeta::new(state.multisig_treasury, false));
+    }
+    // The pool is only checked on-chain - a burn may be escrowed instead of swapped, so
+    // the escrow is passed whenever the fallback is enabled. The CPIs assume the swap.
+    let burns = outcome.effects.iter().any(|effect| matches!(effect, Effect::Burn { .. }));
+    if burns && state.burn_escrow_enabled {
+        push_writable(&mut remaining_accounts, pda::burn_escrow());
+    }
+
+    let cpis = expected_cpis(&outcome, referrer.chain.filled_slots, state.airdrop_active, protocol_fee);
+    let estimate = CU_BASE
+        + CU_PER_UPLINE * outcome.uplines.len() as u32
+        + cpis.iter().map(Cpi::compute_units).sum::<u32>()
+        + CU_PER_REDIRECT * refresh.as_ref().map_or(0, |refresh| refresh.redirects.len() as u32);
+    let estimate = estimate + estimate * CU_MARGIN_PERCENT / 100;
+
+    let missing_token_accounts = [pda::wsol_account(&request.user_wallet), pda::donut_account(&request.user_wallet)]
+        .into_iter()
+        .filter(|address| fetcher.account_data(address).is_none())
+        .collect();
+
+    let instruction = request.instruction_with_remaining_accounts(remaining_accounts.clone());
+
+    // Legacy transaction with a compute unit limit and price, paid by the user wallet
+    let mut instructions = vec![set_compute_unit_limit(estimate), set_compute_unit_price(0)];
+    instructions.extend(refresh.as_ref().map(RefreshUserReferences::instruction));
+    instructions.push(instruction.clone());
+    let message = Message::new(&instructions, Some(&request.user_wallet));
+    let legacy_size = 1 + 64 * message.header.num_required_signatures as usize + message.serialize().len();
+    let account_count = message.account_keys.len();
+
+    Ok(RegistrationPlan {
+        slot: referrer.chain.filled_slots,
+        cascade_depth: outcome.uplines.len(),
+        protocol_fee,
+        airdrop_weeks,
+        remaining_accounts,
+        refresh,
+        cpis,
+        compute_units: estimate.min(MAX_COMPUTE_UNITS),
+        fits_compute_limit: estimate <= MAX_COMPUTE_UNITS,
+        missing_token_accounts,
+        instruction,
+        legacy_size,
+        account_count,
+        needs_lookup_table: legacy_size > PACKET_DATA_SIZE || account_count > MAX_TX_ACCOUNT_LOCKS,
+        outcome,
+    })
+}
+
+// CPIs of register_with_sol_deposit for the engine's effects, in execution order
+fn expected_cpis(outcome: &Outcome, slot: u8, airdrop_active: bool, protocol_fee: u64) -> Vec<Cpi> {
+    let mut cpis = vec![Cpi::CreateUserAccount, Cpi::ChainlinkRead, Cpi::ChainlinkRead];
+    if protocol_fee > 0 {
+        cpis.extend([Cpi::TreasuryTransfer, Cpi::Event]);
+    }
+    let mut wsol_closed = false;
+
+    for effect in &outcome.effects {
+        match effect {
+            Effect::Write { .. } => {}
+            Effect::Burn { .. } => {
+                cpis.extend([Cpi::WrapSol, Cpi::SyncNative, Cpi::MeteoraSwap, Cpi::BurnDonut, Cpi::Event, Cpi::Event]);
+            }
+            Effect::Reserve { .. } => {
+                if !wsol_closed {
+                    cpis.push(Cpi::CloseWsol);
+                    wsol_closed = true;
+                }
+                cpis.extend([Cpi::ReserveSol, Cpi::Event, Cpi::Event]);
+            }
+            Effect::Pay { token: RESERVE_SOL, .. } => cpis.extend([Cpi::PayReserve, Cpi::Event]),
+            Effect::Pay { .. } => cpis.extend([Cpi::PayTokenReserve, Cpi::Event]),
+            Effect::Notify { .. } => {
+                if airdrop_active {
+                    cpis.extend([Cpi::AirdropNotify, Cpi::Event]);
+                }
+                cpis.push(Cpi::Event);
+            }
+            Effect::Overflow { policy, .. } => {
+                if !wsol_closed {
+                    cpis.push(Cpi::CloseWsol);
+                    wsol_closed = true;
+                }
+                if *policy == DepthOverflowPolicy::Treasury {
+                    cpis.push(Cpi::TreasuryTransfer);
+                }
+                cpis.push(Cpi::Event);
+            }
+        }
+    }
+
+    if slot == 2 && !wsol_closed {
+        cpis.push(Cpi::CloseWsol);
+    }
+    cpis.push(Cpi::Event); // UserRegistered
+    cpis
+}
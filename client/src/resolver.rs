@@ -0,0 +1,162 @@
+// Remaining accounts for the registration instructions. The layout mirrors what
+// register_with_sol_deposit reads:
+//   [0..3]  vault A: a_vault, a_vault_lp, a_vault_lp_mint, a_token_vault (writable)
+//   [4..5]  Chainlink: SOL/USD feed, Chainlink program (readonly)
+//   [6..12] airdrop: program_state, referrer airdrop account, current week data,
+//           actual week data, referrer wallet (writable), airdrop program, instructions sysvar
+//   then, only when the registration fills the referrer's third slot and the referrer
+//   is not a base user, one airdrop account per stored upline followed by the upline
+//   (pda, wallet) pairs - both in the order of UserAccount.upline.
+
+use anchor_lang::{AccountDeserialize, Discriminator};
+use bytemuck::Zeroable;
+use matrix_system::{
+    airdrop_addresses::AIRDROP_ACCOUNT, verified_addresses::*, ReferralCode, TokenDepositConfig, UserAccount, UserAccountRedirect,
+};
+use solana_program::{instruction::AccountMeta, pubkey::Pubkey, sysvar};
+
+use crate::pda;
+
+// Airdrop program state layout (see check_and_update_airdrop_status)
+const AIRDROP_CURRENT_WEEK_OFFSET: usize = 72;
+const AIRDROP_START_TIMESTAMP_OFFSET: usize = 104;
+const AIRDROP_MAX_WEEKS: u8 = 36;
+const AIRDROP_WEEK_SECONDS: i64 = 1800;
+
+/// Weekly data accounts the airdrop notification expects at [8] and [9]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AirdropWeeks {
+    pub current: u8, // Week stored in the airdrop program state
+    pub actual: u8,  // Week computed from the start timestamp at `unix_timestamp`
+}
+
+impl AirdropWeeks {
+    /// Read the weeks from the airdrop program state data, the way the program does at
+    /// `unix_timestamp`. Returns None if the data is too small to hold the state.
+    pub fn from_program_state(data: &[u8], unix_timestamp: i64) -> Option<Self> {
+        let elapsed = unix_timestamp.saturating_sub(airdrop_start_timestamp(data)?);
+
+        Some(Self {
+            current: data[AIRDROP_CURRENT_WEEK_OFFSET],
+            actual: ((elapsed / AIRDROP_WEEK_SECONDS) + 1).min(AIRDROP_MAX_WEEKS as i64) as u8,
+        })
+    }
+}
+
+/// Start of week 1 from the airdrop program state data - the week_start of the referrer
+/// limits. Returns None if the data is too small to hold the state.
+pub fn airdrop_start_timestamp(data: &[u8]) -> Option<i64> {
+    let start = data.get(AIRDROP_START_TIMESTAMP_OFFSET..AIRDROP_START_TIMESTAMP_OFFSET + 8)?;
+    Some(i64::from_le_bytes(start.try_into().ok()?))
+}
+
+/// Decode a UserAccount from its account data. Accounts resized to a smaller upline
+/// capacity or created before payout_address or cycles are accepted; returns None for
+/// other account types, truncated data and accounts still in the legacy Borsh layout,
+/// which share the discriminator.
+pub fn decode_user_account(data: &[u8]) -> Option<UserAccount> {
+    if data.len() < 8 || data[..8] != UserAccount::DISCRIMINATOR || data.len() > 8 + UserAccount::SIZE {
+        return None;
+    }
+
+    // Without payout_address or cycles the upline starts earlier - decode them as unset
+    let mut body = data[8..].to_vec();
+    let missing_fields_offset = match (UserAccount::SIZE - body.len()) % (32 + 32) {
+        UserAccount::CYCLE_STATS_SIZE => Some(UserAccount::CYCLE_STATS_OFFSET - 8),
+        n if n == 32 + UserAccount::CYCLE_STATS_SIZE => Some(UserAccount::PAYOUT_ADDRESS_OFFSET - 8),
+        _ => None,
+    };
+    if let Some(offset) = missing_fields_offset {
+        let missing = UserAccount::CYCLE_STATS_OFFSET - 8 + UserAccount::CYCLE_STATS_SIZE - offset;
+        let offset = offset.min(body.len());
+        body.splice(offset..offset, vec![0; missing]);
+    }
+    let upline_len = body.len().checked_sub(UserAccount::size_with_capacity(0))?;
+    if upline_len % (32 + 32) != 0 {
+        return None;
+    }
+
+    let mut account = UserAccount::zeroed();
+    let bytes = bytemuck::bytes_of_mut(&mut account);
+    bytes[..body.len()].copy_from_slice(&body);
+
+    if body.len() < UserAccount::size_with_capacity(account.upline.count as usize) {
+        return None;
+    }
+    Some(account)
+}
+
+/// Decode the redirect left by migrate_user_wallet at a UserAccount PDA, None for any
+/// other account
+pub fn decode_user_account_redirect(data: &[u8]) -> Option<UserAccountRedirect> {
+    UserAccountRedirect::try_deserialize(&mut &data[..]).ok()
+}
+
+/// Decode a ReferralCode, None for other account types
+pub fn decode_referral_code(data: &[u8]) -> Option<ReferralCode> {
+    ReferralCode::try_deserialize(&mut &data[..]).ok()
+}
+
+fn writable(pubkey: Pubkey) -> AccountMeta {
+    AccountMeta::new(pubkey, false)
+}
+
+fn readonly(pubkey: Pubkey) -> AccountMeta {
+    AccountMeta::new_readonly(pubkey, false)
+}
+
+/// Vault A accounts - the whole remaining-accounts list of register_without_referrer
+pub fn vault_a_accounts() -> Vec<AccountMeta> {
+    vec![
+        writable(A_VAULT),
+        writable(A_VAULT_LP),
+        writable(A_VAULT_LP_MINT),
+        writable(A_TOKEN_VAULT),
+    ]
+}
+
+/// Remaining accounts of register_with_sol_deposit for a registration under
+/// `referrer` (the referrer's decoded UserAccount, owned by `referrer_wallet`).
+pub fn register_remaining_accounts(referrer_wallet: &Pubkey, referrer: &UserAccount, weeks: AirdropWeeks) -> Vec<AccountMeta> {
+    let mut accounts = vault_a_accounts();
+    accounts.push(readonly(SOL_USD_FEED));
+    accounts.push(readonly(CHAINLINK_PROGRAM));
+
+    // Airdrop accounts for the referrer's notification - always sent to keep the layout
+    accounts.push(writable(pda::airdrop_program_state()));
+    accounts.push(writable(pda::airdrop_user_account(referrer_wallet)));
+    accounts.push(writable(pda::airdrop_weekly_data(weeks.current)));
+    accounts.push(writable(pda::airdrop_weekly_data(weeks.actual)));
+    accounts.push(writable(*referrer_wallet));
+    accounts.push(readonly(AIRDROP_ACCOUNT));
+    accounts.push(readonly(sysvar::instructions::ID));
+
+    // Slot 3 of a non-base referrer - the deposit cascades through the stored upline
+    if referrer.chain.filled_slots == 2 && referrer.referrer().is_some() {
+        let uplines = referrer.upline.entries();
+        for entry in uplines {
+            accounts.push(writable(pda::airdrop_user_account(&entry.wallet)));
+        }
+        for entry in uplines {
+            accounts.push(writable(entry.pda));
+            accounts.push(writable(entry.wallet));
+        }
+    }
+
+    accounts
+}
+
+/// Remaining accounts of register_with_token_deposit - the register_with_sol_deposit
+/// layout without the Chainlink accounts, with the vault A LP account of the token's pool
+pub fn register_token_remaining_accounts(
+    referrer_wallet: &Pubkey,
+    referrer: &UserAccount,
+    config: &TokenDepositConfig,
+    weeks: AirdropWeeks,
+) -> Vec<AccountMeta> {
+    let mut accounts = register_remaining_accounts(referrer_wallet, referrer, weeks);
+    let vault_a_count = vault_a_accounts().len();
+    accounts.drain(vault_a_count..vault_a_count + 2);
+    accounts[1] = writable(config.a_vault_lp);
+    accounts
+}
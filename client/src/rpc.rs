@@ -0,0 +1,54 @@
+// RPC access (feature "rpc"): the planner's AccountFetcher over an RpcClient, the
+// scan of every UserAccount owned by the program and the referral graph built from it.
+
+// ClientError is solana_client's own error - returned as is, like RpcClient does
+#![allow(clippy::result_large_err)]
+
+use anchor_lang::Discriminator;
+use matrix_system::UserAccount;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    client_error::ClientError,
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_program::pubkey::Pubkey;
+
+use crate::{decode_user_account, export::ReferralGraph, AccountFetcher};
+
+pub struct RpcFetcher<'a>(pub &'a RpcClient);
+
+impl AccountFetcher for RpcFetcher<'_> {
+    fn account_data(&self, address: &Pubkey) -> Option<Vec<u8>> {
+        self.0
+            .get_account_with_commitment(address, self.0.commitment())
+            .ok()
+            .and_then(|response| response.value)
+            .map(|account| account.data)
+    }
+}
+
+/// Every UserAccount of the program, selected by discriminator with getProgramAccounts.
+/// Accounts still in the legacy Borsh layout (not migrated yet) are skipped.
+pub fn all_user_accounts(rpc: &RpcClient) -> Result<Vec<(Pubkey, UserAccount)>, ClientError> {
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(0, UserAccount::DISCRIMINATOR.to_vec()))]),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+
+    let accounts = rpc.get_program_accounts_with_config(&matrix_system::ID, config)?;
+    Ok(accounts
+        .into_iter()
+        .filter_map(|(address, account)| decode_user_account(&account.data).map(|user| (address, user)))
+        .collect())
+}
+
+/// Referral graph of every migrated UserAccount
+pub fn referral_graph(rpc: &RpcClient) -> Result<ReferralGraph, ClientError> {
+    Ok(ReferralGraph::new(all_user_accounts(rpc)?))
+}
@@ -0,0 +1,104 @@
+// Referral graph export: edges rebuilt from referrer/upline, subtree selection and the
+// JSON, CSV and DOT renderings.
+
+use bytemuck::Zeroable;
+use matrix_system::{initialize_base_user_data, initialize_referred_user_data, UplineEntry, UserAccount};
+use matrix_system_client::{pda, ReferralGraph};
+use solana_program::pubkey::Pubkey;
+
+struct Users(Vec<(Pubkey, UserAccount)>);
+
+impl Users {
+    fn base(&mut self) -> usize {
+        let wallet = Pubkey::new_unique();
+        let mut account = UserAccount::zeroed();
+        let id = self.0.len() as u32 + 1;
+        initialize_base_user_data(&mut account, &wallet, id, id).unwrap();
+        self.0.push((pda::user_account(&wallet), account));
+        self.0.len() - 1
+    }
+
+    fn referred(&mut self, referrer: usize) -> usize {
+        let (referrer_pda, referrer_account) = self.0[referrer];
+        let entry = UplineEntry { pda: referrer_pda, wallet: referrer_account.owner_wallet };
+
+        let wallet = Pubkey::new_unique();
+        let mut account = UserAccount::zeroed();
+        let id = self.0.len() as u32 + 1;
+        initialize_referred_user_data(&mut account, &wallet, &referrer_pda, &referrer_account, entry, id, id, 6).unwrap();
+        self.0.push((pda::user_account(&wallet), account));
+        self.0.len() - 1
+    }
+
+    fn pda(&self, index: usize) -> Pubkey {
+        self.0[index].0
+    }
+}
+
+// root -> a -> c, root -> b
+fn tree() -> Users {
+    let mut users = Users(Vec::new());
+    let root = users.base();
+    let a = users.referred(root);
+    users.referred(root);
+    users.referred(a);
+    users
+}
+
+#[test]
+fn edges_come_from_referrer_and_upline() {
+    let users = tree();
+    // Registration order is restored whatever order the RPC returns
+    let graph = ReferralGraph::new(users.0.iter().rev().copied());
+
+    assert_eq!(graph.users.iter().map(|user| user.pda).collect::<Vec<_>>(), (0..4).map(|i| users.pda(i)).collect::<Vec<_>>());
+    assert_eq!(graph.roots().map(|user| user.pda).collect::<Vec<_>>(), vec![users.pda(0)]);
+    assert_eq!(graph.referrals(&users.pda(0)).count(), 2);
+    assert!(graph.missing_referrers.is_empty());
+
+    let c = &graph.users[3];
+    assert_eq!(c.referrer, Some(users.pda(1)));
+    assert_eq!(c.referrer_wallet, Some(users.0[1].1.owner_wallet));
+    assert_eq!(c.depth, 3);
+}
+
+#[test]
+fn subtree_and_missing_referrers() {
+    let users = tree();
+    let graph = ReferralGraph::new(users.0.iter().copied());
+
+    let subtree = graph.subtree(&users.pda(1));
+    assert_eq!(subtree.users.iter().map(|user| user.pda).collect::<Vec<_>>(), vec![users.pda(1), users.pda(3)]);
+
+    // Without the root (e.g. still in the legacy layout) its referrals point to nothing
+    let partial = ReferralGraph::new(users.0[1..].iter().copied());
+    assert_eq!(partial.missing_referrers, vec![users.pda(0)]);
+    assert_eq!(partial.roots().count(), 0);
+}
+
+#[test]
+fn renders_json_csv_and_dot() {
+    let mut users = tree();
+    users.0[1].1.chain.filled_slots = 1;
+    users.0[1].1.reserved_sol = 50_000_000;
+    let graph = ReferralGraph::new(users.0.iter().copied());
+
+    let json: serde_json::Value = serde_json::from_str(&graph.to_json().unwrap()).unwrap();
+    assert_eq!(json["users"][1]["pda"], users.pda(1).to_string());
+    assert_eq!(json["users"][1]["reserved_sol"], 50_000_000);
+    assert_eq!(json["users"][0]["referrer"], serde_json::Value::Null);
+
+    let csv = graph.to_csv();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[0], "pda,wallet,referrer,referrer_wallet,upline_id,depth,chain_id,filled_slots,reserved_sol");
+    assert!(lines[1].contains(",,,1,1,1,0,0"));
+    assert!(lines[2].ends_with(",2,2,2,1,50000000"));
+
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("digraph referrals {"));
+    assert!(dot.contains(&format!("\"{}\" -> \"{}\";", users.pda(1), users.pda(3))));
+    assert!(dot.contains("peripheries=2"));
+    assert!(dot.contains("chain 2 | 1/3\\nreserved 50000000 lamports"));
+    assert_eq!(dot.matches(" -> ").count(), 3);
+}
@@ -0,0 +1,413 @@
+// Registration planner over an in-memory account map: slot prediction, the slot 3
+// cascade, the treasury account for the overflow policy, the lookup table decision,
+// referral code resolution, the Merkle allowlist, the campaign window, the protocol fee
+// and the burn escrow.
+
+use std::collections::HashMap;
+
+use anchor_lang::{AccountDeserialize, AccountSerialize, Discriminator};
+use bytemuck::Zeroable;
+use matrix_system::{
+    initialize_base_user_data, initialize_referred_user_data, matrix::Effect, AllowlistMode, Campaign, DepthOverflowPolicy, ProgramState,
+    ReferralCode, ReferrerLimits, UplineEntry, UserAccount, UserAccountRedirect,
+};
+use matrix_system_client::{
+    allowlist, pda,
+    planner::{Cpi, PACKET_DATA_SIZE},
+    plan_registration, resolve_referral_code, PlanError, RegisterWithSolDeposit,
+};
+use solana_program::pubkey::Pubkey;
+
+const DEPOSIT: u64 = 100_000_000;
+const NOW: i64 = 1_000_000;
+
+struct World {
+    accounts: HashMap<Pubkey, Vec<u8>>,
+    state: Pubkey,
+    treasury: Pubkey,
+    wallets: Vec<Pubkey>,
+}
+
+impl World {
+    // A line of `length` users, each referred by the previous one, starting with a base
+    // user. Each stores up to `depth` uplines, the configured upline depth.
+    fn line(length: usize, depth: u8, policy: DepthOverflowPolicy) -> Self {
+        let mut world = World {
+            accounts: HashMap::new(),
+            state: Pubkey::new_unique(),
+            treasury: Pubkey::new_unique(),
+            wallets: Vec::new(),
+        };
+
+        let mut previous: Option<(Pubkey, UserAccount)> = None;
+        for i in 0..length {
+            let wallet = Pubkey::new_unique();
+            let mut account = UserAccount::zeroed();
+            match previous {
+                None => initialize_base_user_data(&mut account, &wallet, 1, 1).unwrap(),
+                Some((referrer_wallet, referrer)) => {
+                    let referrer_pda = pda::user_account(&referrer_wallet);
+                    let entry = UplineEntry { pda: referrer_pda, wallet: referrer_wallet };
+                    let id = i as u32 + 1;
+                    initialize_referred_user_data(&mut account, &wallet, &referrer_pda, &referrer, entry, id, id, depth as usize)
+                        .unwrap();
+                }
+            }
+            world.set_user(&wallet, &account);
+            world.accounts.insert(pda::airdrop_user_account(&wallet), vec![1; 64]);
+            world.wallets.push(wallet);
+            previous = Some((wallet, account));
+        }
+
+        let state = ProgramState {
+            owner: Pubkey::new_unique(),
+            multisig_treasury: world.treasury,
+            next_upline_id: length as u32 + 1,
+            next_chain_id: length as u32 + 1,
+            airdrop_active: true,
+            airdrop_end_timestamp: 0,
+            max_upline_depth: depth,
+            depth_overflow_policy: policy,
+            total_reserved_lamports: 0,
+            allowlist_mode: AllowlistMode::Open,
+            allowlist_root: [0; 32],
+            invite_signer: Pubkey::default(),
+            campaign: Campaign::default(),
+            referrer_limits: ReferrerLimits::default(),
+            protocol_fee_bps: 0,
+            burn_escrow_enabled: false,
+            pending_burn_lamports: 0,
+            reserves_reconciled: true,
+            burn_retry_slippage_bps: 0,
+        };
+        let mut data = Vec::new();
+        state.try_serialize(&mut data).unwrap();
+        world.accounts.insert(world.state, data);
+
+        // Airdrop in its second week, started two and a half weeks ago
+        let mut airdrop_state = vec![0; 128];
+        airdrop_state[72] = 2;
+        airdrop_state[104..112].copy_from_slice(&(NOW - 2 * 1800 - 900).to_le_bytes());
+        world.accounts.insert(pda::airdrop_program_state(), airdrop_state);
+
+        world
+    }
+
+    fn user(&self, wallet: &Pubkey) -> UserAccount {
+        let data = &self.accounts[&pda::user_account(wallet)];
+        bytemuck::pod_read_unaligned(&data[8..8 + std::mem::size_of::<UserAccount>()])
+    }
+
+    fn set_user(&mut self, wallet: &Pubkey, account: &UserAccount) {
+        let mut data = UserAccount::DISCRIMINATOR.to_vec();
+        data.extend_from_slice(bytemuck::bytes_of(account));
+        self.accounts.insert(pda::user_account(wallet), data);
+    }
+
+    fn fill_slots(&mut self, wallet: &Pubkey, filled_slots: u8, reserved_sol: u64) {
+        let mut account = self.user(wallet);
+        account.chain.filled_slots = filled_slots;
+        for slot in account.chain.slots.iter_mut().take(filled_slots as usize) {
+            *slot = Pubkey::new_unique();
+        }
+        account.reserved_sol = reserved_sol;
+        self.set_user(wallet, &account);
+    }
+
+    // migrate_user_wallet: the position of `wallet` moves to a new wallet, returned
+    fn migrate(&mut self, wallet: &Pubkey) -> Pubkey {
+        let new_wallet = Pubkey::new_unique();
+        let mut account = self.user(wallet);
+        account.owner_wallet = new_wallet;
+        self.set_user(&new_wallet, &account);
+
+        let redirect = UserAccountRedirect {
+            owner_wallet: *wallet,
+            new_wallet,
+            new_user: pda::user_account(&new_wallet),
+            migrated_at: NOW,
+        };
+        let mut data = Vec::new();
+        redirect.try_serialize(&mut data).unwrap();
+        self.accounts.insert(pda::user_account(wallet), data);
+        new_wallet
+    }
+
+    // claim_referral_code by `wallet`
+    fn claim(&mut self, wallet: &Pubkey, code: &str) {
+        let referral_code = ReferralCode {
+            code: code.to_string(),
+            user: pda::user_account(wallet),
+            owner_wallet: *wallet,
+            claimed_at: NOW,
+            bump: 255,
+        };
+        let mut data = Vec::new();
+        referral_code.try_serialize(&mut data).unwrap();
+        self.accounts.insert(pda::referral_code(code), data);
+    }
+
+    fn request(&self, referrer: usize) -> RegisterWithSolDeposit {
+        RegisterWithSolDeposit {
+            state: self.state,
+            user_wallet: Pubkey::new_unique(),
+            referrer_wallet: self.wallets[referrer],
+            deposit_amount: DEPOSIT,
+            allowlist_proof: Vec::new(),
+        }
+    }
+}
+
+#[test]
+fn first_slot_burns_without_uplines() {
+    let world = World::line(3, 6, DepthOverflowPolicy::Burn);
+    let plan = plan_registration(&world.accounts, &world.request(2), NOW).unwrap();
+
+    assert_eq!(plan.slot, 0);
+    assert_eq!(plan.cascade_depth, 0);
+    assert_eq!(plan.airdrop_weeks.current, 2);
+    assert_eq!(plan.airdrop_weeks.actual, 3);
+    assert_eq!(plan.remaining_accounts.len(), 13);
+    assert!(plan.cpis.contains(&Cpi::MeteoraSwap));
+    assert!(!plan.cpis.contains(&Cpi::AirdropNotify));
+    assert!(plan.fits_compute_limit);
+    assert_eq!(plan.missing_token_accounts.len(), 2);
+    assert_eq!(plan.outcome.next_chain_id, 5); // The new user took next_chain_id 4
+}
+
+#[test]
+fn third_slot_cascades_until_an_open_upline() {
+    let mut world = World::line(4, 6, DepthOverflowPolicy::Burn);
+    let wallets = world.wallets.clone();
+    world.fill_slots(&wallets[3], 2, DEPOSIT);
+
+    // The stored upline is walked root first: the base user, wallets[1], wallets[2]
+    world.fill_slots(&wallets[0], 2, DEPOSIT);
+    world.fill_slots(&wallets[1], 2, DEPOSIT);
+    world.fill_slots(&wallets[2], 1, 0);
+
+    let plan = plan_registration(&world.accounts, &world.request(3), NOW).unwrap();
+
+    assert_eq!(plan.slot, 2);
+    assert_eq!(plan.remaining_accounts.len(), 13 + 3 * 3);
+    assert_eq!(plan.cascade_depth, 3);
+    assert_eq!(plan.cpis.iter().filter(|cpi| **cpi == Cpi::PayReserve).count(), 3);
+    assert_eq!(plan.cpis.iter().filter(|cpi| **cpi == Cpi::AirdropNotify).count(), 3);
+    assert!(plan.cpis.contains(&Cpi::ReserveSol));
+    assert!(!plan.cpis.contains(&Cpi::MeteoraSwap));
+    assert!(plan.legacy_size > PACKET_DATA_SIZE);
+    assert!(plan.needs_lookup_table);
+}
+
+#[test]
+fn treasury_overflow_adds_the_treasury_account() {
+    // The only upline is a base user - its completion burns regardless of the policy
+    let mut world = World::line(2, 6, DepthOverflowPolicy::Treasury);
+    let wallets = world.wallets.clone();
+    world.fill_slots(&wallets[1], 2, 0);
+    world.fill_slots(&wallets[0], 2, 0);
+
+    let plan = plan_registration(&world.accounts, &world.request(1), NOW).unwrap();
+    assert!(matches!(plan.outcome.effects.last(), Some(Effect::Burn { .. })));
+    assert!(!plan.remaining_accounts.iter().any(|meta| meta.pubkey == world.treasury));
+
+    // Depth 2: wallets[3] stores wallets[1] and wallets[2], the base user is never reached
+    let mut world = World::line(4, 2, DepthOverflowPolicy::Treasury);
+    let wallets = world.wallets.clone();
+    for wallet in &wallets[1..] {
+        world.fill_slots(wallet, 2, 0);
+    }
+
+    let plan = plan_registration(&world.accounts, &world.request(3), NOW).unwrap();
+    assert!(matches!(plan.outcome.effects.last(), Some(Effect::Overflow { policy: DepthOverflowPolicy::Treasury, .. })));
+    assert_eq!(plan.remaining_accounts.last().unwrap().pubkey, world.treasury);
+    assert!(plan.cpis.contains(&Cpi::TreasuryTransfer));
+}
+
+#[test]
+fn payout_addresses_follow_the_upline() {
+    let mut world = World::line(2, 6, DepthOverflowPolicy::Burn);
+    let wallets = world.wallets.clone();
+    let payouts = [Pubkey::new_unique(), Pubkey::new_unique()];
+    for (wallet, payout) in wallets.iter().zip(payouts) {
+        world.fill_slots(wallet, 2, DEPOSIT);
+        let mut account = world.user(wallet);
+        account.payout_address = payout;
+        world.set_user(wallet, &account);
+    }
+
+    let plan = plan_registration(&world.accounts, &world.request(1), NOW).unwrap();
+    let paid: Vec<Pubkey> = plan
+        .outcome
+        .effects
+        .iter()
+        .filter_map(|effect| match effect {
+            Effect::Pay { wallet, .. } => Some(*wallet),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(paid, vec![payouts[1], payouts[0]]);
+
+    let tail: Vec<Pubkey> = plan.remaining_accounts.iter().rev().take(2).map(|meta| meta.pubkey).collect();
+    assert_eq!(tail, vec![payouts[0], payouts[1]]);
+    assert!(plan.remaining_accounts.iter().rev().take(2).all(|meta| meta.is_writable));
+}
+
+#[test]
+fn missing_accounts_are_reported() {
+    let mut world = World::line(3, 6, DepthOverflowPolicy::Burn);
+    let wallets = world.wallets.clone();
+    world.fill_slots(&wallets[2], 2, 0);
+    world.fill_slots(&wallets[0], 2, 0);
+
+    // The base user completes, the cascade needs wallets[1] next
+    let missing = pda::user_account(&wallets[1]);
+    world.accounts.remove(&missing);
+    assert!(matches!(
+        plan_registration(&world.accounts, &world.request(2), NOW),
+        Err(PlanError::UplineNotFound(pda)) if pda == missing
+    ));
+
+    world.accounts.remove(&pda::airdrop_user_account(&wallets[0]));
+    assert!(matches!(
+        plan_registration(&world.accounts, &world.request(0), NOW),
+        Err(PlanError::ReferrerNotInAirdrop(_))
+    ));
+}
+
+#[test]
+fn migrated_uplines_are_refreshed_first() {
+    let mut world = World::line(4, 6, DepthOverflowPolicy::Burn);
+    let wallets = world.wallets.clone();
+    for wallet in &wallets[..3] {
+        world.fill_slots(wallet, 2, DEPOSIT);
+    }
+    world.fill_slots(&wallets[3], 2, 0);
+
+    // wallets[2] moves twice, the referrer still stores the first PDA
+    let first = world.migrate(&wallets[2]);
+    let second = world.migrate(&first);
+
+    let plan = plan_registration(&world.accounts, &world.request(3), NOW).unwrap();
+    let refresh = plan.refresh.as_ref().unwrap();
+    assert_eq!(refresh.user, pda::user_account(&wallets[3]));
+    assert_eq!(refresh.redirects, vec![pda::user_account(&wallets[2]), pda::user_account(&first)]);
+    assert_eq!(plan.instructions().len(), 3);
+
+    // The cascade pays the current wallet
+    assert!(plan.remaining_accounts.iter().any(|meta| meta.pubkey == second));
+    assert!(!plan.remaining_accounts.iter().any(|meta| meta.pubkey == wallets[2] || meta.pubkey == first));
+    assert!(plan.outcome.effects.iter().any(|effect| matches!(effect, Effect::Pay { wallet, .. } if *wallet == second)));
+
+    // A migrated referrer is reported with its new wallet
+    let mut request = world.request(3);
+    request.referrer_wallet = wallets[2];
+    assert!(matches!(
+        plan_registration(&world.accounts, &request, NOW),
+        Err(PlanError::ReferrerMigrated { new_wallet, .. }) if new_wallet == first
+    ));
+}
+
+#[test]
+fn referral_codes_resolve_to_the_current_wallet() {
+    let mut world = World::line(3, 6, DepthOverflowPolicy::Burn);
+    let wallets = world.wallets.clone();
+    world.fill_slots(&wallets[1], 1, 0);
+    world.claim(&wallets[1], "donut-42");
+
+    assert_eq!(resolve_referral_code(&world.accounts, " Donut-42 ").unwrap(), wallets[1]);
+    let mut request = world.request(0);
+    request.referrer_wallet = resolve_referral_code(&world.accounts, "donut-42").unwrap();
+    assert_eq!(plan_registration(&world.accounts, &request, NOW).unwrap().slot, 1);
+
+    // The code keeps pointing at the old PDA after a migration
+    let new_wallet = world.migrate(&wallets[1]);
+    assert_eq!(resolve_referral_code(&world.accounts, "donut-42").unwrap(), new_wallet);
+
+    assert!(matches!(resolve_referral_code(&world.accounts, "unknown"), Err(PlanError::ReferralCodeNotFound(_))));
+    assert!(matches!(resolve_referral_code(&world.accounts, "no"), Err(PlanError::InvalidReferralCode(_))));
+    assert!(matches!(resolve_referral_code(&world.accounts, "two words"), Err(PlanError::InvalidReferralCode(_))));
+}
+
+#[test]
+fn merkle_allowlist_proofs_are_checked_before_planning() {
+    let mut world = World::line(2, 6, DepthOverflowPolicy::Burn);
+    let mut request = world.request(1);
+    let mut wallets: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+    wallets.push(request.user_wallet);
+
+    let root = allowlist::merkle_root(&wallets).unwrap();
+    for wallet in &wallets {
+        let proof = allowlist::merkle_proof(&wallets, wallet).unwrap();
+        assert!(matrix_system::allowlist::verify_proof(&root, wallet, &proof));
+    }
+    assert!(allowlist::merkle_proof(&wallets, &Pubkey::new_unique()).is_none());
+
+    let mut state = ProgramState::try_deserialize(&mut world.accounts[&world.state].as_slice()).unwrap();
+    state.allowlist_mode = AllowlistMode::MerkleRoot;
+    state.allowlist_root = root;
+    let mut data = Vec::new();
+    state.try_serialize(&mut data).unwrap();
+    world.accounts.insert(world.state, data);
+
+    assert!(matches!(plan_registration(&world.accounts, &request, NOW), Err(PlanError::NotAllowlisted(_))));
+    request.allowlist_proof = allowlist::merkle_proof(&wallets, &request.user_wallet).unwrap();
+    let plan = plan_registration(&world.accounts, &request, NOW).unwrap();
+    assert_eq!(plan.slot, 0);
+}
+
+#[test]
+fn registrations_outside_the_campaign_are_reported() {
+    let mut world = World::line(2, 6, DepthOverflowPolicy::Burn);
+    let mut state = ProgramState::try_deserialize(&mut world.accounts[&world.state].as_slice()).unwrap();
+    state.campaign = Campaign {
+        id: 1,
+        start_timestamp: NOW + 1,
+        end_timestamp: NOW + 3600,
+        first_upline_id: state.next_upline_id,
+        max_registrations: 0,
+        closed: false,
+    };
+    let mut data = Vec::new();
+    state.try_serialize(&mut data).unwrap();
+    world.accounts.insert(world.state, data);
+
+    let request = world.request(1);
+    assert!(matches!(plan_registration(&world.accounts, &request, NOW), Err(PlanError::Campaign(_))));
+    assert!(plan_registration(&world.accounts, &request, NOW + 1).is_ok());
+    assert!(matches!(plan_registration(&world.accounts, &request, NOW + 3600), Err(PlanError::Campaign(_))));
+}
+
+#[test]
+fn protocol_fee_is_sent_to_the_treasury_before_routing() {
+    let mut world = World::line(2, 6, DepthOverflowPolicy::Burn);
+    let mut state = ProgramState::try_deserialize(&mut world.accounts[&world.state].as_slice()).unwrap();
+    state.protocol_fee_bps = 250;
+    let mut data = Vec::new();
+    state.try_serialize(&mut data).unwrap();
+    world.accounts.insert(world.state, data);
+
+    let plan = plan_registration(&world.accounts, &world.request(1), NOW).unwrap();
+    let fee = DEPOSIT * 250 / 10_000;
+    assert_eq!(plan.protocol_fee, fee);
+    assert!(plan.outcome.effects.iter().any(|effect| matches!(effect, Effect::Burn { amount, .. } if *amount == DEPOSIT - fee)));
+    assert_eq!(plan.remaining_accounts.last().unwrap().pubkey, world.treasury);
+    assert_eq!(plan.cpis[3], Cpi::TreasuryTransfer);
+}
+
+#[test]
+fn burn_escrow_is_passed_when_the_fallback_is_enabled() {
+    let mut world = World::line(2, 6, DepthOverflowPolicy::Burn);
+    let plan = plan_registration(&world.accounts, &world.request(1), NOW).unwrap();
+    assert!(plan.remaining_accounts.iter().all(|meta| meta.pubkey != pda::burn_escrow()));
+
+    let mut state = ProgramState::try_deserialize(&mut world.accounts[&world.state].as_slice()).unwrap();
+    state.burn_escrow_enabled = true;
+    let mut data = Vec::new();
+    state.try_serialize(&mut data).unwrap();
+    world.accounts.insert(world.state, data);
+
+    let plan = plan_registration(&world.accounts, &world.request(1), NOW).unwrap();
+    assert_eq!(plan.remaining_accounts.last().unwrap().pubkey, pda::burn_escrow());
+    assert!(plan.remaining_accounts.last().unwrap().is_writable);
+}
@@ -0,0 +1,160 @@
+// Remaining-accounts resolution, UserAccount decoding and the airdrop week
+// computation, checked against accounts built with the program's own initializers.
+
+use anchor_lang::Discriminator;
+use bytemuck::Zeroable;
+use matrix_system::{initialize_base_user_data, initialize_referred_user_data, UplineEntry, UserAccount};
+use matrix_system_client::{decode_user_account, pda, register_remaining_accounts, AirdropWeeks, RegisterWithSolDeposit};
+use solana_program::pubkey::Pubkey;
+
+const WEEKS: AirdropWeeks = AirdropWeeks { current: 3, actual: 4 };
+
+struct User {
+    wallet: Pubkey,
+    account: UserAccount,
+}
+
+// A line of users, each referred by the previous one, starting with a base user
+fn line(length: usize) -> Vec<User> {
+    let wallet = Pubkey::new_unique();
+    let mut account = UserAccount::zeroed();
+    initialize_base_user_data(&mut account, &wallet, 1, 1).unwrap();
+    let mut users = vec![User { wallet, account }];
+
+    for i in 1..length {
+        let referrer = &users[i - 1];
+        let referrer_pda = pda::user_account(&referrer.wallet);
+        let entry = UplineEntry { pda: referrer_pda, wallet: referrer.wallet };
+
+        let wallet = Pubkey::new_unique();
+        let mut account = UserAccount::zeroed();
+        initialize_referred_user_data(&mut account, &wallet, &referrer_pda, &referrer.account, entry, i as u32 + 1, i as u32 + 1, 6)
+            .unwrap();
+        users.push(User { wallet, account });
+    }
+    users
+}
+
+fn account_data(account: &UserAccount, capacity: usize) -> Vec<u8> {
+    let mut data = UserAccount::DISCRIMINATOR.to_vec();
+    data.extend_from_slice(&bytemuck::bytes_of(account)[..UserAccount::size_with_capacity(capacity)]);
+    data
+}
+
+#[test]
+fn open_slots_only_need_the_fixed_accounts() {
+    let users = line(3);
+    let referrer = &users[2];
+
+    let accounts = register_remaining_accounts(&referrer.wallet, &referrer.account, WEEKS);
+    assert_eq!(accounts.len(), 13);
+    assert_eq!(accounts[7].pubkey, pda::airdrop_user_account(&referrer.wallet));
+    assert_eq!(accounts[8].pubkey, pda::airdrop_weekly_data(3));
+    assert_eq!(accounts[9].pubkey, pda::airdrop_weekly_data(4));
+    assert_eq!(accounts[10].pubkey, referrer.wallet);
+    assert!(accounts.iter().all(|meta| !meta.is_signer));
+}
+
+#[test]
+fn third_slot_of_referred_user_adds_the_upline() {
+    let users = line(4);
+    let mut referrer = users[3].account;
+    referrer.chain.filled_slots = 2;
+
+    let accounts = register_remaining_accounts(&users[3].wallet, &referrer, WEEKS);
+    let uplines = &accounts[13..];
+    assert_eq!(uplines.len(), 3 * 3);
+
+    // Root first, direct referrer of the referrer last
+    for (i, user) in users[..3].iter().enumerate() {
+        assert_eq!(uplines[i].pubkey, pda::airdrop_user_account(&user.wallet));
+        assert_eq!(uplines[3 + 2 * i].pubkey, pda::user_account(&user.wallet));
+        assert_eq!(uplines[3 + 2 * i + 1].pubkey, user.wallet);
+    }
+    assert!(uplines.iter().all(|meta| meta.is_writable));
+}
+
+#[test]
+fn third_slot_of_base_user_has_no_upline() {
+    let users = line(1);
+    let mut referrer = users[0].account;
+    referrer.chain.filled_slots = 2;
+
+    assert_eq!(register_remaining_accounts(&users[0].wallet, &referrer, WEEKS).len(), 13);
+}
+
+#[test]
+fn instruction_appends_remaining_accounts_after_the_named_ones() {
+    let users = line(2);
+    let builder = RegisterWithSolDeposit {
+        state: Pubkey::new_unique(),
+        user_wallet: Pubkey::new_unique(),
+        referrer_wallet: users[1].wallet,
+        deposit_amount: 100_000_000,
+        allowlist_proof: Vec::new(),
+    };
+
+    let instruction = builder.instruction(&users[1].account, WEEKS);
+    assert_eq!(instruction.program_id, matrix_system::ID);
+    assert_eq!(instruction.accounts.len(), 24 + 13);
+    assert_eq!(instruction.accounts[2].pubkey, pda::user_account(&users[1].wallet));
+    assert_eq!(instruction.accounts[24].pubkey, matrix_system::verified_addresses::A_VAULT);
+
+    let signers: Vec<_> = instruction.accounts.iter().filter(|meta| meta.is_signer).map(|meta| meta.pubkey).collect();
+    assert_eq!(signers, vec![builder.user_wallet]);
+}
+
+#[test]
+fn decodes_full_and_resized_user_accounts() {
+    let users = line(3);
+    let account = &users[2].account;
+
+    for capacity in [2, 6, 16] {
+        let decoded = decode_user_account(&account_data(account, capacity)).unwrap();
+        assert_eq!(decoded.owner_wallet, users[2].wallet);
+        let pdas: Vec<Pubkey> = decoded.upline.entries().iter().map(|entry| entry.pda).collect();
+        assert_eq!(pdas, vec![pda::user_account(&users[0].wallet), pda::user_account(&users[1].wallet)]);
+    }
+
+    // Created before payout_address - the upline follows referral_count
+    let mut account = *account;
+    account.payout_address = Pubkey::new_unique();
+    account.cycles.count = 1;
+    let mut data = account_data(&account, 6);
+    data.drain(UserAccount::PAYOUT_ADDRESS_OFFSET..UserAccount::CYCLE_STATS_OFFSET + UserAccount::CYCLE_STATS_SIZE);
+    let decoded = decode_user_account(&data).unwrap();
+    assert_eq!(decoded.payout_address(), None);
+    assert_eq!(decoded.cycles.count, 0);
+    assert_eq!(decoded.upline.count, 2);
+    assert_eq!(decoded.upline.upline[1].wallet, users[1].wallet);
+
+    // Created before cycles - the upline follows payout_address
+    let mut data = account_data(&account, 6);
+    data.drain(UserAccount::CYCLE_STATS_OFFSET..UserAccount::CYCLE_STATS_OFFSET + UserAccount::CYCLE_STATS_SIZE);
+    let decoded = decode_user_account(&data).unwrap();
+    assert_eq!(decoded.payout_address(), Some(account.payout_address));
+    assert_eq!(decoded.cycles.count, 0);
+    assert_eq!(decoded.upline.upline[1].wallet, users[1].wallet);
+    let account = &users[2].account;
+
+    // Too small for the stored upline, a legacy Borsh account, or not a UserAccount
+    assert!(decode_user_account(&account_data(account, 1)).is_none());
+    let mut legacy = account_data(account, 16);
+    legacy.truncate(8 + matrix_system::LegacyUserAccount::SIZE);
+    assert!(decode_user_account(&legacy).is_none());
+    let mut data = account_data(account, 16);
+    data[0] ^= 1;
+    assert!(decode_user_account(&data).is_none());
+}
+
+#[test]
+fn airdrop_weeks_follow_the_program_state() {
+    let mut data = vec![0; 128];
+    data[72] = 2;
+    data[104..112].copy_from_slice(&1_000i64.to_le_bytes());
+
+    assert_eq!(AirdropWeeks::from_program_state(&data, 1_000), Some(AirdropWeeks { current: 2, actual: 1 }));
+    assert_eq!(AirdropWeeks::from_program_state(&data, 1_000 + 3 * 1800), Some(AirdropWeeks { current: 2, actual: 4 }));
+    assert_eq!(AirdropWeeks::from_program_state(&data, i64::MAX).unwrap().actual, 36);
+    assert_eq!(AirdropWeeks::from_program_state(&data[..100], 1_000), None);
+}
@@ -208,6 +208,19 @@ async function main() {
       console.log("🪂 Airdrop Ativo: " + stateInfo.airdropActive)
       console.log("📅 Airdrop End Timestamp: " + stateInfo.airdropEndTimestamp)
 
+      // Contas de usuário usam o layout zero-copy do IDL - contas Borsh antigas
+      // precisam de migrate_user_account
+      const userAccountSize = program.account.userAccount.size
+      const userAccountRent =
+        await connection.getMinimumBalanceForRentExemption(userAccountSize)
+      console.log(
+        "👤 Conta de usuário: " +
+          userAccountSize +
+          " bytes (rent: " +
+          userAccountRent / 1e9 +
+          " SOL)"
+      )
+
       // Verificar PDAs necessárias para integração
       console.log("\n🔑 PDAS PARA INTEGRAÇÃO:")
 
@@ -0,0 +1,371 @@
+// Fuzz register_with_sol_deposit's upline handling with adversarial remaining_accounts.
+// An honest population is registered through the matrix engine, then one more user is
+// registered under a random referrer with shuffled, repeated, foreign-owned, zero-lamport
+// or substituted accounts, or with the upline cut short. A transaction that gets through the handler's checks may only
+// pay a reserve out of program_sol_vault to the wallet of the user who owns it, and the
+// vault must always hold exactly the SOL reserved by the users, and the depth overflow
+// policy may only apply once the configured depth is reached.
+//
+// Run with: cargo fuzz run register_accounts
+
+#![no_main]
+
+use std::collections::{HashMap, HashSet};
+
+use anchor_lang::prelude::{AccountInfo, Pubkey};
+use anchor_lang::Discriminator;
+use arbitrary::Arbitrary;
+use bytemuck::Zeroable;
+use libfuzzer_sys::fuzz_target;
+use matrix_system::matrix::{self, Effect, Outcome, RecordRef, Throttle, UserRecord};
+use matrix_system::{
+    airdrop_addresses, cascade_upline_pairs, find_upline_pairs, initialize_base_user_data, initialize_referred_user_data,
+    load_upline_record, DepthOverflowPolicy, UplineEntry, UserAccount, RESERVE_SOL,
+};
+
+const MIN_DEPOSIT: u64 = 66_666_667;
+const WALLET_LAMPORTS: u64 = 1_000_000_000;
+const MAX_REGISTRATIONS: usize = 48;
+const MAX_TAMPERS: usize = 16;
+
+// Vault A, Chainlink and airdrop base accounts ahead of the upline airdrop PDAs
+const FIXED_ACCOUNTS: usize = 13;
+
+#[derive(Arbitrary, Debug)]
+enum Tamper {
+    Swap(u8, u8),      // Exchange two remaining accounts
+    Repeat(u8, u8),    // Overwrite an account with a copy of another
+    ForeignOwner(u8),  // Reassign the account to another program
+    AirdropOwner(u8),  // Make the account look like an upline airdrop PDA
+    ZeroLamports(u8),  // Drain the account
+    Remove(u8),        // Drop the account
+    InsertWallet(u8),  // Insert a fresh system account
+    OtherUser(u8, u8), // Replace an account with another registered user or its wallet
+    Truncate(u8),      // Drop everything from the account on
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    upline_depth: u8,
+    policy: u8,
+    registrations: Vec<(u8, u16)>, // (referrer, deposit step) of the honest population
+    referrer: u8,                  // Referrer of the attacked registration
+    deposit: u16,
+    airdrop_pdas: u8,
+    tampers: Vec<Tamper>,
+}
+
+struct SimUser {
+    pda: Pubkey,
+    wallet: Pubkey,
+    account: UserAccount,
+}
+
+#[derive(Clone)]
+struct SimAccount {
+    key: Pubkey,
+    owner: Pubkey,
+    lamports: u64,
+    data: Vec<u8>,
+}
+
+impl SimAccount {
+    fn system(key: Pubkey) -> Self {
+        Self { key, owner: Pubkey::default(), lamports: WALLET_LAMPORTS, data: Vec::new() }
+    }
+
+    fn user(user: &SimUser) -> Self {
+        let mut data = vec![0u8; 8 + UserAccount::SIZE];
+        data[..8].copy_from_slice(&UserAccount::DISCRIMINATOR);
+        data[8..].copy_from_slice(bytemuck::bytes_of(&user.account));
+        Self { key: user.pda, owner: matrix_system::ID, lamports: WALLET_LAMPORTS, data }
+    }
+}
+
+struct Ledger {
+    users: Vec<SimUser>,
+    index: HashMap<Pubkey, usize>,
+    upline_depth: usize,
+    policy: DepthOverflowPolicy,
+    next_upline_id: u32,
+    next_chain_id: u32,
+    vault: u64,
+}
+
+impl Ledger {
+    fn new(upline_depth: usize, policy: DepthOverflowPolicy) -> Self {
+        let mut ledger = Self {
+            users: Vec::new(),
+            index: HashMap::new(),
+            upline_depth,
+            policy,
+            next_upline_id: 1,
+            next_chain_id: 1,
+            vault: 0,
+        };
+
+        let mut account = UserAccount::zeroed();
+        let wallet = Pubkey::new_unique();
+        initialize_base_user_data(&mut account, &wallet, 1, 1).unwrap();
+        ledger.push(SimUser { pda: Pubkey::new_unique(), wallet, account });
+        ledger
+    }
+
+    fn push(&mut self, user: SimUser) {
+        self.index.insert(user.pda, self.users.len());
+        self.users.push(user);
+        self.next_upline_id += 1;
+        self.next_chain_id += 1;
+    }
+
+    fn record(&self, idx: usize) -> UserRecord {
+        let user = &self.users[idx];
+        UserRecord::new(user.pda, user.wallet, &user.account)
+    }
+
+    // New user under `referrer`, not stored until its registration succeeds
+    fn new_user(&self, referrer: usize) -> SimUser {
+        let referrer = &self.users[referrer];
+        let wallet = Pubkey::new_unique();
+        let mut account = UserAccount::zeroed();
+        initialize_referred_user_data(
+            &mut account,
+            &wallet,
+            &referrer.pda,
+            &referrer.account,
+            UplineEntry { pda: referrer.pda, wallet: referrer.wallet },
+            self.next_upline_id,
+            self.next_chain_id,
+            self.upline_depth,
+        )
+        .unwrap();
+
+        SimUser { pda: Pubkey::new_unique(), wallet, account }
+    }
+
+    fn cascades(&self, referrer: usize) -> bool {
+        let account = &self.users[referrer].account;
+        account.chain.filled_slots == 2 && account.referrer().is_some()
+    }
+
+    // Client that sends the stored upline as is
+    fn register_honest(&mut self, referrer: usize, deposit: u64) {
+        let user = self.new_user(referrer);
+        let uplines: Vec<UserRecord> = self.users[referrer]
+            .account
+            .upline
+            .entries()
+            .iter()
+            .take(self.upline_depth)
+            .map(|entry| self.record(self.index[&entry.pda]))
+            .collect();
+        let wallets: Vec<Pubkey> = uplines.iter().map(|upline| upline.wallet).collect();
+
+        let outcome = matrix::register(
+            user.pda,
+            deposit,
+            RESERVE_SOL,
+            self.record(referrer),
+            uplines.len(),
+            |i| Ok(uplines[i]),
+            self.next_chain_id + 1,
+            self.policy,
+            &Throttle::default(),
+        )
+        .expect("honest registration must succeed");
+
+        let referrer_wallet = self.users[referrer].wallet;
+        self.push(user);
+        self.execute(&outcome, |target| match target {
+            RecordRef::Referrer => referrer_wallet,
+            RecordRef::Upline(i) => wallets[i],
+        });
+    }
+
+    // Client that sends tampered remaining_accounts
+    fn register_tampered(&mut self, referrer: usize, deposit: u64, airdrop_pdas: usize, tampers: &[Tamper]) {
+        let user = self.new_user(referrer);
+
+        let mut accounts: Vec<SimAccount> = (0..FIXED_ACCOUNTS).map(|_| SimAccount::system(Pubkey::new_unique())).collect();
+        for _ in 0..airdrop_pdas.min(self.upline_depth) {
+            let mut pda = SimAccount::system(Pubkey::new_unique());
+            pda.owner = airdrop_addresses::AIRDROP_ACCOUNT;
+            accounts.push(pda);
+        }
+        if self.cascades(referrer) {
+            for entry in self.users[referrer].account.upline.entries() {
+                accounts.push(SimAccount::user(&self.users[self.index[&entry.pda]]));
+                accounts.push(SimAccount::system(entry.wallet));
+            }
+        }
+
+        for tamper in tampers.iter().take(MAX_TAMPERS) {
+            self.tamper(&mut accounts, tamper);
+        }
+
+        let infos: Vec<AccountInfo> = accounts
+            .iter_mut()
+            .map(|SimAccount { key, owner, lamports, data }| AccountInfo::new(key, false, true, lamports, data, owner, false, 0))
+            .collect();
+        let pairs = find_upline_pairs(&infos, self.upline_depth);
+
+        // Same checks as the handler ahead of the engine
+        let expected_uplines = self.users[referrer].account.upline.entries();
+        let uplines = if self.cascades(referrer) {
+            if pairs.is_empty() {
+                return;
+            }
+            match cascade_upline_pairs(expected_uplines, self.upline_depth, pairs) {
+                Ok(uplines) => uplines,
+                Err(_) => return,
+            }
+        } else {
+            Vec::new()
+        };
+        let full_depth = expected_uplines.len().min(self.upline_depth);
+
+        let mut processed_uplines = HashSet::new();
+        let outcome = matrix::register(
+            user.pda,
+            deposit,
+            RESERVE_SOL,
+            self.record(referrer),
+            uplines.len(),
+            |i| load_upline_record(&uplines[i], &mut processed_uplines),
+            self.next_chain_id + 1,
+            self.policy,
+            &Throttle::default(),
+        );
+
+        // Rejected - the transaction is rolled back
+        let Ok(outcome) = outcome else {
+            return;
+        };
+
+        // Written records are the accounts that were sent, and a cascade only overflows
+        // at the configured depth
+        for effect in &outcome.effects {
+            match *effect {
+                Effect::Write { target: RecordRef::Upline(i), owner, .. } => {
+                    assert_eq!(*uplines[i].user.key, owner, "engine wrote a record to another account");
+                }
+                Effect::Overflow { depth, .. } => {
+                    assert_eq!(depth as usize, full_depth, "cascade overflowed above the configured depth");
+                }
+                _ => {}
+            }
+        }
+
+        let referrer_wallet = self.users[referrer].wallet;
+        let paid_wallets: Vec<Pubkey> = uplines.iter().map(|upline| *upline.wallet.key).collect();
+        self.push(user);
+        self.execute(&outcome, |target| match target {
+            RecordRef::Referrer => referrer_wallet,
+            RecordRef::Upline(i) => paid_wallets[i],
+        });
+    }
+
+    fn tamper(&self, accounts: &mut Vec<SimAccount>, tamper: &Tamper) {
+        if accounts.is_empty() {
+            return;
+        }
+        let at = |i: u8| i as usize % accounts.len();
+
+        match *tamper {
+            Tamper::Swap(a, b) => {
+                let (a, b) = (at(a), at(b));
+                accounts.swap(a, b);
+            }
+            Tamper::Repeat(from, to) => {
+                let (from, to) = (at(from), at(to));
+                accounts[to] = accounts[from].clone();
+            }
+            Tamper::ForeignOwner(i) => {
+                let i = at(i);
+                accounts[i].owner = Pubkey::new_unique();
+            }
+            Tamper::AirdropOwner(i) => {
+                let i = at(i);
+                accounts[i].owner = airdrop_addresses::AIRDROP_ACCOUNT;
+            }
+            Tamper::ZeroLamports(i) => {
+                let i = at(i);
+                accounts[i].lamports = 0;
+            }
+            Tamper::Remove(i) => {
+                let i = at(i);
+                accounts.remove(i);
+            }
+            Tamper::InsertWallet(i) => {
+                let i = at(i);
+                accounts.insert(i, SimAccount::system(Pubkey::new_unique()));
+            }
+            Tamper::OtherUser(i, user) => {
+                let (i, user) = (at(i), &self.users[user as usize % self.users.len()]);
+                accounts[i] = if accounts[i].owner == matrix_system::ID {
+                    SimAccount::user(user)
+                } else {
+                    SimAccount::system(user.wallet)
+                };
+            }
+            Tamper::Truncate(i) => {
+                let i = at(i);
+                accounts.truncate(i);
+            }
+        }
+    }
+
+    // Apply the effects of an accepted registration and check the vault
+    fn execute(&mut self, outcome: &Outcome, paid_wallet: impl Fn(RecordRef) -> Pubkey) {
+        for effect in &outcome.effects {
+            match *effect {
+                Effect::Write { target, owner, .. } => {
+                    let record = outcome.record(target).expect("write without a record");
+                    assert_eq!(record.key, owner);
+                    let idx = *self.index.get(&owner).expect("write to an unregistered account");
+                    record.apply(&mut self.users[idx].account);
+                }
+                Effect::Reserve { amount, .. } => {
+                    self.vault += amount;
+                }
+                Effect::Pay { target, owner, wallet, amount, .. } => {
+                    let user = &self.users[*self.index.get(&owner).expect("reserve of an unregistered account")];
+
+                    // Only the owner of the reserve is paid, and only what it holds
+                    assert_eq!(paid_wallet(target), user.wallet, "reserve paid to a foreign wallet");
+                    assert_eq!(wallet, user.wallet);
+                    assert_eq!(amount, user.account.reserved_sol, "paid amount differs from the reserve");
+                    self.vault = self.vault.checked_sub(amount).expect("program_sol_vault overdrawn");
+                }
+                Effect::Burn { .. } | Effect::Notify { .. } | Effect::Overflow { .. } => {}
+            }
+        }
+        self.next_chain_id = outcome.next_chain_id;
+
+        let reserved: u64 = self.users.iter().map(|user| user.account.reserved_sol).sum();
+        assert_eq!(self.vault, reserved, "program_sol_vault differs from the reserved SOL");
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let upline_depth = 1 + input.upline_depth as usize % 16;
+    let policy = match input.policy % 3 {
+        0 => DepthOverflowPolicy::Burn,
+        1 => DepthOverflowPolicy::Treasury,
+        _ => DepthOverflowPolicy::Refund,
+    };
+    let mut ledger = Ledger::new(upline_depth, policy);
+
+    for &(referrer, step) in input.registrations.iter().take(MAX_REGISTRATIONS) {
+        let referrer = referrer as usize % ledger.users.len();
+        ledger.register_honest(referrer, MIN_DEPOSIT + step as u64 * 1_000_000);
+    }
+
+    let referrer = input.referrer as usize % ledger.users.len();
+    ledger.register_tampered(
+        referrer,
+        MIN_DEPOSIT + input.deposit as u64 * 1_000_000,
+        input.airdrop_pdas as usize,
+        &input.tampers,
+    );
+});
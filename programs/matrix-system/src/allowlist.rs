@@ -0,0 +1,87 @@
+// Allowlist checks for the invite-only registration phase - Merkle proofs against the
+// configured root and admin invites signed with ed25519. Both are pure functions over
+// their inputs (the invite reads the instructions sysvar), shared with the client that
+// builds the trees and the invite instructions.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    ed25519_program,
+    hash::hashv,
+    sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
+};
+
+// Signed message of an invite: this prefix followed by the invited wallet
+pub const INVITE_MESSAGE_PREFIX: &[u8] = b"matrix-system invite:";
+
+// Ed25519 program instruction data: count, padding, then one offsets record per signature
+const ED25519_OFFSETS_START: usize = 2;
+const ED25519_OFFSETS_SIZE: usize = 14;
+
+// Offsets that point into the Ed25519 instruction itself
+const CURRENT_INSTRUCTION: u16 = u16::MAX;
+
+/// Merkle leaf of an allowed wallet. Leaves and nodes use different prefixes so a node
+/// can never be passed off as a leaf.
+pub fn leaf(wallet: &Pubkey) -> [u8; 32] {
+    hashv(&[&[0], wallet.as_ref()]).to_bytes()
+}
+
+/// Parent of two nodes, hashed in sorted order so a proof needs no left/right flags
+pub fn node(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (left, right) = if a <= b { (a, b) } else { (b, a) };
+    hashv(&[&[1], left, right]).to_bytes()
+}
+
+/// Whether `proof` links the leaf of `wallet` to `root`
+pub fn verify_proof(root: &[u8; 32], wallet: &Pubkey, proof: &[[u8; 32]]) -> bool {
+    proof.iter().fold(leaf(wallet), |hash, sibling| node(&hash, sibling)) == *root
+}
+
+pub fn invite_message(wallet: &Pubkey) -> Vec<u8> {
+    [INVITE_MESSAGE_PREFIX, wallet.as_ref()].concat()
+}
+
+// Public key and message of a single-signature Ed25519 instruction whose data holds
+// everything it verified. Offsets into other instructions are rejected - the signature
+// checked by the precompile would not be the one read here.
+fn ed25519_signed_message(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    if data.len() < ED25519_OFFSETS_START + ED25519_OFFSETS_SIZE || data[0] != 1 {
+        return None;
+    }
+    let read = |field: usize| {
+        let at = ED25519_OFFSETS_START + field * 2;
+        u16::from_le_bytes([data[at], data[at + 1]])
+    };
+    // signature_offset(0) signature_ix(1) public_key_offset(2) public_key_ix(3)
+    // message_offset(4) message_size(5) message_ix(6)
+    if read(1) != CURRENT_INSTRUCTION || read(3) != CURRENT_INSTRUCTION || read(6) != CURRENT_INSTRUCTION {
+        return None;
+    }
+
+    let public_key_offset = read(2) as usize;
+    let message_offset = read(4) as usize;
+    let public_key = data.get(public_key_offset..public_key_offset + 32)?;
+    let message = data.get(message_offset..message_offset + read(5) as usize)?;
+    Some((public_key, message))
+}
+
+/// Whether an Ed25519 instruction earlier in the transaction verified an invite of
+/// `wallet` signed by `invite_signer`. The precompile fails the whole transaction on a
+/// bad signature, so finding the instruction is enough.
+pub fn has_invite(instructions_sysvar: &AccountInfo, invite_signer: &Pubkey, wallet: &Pubkey) -> Result<bool> {
+    let current = load_current_index_checked(instructions_sysvar)?;
+    let message = invite_message(wallet);
+
+    for index in 0..current {
+        let instruction = load_instruction_at_checked(index as usize, instructions_sysvar)?;
+        if instruction.program_id != ed25519_program::ID {
+            continue;
+        }
+        if let Some((public_key, signed)) = ed25519_signed_message(&instruction.data) {
+            if public_key == invite_signer.as_ref() && signed == message.as_slice() {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
@@ -1,6 +1,7 @@
 use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
 use anchor_lang::solana_program::{self, clock::Clock};
-use anchor_spl::token::{self, Token};
+use anchor_spl::token::{self, Mint, Token, TokenAccount};
 use anchor_spl::associated_token::AssociatedToken;
 use chainlink_solana as chainlink;
 use solana_program::instruction::{AccountMeta, Instruction};
@@ -25,6 +26,33 @@ security_txt! {
      acknowledgements: "We thank all security researchers who contributed to the security of our protocol."
  }
 
+// Diagnostic logging - compiled in with the `verbose-logs` feature (default, devnet builds)
+// and stripped with `release-logs` (mainnet builds). Errors and admin actions keep using msg!.
+#[cfg(all(feature = "verbose-logs", not(feature = "release-logs")))]
+macro_rules! debug_msg {
+    ($($arg:tt)*) => {
+        msg!($($arg)*)
+    };
+}
+
+// The arguments are still type-checked so variables used only in logs stay "used"
+#[cfg(not(all(feature = "verbose-logs", not(feature = "release-logs"))))]
+macro_rules! debug_msg {
+    ($($arg:tt)*) => {
+        if false {
+            msg!($($arg)*)
+        }
+    };
+}
+
+// Matrix engine - placement and deposit routing without account access
+pub mod matrix;
+pub use matrix::{place_in_matrix, SlotPlacement};
+use matrix::{Effect, RecordRef, UserRecord};
+
+// Allowlist checks - Merkle proofs and ed25519 invites
+pub mod allowlist;
+
 // Minimum deposit amount in USD (10 dollars in base units - 8 decimals)
 const MINIMUM_USD_DEPOSIT: u64 = 10_00000000; // 10 USD with 8 decimals (Chainlink format)
 
@@ -37,9 +65,45 @@ const DEFAULT_SOL_PRICE: i128 = 100_00000000; // $100 with 8 decimals
 //AIRDROP
 const AIRDROP_MAX_WEEKS: u8 = 36;
 const AIRDROP_TOTAL_DURATION: i64 = 36 * 900; // 36 semanas
+const AIRDROP_WEEK_SECONDS: i64 = 1800; // 30 min - actual week of notify_airdrop_program
+
+// Default number of uplines stored per user and processed by the cascade
+const DEFAULT_UPLINE_DEPTH: u8 = 6;
+
+// Capacity of the fixed upline array in UserAccount - upper bound for the configured depth.
+// Every account is allocated at this capacity; see MIGRATION.md for the rent cost
+const MAX_UPLINE_CAPACITY: usize = 16;
+
+// Upline depth of UserAccounts created before the depth became configurable
+const LEGACY_UPLINE_DEPTH: usize = 6;
+
+// UserAccount.flags - set at registration, zero in accounts created before the flag
+pub const USER_FLAG_COUNTS_REFERRALS: u8 = 1;
+
+// UserAccount.flags - set by claim_referral_code, a user claims at most one code
+pub const USER_FLAG_HAS_REFERRAL_CODE: u8 = 2;
+
+// Length bounds of a referral code - the code is a PDA seed, at most 32 bytes
+pub const MIN_REFERRAL_CODE_LEN: usize = 3;
+pub const MAX_REFERRAL_CODE_LEN: usize = 16;
+
+// UserAccount.reserve_token of a reserve held in lamports by program_sol_vault. Other
+// values name the TokenDepositConfig whose token vault holds it.
+pub const RESERVE_SOL: u8 = 0;
 
-// Maximum number of upline accounts that can be processed in a single transaction
-const MAX_UPLINE_DEPTH: usize = 6;
+// Upper bound of ProgramState.protocol_fee_bps - 10% of each deposit
+pub const MAX_PROTOCOL_FEE_BPS: u16 = 1_000;
+
+// Seconds a pending registration is left to its wallet - afterwards anyone can settle it
+// with finish_pending_registration
+pub const PENDING_REGISTRATION_TIMEOUT: i64 = 3600; // 1 hour
+
+// Escrowed lamports swapped and burned by one retry_pending_burns call - bounds the
+// price impact of a single swap
+pub const MAX_BURN_RETRY_LAMPORTS: u64 = 10_000_000_000; // 10 SOL
+
+// Upper bound of ProgramState.burn_retry_slippage_bps - 5% below the pool's quote
+pub const MAX_BURN_RETRY_SLIPPAGE_BPS: u16 = 500;
 
 // Number of Vault A accounts in the remaining_accounts
 const VAULT_A_ACCOUNTS_COUNT: usize = 4;
@@ -104,22 +168,49 @@ static AIRDROP_PROGRAM_ID: Pubkey = airdrop_addresses::AIRDROP_ACCOUNT;
 // discriminator for instruction notify_matrix_completion
 const NOTIFY_MATRIX_COMPLETION_DISCRIMINATOR: [u8; 8] = [88, 30, 2, 65, 55, 218, 137, 194];
 
+// Allowlist check of a registering wallet, run before any deposit moves. Invites are
+// read from the instructions sysvar, found by key among the remaining accounts.
+fn verify_allowlist(
+    state: &ProgramState,
+    user_wallet: &Pubkey,
+    allowlist_proof: &[[u8; 32]],
+    remaining_accounts: &[AccountInfo],
+) -> Result<()> {
+    let allowed = match state.allowlist_mode {
+        AllowlistMode::Open => true,
+        AllowlistMode::MerkleRoot => allowlist::verify_proof(&state.allowlist_root, user_wallet, allowlist_proof),
+        AllowlistMode::InviteSigner => {
+            let instructions_sysvar = remaining_accounts
+                .iter()
+                .find(|account| account.key() == solana_program::sysvar::instructions::ID)
+                .ok_or(error!(ErrorCode::MissingInstructionsSysvar))?;
+            allowlist::has_invite(instructions_sysvar, &state.invite_signer, user_wallet)?
+        }
+    };
+
+    if !allowed {
+        msg!("❌ Wallet {} is not on the {:?} allowlist", user_wallet, state.allowlist_mode);
+        return Err(error!(ErrorCode::NotAllowlisted));
+    }
+    Ok(())
+}
+
 // Function to verify if the user exists in the Airdrop Program
 fn user_exists_in_airdrop<'info>(
     remaining_accounts: &[AccountInfo<'info>], 
     user_wallet: &Pubkey
 ) -> bool {
     // derives the user's PDA in the AirDrop program
-    msg!("Checking if user exists in airdrop program: {}", user_wallet);
+    debug_msg!("Checking if user exists in airdrop program: {}", user_wallet);
     let seeds = &[b"user_account", user_wallet.as_ref()];
     let (user_pda, _) = Pubkey::find_program_address(seeds, &AIRDROP_PROGRAM_ID);
-    msg!("Seeds: {:?}", seeds);
-    msg!("User PDA: {}", user_pda);
+    debug_msg!("Seeds: {:?}", seeds);
+    debug_msg!("User PDA: {}", user_pda);
     
     // Search for remaining_accounts by the user's PDA
     for account_info in remaining_accounts {
         if account_info.key() == user_pda {
-            msg!("Account found: {}", account_info.key());
+            debug_msg!("Account found: {}", account_info.key());
             // checks if the account exists, belongs to the AirDrop program and has data
             if account_info.owner == &AIRDROP_PROGRAM_ID && 
                account_info.lamports() > 0 && 
@@ -128,13 +219,13 @@ fn user_exists_in_airdrop<'info>(
             }
             
             // The account was found but is not initialized correctly
-            msg!("The user's account was found but is not initialized correctly");
+            debug_msg!("The user's account was found but is not initialized correctly");
             return false;
         }
     }
     
     // PDA of the user was not found, assuming that the user does not exist
-    msg!("User PDA not found, assuming that the user does not exist");
+    debug_msg!("User PDA not found, assuming that the user does not exist");
     false
 }
 
@@ -148,14 +239,14 @@ fn check_and_update_airdrop_status<'info>(
         return Ok(());
     }
     
-    msg!("🔍 Verificando status do airdrop...");
+    debug_msg!("🔍 Checking airdrop status...");
     
     // Ler dados do program_state do airdrop
     let data = program_state_account.try_borrow_data()?;
     
     // Verificar tamanho mínimo
     if data.len() < 112 { // Precisa ter pelo menos até start_timestamp
-        msg!("⚠️ Dados do airdrop muito pequenos para verificar");
+        debug_msg!("⚠️ Airdrop data too small to check");
         return Ok(());
     }
     
@@ -168,7 +259,7 @@ fn check_and_update_airdrop_status<'info>(
         data[108], data[109], data[110], data[111]
     ]);
     
-    msg!("📊 Airdrop - Semana: {}, Start: {}", current_week, start_timestamp);
+    debug_msg!("📊 Airdrop - Week: {}, Start: {}", current_week, start_timestamp);
     
     // Verificar se o airdrop terminou
     let clock = Clock::get()?;
@@ -178,9 +269,9 @@ fn check_and_update_airdrop_status<'info>(
     let ended_by_time = elapsed >= AIRDROP_TOTAL_DURATION;
     
     if ended_by_week || ended_by_time {
-        msg!("🏁 Airdrop terminou!");
-        msg!("  - Por semana: {} (semana {})", ended_by_week, current_week);
-        msg!("  - Por tempo: {} ({}s decorridos)", ended_by_time, elapsed);
+        debug_msg!("🏁 Airdrop ended!");
+        debug_msg!("  - By week: {} (week {})", ended_by_week, current_week);
+        debug_msg!("  - By time: {} ({}s elapsed)", ended_by_time, elapsed);
         
         // Atualizar estado
         state.airdrop_active = false;
@@ -188,12 +279,13 @@ fn check_and_update_airdrop_status<'info>(
         
         msg!("✅ Integração com airdrop desativada em: {}", state.airdrop_end_timestamp);
     } else {
-        msg!("✅ Airdrop ainda ativo - Semana {}/36", current_week);
+        debug_msg!("✅ Airdrop still active - Week {}/36", current_week);
     }
     
     Ok(())
 }
 // Function to notify complete matrix in the airdrop system
+// Returns true if the airdrop program was actually called
 fn notify_airdrop_program<'info>(
     referrer_wallet: &Pubkey,
     _program_id: &Pubkey,
@@ -202,13 +294,13 @@ fn notify_airdrop_program<'info>(
     user_wallet: &AccountInfo<'info>,
     is_last_notification: bool,
     state: &mut Account<'info, ProgramState>, // ADICIONAR PARÂMETRO
- ) -> Result<()> {
-    msg!("Notifying airdrop: {} (last: {})", referrer_wallet, is_last_notification);
+ ) -> Result<bool> {
+    debug_msg!("Notifying airdrop: {} (last: {})", referrer_wallet, is_last_notification);
     
     // ADICIONAR: Verificar se airdrop está ativo
     if !state.airdrop_active {
-        msg!("📴 Airdrop não está mais ativo. Pulando notificação.");
-        return Ok(());
+        debug_msg!("📴 Airdrop no longer active. Skipping notification.");
+        return Ok(false);
     }
     
     // Check if the user exists in the airdrop program
@@ -244,8 +336,8 @@ fn notify_airdrop_program<'info>(
     
     // ADICIONAR: Se após a verificação o airdrop não está mais ativo, retornar
     if !state.airdrop_active {
-        msg!("📴 Airdrop foi desativado durante verificação. Pulando notificação.");
-        return Ok(());
+        debug_msg!("📴 Airdrop was deactivated during the check. Skipping notification.");
+        return Ok(false);
     }
     
     // Get current_week AND calculate actual_week
@@ -266,10 +358,10 @@ fn notify_airdrop_program<'info>(
         
         let clock = Clock::get()?;
         let elapsed = clock.unix_timestamp.saturating_sub(start_timestamp);
-        let calculated_week = ((elapsed / 1800) + 1).min(36) as u8; // 1800 = 30 min
+        let calculated_week = ((elapsed / AIRDROP_WEEK_SECONDS) + 1).min(36) as u8;
         
-        msg!("Current airdrop week (stored): {}", stored_week);
-        msg!("Actual week (calculated): {}", calculated_week);
+        debug_msg!("Current airdrop week (stored): {}", stored_week);
+        debug_msg!("Actual week (calculated): {}", calculated_week);
         
         (stored_week, calculated_week)
     };
@@ -292,7 +384,7 @@ fn notify_airdrop_program<'info>(
         &AIRDROP_PROGRAM_ID
     );
     
-    msg!("📅 Week PDAs - Current: {} (week {}), Actual: {} (week {})", 
+    debug_msg!("📅 Week PDAs - Current: {} (week {}), Actual: {} (week {})", 
         current_week_data_pda, current_week, actual_week_data_pda, actual_week);
     
     // CHANGE: Smarter search considering known positions
@@ -446,8 +538,8 @@ fn notify_airdrop_program<'info>(
         error!(ErrorCode::ReferrerPaymentFailed)
     })?;
     
-    msg!("Airdrop notified successfully for {}", referrer_wallet);
-    Ok(())
+    debug_msg!("Airdrop notified successfully for {}", referrer_wallet);
+    Ok(true)
  }
 
 #[derive(Accounts)]
@@ -462,9 +554,9 @@ pub struct MatrixCompletion<'info> {
         mut,
         seeds = [b"user_account", referrer_wallet.key().as_ref()],
         bump,
-        constraint = referrer.owner_wallet == referrer_wallet.key() @ ErrorCode::InvalidAccountOwner
+        constraint = referrer.load()?.owner_wallet == referrer_wallet.key() @ ErrorCode::InvalidAccountOwner
     )]
-    pub referrer: Box<Account<'info, UserAccount>>,
+    pub referrer: AccountLoader<'info, UserAccount>,
     
     // NEW: User account being registered
     #[account(mut)]
@@ -489,10 +581,203 @@ pub struct ProgramState {
     pub next_chain_id: u32,
     pub airdrop_active: bool,          
     pub airdrop_end_timestamp: i64,    
+    pub max_upline_depth: u8,                           // 0 = DEFAULT_UPLINE_DEPTH
+    pub depth_overflow_policy: DepthOverflowPolicy,     // Deposit routing past max_upline_depth
+    pub total_reserved_lamports: u64,                   // Sum of UserAccount.reserved_sol held in program_sol_vault
+    pub allowlist_mode: AllowlistMode,
+    pub allowlist_root: [u8; 32],                       // Merkle root of allowed wallets (MerkleRoot mode)
+    pub invite_signer: Pubkey,                          // Signer of invites (InviteSigner mode)
+    pub campaign: Campaign,                             // Registration window - id 0 = always open
+    pub referrer_limits: ReferrerLimits,                // Matrix completion throttling - zero = unlimited
+    pub protocol_fee_bps: u16,                          // Share of each SOL deposit sent to multisig_treasury
+    pub burn_escrow_enabled: bool,                      // Escrow burns while the swap route is unavailable
+    pub pending_burn_lamports: u64,                     // Lamports held in burn_escrow until retry_pending_burns
+    pub reserves_reconciled: bool,                      // total_reserved_lamports covers every reserve - sweeps allowed
+    pub burn_retry_slippage_bps: u16,                   // How far a retry_pending_burns swap may land below the pool's quote
 }
 
 impl ProgramState {
-    pub const SIZE: usize = 32 + 32 + 4 + 4 + 1 + 8; // owner + multisig_treasury + next_upline_id + next_chain_id + airdrop_active airdrop_end_timestamp
+    pub const SIZE: usize = 32 + 32 + 4 + 4 + 1 + 8 + // owner + multisig_treasury + next_upline_id + next_chain_id + airdrop_active airdrop_end_timestamp
+                           1 + 1 + // max_upline_depth + depth_overflow_policy
+                           8 + // total_reserved_lamports
+                           1 + 32 + 32 + // allowlist_mode + allowlist_root + invite_signer
+                           Campaign::SIZE +
+                           ReferrerLimits::SIZE +
+                           2 + // protocol_fee_bps
+                           1 + 8 + // burn_escrow_enabled + pending_burn_lamports
+                           1 + // reserves_reconciled
+                           2; // burn_retry_slippage_bps
+
+    // Configured upline depth, bounded by the UserAccount array capacity
+    pub fn upline_depth(&self) -> usize {
+        if self.max_upline_depth == 0 {
+            DEFAULT_UPLINE_DEPTH as usize
+        } else {
+            (self.max_upline_depth as usize).min(MAX_UPLINE_CAPACITY)
+        }
+    }
+
+    // Protocol fee taken from a deposit of `amount` lamports, rounded down
+    pub fn protocol_fee(&self, amount: u64) -> u64 {
+        (amount as u128 * self.protocol_fee_bps as u128 / 10_000) as u64
+    }
+
+    // Least DONUT a retry_pending_burns swap must return for a pool quote of `quote`
+    pub fn burn_retry_min_out(&self, quote: u64) -> u64 {
+        let slippage_bps = self.burn_retry_slippage_bps.min(MAX_BURN_RETRY_SLIPPAGE_BPS);
+        (quote as u128 * (10_000 - slippage_bps) as u128 / 10_000) as u64
+    }
+
+    // Referrer limits for the matrix engine at the instruction's Clock
+    pub fn throttle(&self, now: i64) -> matrix::Throttle {
+        matrix::Throttle { limits: self.referrer_limits, now }
+    }
+
+    // Vault accounting - called next to every reserve transfer into program_sol_vault
+    pub fn credit_reserve(&mut self, amount: u64) -> Result<()> {
+        self.total_reserved_lamports = self.total_reserved_lamports
+            .checked_add(amount)
+            .ok_or(error!(ErrorCode::ReservedTotalOverflow))?;
+        Ok(())
+    }
+
+    // Vault accounting - called next to every reserve payout. A state resized from the
+    // layout without the counter starts at zero until reconcile_vault seeds it, so the
+    // payout must not fail on it.
+    pub fn release_reserve(&mut self, amount: u64) {
+        if amount > self.total_reserved_lamports {
+            msg!("⚠️ Reserved total {} below payout {} - run reconcile_vault", self.total_reserved_lamports, amount);
+        }
+        self.total_reserved_lamports = self.total_reserved_lamports.saturating_sub(amount);
+    }
+
+    // Burn escrow accounting - called next to every transfer into burn_escrow
+    pub fn escrow_burn(&mut self, amount: u64) -> Result<()> {
+        self.pending_burn_lamports = self.pending_burn_lamports
+            .checked_add(amount)
+            .ok_or(error!(ErrorCode::PendingBurnOverflow))?;
+        Ok(())
+    }
+
+    // Vault balance above the tracked reserves and the vault's own rent-exempt minimum
+    pub fn vault_surplus(&self, vault_balance: u64, rent_exempt_minimum: u64) -> u64 {
+        vault_balance.saturating_sub(self.total_reserved_lamports.saturating_add(rent_exempt_minimum))
+    }
+}
+
+// What happens to a deposit that is still unallocated when the cascade stops at
+// max_upline_depth while the last processed upline still has a referrer.
+// A cascade that reaches a base user always swaps and burns.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum DepthOverflowPolicy {
+    #[default]
+    Burn,       // Swap to DONUT and burn (previous implicit behaviour)
+    Treasury,   // Send the deposit to multisig_treasury
+    Refund,     // Return the deposit to the registering wallet
+}
+
+// Registration window of a campaign, checked against the Clock by every registration
+// under a referrer. Registrations are counted with next_upline_id: the campaign is full
+// once max_registrations upline ids were taken after it was scheduled, base users
+// registered by the owner included.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Campaign {
+    pub id: u32,                  // 0 - no campaign was ever scheduled, registration is open
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,       // Exclusive
+    pub first_upline_id: u32,     // next_upline_id when the campaign was scheduled
+    pub max_registrations: u32,   // 0 = no cap
+    pub closed: bool,             // Closed early by the owner
+}
+
+impl Campaign {
+    pub const SIZE: usize = 4 + 8 + 8 + 4 + 4 + 1;
+
+    pub fn registrations(&self, next_upline_id: u32) -> u32 {
+        next_upline_id.saturating_sub(self.first_upline_id)
+    }
+
+    // Scheduled, running or full - a new campaign cannot replace it
+    pub fn is_pending(&self, now: i64) -> bool {
+        self.id != 0 && !self.closed && now < self.end_timestamp
+    }
+
+    pub fn check_registration(&self, now: i64, next_upline_id: u32) -> Result<()> {
+        if self.id == 0 {
+            return Ok(());
+        }
+        if self.closed || now >= self.end_timestamp {
+            msg!("❌ Campaign {} ended at {}", self.id, self.end_timestamp);
+            return Err(error!(ErrorCode::CampaignEnded));
+        }
+        if now < self.start_timestamp {
+            msg!("❌ Campaign {} starts at {}", self.id, self.start_timestamp);
+            return Err(error!(ErrorCode::CampaignNotStarted));
+        }
+        if self.max_registrations != 0 && self.registrations(next_upline_id) >= self.max_registrations {
+            msg!("❌ Campaign {} reached its {} registrations", self.id, self.max_registrations);
+            return Err(error!(ErrorCode::CampaignFull));
+        }
+        Ok(())
+    }
+}
+
+// Anti-sybil limits on how often a referrer's matrix may complete ("cycle"). They are
+// checked against the Clock when a registration would complete its direct referrer's
+// matrix; cycles a cascade completes higher up are counted but never rejected, the
+// registering user did not choose those uplines. Weeks are airdrop weeks counted from
+// week_start.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct ReferrerLimits {
+    pub max_cycles_per_week: u32,   // 0 = no cap
+    pub min_cycle_interval: i64,    // Seconds between two cycles of a referrer, 0 = none
+    pub week_start: i64,            // Start of week 1 - the airdrop program's start_timestamp
+}
+
+impl ReferrerLimits {
+    pub const SIZE: usize = 4 + 8 + 8;
+
+    // Airdrop week of `now`, 0 before week_start
+    pub fn week(&self, now: i64) -> u32 {
+        if now < self.week_start {
+            0
+        } else {
+            ((now - self.week_start) / AIRDROP_WEEK_SECONDS + 1) as u32
+        }
+    }
+
+    pub fn check_cycle(&self, cycles: &CycleStats, now: i64) -> Result<()> {
+        if self.min_cycle_interval > 0 && cycles.last_cycle_at != 0 {
+            let next_cycle_at = cycles.last_cycle_at.saturating_add(self.min_cycle_interval);
+            if now < next_cycle_at {
+                msg!("❌ Referrer cycled at {}, next cycle allowed at {}", cycles.last_cycle_at, next_cycle_at);
+                return Err(error!(ErrorCode::CycleTooSoon));
+            }
+        }
+        if self.max_cycles_per_week > 0 && cycles.week == self.week(now) && cycles.count >= self.max_cycles_per_week {
+            msg!("❌ Referrer reached {} cycles in week {}", cycles.count, cycles.week);
+            return Err(error!(ErrorCode::WeeklyCycleLimitReached));
+        }
+        Ok(())
+    }
+
+    // Count a completion at `now` - always tracked, so limits set later apply at once
+    pub fn record_cycle(&self, cycles: &mut CycleStats, now: i64) {
+        let week = self.week(now);
+        cycles.count = if cycles.week == week { cycles.count.saturating_add(1) } else { 1 };
+        cycles.week = week;
+        cycles.last_cycle_at = now;
+    }
+}
+
+// Who may register under a referrer. The owner's register_without_referrer is never
+// restricted.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum AllowlistMode {
+    #[default]
+    Open,           // Any wallet
+    MerkleRoot,     // Wallets proven against allowlist_root with the instruction's proof
+    InviteSigner,   // Wallets with an ed25519 invite from invite_signer in the transaction
 }
 
 // Separate struct to deserialize the airdrop program's state
@@ -513,49 +798,286 @@ pub struct AirdropProgramState {
 }
 
 // Structure to store complete information for each upline
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, Debug)]
+#[zero_copy]
+#[derive(AnchorSerialize, AnchorDeserialize, Default, Debug)]
 pub struct UplineEntry {
     pub pda: Pubkey,       // PDA of the user account
     pub wallet: Pubkey,    // Original user wallet
 }
 
-// Referral upline structure
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+// Referral upline structure - fixed-size array, only the first `count` entries are valid
+#[zero_copy]
 pub struct ReferralUpline {
     pub id: u32,
     pub depth: u8,
-    pub upline: Vec<UplineEntry>, // Stores UplineEntry with all information
+    pub count: u8,                                  // Number of valid entries in upline
+    pub _padding: [u8; 2],
+    pub upline: [UplineEntry; MAX_UPLINE_CAPACITY], // Stores UplineEntry with all information
 }
 
-// Referral matrix structure
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+impl ReferralUpline {
+    pub fn entries(&self) -> &[UplineEntry] {
+        &self.upline[..self.count as usize]
+    }
+}
+
+// Referral matrix structure - an empty slot holds Pubkey::default()
+#[zero_copy]
 pub struct ReferralChain {
+    pub slots: [Pubkey; 3],
     pub id: u32,
-    pub slots: [Option<Pubkey>; 3],
     pub filled_slots: u8,
+    pub _padding: [u8; 3],
 }
 
-// User account structure
-#[account]
-#[derive(Default)]
+// Matrix completions of a user, tracked for ReferrerLimits
+#[zero_copy]
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct CycleStats {
+    pub last_cycle_at: i64,     // Unix timestamp of the last completion, 0 = never
+    pub week: u32,              // Airdrop week `count` applies to
+    pub count: u32,             // Completions in `week`
+}
+
+// User account structure (zero-copy, fields ordered to avoid implicit padding)
+#[account(zero_copy)]
 pub struct UserAccount {
-    pub is_registered: bool,
-    pub referrer: Option<Pubkey>,
-    pub owner_wallet: Pubkey,           // Account owner's wallet
-    pub upline: ReferralUpline,
-    pub chain: ReferralChain,
     pub reserved_sol: u64,       // SOL reserved from the second slot
+    pub owner_wallet: Pubkey,    // Account owner's wallet
+    pub referrer: Pubkey,        // Referrer PDA, Pubkey::default() for base users
+    pub chain: ReferralChain,
+    pub is_registered: u8,
+    pub flags: u8,               // USER_FLAG_* bits
+    pub reserve_token: u8,       // Currency of reserved_sol - RESERVE_SOL or TokenDepositConfig.token
+    pub _padding: [u8; 1],
+    pub referral_count: u32,     // Direct referrals ever registered - valid with USER_FLAG_COUNTS_REFERRALS
+    pub payout_address: Pubkey,  // Receives slot 3 payouts, Pubkey::default() pays owner_wallet
+    pub cycles: CycleStats,
+    pub upline: ReferralUpline,  // Kept last so the upline array can grow at the end
 }
 
 impl UserAccount {
+    pub const SIZE: usize = 8 + // reserved_sol
+                           32 + // owner_wallet
+                           32 + // referrer
+                           (3 * 32) + 4 + 1 + 3 + // ReferralChain
+                           1 + 1 + 1 + 1 + 4 + // is_registered + flags + reserve_token + padding + referral_count
+                           32 + // payout_address
+                           8 + 4 + 4 + // CycleStats
+                           4 + 1 + 1 + 2 + (MAX_UPLINE_CAPACITY * (32 + 32)); // ReferralUpline
+
+    // Offset of payout_address in the account data, discriminator included. Accounts
+    // created before the field have the upline there instead.
+    pub const PAYOUT_ADDRESS_OFFSET: usize = 8 + 8 + 32 + 32 + (3 * 32) + 4 + 1 + 3 + 1 + 1 + 2 + 4;
+
+    // Offset of cycles, which follows payout_address. Accounts created before it have
+    // the upline there instead; accounts without payout_address lack both.
+    pub const CYCLE_STATS_OFFSET: usize = Self::PAYOUT_ADDRESS_OFFSET + 32;
+    pub const CYCLE_STATS_SIZE: usize = 8 + 4 + 4;
+
+    // Size of a zero-copy UserAccount holding `capacity` upline entries
+    pub const fn size_with_capacity(capacity: usize) -> usize {
+        Self::SIZE - (MAX_UPLINE_CAPACITY - capacity) * (32 + 32)
+    }
+
+    pub fn is_registered(&self) -> bool {
+        self.is_registered != 0
+    }
+
+    pub fn referrer(&self) -> Option<Pubkey> {
+        if self.referrer == Pubkey::default() {
+            None
+        } else {
+            Some(self.referrer)
+        }
+    }
+
+    pub fn payout_address(&self) -> Option<Pubkey> {
+        if self.payout_address == Pubkey::default() {
+            None
+        } else {
+            Some(self.payout_address)
+        }
+    }
+
+    // No other account can reference this one: empty matrix, no reserve and no direct
+    // referral ever. Accounts registered before referrals were counted never qualify.
+    pub fn is_closable(&self) -> bool {
+        self.flags & USER_FLAG_COUNTS_REFERRALS != 0
+            && self.referral_count == 0
+            && self.chain.filled_slots == 0
+            && self.reserved_sol == 0
+    }
+
+    // Point every reference to the migrated account `from` at its new position: the
+    // referrer, the current matrix slots and the stored upline. Returns whether
+    // anything changed.
+    pub fn redirect(&mut self, from: &Pubkey, to: &UplineEntry) -> bool {
+        let mut changed = false;
+        if self.referrer == *from {
+            self.referrer = to.pda;
+            changed = true;
+        }
+        for slot in self.chain.slots.iter_mut().filter(|slot| *slot == from) {
+            *slot = to.pda;
+            changed = true;
+        }
+        let count = (self.upline.count as usize).min(MAX_UPLINE_CAPACITY);
+        for entry in self.upline.upline[..count].iter_mut().filter(|entry| entry.pda == *from) {
+            *entry = *to;
+            changed = true;
+        }
+        changed
+    }
+}
+
+// What is left of a closed UserAccount - keeps the PDA allocated so the wallet cannot
+// register again, under the same or a different referrer
+#[account]
+pub struct ClosedUserAccount {
+    pub owner_wallet: Pubkey,
+    pub referrer: Pubkey,         // Referrer at registration, Pubkey::default() for base users
+    pub upline_id: u32,
+    pub closed_at: i64,           // Unix timestamp of close_user_account
+}
+
+impl ClosedUserAccount {
+    pub const SIZE: usize = 32 + 32 + 4 + 8; // owner_wallet + referrer + upline_id + closed_at
+}
+
+// What is left of a UserAccount moved to a new wallet by migrate_user_wallet. Accounts
+// that still reference the old PDA are pointed at new_user by refresh_user_references.
+#[account]
+pub struct UserAccountRedirect {
+    pub owner_wallet: Pubkey,     // Wallet before the migration
+    pub new_wallet: Pubkey,
+    pub new_user: Pubkey,         // UserAccount PDA of new_wallet
+    pub migrated_at: i64,         // Unix timestamp of migrate_user_wallet
+}
+
+impl UserAccountRedirect {
+    pub const SIZE: usize = 32 + 32 + 32 + 8; // owner_wallet + new_wallet + new_user + migrated_at
+
+    pub fn entry(&self) -> UplineEntry {
+        UplineEntry { pda: self.new_user, wallet: self.new_wallet }
+    }
+}
+
+// Borsh layout of UserAccount before the zero-copy conversion, read by migrate_user_account
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct LegacyReferralUpline {
+    pub id: u32,
+    pub depth: u8,
+    pub upline: Vec<UplineEntry>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct LegacyReferralChain {
+    pub id: u32,
+    pub slots: [Option<Pubkey>; 3],
+    pub filled_slots: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct LegacyUserAccount {
+    pub is_registered: bool,
+    pub referrer: Option<Pubkey>,
+    pub owner_wallet: Pubkey,
+    pub upline: LegacyReferralUpline,
+    pub chain: LegacyReferralChain,
+    pub reserved_sol: u64,
+}
+
+impl LegacyUserAccount {
     pub const SIZE: usize = 1 + // is_registered
                            1 + 32 + // Option<Pubkey> (1 for is_some + 32 for Pubkey)
                            32 + // owner_wallet
-                           4 + 1 + 4 + (MAX_UPLINE_DEPTH * (32 + 32)) + // ReferralUpline
+                           4 + 1 + 4 + (LEGACY_UPLINE_DEPTH * (32 + 32)) + // ReferralUpline
                            4 + (3 * (1 + 32)) + 1 + // ReferralChain
                            8;  // reserved_sol
 }
 
+// Stablecoin accepted by register_with_token_deposit, configured by the owner. The
+// deposit is swapped through its own Meteora pool against the DONUT vault A, reserved
+// in the token_reserve_vault PDA (authority: this account) and paid out in the token.
+// Vault A is shared by every DONUT pool, but each pool holds its own vault A LP account.
+#[account]
+pub struct TokenDepositConfig {
+    pub token: u8,                      // UserAccount.reserve_token value, never RESERVE_SOL
+    pub mint: Pubkey,
+    pub min_deposit: u64,               // In token base units - the USD minimum for a stablecoin
+    pub pool: Pubkey,                   // Meteora DONUT/token pool and its vault B accounts
+    pub b_vault: Pubkey,
+    pub b_token_vault: Pubkey,
+    pub b_vault_lp_mint: Pubkey,
+    pub b_vault_lp: Pubkey,
+    pub protocol_token_fee: Pubkey,
+    pub a_vault_lp: Pubkey,             // The pool's LP token account of vault A
+    pub total_reserved: u64,            // Sum of the UserAccount reserves held in the vault
+    pub bump: u8,
+    pub vault_bump: u8,
+}
+
+impl TokenDepositConfig {
+    pub const SIZE: usize = 1 + 32 + 8 + (7 * 32) + 8 + 1 + 1;
+
+    pub fn credit_reserve(&mut self, amount: u64) -> Result<()> {
+        self.total_reserved = self.total_reserved
+            .checked_add(amount)
+            .ok_or(error!(ErrorCode::ReservedTotalOverflow))?;
+        Ok(())
+    }
+
+    // Same as ProgramState::release_reserve, for the token vault
+    pub fn release_reserve(&mut self, amount: u64) {
+        if amount > self.total_reserved {
+            msg!("⚠️ Token {} reserved total {} below payout {}", self.token, self.total_reserved, amount);
+        }
+        self.total_reserved = self.total_reserved.saturating_sub(amount);
+    }
+}
+
+// Short code mapped to a UserAccount, so a referrer can be shared without its PDA or
+// wallet. Seeded by the code itself; a migrated user keeps its code through the redirect.
+#[account]
+pub struct ReferralCode {
+    pub code: String,            // Lowercase letters, digits, '-' and '_'
+    pub user: Pubkey,            // UserAccount PDA the code resolves to
+    pub owner_wallet: Pubkey,    // Wallet that claimed the code
+    pub claimed_at: i64,         // Unix timestamp of claim_referral_code
+    pub bump: u8,
+}
+
+impl ReferralCode {
+    pub const SIZE: usize = 4 + MAX_REFERRAL_CODE_LEN + 32 + 32 + 8 + 1;
+
+    // Codes are case-sensitive seeds - only the lowercase form can be claimed
+    pub fn is_valid(code: &str) -> bool {
+        (MIN_REFERRAL_CODE_LEN..=MAX_REFERRAL_CODE_LEN).contains(&code.len())
+            && code.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+    }
+}
+
+// Pending registration created by begin_registration and consumed by advance_registration,
+// or by finish_pending_registration once PENDING_REGISTRATION_TIMEOUT has passed
+#[account]
+#[derive(Default)]
+pub struct PendingRegistration {
+    pub user_wallet: Pubkey,        // Wallet that is registering (signs every step)
+    pub user: Pubkey,               // New user account PDA
+    pub referrer: Pubkey,           // Direct referrer PDA whose upline is being walked
+    pub current_user: Pubkey,       // Account to place in the next upline matrix
+    pub remaining_deposit: u64,     // Lamports held in this PDA until allocated
+    pub next_upline_index: u8,      // Next index in referrer.upline.upline to process
+    pub bump: u8,
+    pub created_at: i64,            // begin_registration timestamp - starts the timeout
+    pub protocol_fee: u64,          // Held with the deposit - sent to the treasury unless it is refunded
+}
+
+impl PendingRegistration {
+    pub const SIZE: usize = 32 + 32 + 32 + 32 + 8 + 1 + 1 + 8 + 8; // user_wallet + user + referrer + current_user + remaining_deposit + next_upline_index + bump + created_at + protocol_fee
+}
+
 // Error codes
 #[error_code]
 pub enum ErrorCode {
@@ -678,6 +1200,123 @@ pub enum ErrorCode {
 
     #[msg("Invalid airdrop account data")]
     InvalidAccountData,
+
+    #[msg("Registration has no pending upline cascade")]
+    NoPendingCascade,
+
+    #[msg("Pending registration does not match the provided accounts")]
+    InvalidPendingRegistration,
+
+    #[msg("User account already uses the current layout")]
+    AccountAlreadyMigrated,
+
+    #[msg("User account must be resized to the current upline capacity")]
+    UserAccountNeedsResize,
+
+    #[msg("Upline depth must be between 1 and the upline capacity")]
+    InvalidUplineDepth,
+
+    #[msg("Treasury account not provided")]
+    MissingTreasuryAccount,
+
+    #[msg("Matrix has no free slot")]
+    MatrixFull,
+
+    #[msg("Reserved lamports total overflow")]
+    ReservedTotalOverflow,
+
+    #[msg("Program SOL vault has no surplus over the reserved lamports")]
+    NoVaultSurplus,
+
+    #[msg("Reserved total can only be raised, up to the vault balance")]
+    InvalidReservedTotal,
+
+    #[msg("User account has filled slots, reserved lamports or referrals and cannot be closed")]
+    UserAccountInUse,
+
+    #[msg("Registration of this wallet is still pending")]
+    RegistrationPending,
+
+    #[msg("User account has no reference to the given redirects")]
+    NoStaleReferences,
+
+    #[msg("Payout address account not provided")]
+    MissingPayoutAccount,
+
+    #[msg("Deposit token is not configured or does not match its mint")]
+    InvalidDepositToken,
+
+    #[msg("Deposit token config or reserve vault not provided")]
+    MissingTokenReserveAccount,
+
+    #[msg("Deposit token transfer failed")]
+    TokenTransferFailed,
+
+    #[msg("Referral code must be 3-16 lowercase letters, digits, '-' or '_'")]
+    InvalidReferralCode,
+
+    #[msg("User already claimed a referral code")]
+    ReferralCodeAlreadyClaimed,
+
+    #[msg("Allowlist mode needs a Merkle root or an invite signer")]
+    InvalidAllowlist,
+
+    #[msg("Wallet is not allowlisted for registration")]
+    NotAllowlisted,
+
+    #[msg("Instructions sysvar not provided")]
+    MissingInstructionsSysvar,
+
+    #[msg("Registration campaign has not started yet")]
+    CampaignNotStarted,
+
+    #[msg("Registration campaign has ended")]
+    CampaignEnded,
+
+    #[msg("Registration campaign reached its registration cap")]
+    CampaignFull,
+
+    #[msg("Campaign window or cap is invalid")]
+    InvalidCampaignWindow,
+
+    #[msg("A campaign is still scheduled or running")]
+    CampaignInProgress,
+
+    #[msg("No campaign is scheduled or running")]
+    NoCampaignInProgress,
+
+    #[msg("Referrer limits cannot be negative")]
+    InvalidReferrerLimits,
+
+    #[msg("Referrer's matrix completed too recently")]
+    CycleTooSoon,
+
+    #[msg("Referrer reached its matrix completions for this week")]
+    WeeklyCycleLimitReached,
+
+    #[msg("Protocol fee is above the maximum")]
+    InvalidProtocolFee,
+
+    #[msg("Burn escrow account not provided")]
+    MissingBurnEscrowAccount,
+
+    #[msg("Pending burn total overflow")]
+    PendingBurnOverflow,
+
+    #[msg("No escrowed burns to retry")]
+    NoPendingBurns,
+
+    #[msg("Reserved total must be reconciled before the vault surplus can be swept")]
+    ReservesNotReconciled,
+
+    #[msg("Pending registration can only be finished by its wallet until the timeout")]
+    PendingRegistrationNotExpired,
+
+    #[msg("DONUT received from the swap is below the minimum")]
+    BurnSlippageExceeded,
+
+    #[msg("Burn retry slippage above the maximum")]
+    InvalidBurnRetrySlippage,
 }
 
 // Event structure for slot filling
@@ -689,52 +1328,303 @@ pub struct SlotFilled {
     pub owner: Pubkey,    // Owner of the matrix
 }
 
-// Decimal handling for price display
-#[derive(Default)]
-pub struct Decimal {
-    pub value: i128,
-    pub decimals: u32,
+// Where a registration deposit ended up
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DepositRoute {
+    Burned,      // Swapped to DONUT and burned
+    Reserved,    // Held in the program vault for the matrix owner
+    Pending,     // Held by a pending registration until advance_registration
+    Treasury,    // Sent to the multisig treasury by the depth overflow policy
+    Refunded,    // Returned to the user by the depth overflow policy
+    Escrowed,    // Held in burn_escrow until retry_pending_burns swaps and burns it
 }
 
-impl Decimal {
-    pub fn new(value: i128, decimals: u32) -> Self {
-        Decimal { value, decimals }
-    }
+// Events emitted through emit_cpi! so they survive log truncation.
+// depth is the position in the cascade: 0 = direct referrer, n = n-th stored upline.
+
+// Event for a completed registration
+#[event]
+pub struct UserRegistered {
+    pub user: Pubkey,             // New UserAccount PDA
+    pub user_wallet: Pubkey,      // New user's wallet
+    pub referrer: Option<Pubkey>, // Referrer UserAccount PDA (None for base users)
+    pub upline_id: u32,           // Upline ID assigned to the user
+    pub chain_id: u32,            // Chain ID of the user's first matrix
+    pub upline_depth: u8,         // Depth of the user in the referral tree
+    pub deposit_amount: u64,      // Deposit in units of deposit_token
+    pub deposit_token: u8,        // RESERVE_SOL or the TokenDepositConfig.token paid
 }
 
-impl std::fmt::Display for Decimal {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut scaled_val = self.value.to_string();
-        if scaled_val.len() <= self.decimals as usize {
-            scaled_val.insert_str(
-                0,
-                &vec!["0"; self.decimals as usize - scaled_val.len()].join(""),
-            );
-            scaled_val.insert_str(0, "0.");
-        } else {
-            scaled_val.insert(scaled_val.len() - self.decimals as usize, '.');
-        }
-        f.write_str(&scaled_val)
-    }
+// Event for a deposit held in the vault for a matrix owner (slot 2)
+#[event]
+pub struct ReserveCredited {
+    pub owner: Pubkey,    // Matrix owner UserAccount PDA
+    pub chain_id: u32,    // Matrix chain ID
+    pub amount: u64,      // Amount reserved, in units of the reserve token
+    pub depth: u8,        // Cascade depth
+    pub token: u8,        // Reserve token (RESERVE_SOL for lamports)
 }
 
-// Helper function to force memory cleanup
-fn force_memory_cleanup() {
-    // Just create a vector to force a heap allocation
-    let _dummy = Vec::<u8>::new();
-    // The vector will be automatically freed when it goes out of scope
+// Event for a reserve paid out to a matrix owner (slot 3)
+#[event]
+pub struct ReservePaid {
+    pub owner: Pubkey,    // Matrix owner UserAccount PDA
+    pub wallet: Pubkey,   // Wallet that received the reserve
+    pub chain_id: u32,    // Matrix chain ID
+    pub amount: u64,      // Amount paid, in units of the reserve token
+    pub depth: u8,        // Cascade depth
+    pub token: u8,        // Reserve token (RESERVE_SOL for lamports)
 }
 
-// Function to get SOL/USD price from Chainlink feed
-fn get_sol_usd_price<'info>(
-    chainlink_feed: &AccountInfo<'info>,
-    chainlink_program: &AccountInfo<'info>,
-) -> Result<(i128, u32, i64, i64)> { // Returns also the feed_timestamp
-    // Get the latest round data
-    let round = chainlink::latest_round_data(
-        chainlink_program.clone(),
-        chainlink_feed.clone(),
-    ).map_err(|_| error!(ErrorCode::PriceFeedReadFailed))?;
+// Event for a deposit swapped to DONUT and burned
+#[event]
+pub struct DonutBurned {
+    pub user_wallet: Pubkey,  // Wallet that paid the deposit
+    pub chain_id: u32,        // Matrix chain ID the deposit landed in
+    pub sol_amount: u64,      // Amount swapped, in units of token
+    pub donut_amount: u64,    // DONUT burned
+    pub depth: u8,            // Cascade depth
+    pub token: u8,            // Deposit token (RESERVE_SOL for lamports)
+}
+
+// Event for a matrix that reached 3/3 slots and was reset
+#[event]
+pub struct MatrixCompleted {
+    pub owner: Pubkey,        // Matrix owner UserAccount PDA
+    pub chain_id: u32,        // Completed chain ID
+    pub next_chain_id: u32,   // Chain ID of the new matrix
+    pub depth: u8,            // Cascade depth
+}
+
+// Event for a matrix completion forwarded to the airdrop program
+#[event]
+pub struct AirdropNotified {
+    pub wallet: Pubkey,           // Matrix owner wallet
+    pub chain_id: u32,            // Completed chain ID
+    pub depth: u8,                // Cascade depth
+    pub is_last_notification: bool,
+}
+
+// Event for the airdrop integration being switched off
+#[event]
+pub struct AirdropDeactivated {
+    pub end_timestamp: i64,       // Unix timestamp of the deactivation
+    pub next_chain_id: u32,       // Chain ID counter at deactivation
+}
+
+// Event for the final destination of a registration deposit
+#[event]
+pub struct DepositRouted {
+    pub user: Pubkey,         // Registering UserAccount PDA
+    pub owner: Pubkey,        // Matrix owner where the cascade stopped
+    pub chain_id: u32,        // Matrix chain ID
+    pub route: DepositRoute,
+    pub amount: u64,          // Lamports routed
+    pub depth: u8,            // Cascade depth
+}
+
+// Event for vault lamports above the tracked reserves moved to the treasury
+#[event]
+pub struct VaultSurplusSwept {
+    pub treasury: Pubkey,             // Multisig treasury that received the surplus
+    pub amount: u64,                  // Lamports swept
+    pub total_reserved_lamports: u64, // Reserves left in the vault
+}
+
+// Event for the reserved total seeded by reconcile_vault
+#[event]
+pub struct VaultReconciled {
+    pub previous_total: u64,          // Tracked total before the call
+    pub total_reserved_lamports: u64, // New tracked total
+    pub vault_balance: u64,           // Vault lamports at the time
+}
+
+// Event for a UserAccount closed and replaced by its tombstone
+#[event]
+pub struct UserAccountClosed {
+    pub user: Pubkey,             // UserAccount PDA, now a ClosedUserAccount
+    pub owner_wallet: Pubkey,     // Wallet that received the rent
+    pub referrer: Option<Pubkey>, // Referrer UserAccount PDA (None for base users)
+    pub reclaimed: u64,           // Lamports returned to the wallet
+}
+
+// Event for a UserAccount moved to a new wallet
+#[event]
+pub struct UserWalletMigrated {
+    pub old_user: Pubkey,         // Previous UserAccount PDA, now a UserAccountRedirect
+    pub new_user: Pubkey,         // UserAccount PDA of the new wallet
+    pub old_wallet: Pubkey,
+    pub new_wallet: Pubkey,
+    pub upline_id: u32,
+}
+
+// Event for a payout address set or cleared by the owner
+#[event]
+pub struct PayoutAddressSet {
+    pub user: Pubkey,
+    pub owner_wallet: Pubkey,
+    pub payout_address: Option<Pubkey>, // None - payouts go to owner_wallet again
+}
+
+// Event for a deposit token added or updated by the owner
+#[event]
+pub struct DepositTokenConfigured {
+    pub token: u8,
+    pub mint: Pubkey,
+    pub min_deposit: u64,   // In token base units
+    pub pool: Pubkey,       // Meteora DONUT/token pool
+}
+
+// Event for an allowlist mode, root or invite signer set by the owner
+#[event]
+pub struct AllowlistUpdated {
+    pub mode: AllowlistMode,
+    pub root: [u8; 32],
+    pub invite_signer: Pubkey,
+}
+
+// Event for a campaign scheduled by the owner
+#[event]
+pub struct CampaignScheduled {
+    pub id: u32,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+    pub max_registrations: u32,   // 0 = no cap
+    pub first_upline_id: u32,     // Registrations are counted from this upline id
+}
+
+// Event for a campaign end or cap raised by the owner
+#[event]
+pub struct CampaignExtended {
+    pub id: u32,
+    pub end_timestamp: i64,
+    pub max_registrations: u32,
+}
+
+// Event for a campaign closed early by the owner
+#[event]
+pub struct CampaignClosed {
+    pub id: u32,
+    pub closed_at: i64,
+    pub registrations: u32,
+}
+
+// Event for referrer limits changed by the owner
+#[event]
+pub struct ReferrerLimitsUpdated {
+    pub max_cycles_per_week: u32,
+    pub min_cycle_interval: i64,
+    pub week_start: i64,
+}
+
+// Event for the protocol fee changed by the owner
+#[event]
+pub struct ProtocolFeeUpdated {
+    pub fee_bps: u16,
+}
+
+// Event for the protocol fee taken from a SOL deposit
+#[event]
+pub struct ProtocolFeeCollected {
+    pub user: Pubkey,
+    pub treasury: Pubkey,
+    pub deposit_amount: u64,      // Gross deposit, checked against the oracle minimum
+    pub fee_amount: u64,          // Sent to the treasury - the rest is routed by the matrix
+    pub fee_bps: u16,
+}
+
+// Event for the burn escrow fallback toggled by the owner
+#[event]
+pub struct BurnEscrowUpdated {
+    pub enabled: bool,
+    pub retry_slippage_bps: u16,
+}
+
+// Event for escrowed burns swapped and burned by retry_pending_burns
+#[event]
+pub struct PendingBurnsRetried {
+    pub cranker: Pubkey,
+    pub sol_amount: u64,          // Lamports taken out of burn_escrow
+    pub donut_amount: u64,        // DONUT received from the swap and burned
+    pub min_donut_amount: u64,    // Pool quote less the slippage bound
+    pub remaining_lamports: u64,  // Still escrowed for the next call
+}
+
+// Event for a pending registration settled by finish_pending_registration
+#[event]
+pub struct PendingRegistrationFinished {
+    pub user: Pubkey,             // Registering UserAccount PDA
+    pub user_wallet: Pubkey,      // Receives refunds and the pending PDA rent
+    pub cranker: Pubkey,
+    pub amount: u64,              // Deposit held by the pending PDA
+    pub upline_index: u8,         // Where the wallet left the cascade
+}
+
+// Event for a referral code claimed by a registered user
+#[event]
+pub struct ReferralCodeClaimed {
+    pub code: String,
+    pub referral_code: Pubkey,    // ReferralCode PDA
+    pub user: Pubkey,
+    pub owner_wallet: Pubkey,
+}
+
+// Result of audit_vault, returned as instruction return data
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VaultAudit {
+    pub vault_balance: u64,
+    pub rent_exempt_minimum: u64,     // Kept in the vault, never swept
+    pub total_reserved_lamports: u64,
+    pub surplus: u64,                 // Sweepable: balance - reserved - rent minimum
+    pub deficit: u64,                 // Reserved lamports the vault cannot cover
+}
+
+// Decimal handling for price display
+#[derive(Default)]
+pub struct Decimal {
+    pub value: i128,
+    pub decimals: u32,
+}
+
+impl Decimal {
+    pub fn new(value: i128, decimals: u32) -> Self {
+        Decimal { value, decimals }
+    }
+}
+
+impl std::fmt::Display for Decimal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut scaled_val = self.value.to_string();
+        if scaled_val.len() <= self.decimals as usize {
+            scaled_val.insert_str(
+                0,
+                &vec!["0"; self.decimals as usize - scaled_val.len()].join(""),
+            );
+            scaled_val.insert_str(0, "0.");
+        } else {
+            scaled_val.insert(scaled_val.len() - self.decimals as usize, '.');
+        }
+        f.write_str(&scaled_val)
+    }
+}
+
+// Helper function to force memory cleanup
+fn force_memory_cleanup() {
+    // Just create a vector to force a heap allocation
+    let _dummy = Vec::<u8>::new();
+    // The vector will be automatically freed when it goes out of scope
+}
+
+// Function to get SOL/USD price from Chainlink feed
+fn get_sol_usd_price<'info>(
+    chainlink_feed: &AccountInfo<'info>,
+    chainlink_program: &AccountInfo<'info>,
+) -> Result<(i128, u32, i64, i64)> { // Returns also the feed_timestamp
+    // Get the latest round data
+    let round = chainlink::latest_round_data(
+        chainlink_program.clone(),
+        chainlink_feed.clone(),
+    ).map_err(|_| error!(ErrorCode::PriceFeedReadFailed))?;
 
     // Get the decimals
     let decimals = chainlink::decimals(
@@ -829,11 +1719,23 @@ fn verify_vault_a_addresses(
     a_vault_lp_mint: &Pubkey,
     a_token_vault: &Pubkey
 ) -> Result<()> {
-    verify_address_strict(a_vault, &verified_addresses::A_VAULT, ErrorCode::InvalidVaultAddress)?;
+    verify_shared_vault_a_addresses(a_vault, a_vault_lp_mint, a_token_vault)?;
     verify_address_strict(a_vault_lp, &verified_addresses::A_VAULT_LP, ErrorCode::InvalidVaultAddress)?;
+    
+    Ok(())
+}
+
+// Verify the vault A accounts shared by every DONUT pool. The vault A LP account is the
+// SOL pool's - a deposit token pool holds its own, stored in its TokenDepositConfig.
+fn verify_shared_vault_a_addresses(
+    a_vault: &Pubkey,
+    a_vault_lp_mint: &Pubkey,
+    a_token_vault: &Pubkey
+) -> Result<()> {
+    verify_address_strict(a_vault, &verified_addresses::A_VAULT, ErrorCode::InvalidVaultAddress)?;
     verify_address_strict(a_vault_lp_mint, &verified_addresses::A_VAULT_LP_MINT, ErrorCode::InvalidVaultAddress)?;
     verify_address_strict(a_token_vault, &verified_addresses::A_TOKEN_VAULT, ErrorCode::InvalidVaultAddress)?;
-    
+
     Ok(())
 }
 
@@ -862,7 +1764,31 @@ fn process_reserve_sol<'info>(
         &ix,
         &[from.clone(), to.clone()],
     ).map_err(|_| error!(ErrorCode::SolReserveFailed))?;
-    
+
+    Ok(())
+}
+
+// Function to move a token deposit out of the user's token account
+fn process_transfer_token_deposit<'info>(
+    user_wallet: &AccountInfo<'info>,
+    from: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    let ix = spl_token::instruction::transfer(
+        &token::ID,
+        &from.key(),
+        &to.key(),
+        &user_wallet.key(),
+        &[],
+        amount,
+    )?;
+
+    solana_program::program::invoke(
+        &ix,
+        &[from.clone(), to.clone(), user_wallet.clone()],
+    ).map_err(|_| error!(ErrorCode::TokenTransferFailed))?;
+
     Ok(())
 }
 
@@ -895,8 +1821,8 @@ fn process_pay_referrer<'info>(
     Ok(())
 }
 
-/// Calculate expected swap output
-fn calculate_swap_amount_out<'info>(
+/// DONUT the pool's reserves give for `amount_in` lamports
+fn quote_swap_amount_out<'info>(
     pool: &AccountInfo<'info>,
     a_vault: &AccountInfo<'info>,
     b_vault: &AccountInfo<'info>,
@@ -1047,7 +1973,7 @@ fn calculate_swap_amount_out<'info>(
         u64::try_from(result).map_err(|_| error!(ErrorCode::MeteoraCalculationOverflow))?
     };
     
-    msg!("Token amounts - A: {}, B: {}", token_a_amount, token_b_amount);
+    debug_msg!("Token amounts - A: {}, B: {}", token_a_amount, token_b_amount);
     
     if token_a_amount == 0 || token_b_amount == 0 {
         return Err(error!(ErrorCode::PriceMeteoraReadFailed));
@@ -1069,7 +1995,31 @@ fn calculate_swap_amount_out<'info>(
         return Err(error!(ErrorCode::MeteoraCalculationOverflow));
     }
     
-    let result = donut_tokens as u64;
+    Ok(donut_tokens as u64)
+}
+
+/// Calculate expected swap output
+#[allow(clippy::too_many_arguments)]
+fn calculate_swap_amount_out<'info>(
+    pool: &AccountInfo<'info>,
+    a_vault: &AccountInfo<'info>,
+    b_vault: &AccountInfo<'info>,
+    a_vault_lp: &AccountInfo<'info>,
+    b_vault_lp: &AccountInfo<'info>,
+    a_vault_lp_mint: &AccountInfo<'info>,
+    b_vault_lp_mint: &AccountInfo<'info>,
+    amount_in: u64,
+) -> Result<u64> {
+    let result = quote_swap_amount_out(
+        pool,
+        a_vault,
+        b_vault,
+        a_vault_lp,
+        b_vault_lp,
+        a_vault_lp_mint,
+        b_vault_lp_mint,
+        amount_in,
+    )?;
     
     // Apply 99% slippage tolerance (accept only 1% of the expected)
     let minimum_out = result
@@ -1077,7 +2027,7 @@ fn calculate_swap_amount_out<'info>(
         .and_then(|n| n.checked_div(100))
         .ok_or(error!(ErrorCode::MeteoraCalculationOverflow))?;
     
-    msg!("Expected output: {} DONUT, Minimum accepted (99% slippage): {} DONUT", result, minimum_out);
+    debug_msg!("Expected output: {} DONUT, Minimum accepted (99% slippage): {} DONUT", result, minimum_out);
     
     Ok(if minimum_out == 0 { 1 } else { minimum_out })
 }
@@ -1102,8 +2052,9 @@ fn process_swap_wsol_to_donut<'info>(
     amm_program: &AccountInfo<'info>,
     amount_in: u64,
     minimum_amount_out: u64,
+    signer_seeds: &[&[&[u8]]],
 ) -> Result<()> {
-    msg!("Starting swap: {} WSOL for DONUT (min: {})", amount_in, minimum_amount_out);
+    debug_msg!("Starting swap: {} WSOL for DONUT (min: {})", amount_in, minimum_amount_out);
     
     // Build swap accounts
     let swap_accounts = vec![
@@ -1153,16 +2104,17 @@ fn process_swap_wsol_to_donut<'info>(
     accounts_vec.push(vault_program.clone());
     accounts_vec.push(token_program.clone());
     
-    // Execute swap
-    solana_program::program::invoke(
+    // Execute swap - a PDA owner of the token accounts signs with its seeds
+    solana_program::program::invoke_signed(
         &swap_instruction,
         &accounts_vec,
+        signer_seeds,
     ).map_err(|e| {
         msg!("Swap failed: {:?}", e);
         error!(ErrorCode::SwapFailed)
     })?;
     
-    msg!("Swap completed successfully");
+    debug_msg!("Swap completed successfully");
     Ok(())
 }
 
@@ -1175,7 +2127,7 @@ fn read_and_validate_token_account<'info>(
 ) -> Result<u64> {
     // Scenario 1: Account does not exist (not created)
     if account.data_is_empty() || account.lamports() == 0 {
-        msg!("📝 Token account doesn't exist yet. Balance = 0");
+        debug_msg!("📝 Token account doesn't exist yet. Balance = 0");
         return Ok(0); // Return 0 if account does not exist
     }
     
@@ -1183,13 +2135,13 @@ fn read_and_validate_token_account<'info>(
     
     // Scenario 2: Account exists but invalid data
     if data.len() < 165 {
-        msg!("⚠️ Account exists but invalid size: {}. Treating as 0 balance", data.len());
+        debug_msg!("⚠️ Account exists but invalid size: {}. Treating as 0 balance", data.len());
         return Ok(0); // Safe to return 0
     }
     
     // Scenario 3: Account is not part of the Token Program
     if account.owner != &spl_token::ID {
-        msg!("⚠️ Account not owned by Token Program. Owner: {}. Treating as 0 balance", account.owner);
+        debug_msg!("⚠️ Account not owned by Token Program. Owner: {}. Treating as 0 balance", account.owner);
         return Ok(0); // It's not a token account, balance = 0
     }
     
@@ -1216,7 +2168,7 @@ fn read_and_validate_token_account<'info>(
         data[68], data[69], data[70], data[71],
     ]);
     
-    msg!("✅ Valid DONUT token account. Balance: {}", amount);
+    debug_msg!("✅ Valid DONUT token account. Balance: {}", amount);
     Ok(amount)
 }
 
@@ -1240,7 +2192,8 @@ fn process_swap_and_burn<'info>(
     token_program: &AccountInfo<'info>,
     amm_program: &AccountInfo<'info>,
     amount: u64,
-) -> Result<()> {
+    signer_seeds: &[&[&[u8]]],
+) -> Result<u64> {
     // Validar mint
     verify_address_strict(
         &token_mint.key(),
@@ -1267,11 +2220,11 @@ fn process_swap_and_burn<'info>(
         &token_mint.key(),
         &user_wallet.key(),
     )?;
-    msg!("📸 DONUT balance BEFORE swap: {}", balance_before);
+    debug_msg!("📸 DONUT balance BEFORE swap: {}", balance_before);
     
     // If the account does not exist, swap will create it
     if balance_before == 0 && user_donut_account.data_is_empty() {
-        msg!("🆕 User's first DONUT transaction - account will be created by swap");
+        debug_msg!("🆕 User's first DONUT transaction - account will be created by swap");
     }
 
     // Execute swap
@@ -1294,6 +2247,7 @@ fn process_swap_and_burn<'info>(
         amm_program,
         amount,
         minimum_donut_out,
+        signer_seeds,
     )?;
 
     force_memory_cleanup();
@@ -1304,11 +2258,11 @@ fn process_swap_and_burn<'info>(
         &token_mint.key(),
         &user_wallet.key(),
     )?;
-    msg!("📸 DONUT balance AFTER swap: {}", balance_after);
+    debug_msg!("📸 DONUT balance AFTER swap: {}", balance_after);
     
     // Calculate received
     let exact_received = balance_after.saturating_sub(balance_before);
-    msg!("💰 EXACT amount received from swap: {}", exact_received);
+    debug_msg!("💰 EXACT amount received from swap: {}", exact_received);
     
     // SCENARIO C: Swap failed (did not receive anything)
     if exact_received == 0 {
@@ -1323,7 +2277,7 @@ fn process_swap_and_burn<'info>(
     }
 
     // SCENARIO E: Everything OK - burn received tokens
-    msg!("🔥 Burning EXACT {} DONUT tokens received from swap...", exact_received);
+    debug_msg!("🔥 Burning EXACT {} DONUT tokens received from swap...", exact_received);
     
     let burn_ix = spl_token::instruction::burn(
         &token_program.key(),
@@ -1339,9 +2293,10 @@ fn process_swap_and_burn<'info>(
     burn_accounts.push(token_mint.clone());
     burn_accounts.push(user_wallet.clone());
     
-    solana_program::program::invoke(
+    solana_program::program::invoke_signed(
         &burn_ix,
         &burn_accounts,
+        signer_seeds,
     ).map_err(|e| {
         msg!("Burn failed: {:?}", e);
         error!(ErrorCode::BurnFailed)
@@ -1354,106 +2309,242 @@ fn process_swap_and_burn<'info>(
         &user_wallet.key(),
     )?;
     
-    msg!("✅ Successfully burned {} DONUT tokens", exact_received);
-    msg!("📊 Final balance: {} (started with: {})", end_balance, balance_before);
+    debug_msg!("✅ Successfully burned {} DONUT tokens", exact_received);
+    debug_msg!("📊 Final balance: {} (started with: {})", end_balance, balance_before);
     
     // SCENARIO G: If user started with 0, it should end with 0
     if balance_before == 0 && end_balance != 0 {
-        msg!("⚠️ Warning: User started with 0 but has {} remaining", end_balance);
+        debug_msg!("⚠️ Warning: User started with 0 but has {} remaining", end_balance);
         // This can happen if received more than minimum due to favorable slippage
     }
     
     // SCENARIO H: If user had balance, it should keep the original balance
     if balance_before > 0 && end_balance != balance_before {
-        msg!("⚠️ Warning: Balance mismatch. Expected: {}, Got: {}", balance_before, end_balance);
+        debug_msg!("⚠️ Warning: Balance mismatch. Expected: {}, Got: {}", balance_before, end_balance);
     }
 
-    Ok(())
+    Ok(exact_received)
 }
 
-/// Process the direct referrer's matrix when a new user registers
-/// Returns (bool, Pubkey) where:
-/// - bool: indicates if the matrix was completed
-/// - Pubkey: referrer key for use in recursion
-fn process_referrer_chain<'info>(
-    user_key: &Pubkey,
-    referrer: &mut Account<'_, UserAccount>,
-    next_chain_id: u32,
-    referrer_wallet: &Pubkey,
-    program_id: &Pubkey,
-    remaining_accounts: &[AccountInfo<'info>],
-    system_program: &AccountInfo<'info>,
-    user_wallet: &AccountInfo<'info>,
-    is_last_notification: bool,  // NEW parameter
-    state: &mut Account<'info, ProgramState>, // ADICIONAR PARÂMETRO
-) -> Result<(bool, Pubkey)> {
-    msg!("🔄 Processing referrer chain for user: {}", user_key);
-    msg!("👤 Referrer: {}", referrer.key());
-    msg!("📍 Is last notification: {}", is_last_notification);
-    
-    let slot_idx = referrer.chain.filled_slots as usize;
-    if slot_idx >= 3 {
-        msg!("⚠️ Referrer matrix already full, cannot add user");
-        return Ok((false, referrer.key())); 
-    }
-
-    msg!("📍 Adding user to slot {}", slot_idx);
-    referrer.chain.slots[slot_idx] = Some(*user_key);
-
-    // Emit slot filled event
-    emit!(SlotFilled {
-        slot_idx: slot_idx as u8,
-        chain_id: referrer.chain.id,
-        user: *user_key,
-        owner: referrer.key(),
-    });
+/// Upline (pda, wallet) pairs of register_with_sol_deposit. They follow Vault A, Chainlink,
+/// the airdrop base accounts and the upline airdrop PDAs, which are counted while they are
+/// owned by the airdrop program.
+pub fn find_upline_pairs<'a, 'info>(
+    remaining_accounts: &'a [AccountInfo<'info>],
+    upline_depth: usize,
+) -> &'a [AccountInfo<'info>] {
+    const VAULT_A_COUNT: usize = 4;
+    const CHAINLINK_COUNT: usize = 2;
+
+    find_upline_pairs_after(remaining_accounts, VAULT_A_COUNT + CHAINLINK_COUNT, upline_depth)
+}
+
+/// Same as find_upline_pairs for a layout with `header_count` accounts before the airdrop
+/// accounts - register_with_token_deposit sends no Chainlink accounts.
+pub fn find_upline_pairs_after<'a, 'info>(
+    remaining_accounts: &'a [AccountInfo<'info>],
+    header_count: usize,
+    upline_depth: usize,
+) -> &'a [AccountInfo<'info>] {
+    const AIRDROP_BASE_COUNT: usize = 7;
+
+    // Sem as 36 week PDAs, upline airdrop PDAs começam após os 7 accounts do airdrop
+    let upline_airdrop_start = header_count + AIRDROP_BASE_COUNT;
+
+    // Contar PDAs do airdrop (máximo upline_depth)
+    let mut upline_airdrop_pdas_count = 0;
+    for i in 0..upline_depth {
+        let idx = upline_airdrop_start + i;
+        if idx < remaining_accounts.len() {
+            // PDAs do airdrop têm owner = AIRDROP_PROGRAM_ID
+            if remaining_accounts[idx].owner == &airdrop_addresses::AIRDROP_ACCOUNT {
+                upline_airdrop_pdas_count += 1;
+            } else {
+                break;
+            }
+        }
+    }
 
-    referrer.chain.filled_slots += 1;
-    msg!("📊 Matrix slots filled: {}/3", referrer.chain.filled_slots);
+    // Uplines (pares) começam após PDAs do airdrop
+    let upline_pairs_start = upline_airdrop_start + upline_airdrop_pdas_count;
+    if remaining_accounts.len() > upline_pairs_start {
+        &remaining_accounts[upline_pairs_start..]
+    } else {
+        &[]
+    }
+}
 
-    if referrer.chain.filled_slots == 3 {
-        msg!("🎉 Matrix completed! Notifying airdrop program...");
-        
-        // Call notify_airdrop_program with flag
-        notify_airdrop_program(
-            referrer_wallet,
-            program_id,
-            remaining_accounts,
-            system_program,
-            user_wallet,
-            is_last_notification,  // NEW: pass flag
-            state,
-        )?;
-        
-        msg!("🔄 Resetting matrix with new ID: {}", next_chain_id);
-        referrer.chain.id = next_chain_id;
-        referrer.chain.slots = [None, None, None];
-        referrer.chain.filled_slots = 0;
-        
-        msg!("✅ Matrix completion process finished");
-        return Ok((true, referrer.key()));
+/// Accounts of one stored upline entry as sent by the client: its (pda, wallet) pair, or
+/// (redirect, new_user, new_wallet) when the entry still points at the UserAccountRedirect
+/// left by migrate_user_wallet because the descendant has not refreshed its references.
+#[derive(Clone, Copy)]
+pub struct UplineAccounts<'info> {
+    pub stored: &'info AccountInfo<'info>, // Account at the stored upline pda
+    pub user: &'info AccountInfo<'info>,   // UserAccount the cascade reads and writes
+    pub wallet: &'info AccountInfo<'info>, // Wallet the cascade pays and notifies
+}
+
+impl<'info> UplineAccounts<'info> {
+    fn from_entry(entry: &'info [AccountInfo<'info>]) -> Self {
+        Self { stored: &entry[0], user: &entry[entry.len() - 2], wallet: &entry[entry.len() - 1] }
+    }
+
+    fn is_redirect(info: &AccountInfo) -> bool {
+        info.owner == &crate::ID && info.data_len() == 8 + UserAccountRedirect::SIZE
+    }
+
+    pub fn is_redirected(&self) -> bool {
+        self.stored.key != self.user.key
+    }
+
+    /// Split `count` entries off the front of `accounts`. Returns them with the number of
+    /// accounts they use.
+    pub fn split_front(accounts: &'info [AccountInfo<'info>], count: usize) -> Result<(Vec<Self>, usize)> {
+        let mut uplines = Vec::with_capacity(count);
+        let mut used = 0;
+        while uplines.len() < count {
+            let width = if accounts.get(used).is_some_and(Self::is_redirect) { 3 } else { 2 };
+            let entry = accounts.get(used..used + width).ok_or(error!(ErrorCode::MissingUplineAccount))?;
+            uplines.push(Self::from_entry(entry));
+            used += width;
+        }
+        Ok((uplines, used))
     }
 
-    msg!("📈 Matrix in progress, {} more slots needed", 3 - referrer.chain.filled_slots);
-    Ok((false, referrer.key()))
+    /// Split `count` entries off the back of `accounts`, for the instructions that send
+    /// them last. A redirect two accounts before a wallet starts a redirected entry.
+    pub fn split_back(accounts: &'info [AccountInfo<'info>], count: usize) -> Result<(Vec<Self>, usize)> {
+        let mut uplines = Vec::with_capacity(count);
+        let mut start = accounts.len();
+        while uplines.len() < count {
+            let width = if start >= 3 && Self::is_redirect(&accounts[start - 3]) { 3 } else { 2 };
+            let entry = start
+                .checked_sub(width)
+                .and_then(|entry_start| accounts.get(entry_start..start))
+                .ok_or(error!(ErrorCode::MissingUplineAccount))?;
+            uplines.push(Self::from_entry(entry));
+            start -= width;
+        }
+        uplines.reverse();
+        Ok((uplines, accounts.len() - start))
+    }
 }
 
-fn get_matrix_account_info<'a, 'b, 'c, 'info>(ctx: &Context<'a, 'b, 'c, 'info, RegisterWithSolDeposit<'info>>) -> Result<(AccountInfo<'info>, AccountInfo<'info>, AccountInfo<'info>, AccountInfo<'info>, AccountInfo<'info>, AccountInfo<'info>, AccountInfo<'info>, AccountInfo<'info>, AccountInfo<'info>, AccountInfo<'info>, AccountInfo<'info>, AccountInfo<'info>, AccountInfo<'info>)> {
-    let pool_info = ctx.accounts.pool.to_account_info();
-    let user_wallet_info = ctx.accounts.user_wallet.to_account_info();
-    let user_wsol_account_info = ctx.accounts.user_wsol_account.to_account_info();
-    let user_donut_account_info = ctx.accounts.user_donut_account.to_account_info();
-    let b_vault_info = ctx.accounts.b_vault.to_account_info();
-    let b_token_vault_info = ctx.accounts.b_token_vault.to_account_info();
-    let b_vault_lp_mint_info = ctx.accounts.b_vault_lp_mint.to_account_info();
-    let b_vault_lp_info = ctx.accounts.b_vault_lp.to_account_info();
-    let token_mint_info = ctx.accounts.token_mint.to_account_info();
-    let protocol_token_fee_info = ctx.accounts.protocol_token_fee.to_account_info();
-    let vault_program_info = ctx.accounts.vault_program.to_account_info();
-    let token_program_info = ctx.accounts.token_program.to_account_info();
-    let amm_program_info = ctx.accounts.amm_program.to_account_info();
-    
-    Ok((pool_info, user_wallet_info, user_wsol_account_info, user_donut_account_info, b_vault_info, b_token_vault_info, b_vault_lp_mint_info, b_vault_lp_info, token_mint_info, protocol_token_fee_info, vault_program_info, token_program_info, amm_program_info))
+/// Check the uplines sent by the client against the stored upline, in order. The wallet
+/// of a redirected entry is checked against its redirect by load_upline_record.
+/// A prefix of the stored upline is accepted - advance_registration walks it in batches
+/// and only settles at the configured depth. Single-transaction registrations go through
+/// cascade_upline_pairs, which requires the full list.
+pub fn validate_upline_pairs(expected_uplines: &[UplineEntry], upline_accounts: &[UplineAccounts]) -> Result<()> {
+    // Cannot send more uplines than exist
+    require!(
+        upline_accounts.len() <= expected_uplines.len(),
+        ErrorCode::InvalidUplineCount
+    );
+
+    for (upline, expected) in upline_accounts.iter().zip(expected_uplines) {
+        require!(
+            upline.stored.key() == expected.pda,
+            ErrorCode::InvalidUplineOrder
+        );
+
+        require!(
+            upline.is_redirected() || upline.wallet.key() == expected.wallet,
+            ErrorCode::InvalidUplineWallet
+        );
+    }
+
+    Ok(())
+}
+
+/// Uplines of a slot 3 registration: exactly the first min(stored upline, upline_depth)
+/// entries of the referrer's stored upline, in order. Accounts after them are extras -
+/// payout addresses and the treasury. A shorter list is rejected, so the overflow policy
+/// only applies once the configured depth is really reached.
+pub fn cascade_upline_pairs<'info>(
+    expected_uplines: &[UplineEntry],
+    upline_depth: usize,
+    upline_accounts: &'info [AccountInfo<'info>],
+) -> Result<Vec<UplineAccounts<'info>>> {
+    let pair_count = expected_uplines.len().min(upline_depth);
+    let (uplines, _) = UplineAccounts::split_front(upline_accounts, pair_count)?;
+    validate_upline_pairs(&expected_uplines[..pair_count], &uplines)?;
+    Ok(uplines)
+}
+
+/// Check that `referrer` is the direct referrer of a pending registration: the account at
+/// pending.referrer itself, or the new_user of the redirect migrate_user_wallet left there.
+/// The migrated position keeps the stored upline the cascade walks.
+pub fn verify_pending_referrer(pending_referrer: &AccountInfo, referrer: &Pubkey) -> Result<()> {
+    if pending_referrer.key == referrer {
+        return Ok(());
+    }
+
+    require!(pending_referrer.owner == &crate::ID, ErrorCode::InvalidAccountOwner);
+    let redirect = UserAccountRedirect::try_deserialize(&mut &pending_referrer.try_borrow_data()?[..])?;
+    require!(redirect.new_user == *referrer, ErrorCode::InvalidPendingRegistration);
+    Ok(())
+}
+
+// Account a pending cascade places next: the referrer's new position when the referrer
+// migrated before the first upline was processed
+fn pending_current_user(pending: &PendingRegistration, referrer: &Pubkey) -> Pubkey {
+    if pending.current_user == pending.referrer {
+        *referrer
+    } else {
+        pending.current_user
+    }
+}
+
+/// Validate an upline sent by the client and read it as a matrix engine record.
+/// Rejects repeated uplines, non-system payment wallets, foreign or legacy-sized
+/// accounts and unregistered users. A redirected entry is read from the new_user of its
+/// redirect and paid to the new wallet.
+pub fn load_upline_record<'info>(
+    upline: &UplineAccounts<'info>,
+    processed_uplines: &mut std::collections::HashSet<Pubkey>,
+) -> Result<UserRecord> {
+    // Detect exploit attempt with duplicates
+    require!(
+        processed_uplines.insert(upline.stored.key()),
+        ErrorCode::DuplicateUplineExploit
+    );
+
+    let upline_info = if upline.is_redirected() {
+        require!(upline.stored.owner == &crate::ID, ErrorCode::InvalidSlotOwner);
+        let redirect = UserAccountRedirect::try_deserialize(&mut &upline.stored.try_borrow_data()?[..])?;
+        require!(upline.user.key() == redirect.new_user, ErrorCode::InvalidUplineOrder);
+        require!(upline.wallet.key() == redirect.new_wallet, ErrorCode::InvalidUplineWallet);
+        require!(
+            processed_uplines.insert(upline.user.key()),
+            ErrorCode::DuplicateUplineExploit
+        );
+        upline.user
+    } else {
+        upline.stored
+    };
+    let upline_wallet = upline.wallet;
+
+    if upline_wallet.owner != &solana_program::system_program::ID {
+        return Err(error!(ErrorCode::PaymentWalletInvalid));
+    }
+
+    if !upline_info.owner.eq(&crate::ID) {
+        return Err(error!(ErrorCode::InvalidSlotOwner));
+    }
+
+    if upline_info.data_len() != 8 + UserAccount::SIZE {
+        return Err(error!(ErrorCode::UserAccountNeedsResize));
+    }
+
+    let upline_loader = AccountLoader::<UserAccount>::try_from(upline_info)?;
+    let upline_account_data = upline_loader.load()?;
+
+    if !upline_account_data.is_registered() {
+        return Err(error!(ErrorCode::SlotNotRegistered));
+    }
+
+    Ok(UserRecord::new(upline_info.key(), upline_wallet.key(), &upline_account_data))
 }
 
 // Accounts for initialize instruction
@@ -1471,6 +2562,7 @@ pub struct Initialize<'info> {
 }
 
 // Accounts for registration without referrer - OPTIMIZED WITH UncheckedAccount
+#[event_cpi]
 #[derive(Accounts)]
 #[instruction(deposit_amount: u64)]
 pub struct RegisterWithoutReferrerDeposit<'info> {
@@ -1490,7 +2582,7 @@ pub struct RegisterWithoutReferrerDeposit<'info> {
         seeds = [b"user_account", user_wallet.key().as_ref()],
         bump
     )]
-    pub user: Box<Account<'info, UserAccount>>,
+    pub user: AccountLoader<'info, UserAccount>,
 
     /// User's WSOL account - Using UncheckedAccount to save stack space
     /// CHECK: This account is validated by the token program during operations
@@ -1551,6 +2643,7 @@ pub struct RegisterWithoutReferrerDeposit<'info> {
 }
 
 // Structure for registration with SOL in a single transaction - OPTIMIZED
+#[event_cpi]
 #[derive(Accounts)]
 #[instruction(deposit_amount: u64)]
 pub struct RegisterWithSolDeposit<'info> {
@@ -1561,10 +2654,17 @@ pub struct RegisterWithSolDeposit<'info> {
     pub user_wallet: Signer<'info>,
 
     // Reference accounts
-    #[account(mut)]
-    pub referrer: Box<Account<'info, UserAccount>>,
+    #[account(
+        mut,
+        constraint = referrer.to_account_info().data_len() == 8 + UserAccount::SIZE @ ErrorCode::UserAccountNeedsResize
+    )]
+    pub referrer: AccountLoader<'info, UserAccount>,
     
-    #[account(mut)]
+    // Paid the referrer's reserve when it has no payout address
+    #[account(
+        mut,
+        constraint = referrer.load()?.owner_wallet == referrer_wallet.key() @ ErrorCode::InvalidAccountOwner
+    )]
     pub referrer_wallet: SystemAccount<'info>,
 
     // User account
@@ -1575,7 +2675,7 @@ pub struct RegisterWithSolDeposit<'info> {
         seeds = [b"user_account", user_wallet.key().as_ref()],
         bump
     )]
-    pub user: Box<Account<'info, UserAccount>>,
+    pub user: AccountLoader<'info, UserAccount>,
 
     // WSOL ATA account - Using UncheckedAccount
     /// CHECK: This account is validated by the token program during operations
@@ -1649,1094 +2749,3761 @@ pub struct RegisterWithSolDeposit<'info> {
     // For slot 3 only:
     // [6..12] - Airdrop accounts (7 accounts: program_state, user_account, current_week, next_week, referrer_wallet, airdrop_program, instructions_sysvar)
     // [13..18] - Upline Airdrop PDAs (up to 6 PDAs)
-    // [19+] - Upline accounts (pairs of account_pda, wallet_account)
+    // [19+] - Upline accounts (pairs of account_pda, wallet_account; a stale entry of a
+    //         migrated user is sent as redirect, new_user, new_wallet)
+    //
+    // In InviteSigner allowlist mode the instructions sysvar is found by key - append it
+    // when the airdrop accounts are not passed.
 }
 
-// HELPER FUNCTIONS TO REDUCE STACK USAGE
+// First step of the multi-instruction registration flow - same accounts as
+// RegisterWithSolDeposit plus the pending registration PDA
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(deposit_amount: u64)]
+pub struct BeginRegistration<'info> {
+    #[account(mut)]
+    pub state: Box<Account<'info, ProgramState>>,
 
-// Helper: Validate base registration
-fn validate_base_registration<'info>(
-    owner: &Pubkey,
-    multisig_treasury: &Pubkey,
-    pool: &Pubkey,
-    b_vault: &Pubkey,
-    b_token_vault: &Pubkey,
-    b_vault_lp_mint: &Pubkey,
-    b_vault_lp: &Pubkey,
-    token_mint: &Pubkey,
-    wsol_mint: &Pubkey,
-    vault_program: &Pubkey,
-    amm_program: &Pubkey,
-    protocol_token_fee: &Pubkey,
-) -> Result<()> {
-    // Verify authorization
-    if owner != multisig_treasury {
-        return Err(error!(ErrorCode::NotAuthorized));
-    }
-    
-    // Verify all addresses
-    verify_all_fixed_addresses(
-        pool,
-        b_vault,
-        b_token_vault,
-        b_vault_lp_mint,
-        b_vault_lp,
-        token_mint,
-        wsol_mint,
-    )?;
-    
-    // Verify programs
-    verify_address_strict(vault_program, &verified_addresses::METEORA_VAULT_PROGRAM, ErrorCode::InvalidVaultProgram)?;
-    verify_address_strict(amm_program, &verified_addresses::METEORA_AMM_PROGRAM, ErrorCode::InvalidAmmProgram)?;
-    verify_address_strict(protocol_token_fee, &verified_addresses::PROTOCOL_TOKEN_B_FEE, ErrorCode::InvalidProtocolFeeAccount)?;
-    
-    Ok(())
-}
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
 
-// Helper: Initialize base user data
-fn initialize_base_user_data(
-    user: &mut Account<UserAccount>,
-    user_wallet: &Pubkey,
-    upline_id: u32,
-    chain_id: u32,
-) -> Result<()> {
-    user.is_registered = true;
-    user.referrer = None;
-    user.owner_wallet = *user_wallet;
-    user.upline = ReferralUpline {
-        id: upline_id,
-        depth: 1,
-        upline: vec![],
-    };
-    user.chain = ReferralChain {
-        id: chain_id,
-        slots: [None, None, None],
-        filled_slots: 0,
-    };
-    user.reserved_sol = 0;
-    
-    Ok(())
-}
+    // Reference accounts
+    #[account(
+        mut,
+        constraint = referrer.to_account_info().data_len() == 8 + UserAccount::SIZE @ ErrorCode::UserAccountNeedsResize
+    )]
+    pub referrer: AccountLoader<'info, UserAccount>,
 
-// Helper: Process wrap SOL to WSOL
-fn wrap_sol_to_wsol<'info>(
-    user_wallet: &AccountInfo<'info>,
-    user_wsol_account: &AccountInfo<'info>,
-    amount: u64,
-) -> Result<()> {
-    // Transfer SOL to WSOL account
-    let transfer_ix = solana_program::system_instruction::transfer(
-        &user_wallet.key(),
-        &user_wsol_account.key(),
-        amount
-    );
-    
-    solana_program::program::invoke(
-        &transfer_ix,
-        &[user_wallet.clone(), user_wsol_account.clone()],
-    ).map_err(|_| error!(ErrorCode::WrapSolFailed))?;
-    
-    // Sync native balance
-    let sync_native_ix = spl_token::instruction::sync_native(
-        &token::ID,
-        &user_wsol_account.key(),
-    )?;
-    
-    solana_program::program::invoke(
-        &sync_native_ix,
-        &[user_wsol_account.clone()],
-    ).map_err(|_| error!(ErrorCode::WrapSolFailed))?;
-    
-    Ok(())
-}
+    // Paid the referrer's reserve when it has no payout address
+    #[account(
+        mut,
+        constraint = referrer.load()?.owner_wallet == referrer_wallet.key() @ ErrorCode::InvalidAccountOwner
+    )]
+    pub referrer_wallet: SystemAccount<'info>,
 
-// Helper: Extract vault A accounts from remaining accounts
-struct VaultAAccounts<'a, 'info> {
-    a_vault: &'a AccountInfo<'info>,
-    a_vault_lp: &'a AccountInfo<'info>,
-    a_vault_lp_mint: &'a AccountInfo<'info>,
-    a_token_vault: &'a AccountInfo<'info>,
-}
+    // User account
+    #[account(
+        init,
+        payer = user_wallet,
+        space = 8 + UserAccount::SIZE,
+        seeds = [b"user_account", user_wallet.key().as_ref()],
+        bump
+    )]
+    pub user: AccountLoader<'info, UserAccount>,
 
-fn extract_and_verify_vault_a_accounts<'a, 'info>(
-    remaining_accounts: &'a [AccountInfo<'info>]
-) -> Result<VaultAAccounts<'a, 'info>> {
-    if remaining_accounts.len() < VAULT_A_ACCOUNTS_COUNT {
-        return Err(error!(ErrorCode::MissingVaultAAccounts));
-    }
-    
-    let accounts = VaultAAccounts {
-        a_vault: &remaining_accounts[0],
-        a_vault_lp: &remaining_accounts[1],
-        a_vault_lp_mint: &remaining_accounts[2],
-        a_token_vault: &remaining_accounts[3],
-    };
-    
-    // Verify addresses
-    verify_vault_a_addresses(
-        &accounts.a_vault.key(),
-        &accounts.a_vault_lp.key(),
-        &accounts.a_vault_lp_mint.key(),
-        &accounts.a_token_vault.key()
-    )?;
-    
-    Ok(accounts)
-}
+    // Pending registration - holds the deposit while the upline cascade is processed
+    #[account(
+        init,
+        payer = user_wallet,
+        space = 8 + PendingRegistration::SIZE,
+        seeds = [b"pending_registration", user_wallet.key().as_ref()],
+        bump
+    )]
+    pub pending: Box<Account<'info, PendingRegistration>>,
 
-#[program]
-pub mod referral_system {
-    use super::*;
+    // WSOL ATA account - Using UncheckedAccount
+    /// CHECK: This account is validated by the token program during operations
+    #[account(mut)]
+    pub user_wsol_account: UncheckedAccount<'info>,
 
-    // Initialize program state
-    pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
-        if ctx.accounts.owner.key() != admin_addresses::AUTHORIZED_INITIALIZER {
-            return Err(error!(ErrorCode::NotAuthorized));
-        }
+    // Account to receive DONUT tokens - Using UncheckedAccount
+    /// CHECK: This account is validated by the token program during operations
+    #[account(mut)]
+    pub user_donut_account: UncheckedAccount<'info>,
 
-        let state = &mut ctx.accounts.state;
-        state.owner = ctx.accounts.owner.key();
-        state.multisig_treasury = admin_addresses::MULTISIG_TREASURY;
-        state.next_upline_id = 1;
-        state.next_chain_id = 1;
-        state.airdrop_active = true;    
-        state.airdrop_end_timestamp = 0;     
-        
-        Ok(())
-    }
+    // WSOL mint
+    /// CHECK: This is the fixed WSOL mint address
+    pub wsol_mint: AccountInfo<'info>,
 
-    // Register without referrer - optimized version
-    pub fn register_without_referrer<'a, 'b, 'c, 'info>(
-        ctx: Context<'a, 'b, 'c, 'info, RegisterWithoutReferrerDeposit<'info>>, 
-        deposit_amount: u64
-    ) -> Result<()> {
-        // Step 1: Validate registration
-        validate_base_registration(
-            &ctx.accounts.owner.key(),
-            &ctx.accounts.state.multisig_treasury,
-            &ctx.accounts.pool.key(),
-            &ctx.accounts.b_vault.key(),
-            &ctx.accounts.b_token_vault.key(),
-            &ctx.accounts.b_vault_lp_mint.key(),
-            &ctx.accounts.b_vault_lp.key(),
-            &ctx.accounts.token_mint.key(),
-            &ctx.accounts.wsol_mint.key(),
-            &ctx.accounts.vault_program.key(),
-            &ctx.accounts.amm_program.key(),
-            &ctx.accounts.protocol_token_fee.key(),
-        )?;
-        
-        // Step 2: Update state and get IDs
-        let (upline_id, chain_id) = {
-            let state = &mut ctx.accounts.state;
-            let uid = state.next_upline_id;
-            let cid = state.next_chain_id;
-            state.next_upline_id += 1;
-            state.next_chain_id += 1;
-            (uid, cid)
-        };
-        
-        // Step 3: Initialize user
-        initialize_base_user_data(
-            &mut ctx.accounts.user,
-            &ctx.accounts.user_wallet.key(),
-            upline_id,
-            chain_id,
-        )?;
-        
-        // Step 4: Extract and verify vault A accounts
-        let vault_a = extract_and_verify_vault_a_accounts(&ctx.remaining_accounts)?;
-        
-        // Step 5: Wrap SOL to WSOL
-        wrap_sol_to_wsol(
-            &ctx.accounts.user_wallet.to_account_info(),
-            &ctx.accounts.user_wsol_account.to_account_info(),
-            deposit_amount,
-        )?;
-        
-        // Step 6: Process swap and burn
-        process_swap_and_burn(
-            &ctx.accounts.pool.to_account_info(),
-            &ctx.accounts.user_wallet.to_account_info(),
-            &ctx.accounts.user_wsol_account.to_account_info(),
-            &ctx.accounts.user_donut_account.to_account_info(),
-            vault_a.a_vault,
-            &ctx.accounts.b_vault.to_account_info(),
-            vault_a.a_token_vault,
-            &ctx.accounts.b_token_vault.to_account_info(),
-            vault_a.a_vault_lp_mint,
-            &ctx.accounts.b_vault_lp_mint.to_account_info(),
-            vault_a.a_vault_lp,
-            &ctx.accounts.b_vault_lp.to_account_info(),
-            &ctx.accounts.token_mint.to_account_info(),
-            &ctx.accounts.protocol_token_fee.to_account_info(),
-            &ctx.accounts.vault_program.to_account_info(),
-            &ctx.accounts.token_program.to_account_info(),
-            &ctx.accounts.amm_program.to_account_info(),
-            deposit_amount
-        )?;
-        
-        Ok(())
-    }
+    // Deposit Accounts (Slot 1 and 3)
+    /// CHECK: Pool account (PDA)
+    #[account(mut)]
+    pub pool: UncheckedAccount<'info>,
 
-// Register with referrer
-pub fn register_with_sol_deposit<'a, 'b, 'c, 'info>(
-    ctx: Context<'a, 'b, 'c, 'info, RegisterWithSolDeposit<'info>>, 
-    deposit_amount: u64
-) -> Result<()> {
-    msg!("🚀 Starting user registration with SOL deposit");
-    msg!("👤 User wallet: {}", ctx.accounts.user_wallet.key());
-    msg!("👤 Referrer wallet: {}", ctx.accounts.referrer_wallet.key());
-    msg!("💰 Deposit amount: {} lamports", deposit_amount);
-    msg!("📊 Remaining accounts count: {}", ctx.remaining_accounts.len());
-    msg!("🎯 Matrix program ID: {}", ctx.program_id);
-    msg!("🎯 Airdrop program ID: {}", AIRDROP_PROGRAM_ID);
-    
-    // Check if referrer is registered
-    if !ctx.accounts.referrer.is_registered {
-        msg!("❌ Referrer is not registered");
-        return Err(error!(ErrorCode::ReferrerNotRegistered));
+    /// CHECK: Vault account for token B (SOL)
+    #[account(mut)]
+    pub b_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Token vault account for token B (SOL)
+    #[account(mut)]
+    pub b_token_vault: UncheckedAccount<'info>,
+
+    /// CHECK: LP token mint for vault B
+    #[account(mut)]
+    pub b_vault_lp_mint: UncheckedAccount<'info>,
+
+    /// CHECK: LP token account for vault B
+    #[account(mut)]
+    pub b_vault_lp: UncheckedAccount<'info>,
+
+    /// CHECK: Vault program
+    pub vault_program: UncheckedAccount<'info>,
+
+    // Accounts for SOL reserve (Slot 2)
+    #[account(
+        mut,
+        seeds = [b"program_sol_vault"],
+        bump
+    )]
+    pub program_sol_vault: SystemAccount<'info>,
+
+    /// CHECK: Token mint for token operations
+    #[account(mut)]
+    pub token_mint: UncheckedAccount<'info>,
+
+    /// CHECK: Protocol fee account for Meteora
+    #[account(mut)]
+    pub protocol_token_fee: UncheckedAccount<'info>,
+
+    /// CHECK: Meteora Dynamic AMM program
+    pub amm_program: UncheckedAccount<'info>,
+
+    // Required programs
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+
+    // remaining_accounts:
+    // [0..3] - Vault A accounts (a_vault, a_vault_lp, a_vault_lp_mint, a_token_vault)
+    // [4..5] - Chainlink accounts (chainlink_feed, chainlink_program)
+    // For slot 3 only:
+    // [6..12] - Airdrop accounts for the direct referrer (same layout as RegisterWithSolDeposit)
+}
+
+// Following steps of the multi-instruction registration flow
+#[event_cpi]
+#[derive(Accounts)]
+pub struct AdvanceRegistration<'info> {
+    #[account(mut)]
+    pub state: Box<Account<'info, ProgramState>>,
+
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"pending_registration", user_wallet.key().as_ref()],
+        bump = pending.bump,
+        constraint = pending.user_wallet == user_wallet.key() @ ErrorCode::InvalidPendingRegistration
+    )]
+    pub pending: Box<Account<'info, PendingRegistration>>,
+
+    /// CHECK: Account at pending.referrer - the direct referrer, or the UserAccountRedirect
+    /// left there by migrate_user_wallet. Checked against referrer in the handler.
+    #[account(
+        constraint = pending_referrer.key() == pending.referrer @ ErrorCode::InvalidPendingRegistration
+    )]
+    pub pending_referrer: UncheckedAccount<'info>,
+
+    // Direct referrer, at its new position if it has migrated - only its stored upline is read
+    #[account(
+        constraint = referrer.to_account_info().data_len() == 8 + UserAccount::SIZE @ ErrorCode::UserAccountNeedsResize
+    )]
+    pub referrer: AccountLoader<'info, UserAccount>,
+
+    /// CHECK: This account is validated by the token program during operations
+    #[account(mut)]
+    pub user_wsol_account: UncheckedAccount<'info>,
+
+    /// CHECK: This account is validated by the token program during operations
+    #[account(mut)]
+    pub user_donut_account: UncheckedAccount<'info>,
+
+    /// CHECK: This is the fixed WSOL mint address
+    pub wsol_mint: AccountInfo<'info>,
+
+    /// CHECK: Pool account (PDA)
+    #[account(mut)]
+    pub pool: UncheckedAccount<'info>,
+
+    /// CHECK: Vault account for token B (SOL)
+    #[account(mut)]
+    pub b_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Token vault account for token B (SOL)
+    #[account(mut)]
+    pub b_token_vault: UncheckedAccount<'info>,
+
+    /// CHECK: LP token mint for vault B
+    #[account(mut)]
+    pub b_vault_lp_mint: UncheckedAccount<'info>,
+
+    /// CHECK: LP token account for vault B
+    #[account(mut)]
+    pub b_vault_lp: UncheckedAccount<'info>,
+
+    /// CHECK: Vault program
+    pub vault_program: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"program_sol_vault"],
+        bump
+    )]
+    pub program_sol_vault: SystemAccount<'info>,
+
+    /// CHECK: Token mint for token operations
+    #[account(mut)]
+    pub token_mint: UncheckedAccount<'info>,
+
+    /// CHECK: Protocol fee account for Meteora
+    #[account(mut)]
+    pub protocol_token_fee: UncheckedAccount<'info>,
+
+    /// CHECK: Meteora Dynamic AMM program
+    pub amm_program: UncheckedAccount<'info>,
+
+    // Required programs
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+
+    // remaining_accounts:
+    // [0..3] - Vault A accounts (a_vault, a_vault_lp, a_vault_lp_mint, a_token_vault)
+    // [4..]  - Airdrop accounts needed by completing uplines (program_state, week PDAs,
+    //          airdrop user PDAs, airdrop program, instructions sysvar), payout accounts
+    //          and the treasury for the Treasury policy and the held protocol fee
+    // Last accounts - pair_count upline pairs (account_pda, wallet_account), starting
+    //          at pending.next_upline_index in the referrer's stored upline; a stale
+    //          entry of a migrated user is sent as redirect, new_user, new_wallet
+}
+
+// Accounts for settling an expired pending registration - permissionless, the cranker
+// pays the airdrop notifications
+#[event_cpi]
+#[derive(Accounts)]
+pub struct FinishPendingRegistration<'info> {
+    #[account(mut)]
+    pub state: Box<Account<'info, ProgramState>>,
+
+    #[account(mut)]
+    pub cranker: Signer<'info>,
+
+    // Registering wallet - receives refunds and the pending PDA rent
+    #[account(mut)]
+    pub user_wallet: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"pending_registration", user_wallet.key().as_ref()],
+        bump = pending.bump,
+        constraint = pending.user_wallet == user_wallet.key() @ ErrorCode::InvalidPendingRegistration
+    )]
+    pub pending: Box<Account<'info, PendingRegistration>>,
+
+    /// CHECK: Account at pending.referrer - the direct referrer, or the UserAccountRedirect
+    /// left there by migrate_user_wallet. Checked against referrer in the handler.
+    #[account(
+        constraint = pending_referrer.key() == pending.referrer @ ErrorCode::InvalidPendingRegistration
+    )]
+    pub pending_referrer: UncheckedAccount<'info>,
+
+    // Direct referrer, at its new position if it has migrated - only its stored upline is read
+    #[account(
+        constraint = referrer.to_account_info().data_len() == 8 + UserAccount::SIZE @ ErrorCode::UserAccountNeedsResize
+    )]
+    pub referrer: AccountLoader<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"program_sol_vault"],
+        bump
+    )]
+    pub program_sol_vault: SystemAccount<'info>,
+
+    // Burns are held here for retry_pending_burns - the cranker has no WSOL account to swap from
+    #[account(
+        mut,
+        seeds = [b"burn_escrow"],
+        bump
+    )]
+    pub burn_escrow: SystemAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    // remaining_accounts:
+    // [0..]  - Airdrop accounts needed by completing uplines, payout accounts and the
+    //          treasury for the Treasury policy and the held protocol fee
+    // Last accounts - Upline pairs (account_pda, wallet_account) from
+    //          pending.next_upline_index to the end of the referrer's stored upline,
+    //          at most the configured depth; redirected entries as for advance_registration
+}
+
+// Accounts for converting a legacy Borsh UserAccount to the zero-copy layout
+#[derive(Accounts)]
+pub struct MigrateUserAccount<'info> {
+    /// CHECK: Legacy UserAccount - discriminator, PDA and layout are validated in the handler
+    #[account(
+        mut,
+        owner = crate::ID @ ErrorCode::InvalidAccountOwner
+    )]
+    pub user: UncheckedAccount<'info>,
+
+    // Pays the rent difference for the larger account
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// Accounts for admin configuration of the upline depth
+#[derive(Accounts)]
+pub struct SetUplineDepth<'info> {
+    #[account(
+        mut,
+        constraint = state.owner == owner.key() @ ErrorCode::NotAuthorized
+    )]
+    pub state: Account<'info, ProgramState>,
+
+    pub owner: Signer<'info>,
+}
+
+// Accounts for configuring the registration allowlist
+#[derive(Accounts)]
+pub struct SetAllowlist<'info> {
+    #[account(
+        mut,
+        constraint = state.owner == owner.key() @ ErrorCode::NotAuthorized
+    )]
+    pub state: Account<'info, ProgramState>,
+
+    pub owner: Signer<'info>,
+}
+
+// Accounts for scheduling, extending and closing a campaign
+#[derive(Accounts)]
+pub struct ManageCampaign<'info> {
+    #[account(
+        mut,
+        constraint = state.owner == owner.key() @ ErrorCode::NotAuthorized
+    )]
+    pub state: Account<'info, ProgramState>,
+
+    pub owner: Signer<'info>,
+}
+
+// Accounts for configuring the referrer limits
+#[derive(Accounts)]
+pub struct SetReferrerLimits<'info> {
+    #[account(
+        mut,
+        constraint = state.owner == owner.key() @ ErrorCode::NotAuthorized
+    )]
+    pub state: Account<'info, ProgramState>,
+
+    pub owner: Signer<'info>,
+}
+
+// Accounts for configuring the protocol fee
+#[derive(Accounts)]
+pub struct SetProtocolFee<'info> {
+    #[account(
+        mut,
+        constraint = state.owner == owner.key() @ ErrorCode::NotAuthorized
+    )]
+    pub state: Account<'info, ProgramState>,
+
+    pub owner: Signer<'info>,
+}
+
+// Accounts for toggling the burn escrow fallback
+#[derive(Accounts)]
+pub struct SetBurnEscrow<'info> {
+    #[account(
+        mut,
+        constraint = state.owner == owner.key() @ ErrorCode::NotAuthorized
+    )]
+    pub state: Account<'info, ProgramState>,
+
+    pub owner: Signer<'info>,
+}
+
+// Accounts for swapping and burning the escrowed deposits - permissionless, the
+// cranker only pays the transaction fee
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RetryPendingBurns<'info> {
+    #[account(mut)]
+    pub state: Box<Account<'info, ProgramState>>,
+
+    pub cranker: Signer<'info>,
+
+    // Holds the escrowed lamports and signs the swap and burn
+    #[account(
+        mut,
+        seeds = [b"burn_escrow"],
+        bump
+    )]
+    pub burn_escrow: SystemAccount<'info>,
+
+    // WSOL ATA of the burn escrow - must exist before the crank
+    /// CHECK: Address is checked, the account is validated by the token program
+    #[account(
+        mut,
+        address = anchor_spl::associated_token::get_associated_token_address(&burn_escrow.key(), &verified_addresses::WSOL_MINT)
+    )]
+    pub escrow_wsol_account: UncheckedAccount<'info>,
+
+    // DONUT ATA of the burn escrow - must exist before the crank
+    /// CHECK: Address is checked, the account is validated by the token program
+    #[account(
+        mut,
+        address = anchor_spl::associated_token::get_associated_token_address(&burn_escrow.key(), &verified_addresses::TOKEN_MINT)
+    )]
+    pub escrow_donut_account: UncheckedAccount<'info>,
+
+    /// CHECK: Pool account (PDA)
+    #[account(mut)]
+    pub pool: UncheckedAccount<'info>,
+
+    /// CHECK: Vault account for token B (SOL)
+    #[account(mut)]
+    pub b_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Token vault account for token B (SOL)
+    #[account(mut)]
+    pub b_token_vault: UncheckedAccount<'info>,
+
+    /// CHECK: LP token mint for vault B
+    #[account(mut)]
+    pub b_vault_lp_mint: UncheckedAccount<'info>,
+
+    /// CHECK: LP token account for vault B
+    #[account(mut)]
+    pub b_vault_lp: UncheckedAccount<'info>,
+
+    /// CHECK: Vault program
+    pub vault_program: UncheckedAccount<'info>,
+
+    /// CHECK: Token mint for token operations
+    #[account(mut)]
+    pub token_mint: UncheckedAccount<'info>,
+
+    /// CHECK: Protocol fee account for Meteora
+    #[account(mut)]
+    pub protocol_token_fee: UncheckedAccount<'info>,
+
+    /// CHECK: Meteora Dynamic AMM program
+    pub amm_program: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+
+    // remaining_accounts:
+    // [0..3] - Vault A accounts (a_vault, a_vault_lp, a_vault_lp_mint, a_token_vault)
+}
+
+// Accounts for growing ProgramState after new fields were appended
+#[derive(Accounts)]
+pub struct ResizeProgramState<'info> {
+    /// CHECK: May still have the previous layout - owner is read from the raw data
+    #[account(
+        mut,
+        owner = crate::ID @ ErrorCode::InvalidAccountOwner
+    )]
+    pub state: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// Accounts for growing a zero-copy UserAccount to the current upline capacity
+#[derive(Accounts)]
+pub struct ResizeUserAccount<'info> {
+    /// CHECK: Zero-copy UserAccount with a smaller upline array - validated in the handler
+    #[account(
+        mut,
+        owner = crate::ID @ ErrorCode::InvalidAccountOwner
+    )]
+    pub user: UncheckedAccount<'info>,
+
+    // Pays the rent difference for the larger account
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// Accounts for the read-only vault solvency check
+#[derive(Accounts)]
+pub struct AuditVault<'info> {
+    pub state: Account<'info, ProgramState>,
+
+    #[account(
+        seeds = [b"program_sol_vault"],
+        bump
+    )]
+    pub program_sol_vault: SystemAccount<'info>,
+}
+
+// Accounts for moving the vault surplus to the multisig treasury
+#[derive(Accounts)]
+pub struct SweepVaultSurplus<'info> {
+    #[account(
+        constraint = state.owner == owner.key() @ ErrorCode::NotAuthorized
+    )]
+    pub state: Account<'info, ProgramState>,
+
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"program_sol_vault"],
+        bump
+    )]
+    pub program_sol_vault: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        address = state.multisig_treasury @ ErrorCode::MissingTreasuryAccount
+    )]
+    pub multisig_treasury: SystemAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// Accounts for seeding the tracked reserved total
+#[derive(Accounts)]
+pub struct ReconcileVault<'info> {
+    #[account(
+        mut,
+        constraint = state.owner == owner.key() @ ErrorCode::NotAuthorized
+    )]
+    pub state: Account<'info, ProgramState>,
+
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [b"program_sol_vault"],
+        bump
+    )]
+    pub program_sol_vault: SystemAccount<'info>,
+}
+
+// Accounts for closing an unused UserAccount and returning its rent to the wallet
+#[derive(Accounts)]
+pub struct CloseUserAccount<'info> {
+    /// CHECK: UserAccount read in the handler - an AccountLoader would rewrite the
+    /// UserAccount discriminator over the tombstone on exit
+    #[account(
+        mut,
+        seeds = [b"user_account", owner_wallet.key().as_ref()],
+        bump,
+        owner = crate::ID @ ErrorCode::InvalidAccountOwner
+    )]
+    pub user: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub owner_wallet: Signer<'info>,
+
+    /// CHECK: Must not exist - a pending registration still places this user upline
+    #[account(
+        seeds = [b"pending_registration", owner_wallet.key().as_ref()],
+        bump
+    )]
+    pub pending_registration: UncheckedAccount<'info>,
+}
+
+// Accounts for moving a UserAccount position to a new wallet
+#[derive(Accounts)]
+pub struct MigrateUserWallet<'info> {
+    pub state: Box<Account<'info, ProgramState>>,
+
+    /// CHECK: UserAccount read in the handler and replaced by a UserAccountRedirect
+    #[account(
+        mut,
+        seeds = [b"user_account", owner_wallet.key().as_ref()],
+        bump,
+        owner = crate::ID @ ErrorCode::InvalidAccountOwner
+    )]
+    pub user: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub owner_wallet: Signer<'info>,
+
+    // Signs to prove control of the wallet that receives future payouts
+    #[account(mut)]
+    pub new_wallet: Signer<'info>,
+
+    #[account(
+        init,
+        payer = new_wallet,
+        space = 8 + UserAccount::SIZE,
+        seeds = [b"user_account", new_wallet.key().as_ref()],
+        bump
+    )]
+    pub new_user: AccountLoader<'info, UserAccount>,
+
+    /// CHECK: Must not exist - a pending registration still places this user upline
+    #[account(
+        seeds = [b"pending_registration", owner_wallet.key().as_ref()],
+        bump
+    )]
+    pub pending_registration: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    // remaining_accounts while the airdrop is active:
+    // [0] - airdrop user account of new_wallet
+}
+
+// Accounts for pointing a UserAccount's references at migrated positions
+#[derive(Accounts)]
+pub struct RefreshUserReferences<'info> {
+    #[account(
+        mut,
+        constraint = user.to_account_info().data_len() == 8 + UserAccount::SIZE @ ErrorCode::UserAccountNeedsResize
+    )]
+    pub user: AccountLoader<'info, UserAccount>,
+
+    // remaining_accounts: UserAccountRedirect accounts, applied in order
+}
+
+// Accounts for setting the wallet that receives a user's reserve payments
+#[derive(Accounts)]
+pub struct SetPayoutAddress<'info> {
+    #[account(
+        mut,
+        seeds = [b"user_account", owner_wallet.key().as_ref()],
+        bump,
+        constraint = user.to_account_info().data_len() == 8 + UserAccount::SIZE @ ErrorCode::UserAccountNeedsResize
+    )]
+    pub user: AccountLoader<'info, UserAccount>,
+
+    pub owner_wallet: Signer<'info>,
+
+    // Must be a system account or every cascade paying this user would fail.
+    // owner_wallet clears the payout address.
+    pub payout_address: SystemAccount<'info>,
+}
+
+// Accounts for adding or updating a stablecoin accepted by register_with_token_deposit
+#[derive(Accounts)]
+#[instruction(token: u8)]
+pub struct ConfigureDepositToken<'info> {
+    #[account(
+        constraint = state.owner == owner.key() @ ErrorCode::NotAuthorized
+    )]
+    pub state: Account<'info, ProgramState>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + TokenDepositConfig::SIZE,
+        seeds = [b"token_deposit".as_ref(), &[token]],
+        bump
+    )]
+    pub deposit_config: Account<'info, TokenDepositConfig>,
+
+    // Reserves of the token - only the config PDA can move them
+    #[account(
+        init_if_needed,
+        payer = owner,
+        seeds = [b"token_reserve_vault".as_ref(), &[token]],
+        bump,
+        token::mint = mint,
+        token::authority = deposit_config
+    )]
+    pub token_reserve_vault: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, Mint>,
+
+    // Meteora DONUT/token pool and its vault B accounts - stored in the config and
+    // required by address at every deposit
+    /// CHECK: Pool account
+    pub pool: UncheckedAccount<'info>,
+
+    /// CHECK: Vault account for token B
+    pub b_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Token vault account for token B
+    pub b_token_vault: UncheckedAccount<'info>,
+
+    /// CHECK: LP token mint for vault B
+    pub b_vault_lp_mint: UncheckedAccount<'info>,
+
+    /// CHECK: LP token account for vault B
+    pub b_vault_lp: UncheckedAccount<'info>,
+
+    /// CHECK: Protocol fee account of the pool for token B
+    pub protocol_token_fee: UncheckedAccount<'info>,
+
+    // The pool's LP token account of the shared DONUT vault A
+    #[account(
+        constraint = a_vault_lp.mint == verified_addresses::A_VAULT_LP_MINT @ ErrorCode::InvalidVaultAddress,
+        constraint = a_vault_lp.owner == pool.key() @ ErrorCode::InvalidVaultAddress
+    )]
+    pub a_vault_lp: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+// Structure for registration with a configured deposit token in a single transaction.
+// There is no multi-instruction variant - the whole cascade must fit in one transaction.
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(deposit_amount: u64, token: u8)]
+pub struct RegisterWithTokenDeposit<'info> {
+    #[account(mut)]
+    pub state: Box<Account<'info, ProgramState>>,
+
+    #[account(mut)]
+    pub user_wallet: Signer<'info>,
+
+    // Reference accounts
+    #[account(
+        mut,
+        constraint = referrer.to_account_info().data_len() == 8 + UserAccount::SIZE @ ErrorCode::UserAccountNeedsResize
+    )]
+    pub referrer: AccountLoader<'info, UserAccount>,
+
+    // Paid the referrer's reserve when it has no payout address
+    #[account(
+        mut,
+        constraint = referrer.load()?.owner_wallet == referrer_wallet.key() @ ErrorCode::InvalidAccountOwner
+    )]
+    pub referrer_wallet: SystemAccount<'info>,
+
+    // User account
+    #[account(
+        init,
+        payer = user_wallet,
+        space = 8 + UserAccount::SIZE,
+        seeds = [b"user_account", user_wallet.key().as_ref()],
+        bump
+    )]
+    pub user: AccountLoader<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"token_deposit".as_ref(), &[token]],
+        bump = deposit_config.bump
+    )]
+    pub deposit_config: Box<Account<'info, TokenDepositConfig>>,
+
+    // Token account paying the deposit
+    /// CHECK: Mint and owner are validated in the handler
+    #[account(mut)]
+    pub user_token_account: UncheckedAccount<'info>,
+
+    // Accounts for the token reserve (Slot 2)
+    /// CHECK: Token account PDA created by configure_deposit_token
+    #[account(
+        mut,
+        seeds = [b"token_reserve_vault".as_ref(), &[token]],
+        bump = deposit_config.vault_bump
+    )]
+    pub token_reserve_vault: UncheckedAccount<'info>,
+
+    // Account to receive DONUT tokens - Using UncheckedAccount
+    /// CHECK: This account is validated by the token program during operations
+    #[account(mut)]
+    pub user_donut_account: UncheckedAccount<'info>,
+
+    // Deposit Accounts (Slot 1 and 3) - the pool configured for the token
+    /// CHECK: Pool account
+    #[account(mut, address = deposit_config.pool @ ErrorCode::InvalidPoolAddress)]
+    pub pool: UncheckedAccount<'info>,
+
+    /// CHECK: Vault account for token B
+    #[account(mut, address = deposit_config.b_vault @ ErrorCode::InvalidVaultAddress)]
+    pub b_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Token vault account for token B
+    #[account(mut, address = deposit_config.b_token_vault @ ErrorCode::InvalidVaultAddress)]
+    pub b_token_vault: UncheckedAccount<'info>,
+
+    /// CHECK: LP token mint for vault B
+    #[account(mut, address = deposit_config.b_vault_lp_mint @ ErrorCode::InvalidVaultAddress)]
+    pub b_vault_lp_mint: UncheckedAccount<'info>,
+
+    /// CHECK: LP token account for vault B
+    #[account(mut, address = deposit_config.b_vault_lp @ ErrorCode::InvalidVaultAddress)]
+    pub b_vault_lp: UncheckedAccount<'info>,
+
+    /// CHECK: Vault program
+    pub vault_program: UncheckedAccount<'info>,
+
+    // Pays SOL reserves of matrix owners reached by the cascade
+    #[account(
+        mut,
+        seeds = [b"program_sol_vault"],
+        bump
+    )]
+    pub program_sol_vault: SystemAccount<'info>,
+
+    // TOKEN MINT
+    /// CHECK: Token mint for token operations
+    #[account(mut)]
+    pub token_mint: UncheckedAccount<'info>,
+
+    /// CHECK: Protocol fee account for Meteora
+    #[account(mut, address = deposit_config.protocol_token_fee @ ErrorCode::InvalidProtocolFeeAccount)]
+    pub protocol_token_fee: UncheckedAccount<'info>,
+
+    /// CHECK: Meteora Dynamic AMM program
+    pub amm_program: UncheckedAccount<'info>,
+
+    // Required programs
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+
+    // remaining_accounts - same as RegisterWithSolDeposit without the Chainlink accounts:
+    // [0..3] - Vault A accounts (a_vault, a_vault_lp, a_vault_lp_mint, a_token_vault),
+    //          a_vault_lp being the one in deposit_config
+    //
+    // For slot 3 only:
+    // [4..10] - Airdrop accounts
+    // [11..]  - Upline Airdrop PDAs, then upline pairs (account_pda, wallet_account) or
+    //          (redirect, new_user, new_wallet) for a stale entry of a migrated user
+    // After the pairs - payout addresses, their token accounts for token reserves, the
+    //          config and vault of other deposit tokens, and the treasury token account
+}
+
+// Accounts for claiming a referral code
+#[derive(Accounts)]
+#[instruction(code: String)]
+pub struct ClaimReferralCode<'info> {
+    // The code is checked here, before it is used as the referral_code seed
+    #[account(
+        mut,
+        seeds = [b"user_account", owner_wallet.key().as_ref()],
+        bump,
+        constraint = user.to_account_info().data_len() == 8 + UserAccount::SIZE @ ErrorCode::UserAccountNeedsResize,
+        constraint = ReferralCode::is_valid(&code) @ ErrorCode::InvalidReferralCode
+    )]
+    pub user: AccountLoader<'info, UserAccount>,
+
+    #[account(mut)]
+    pub owner_wallet: Signer<'info>,
+
+    // init fails if the code is taken
+    #[account(
+        init,
+        payer = owner_wallet,
+        space = 8 + ReferralCode::SIZE,
+        seeds = [b"referral_code".as_ref(), code.as_bytes()],
+        bump
+    )]
+    pub referral_code: Account<'info, ReferralCode>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// HELPER FUNCTIONS TO REDUCE STACK USAGE
+
+// Copy of a UserAccount held by an UncheckedAccount, which must be in the current layout
+fn read_user_account(user_info: &AccountInfo) -> Result<UserAccount> {
+    let data = user_info.try_borrow_data()?;
+    require!(data.len() >= 8 && data[..8] == UserAccount::DISCRIMINATOR, ErrorCode::InvalidAccountData);
+    require!(data.len() == 8 + UserAccount::SIZE, ErrorCode::UserAccountNeedsResize);
+    Ok(bytemuck::pod_read_unaligned(&data[8..]))
+}
+
+// Shrink a program-owned account to `record` and move the lamports above the new rent
+// minimum to `recipient`. Returns the lamports moved.
+fn replace_with_record<T: AnchorSerialize + Discriminator>(
+    account_info: &AccountInfo,
+    recipient: &AccountInfo,
+    record: &T,
+    size: usize,
+) -> Result<u64> {
+    let new_len = 8 + size;
+    account_info.realloc(new_len, false)?;
+    {
+        let mut data = account_info.try_borrow_mut_data()?;
+        data[..8].copy_from_slice(&T::DISCRIMINATOR);
+        let mut writer: &mut [u8] = &mut data[8..];
+        record.serialize(&mut writer)?;
+    }
+
+    // The program owns the account, so the lamports move directly
+    let reclaimed = account_info.lamports().saturating_sub(Rent::get()?.minimum_balance(new_len));
+    **account_info.try_borrow_mut_lamports()? -= reclaimed;
+    **recipient.try_borrow_mut_lamports()? += reclaimed;
+    Ok(reclaimed)
+}
+
+// Helper: Validate base registration
+fn validate_base_registration<'info>(
+    owner: &Pubkey,
+    multisig_treasury: &Pubkey,
+    pool: &Pubkey,
+    b_vault: &Pubkey,
+    b_token_vault: &Pubkey,
+    b_vault_lp_mint: &Pubkey,
+    b_vault_lp: &Pubkey,
+    token_mint: &Pubkey,
+    wsol_mint: &Pubkey,
+    vault_program: &Pubkey,
+    amm_program: &Pubkey,
+    protocol_token_fee: &Pubkey,
+) -> Result<()> {
+    // Verify authorization
+    if owner != multisig_treasury {
+        return Err(error!(ErrorCode::NotAuthorized));
+    }
+    
+    // Verify all addresses
+    verify_all_fixed_addresses(
+        pool,
+        b_vault,
+        b_token_vault,
+        b_vault_lp_mint,
+        b_vault_lp,
+        token_mint,
+        wsol_mint,
+    )?;
+    
+    // Verify programs
+    verify_address_strict(vault_program, &verified_addresses::METEORA_VAULT_PROGRAM, ErrorCode::InvalidVaultProgram)?;
+    verify_address_strict(amm_program, &verified_addresses::METEORA_AMM_PROGRAM, ErrorCode::InvalidAmmProgram)?;
+    verify_address_strict(protocol_token_fee, &verified_addresses::PROTOCOL_TOKEN_B_FEE, ErrorCode::InvalidProtocolFeeAccount)?;
+    
+    Ok(())
+}
+
+// Helper: Initialize base user data
+pub fn initialize_base_user_data(
+    user: &mut UserAccount,
+    user_wallet: &Pubkey,
+    upline_id: u32,
+    chain_id: u32,
+) -> Result<()> {
+    user.is_registered = 1;
+    user.flags = USER_FLAG_COUNTS_REFERRALS;
+    user.referral_count = 0;
+    user.payout_address = Pubkey::default();
+    user.referrer = Pubkey::default();
+    user.owner_wallet = *user_wallet;
+    user.upline.id = upline_id;
+    user.upline.depth = 1;
+    user.upline.count = 0;
+    user.chain.id = chain_id;
+    user.chain.slots = [Pubkey::default(); 3];
+    user.chain.filled_slots = 0;
+    user.reserved_sol = 0;
+    user.reserve_token = RESERVE_SOL;
+    
+    Ok(())
+}
+
+// Helper: Process wrap SOL to WSOL
+fn wrap_sol_to_wsol<'info>(
+    user_wallet: &AccountInfo<'info>,
+    user_wsol_account: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    // Transfer SOL to WSOL account
+    let transfer_ix = solana_program::system_instruction::transfer(
+        &user_wallet.key(),
+        &user_wsol_account.key(),
+        amount
+    );
+    
+    solana_program::program::invoke(
+        &transfer_ix,
+        &[user_wallet.clone(), user_wsol_account.clone()],
+    ).map_err(|_| error!(ErrorCode::WrapSolFailed))?;
+    
+    // Sync native balance
+    let sync_native_ix = spl_token::instruction::sync_native(
+        &token::ID,
+        &user_wsol_account.key(),
+    )?;
+    
+    solana_program::program::invoke(
+        &sync_native_ix,
+        &[user_wsol_account.clone()],
+    ).map_err(|_| error!(ErrorCode::WrapSolFailed))?;
+    
+    Ok(())
+}
+
+// Helper: Extract vault A accounts from remaining accounts
+struct VaultAAccounts<'a, 'info> {
+    a_vault: &'a AccountInfo<'info>,
+    a_vault_lp: &'a AccountInfo<'info>,
+    a_vault_lp_mint: &'a AccountInfo<'info>,
+    a_token_vault: &'a AccountInfo<'info>,
+}
+
+fn extract_and_verify_vault_a_accounts<'a, 'info>(
+    remaining_accounts: &'a [AccountInfo<'info>]
+) -> Result<VaultAAccounts<'a, 'info>> {
+    if remaining_accounts.len() < VAULT_A_ACCOUNTS_COUNT {
+        return Err(error!(ErrorCode::MissingVaultAAccounts));
+    }
+    
+    let accounts = VaultAAccounts {
+        a_vault: &remaining_accounts[0],
+        a_vault_lp: &remaining_accounts[1],
+        a_vault_lp_mint: &remaining_accounts[2],
+        a_token_vault: &remaining_accounts[3],
+    };
+    
+    // Verify addresses
+    verify_vault_a_addresses(
+        &accounts.a_vault.key(),
+        &accounts.a_vault_lp.key(),
+        &accounts.a_vault_lp_mint.key(),
+        &accounts.a_token_vault.key()
+    )?;
+    
+    Ok(accounts)
+}
+
+// Helper: Verify the Meteora programs and fee account used by the swap
+fn verify_swap_programs(
+    vault_program: &Pubkey,
+    amm_program: &Pubkey,
+    protocol_token_fee: &Pubkey,
+) -> Result<()> {
+    verify_address_strict(vault_program, &verified_addresses::METEORA_VAULT_PROGRAM, ErrorCode::InvalidVaultProgram)?;
+    verify_address_strict(amm_program, &verified_addresses::METEORA_AMM_PROGRAM, ErrorCode::InvalidAmmProgram)?;
+    verify_address_strict(protocol_token_fee, &verified_addresses::PROTOCOL_TOKEN_B_FEE, ErrorCode::InvalidProtocolFeeAccount)?;
+
+    Ok(())
+}
+
+// Helper: Initialize data of a user registered under a referrer. The user's upline is
+// the referrer's upline plus the referrer itself, keeping only the last upline_depth entries.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_referred_user_data(
+    user: &mut UserAccount,
+    user_wallet: &Pubkey,
+    referrer_key: &Pubkey,
+    referrer: &UserAccount,
+    referrer_entry: UplineEntry,
+    upline_id: u32,
+    chain_id: u32,
+    upline_depth: usize,
+) -> Result<()> {
+    let referrer_upline = referrer.upline.entries();
+    let start_idx = if referrer_upline.len() >= upline_depth {
+        referrer_upline.len() - (upline_depth - 1)
+    } else {
+        0
+    };
+    let kept = &referrer_upline[start_idx..];
+
+    user.is_registered = 1;
+    user.flags = USER_FLAG_COUNTS_REFERRALS;
+    user.referral_count = 0;
+    user.payout_address = Pubkey::default();
+    user.referrer = *referrer_key;
+    user.owner_wallet = *user_wallet;
+    user.upline.id = upline_id;
+    user.upline.depth = referrer.upline.depth + 1;
+    user.upline.upline[..kept.len()].copy_from_slice(kept);
+    user.upline.upline[kept.len()] = referrer_entry;
+    user.upline.count = (kept.len() + 1) as u8;
+    user.chain.id = chain_id;
+    user.chain.slots = [Pubkey::default(); 3];
+    user.chain.filled_slots = 0;
+    user.reserved_sol = 0;
+    user.reserve_token = RESERVE_SOL;
+
+    Ok(())
+}
+
+// Helper: Find the multisig treasury among the remaining accounts
+fn find_treasury_account<'a, 'info>(
+    remaining_accounts: &'a [AccountInfo<'info>],
+    treasury: &Pubkey,
+) -> Result<&'a AccountInfo<'info>> {
+    remaining_accounts
+        .iter()
+        .find(|account| account.key() == *treasury)
+        .ok_or_else(|| error!(ErrorCode::MissingTreasuryAccount))
+}
+
+// Helper: Send the protocol fee of a SOL deposit to the multisig treasury, which must
+// then be among the remaining accounts. The fee is paid from the user wallet, or from
+// the pending registration PDA that held it with the deposit.
+fn collect_protocol_fee<'info>(
+    state: &ProgramState,
+    source: DepositSource<'_, 'info>,
+    user_wallet: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    fee: u64,
+) -> Result<()> {
+    if fee > 0 {
+        let treasury = find_treasury_account(remaining_accounts, &state.multisig_treasury)?;
+        match source {
+            DepositSource::Wallet => process_reserve_sol(user_wallet, treasury, fee)?,
+            DepositSource::Pending(pending) => release_pending_lamports(pending, treasury, fee)?,
+        }
+        debug_msg!("🏦 Protocol fee: {} lamports", fee);
+    }
+    Ok(())
+}
+
+// Helper: Burn escrow PDA to hold a SOL burn of `amount`, when the fallback is enabled
+// and the Meteora pool cannot quote the swap (disabled or unreadable). A failing swap
+// CPI aborts the whole transaction, so the quote is the only point where the route can
+// be found unavailable. The escrow must then be among the remaining accounts.
+fn find_burn_escrow<'a, 'info>(
+    state: &ProgramState,
+    remaining_accounts: &'a [AccountInfo<'info>],
+    swap: &SwapAccounts<'_, 'info>,
+    amount: u64,
+) -> Result<Option<&'a AccountInfo<'info>>> {
+    if !state.burn_escrow_enabled {
+        return Ok(None);
+    }
+
+    let quote = calculate_swap_amount_out(
+        &swap.pool,
+        swap.vault_a.a_vault,
+        &swap.b_vault,
+        swap.vault_a.a_vault_lp,
+        &swap.b_vault_lp,
+        swap.vault_a.a_vault_lp_mint,
+        &swap.b_vault_lp_mint,
+        amount,
+    );
+    if quote.is_ok() {
+        return Ok(None);
+    }
+
+    msg!("⚠️ Swap route unavailable - holding {} lamports in the burn escrow", amount);
+    let escrow = Pubkey::find_program_address(&[b"burn_escrow"], &crate::ID).0;
+    remaining_accounts
+        .iter()
+        .find(|account| account.key() == escrow)
+        .map(Some)
+        .ok_or_else(|| error!(ErrorCode::MissingBurnEscrowAccount))
+}
+
+// Helper: Find the account receiving a reserve payment. `default` is the owner wallet
+// sent in the instruction; a separate payout address comes among the remaining accounts.
+fn find_payout_account<'a, 'info>(
+    default: &'a AccountInfo<'info>,
+    remaining_accounts: &'a [AccountInfo<'info>],
+    payout: &Pubkey,
+) -> Result<&'a AccountInfo<'info>> {
+    if default.key() == *payout {
+        return Ok(default);
+    }
+    remaining_accounts
+        .iter()
+        .find(|account| account.key() == *payout)
+        .ok_or_else(|| error!(ErrorCode::MissingPayoutAccount))
+}
+
+// Helper: Find the TokenDepositConfig and reserve vault PDAs of a deposit token among
+// the remaining accounts
+fn find_token_reserve_accounts<'a, 'info>(
+    remaining_accounts: &'a [AccountInfo<'info>],
+    token: u8,
+) -> Result<(&'a AccountInfo<'info>, &'a AccountInfo<'info>)> {
+    let (config, _) = Pubkey::find_program_address(&[b"token_deposit", &[token]], &crate::ID);
+    let (vault, _) = Pubkey::find_program_address(&[b"token_reserve_vault", &[token]], &crate::ID);
+    let find = |key: Pubkey| {
+        remaining_accounts
+            .iter()
+            .find(|account| account.key() == key)
+            .ok_or_else(|| error!(ErrorCode::MissingTokenReserveAccount))
+    };
+
+    Ok((find(config)?, find(vault)?))
+}
+
+// Function to pay a reserve held in a deposit token vault, signed by the config PDA
+fn process_pay_token_reserve<'info>(
+    config: &mut TokenDepositConfig,
+    config_info: &AccountInfo<'info>,
+    vault: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    payout: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    // The destination must be a token account of the payout address, in the reserve token
+    if destination.data_is_empty() {
+        return Err(error!(ErrorCode::MissingPayoutAccount));
+    }
+    read_and_validate_token_account(destination, &config.mint, payout)?;
+
+    let ix = spl_token::instruction::transfer(
+        &token::ID,
+        &vault.key(),
+        &destination.key(),
+        &config_info.key(),
+        &[],
+        amount,
+    )?;
+
+    let token_seed = [config.token];
+    let bump = [config.bump];
+    solana_program::program::invoke_signed(
+        &ix,
+        &[vault.clone(), destination.clone(), config_info.clone()],
+        &[&[b"token_deposit".as_ref(), &token_seed, &bump]],
+    ).map_err(|_| error!(ErrorCode::TokenTransferFailed))?;
+
+    config.release_reserve(amount);
+    Ok(())
+}
+
+// Helper: Pay a token reserve from an instruction that does not take the token accounts -
+// the config, the vault and the payout address' associated token account come among the
+// remaining accounts.
+fn pay_token_reserve_from_remaining<'info>(
+    remaining_accounts: &'info [AccountInfo<'info>],
+    token: u8,
+    payout: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    let (config_info, vault_info) = find_token_reserve_accounts(remaining_accounts, token)?;
+    let mut config = Account::<TokenDepositConfig>::try_from(config_info)?;
+    let destination_key = anchor_spl::associated_token::get_associated_token_address(payout, &config.mint);
+    let destination = remaining_accounts
+        .iter()
+        .find(|account| account.key() == destination_key)
+        .ok_or_else(|| error!(ErrorCode::MissingPayoutAccount))?;
+
+    process_pay_token_reserve(&mut config, config_info, vault_info, destination, payout, amount)?;
+    config.exit(&crate::ID)
+}
+
+// Helper: Close the WSOL account, returning its lamports to the user wallet
+fn close_wsol_account<'info>(
+    user_wallet: &AccountInfo<'info>,
+    user_wsol_account: &AccountInfo<'info>,
+) -> Result<()> {
+    let close_ix = spl_token::instruction::close_account(
+        &token::ID,
+        &user_wsol_account.key(),
+        &user_wallet.key(),
+        &user_wallet.key(),
+        &[]
+    )?;
+
+    solana_program::program::invoke(
+        &close_ix,
+        &[user_wsol_account.clone(), user_wallet.clone(), user_wallet.clone()],
+    ).map_err(|_| error!(ErrorCode::UnwrapSolFailed))?;
+
+    Ok(())
+}
+
+// Helper: Move deposit lamports held by the pending registration PDA.
+// The PDA is owned by this program, so lamports are debited directly.
+fn release_pending_lamports<'info>(
+    pending: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    let pending_lamports = pending.lamports();
+    **pending.try_borrow_mut_lamports()? = pending_lamports
+        .checked_sub(amount)
+        .ok_or(error!(ErrorCode::SolReserveFailed))?;
+
+    let to_lamports = to.lamports();
+    **to.try_borrow_mut_lamports()? = to_lamports
+        .checked_add(amount)
+        .ok_or(error!(ErrorCode::SolReserveFailed))?;
+
+    Ok(())
+}
+
+// Where the deposit routed by the matrix engine is held until an effect moves it
+#[derive(Clone, Copy)]
+enum DepositSource<'a, 'info> {
+    Wallet,                          // Still in the registering wallet - moved with system transfers
+    Pending(&'a AccountInfo<'info>), // Held by the pending registration PDA
+}
+
+// Accounts of the Meteora swap a SOL burn goes through
+struct SwapAccounts<'a, 'info> {
+    pool: AccountInfo<'info>,
+    user_wsol_account: AccountInfo<'info>,
+    user_donut_account: AccountInfo<'info>,
+    vault_a: VaultAAccounts<'a, 'info>,
+    b_vault: AccountInfo<'info>,
+    b_token_vault: AccountInfo<'info>,
+    b_vault_lp_mint: AccountInfo<'info>,
+    b_vault_lp: AccountInfo<'info>,
+    token_mint: AccountInfo<'info>,
+    protocol_token_fee: AccountInfo<'info>,
+    vault_program: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    amm_program: AccountInfo<'info>,
+}
+
+// SwapAccounts from the identically named accounts of a SOL registration instruction
+macro_rules! swap_accounts {
+    ($accounts:expr, $vault_a:expr) => {
+        SwapAccounts {
+            pool: $accounts.pool.to_account_info(),
+            user_wsol_account: $accounts.user_wsol_account.to_account_info(),
+            user_donut_account: $accounts.user_donut_account.to_account_info(),
+            vault_a: $vault_a,
+            b_vault: $accounts.b_vault.to_account_info(),
+            b_token_vault: $accounts.b_token_vault.to_account_info(),
+            b_vault_lp_mint: $accounts.b_vault_lp_mint.to_account_info(),
+            b_vault_lp: $accounts.b_vault_lp.to_account_info(),
+            token_mint: $accounts.token_mint.to_account_info(),
+            protocol_token_fee: $accounts.protocol_token_fee.to_account_info(),
+            vault_program: $accounts.vault_program.to_account_info(),
+            token_program: $accounts.token_program.to_account_info(),
+            amm_program: $accounts.amm_program.to_account_info(),
+        }
+    };
+}
+
+// How the effect executor burns a SOL deposit
+enum BurnRoute<'a, 'info> {
+    // Wrap it in the user's WSOL account, swap and burn - held in the burn escrow instead
+    // when the escrow is enabled and the pool cannot quote the swap
+    Swap(Box<SwapAccounts<'a, 'info>>),
+    // Hold it in the burn escrow for retry_pending_burns
+    Escrow(AccountInfo<'info>),
+}
+
+struct SolEffectAccounts<'a, 'info> {
+    state: &'a mut Account<'info, ProgramState>,
+    user: Pubkey,                                  // Registering user, named in the events
+    user_wallet: AccountInfo<'info>,               // Registering wallet - swap authority and refunds
+    payer: AccountInfo<'info>,                     // Signer paying the airdrop notifications
+    referrer: Option<(&'a AccountLoader<'info, UserAccount>, AccountInfo<'info>)>, // RecordRef::Referrer and its wallet
+    upline_accounts: &'a [UplineAccounts<'info>],  // Validated uplines - RecordRef::Upline
+    program_sol_vault: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    event_authority: AccountInfo<'info>,
+}
+
+struct SolEffectBumps {
+    program_sol_vault: u8,
+    event_authority: u8,
+}
+
+// Executes the effects of a SOL registration. register_with_sol_deposit, begin_registration,
+// advance_registration and finish_pending_registration only differ in where the deposit
+// is held, how burns are routed and which records they write. Shaped like an anchor
+// Context so that emit_cpi! works in execute_sol_effects.
+struct SolEffects<'a, 'info> {
+    accounts: SolEffectAccounts<'a, 'info>,
+    bumps: SolEffectBumps,
+    program_id: &'a Pubkey,
+    remaining_accounts: &'info [AccountInfo<'info>],
+    deposit: DepositSource<'a, 'info>,
+    burn: BurnRoute<'a, 'info>,
+    last_step: bool, // Final step of the registration - only it flags the last notification
+    wsol_closed: bool,
+}
+
+impl<'a, 'info> SolEffects<'a, 'info> {
+    // Move `amount` of the routed deposit to `to`
+    fn move_deposit(&self, to: &AccountInfo<'info>, amount: u64) -> Result<()> {
+        match self.deposit {
+            DepositSource::Wallet => process_reserve_sol(&self.accounts.user_wallet, to, amount),
+            DepositSource::Pending(pending) => release_pending_lamports(pending, to, amount),
+        }
+    }
+
+    // Closing the WSOL account returns its rent to the user wallet
+    fn close_wsol(&mut self) -> Result<()> {
+        if let BurnRoute::Swap(swap) = &self.burn {
+            if !self.wsol_closed && swap.user_wsol_account.data_len() > 0 {
+                close_wsol_account(&self.accounts.user_wallet, &swap.user_wsol_account)?;
+                self.wsol_closed = true;
+            }
+        }
+        Ok(())
+    }
+
+    // Burn escrow receiving a burn of `amount`, None when the deposit is swapped and burned
+    fn burn_escrow(&self, amount: u64) -> Result<Option<&AccountInfo<'info>>> {
+        match &self.burn {
+            BurnRoute::Swap(swap) => find_burn_escrow(self.accounts.state, self.remaining_accounts, swap, amount),
+            BurnRoute::Escrow(escrow) => Ok(Some(escrow)),
+        }
+    }
+
+    // Wrap `amount` of the deposit in the user's WSOL account, swap it and burn the DONUT
+    fn swap_and_burn(&self, amount: u64) -> Result<u64> {
+        let BurnRoute::Swap(swap) = &self.burn else {
+            return Err(error!(ErrorCode::MissingBurnEscrowAccount));
+        };
+
+        match self.deposit {
+            DepositSource::Wallet => wrap_sol_to_wsol(&self.accounts.user_wallet, &swap.user_wsol_account, amount)?,
+            DepositSource::Pending(pending) => {
+                release_pending_lamports(pending, &swap.user_wsol_account, amount)?;
+
+                let sync_native_ix = spl_token::instruction::sync_native(
+                    &token::ID,
+                    &swap.user_wsol_account.key(),
+                )?;
+                solana_program::program::invoke(
+                    &sync_native_ix,
+                    std::slice::from_ref(&swap.user_wsol_account),
+                ).map_err(|_| error!(ErrorCode::WrapSolFailed))?;
+            }
+        }
+
+        process_swap_and_burn(
+            &swap.pool,
+            &self.accounts.user_wallet,
+            &swap.user_wsol_account,
+            &swap.user_donut_account,
+            swap.vault_a.a_vault,
+            &swap.b_vault,
+            swap.vault_a.a_token_vault,
+            &swap.b_token_vault,
+            swap.vault_a.a_vault_lp_mint,
+            &swap.b_vault_lp_mint,
+            swap.vault_a.a_vault_lp,
+            &swap.b_vault_lp,
+            &swap.token_mint,
+            &swap.protocol_token_fee,
+            &swap.vault_program,
+            &swap.token_program,
+            &swap.amm_program,
+            amount,
+            &[],
+        )
+    }
+}
+
+// Execute the effects of a matrix engine outcome, in order
+fn execute_sol_effects(ctx: &mut SolEffects, outcome: &matrix::Outcome) -> Result<()> {
+    let total_notifications = outcome.notification_count();
+    let mut notifications_made = 0;
+    let vault_bump = [ctx.bumps.program_sol_vault];
+    let vault_seeds: &[&[u8]] = &[b"program_sol_vault".as_ref(), &vault_bump];
+
+    for effect in outcome.effects.iter() {
+        match *effect {
+            Effect::Write { target, owner, user, slot_idx, chain_id, .. } => {
+                let record = outcome.record(target).ok_or(error!(ErrorCode::MissingUplineAccount))?;
+                // Zero-copy access - changes are written in place
+                match target {
+                    RecordRef::Referrer => {
+                        let (referrer, _) = ctx.accounts.referrer.as_ref().ok_or(error!(ErrorCode::InvalidUplineOrder))?;
+                        record.apply(&mut *referrer.load_mut()?);
+                    }
+                    RecordRef::Upline(i) => {
+                        let upline_loader = AccountLoader::<UserAccount>::try_from(ctx.accounts.upline_accounts[i].user)?;
+                        record.apply(&mut *upline_loader.load_mut()?);
+                    }
+                }
+
+                emit!(SlotFilled {
+                    slot_idx,
+                    chain_id,
+                    user,
+                    owner,
+                });
+            }
+            Effect::Burn { owner, chain_id, amount, depth } => {
+                if let Some(escrow) = ctx.burn_escrow(amount)? {
+                    let escrow = escrow.clone();
+                    ctx.move_deposit(&escrow, amount)?;
+                    ctx.accounts.state.escrow_burn(amount)?;
+
+                    emit_cpi!(DepositRouted {
+                        user: ctx.accounts.user,
+                        owner,
+                        chain_id,
+                        route: DepositRoute::Escrowed,
+                        amount,
+                        depth,
+                    });
+                    continue;
+                }
+
+                let donut_burned = ctx.swap_and_burn(amount)?;
+
+                emit_cpi!(DonutBurned {
+                    user_wallet: ctx.accounts.user_wallet.key(),
+                    chain_id,
+                    sol_amount: amount,
+                    donut_amount: donut_burned,
+                    depth,
+                    token: RESERVE_SOL,
+                });
+
+                emit_cpi!(DepositRouted {
+                    user: ctx.accounts.user,
+                    owner,
+                    chain_id,
+                    route: DepositRoute::Burned,
+                    amount,
+                    depth,
+                });
+            }
+            Effect::Reserve { owner, chain_id, amount, depth, .. } => {
+                ctx.close_wsol()?;
+
+                ctx.move_deposit(&ctx.accounts.program_sol_vault, amount)?;
+                ctx.accounts.state.credit_reserve(amount)?;
+
+                emit_cpi!(ReserveCredited {
+                    owner,
+                    chain_id,
+                    amount,
+                    depth,
+                    token: RESERVE_SOL,
+                });
+
+                emit_cpi!(DepositRouted {
+                    user: ctx.accounts.user,
+                    owner,
+                    chain_id,
+                    route: DepositRoute::Reserved,
+                    amount,
+                    depth,
+                });
+            }
+            Effect::Pay { target, owner, wallet, chain_id, amount, depth, token } => {
+                if token == RESERVE_SOL {
+                    let owner_wallet_info = match target {
+                        RecordRef::Referrer => &ctx.accounts.referrer.as_ref().ok_or(error!(ErrorCode::InvalidUplineOrder))?.1,
+                        RecordRef::Upline(i) => ctx.accounts.upline_accounts[i].wallet,
+                    };
+                    let wallet_info = find_payout_account(owner_wallet_info, ctx.remaining_accounts, &wallet)?;
+                    verify_wallet_is_system_account(wallet_info)?;
+
+                    process_pay_referrer(
+                        &ctx.accounts.program_sol_vault,
+                        wallet_info,
+                        amount,
+                        &[vault_seeds],
+                    )?;
+                    ctx.accounts.state.release_reserve(amount);
+                } else {
+                    pay_token_reserve_from_remaining(
+                        ctx.remaining_accounts,
+                        token,
+                        &wallet,
+                        amount,
+                    )?;
+                }
+
+                emit_cpi!(ReservePaid {
+                    owner,
+                    wallet,
+                    chain_id,
+                    amount,
+                    depth,
+                    token,
+                });
+            }
+            Effect::Notify { owner, wallet, chain_id, next_chain_id, depth } => {
+                notifications_made += 1;
+                let is_last_notification = ctx.last_step && notifications_made == total_notifications;
+
+                debug_msg!("📊 Notification {}/{} (last: {})",
+                     notifications_made, total_notifications, is_last_notification);
+
+                let airdrop_notified = notify_airdrop_program(
+                    &wallet,
+                    ctx.program_id,
+                    ctx.remaining_accounts,
+                    &ctx.accounts.system_program,
+                    &ctx.accounts.payer,
+                    is_last_notification,
+                    ctx.accounts.state,
+                )?;
+
+                if airdrop_notified {
+                    emit_cpi!(AirdropNotified {
+                        wallet,
+                        chain_id,
+                        depth,
+                        is_last_notification,
+                    });
+                }
+
+                emit_cpi!(MatrixCompleted {
+                    owner,
+                    chain_id,
+                    next_chain_id,
+                    depth,
+                });
+            }
+            Effect::Overflow { owner, chain_id, amount, depth, policy } => {
+                msg!("⚠️ Upline depth {} reached - applying {:?} policy", ctx.accounts.state.upline_depth(), policy);
+
+                // Only the WSOL rent is returned - the deposit is routed below
+                ctx.close_wsol()?;
+
+                let route = if policy == DepthOverflowPolicy::Treasury {
+                    let treasury = find_treasury_account(
+                        ctx.remaining_accounts,
+                        &ctx.accounts.state.multisig_treasury,
+                    )?;
+                    ctx.move_deposit(treasury, amount)?;
+                    DepositRoute::Treasury
+                } else {
+                    // A deposit still in the wallet is simply not taken
+                    if let DepositSource::Pending(pending) = ctx.deposit {
+                        release_pending_lamports(pending, &ctx.accounts.user_wallet, amount)?;
+                    }
+                    DepositRoute::Refunded
+                };
+
+                emit_cpi!(DepositRouted {
+                    user: ctx.accounts.user,
+                    owner,
+                    chain_id,
+                    route,
+                    amount,
+                    depth,
+                });
+            }
+        }
+    }
+
+    ctx.accounts.state.next_chain_id = outcome.next_chain_id;
+    Ok(())
+}
+
+#[program]
+pub mod referral_system {
+    use super::*;
+
+    // Initialize program state
+    pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
+        if ctx.accounts.owner.key() != admin_addresses::AUTHORIZED_INITIALIZER {
+            return Err(error!(ErrorCode::NotAuthorized));
+        }
+
+        let state = &mut ctx.accounts.state;
+        state.owner = ctx.accounts.owner.key();
+        state.multisig_treasury = admin_addresses::MULTISIG_TREASURY;
+        state.next_upline_id = 1;
+        state.next_chain_id = 1;
+        state.airdrop_active = true;    
+        state.airdrop_end_timestamp = 0;     
+        state.max_upline_depth = DEFAULT_UPLINE_DEPTH;
+        state.depth_overflow_policy = DepthOverflowPolicy::Burn;
+        state.total_reserved_lamports = 0;
+        state.allowlist_mode = AllowlistMode::Open;
+        state.allowlist_root = [0; 32];
+        state.invite_signer = Pubkey::default();
+        state.campaign = Campaign::default();
+        state.referrer_limits = ReferrerLimits::default();
+        state.protocol_fee_bps = 0;
+        state.burn_escrow_enabled = false;
+        state.pending_burn_lamports = 0;
+        state.burn_retry_slippage_bps = 0;
+        state.reserves_reconciled = true;
+        
+        Ok(())
+    }
+
+    // Register without referrer - optimized version
+    pub fn register_without_referrer<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, RegisterWithoutReferrerDeposit<'info>>, 
+        deposit_amount: u64
+    ) -> Result<()> {
+        // Step 1: Validate registration
+        validate_base_registration(
+            &ctx.accounts.owner.key(),
+            &ctx.accounts.state.multisig_treasury,
+            &ctx.accounts.pool.key(),
+            &ctx.accounts.b_vault.key(),
+            &ctx.accounts.b_token_vault.key(),
+            &ctx.accounts.b_vault_lp_mint.key(),
+            &ctx.accounts.b_vault_lp.key(),
+            &ctx.accounts.token_mint.key(),
+            &ctx.accounts.wsol_mint.key(),
+            &ctx.accounts.vault_program.key(),
+            &ctx.accounts.amm_program.key(),
+            &ctx.accounts.protocol_token_fee.key(),
+        )?;
+        
+        // Step 2: Update state and get IDs
+        let (upline_id, chain_id) = {
+            let state = &mut ctx.accounts.state;
+            let uid = state.next_upline_id;
+            let cid = state.next_chain_id;
+            state.next_upline_id += 1;
+            state.next_chain_id += 1;
+            (uid, cid)
+        };
+        
+        // Step 3: Initialize user
+        initialize_base_user_data(
+            &mut *ctx.accounts.user.load_init()?,
+            &ctx.accounts.user_wallet.key(),
+            upline_id,
+            chain_id,
+        )?;
+        
+        // Step 4: Extract and verify vault A accounts
+        let vault_a = extract_and_verify_vault_a_accounts(ctx.remaining_accounts)?;
+        
+        // Step 5: Wrap SOL to WSOL
+        wrap_sol_to_wsol(
+            &ctx.accounts.user_wallet.to_account_info(),
+            &ctx.accounts.user_wsol_account.to_account_info(),
+            deposit_amount,
+        )?;
+        
+        // Step 6: Process swap and burn
+        let donut_burned = process_swap_and_burn(
+            &ctx.accounts.pool.to_account_info(),
+            &ctx.accounts.user_wallet.to_account_info(),
+            &ctx.accounts.user_wsol_account.to_account_info(),
+            &ctx.accounts.user_donut_account.to_account_info(),
+            vault_a.a_vault,
+            &ctx.accounts.b_vault.to_account_info(),
+            vault_a.a_token_vault,
+            &ctx.accounts.b_token_vault.to_account_info(),
+            vault_a.a_vault_lp_mint,
+            &ctx.accounts.b_vault_lp_mint.to_account_info(),
+            vault_a.a_vault_lp,
+            &ctx.accounts.b_vault_lp.to_account_info(),
+            &ctx.accounts.token_mint.to_account_info(),
+            &ctx.accounts.protocol_token_fee.to_account_info(),
+            &ctx.accounts.vault_program.to_account_info(),
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.amm_program.to_account_info(),
+            deposit_amount,
+            &[],
+        )?;
+
+        // Step 7: Emit events
+        emit_cpi!(DonutBurned {
+            user_wallet: ctx.accounts.user_wallet.key(),
+            chain_id,
+            sol_amount: deposit_amount,
+            donut_amount: donut_burned,
+            depth: 0,
+            token: RESERVE_SOL,
+        });
+
+        emit_cpi!(DepositRouted {
+            user: ctx.accounts.user.key(),
+            owner: ctx.accounts.user.key(),
+            chain_id,
+            route: DepositRoute::Burned,
+            amount: deposit_amount,
+            depth: 0,
+        });
+
+        emit_cpi!(UserRegistered {
+            user: ctx.accounts.user.key(),
+            user_wallet: ctx.accounts.user_wallet.key(),
+            referrer: None,
+            upline_id,
+            chain_id,
+            upline_depth: 1,
+            deposit_amount,
+            deposit_token: RESERVE_SOL,
+        });
+        
+        Ok(())
+    }
+
+// Register with referrer
+pub fn register_with_sol_deposit<'a, 'b, 'info>(
+    ctx: Context<'a, 'b, 'info, 'info, RegisterWithSolDeposit<'info>>, 
+    deposit_amount: u64,
+    allowlist_proof: Vec<[u8; 32]>
+) -> Result<()> {
+    debug_msg!("🚀 Starting user registration with SOL deposit");
+    debug_msg!("👤 User wallet: {}", ctx.accounts.user_wallet.key());
+    debug_msg!("👤 Referrer wallet: {}", ctx.accounts.referrer_wallet.key());
+    debug_msg!("💰 Deposit amount: {} lamports", deposit_amount);
+    debug_msg!("📊 Remaining accounts count: {}", ctx.remaining_accounts.len());
+    debug_msg!("🎯 Matrix program ID: {}", ctx.program_id);
+    debug_msg!("🎯 Airdrop program ID: {}", AIRDROP_PROGRAM_ID);
+
+    ctx.accounts.state.campaign.check_registration(Clock::get()?.unix_timestamp, ctx.accounts.state.next_upline_id)?;
+    verify_allowlist(&ctx.accounts.state, &ctx.accounts.user_wallet.key(), &allowlist_proof, ctx.remaining_accounts)?;
+    
+    // Read the referrer fields used below without keeping the account borrowed
+    let (referrer_registered, referrer_chain_id, referrer_filled_slots, referrer_is_base) = {
+        let referrer = ctx.accounts.referrer.load()?;
+        (referrer.is_registered(), referrer.chain.id, referrer.chain.filled_slots, referrer.referrer().is_none())
+    };
+    let airdrop_was_active = ctx.accounts.state.airdrop_active;
+
+    // Check if referrer is registered
+    if !referrer_registered {
+        msg!("❌ Referrer is not registered");
+        return Err(error!(ErrorCode::ReferrerNotRegistered));
+    }
+    
+    debug_msg!("✅ Referrer is registered, chain ID: {}, filled slots: {}", 
+         referrer_chain_id, 
+         referrer_filled_slots);
+
+    // ADICIONAR: Verificar se airdrop está ativo antes de validar registro
+    if ctx.accounts.state.airdrop_active {
+        // Check if the user exists in the airdrop program
+        if !user_exists_in_airdrop(ctx.remaining_accounts, &ctx.accounts.referrer_wallet.key()) {
+            msg!("❌ Airdrop está ativo mas referrer não está registrado no airdrop");
+            return Err(error!(ErrorCode::UserNotRegisteredInAirdrop));
+        }
+    } else {
+        debug_msg!("📴 Airdrop no longer active. Skipping registration check.");
+    }
+
+    // Check if we have vault A accounts and Chainlink accounts in remaining_accounts
+    if ctx.remaining_accounts.len() < VAULT_A_ACCOUNTS_COUNT + 2 {
+        return Err(error!(ErrorCode::MissingVaultAAccounts));
+    }
+
+    // Extract vault A accounts from the beginning of remaining_accounts
+    let a_vault = &ctx.remaining_accounts[0];
+    let a_vault_lp = &ctx.remaining_accounts[1];
+    let a_vault_lp_mint = &ctx.remaining_accounts[2];
+    let a_token_vault = &ctx.remaining_accounts[3];
+
+    // Verify Vault A addresses
+    verify_vault_a_addresses(
+        &a_vault.key(),
+        &a_vault_lp.key(),
+        &a_vault_lp_mint.key(),
+        &a_token_vault.key()
+    )?;
+
+    // Extract Chainlink accounts from remaining_accounts
+    let chainlink_feed = &ctx.remaining_accounts[4];
+    let chainlink_program = &ctx.remaining_accounts[5];
+
+    // STRICT VERIFICATION OF ALL POOL ADDRESSES
+    verify_all_fixed_addresses(
+        &ctx.accounts.pool.key(),
+        &ctx.accounts.b_vault.key(),
+        &ctx.accounts.b_token_vault.key(),
+        &ctx.accounts.b_vault_lp_mint.key(),
+        &ctx.accounts.b_vault_lp.key(),
+        &ctx.accounts.token_mint.key(),
+        &ctx.accounts.wsol_mint.key(),
+    )?;
+    
+    // CRITICAL: Validate vault program
+    verify_address_strict(
+        &ctx.accounts.vault_program.key(), 
+        &verified_addresses::METEORA_VAULT_PROGRAM, 
+        ErrorCode::InvalidVaultProgram
+    )?;
+    
+    // Validate AMM program
+    verify_address_strict(
+        &ctx.accounts.amm_program.key(),
+        &verified_addresses::METEORA_AMM_PROGRAM,
+        ErrorCode::InvalidAmmProgram
+    )?;
+    
+    // Validate protocol fee account
+    verify_address_strict(
+        &ctx.accounts.protocol_token_fee.key(),
+        &verified_addresses::PROTOCOL_TOKEN_B_FEE,
+        ErrorCode::InvalidProtocolFeeAccount
+    )?;
+
+    // Verify Chainlink addresses
+    verify_chainlink_addresses(
+        &chainlink_program.key(),
+        &chainlink_feed.key(),
+    )?;
+
+    // Get minimum deposit amount from Chainlink feed
+    let minimum_deposit = calculate_minimum_sol_deposit(
+        chainlink_feed,
+        chainlink_program,
+    )?;
+
+    // Verify deposit amount meets the minimum requirement
+    if deposit_amount < minimum_deposit {
+        msg!("Deposit amount: {}, minimum required: {}", deposit_amount, minimum_deposit);
+        return Err(error!(ErrorCode::InsufficientDeposit));
+    }
+
+    // The protocol fee is taken from the checked amount - the matrix routes the rest.
+    // It is collected once the outcome is known: a refunded deposit is returned whole.
+    let protocol_fee = ctx.accounts.state.protocol_fee(deposit_amount);
+    let routed_amount = deposit_amount - protocol_fee;
+    
+    // Create the new UplineEntry structure for the referrer
+    let referrer_entry = UplineEntry {
+        pda: ctx.accounts.referrer.key(),
+        wallet: ctx.accounts.referrer_wallet.key(),
+    };
+    
+    // Get upline ID from global counter and update state in a limited scope
+    let (upline_id, chain_id) = {
+        let state = &mut ctx.accounts.state;
+        let upline_id = state.next_upline_id;
+        let chain_id = state.next_chain_id;
+
+        state.next_upline_id += 1;
+        state.next_chain_id += 1;
+        
+        (upline_id, chain_id)
+    };
+
+    // Create new user data - the upline is the referrer's upline plus the referrer
+    initialize_referred_user_data(
+        &mut *ctx.accounts.user.load_init()?,
+        &ctx.accounts.user_wallet.key(),
+        &ctx.accounts.referrer.key(),
+        &*ctx.accounts.referrer.load()?,
+        referrer_entry,
+        upline_id,
+        chain_id,
+        ctx.accounts.state.upline_depth(),
+    )?;
+    {
+        let mut referrer = ctx.accounts.referrer.load_mut()?;
+        referrer.referral_count = referrer.referral_count.saturating_add(1);
+    }
+
+    // Number of uplines stored and processed, configured by the admin
+    let upline_depth = ctx.accounts.state.upline_depth();
+
+    // ===== FINANCIAL LOGIC =====
+    // The matrix engine places the user and routes the deposit; this handler executes its effects
+    let slot_idx = referrer_filled_slots as usize;
+
+    // Slot 3 of a non-base referrer cascades - the uplines sent must match the stored ones,
+    // up to the configured depth. Accounts after them are extras - payout addresses and the treasury.
+    let cascades = slot_idx == 2 && !referrer_is_base;
+    let upline_accounts = find_upline_pairs(ctx.remaining_accounts, upline_depth);
+    let upline_accounts = if cascades {
+        if upline_accounts.is_empty() {
+            msg!("❌ Error: Slot 3 of non-base user requires uplines!");
+            return Err(error!(ErrorCode::UplineRequiredForNonBase));
+        }
+
+        let pairs = cascade_upline_pairs(ctx.accounts.referrer.load()?.upline.entries(), upline_depth, upline_accounts)?;
+        debug_msg!("✅ Slot 3 validation passed");
+        pairs
+    } else {
+        Vec::new()
+    };
+
+    let referrer_record = UserRecord::new(
+        ctx.accounts.referrer.key(),
+        ctx.accounts.referrer_wallet.key(),
+        &*ctx.accounts.referrer.load()?,
+    );
+
+    // Uplines are only read when the cascade reaches them
+    let mut processed_uplines = std::collections::HashSet::new();
+    let outcome = matrix::register(
+        ctx.accounts.user.key(),
+        routed_amount,
+        RESERVE_SOL,
+        referrer_record,
+        upline_accounts.len(),
+        |i| load_upline_record(&upline_accounts[i], &mut processed_uplines),
+        ctx.accounts.state.next_chain_id,
+        ctx.accounts.state.depth_overflow_policy,
+        &ctx.accounts.state.throttle(Clock::get()?.unix_timestamp),
+    )?;
+
+    debug_msg!("📊 Matrix engine returned {} effects", outcome.effects.len());
+
+    if protocol_fee > 0 && !outcome.is_refunded() {
+        collect_protocol_fee(
+            &ctx.accounts.state,
+            DepositSource::Wallet,
+            &ctx.accounts.user_wallet.to_account_info(),
+            ctx.remaining_accounts,
+            protocol_fee,
+        )?;
+        emit_cpi!(ProtocolFeeCollected {
+            user: ctx.accounts.user.key(),
+            treasury: ctx.accounts.state.multisig_treasury,
+            deposit_amount,
+            fee_amount: protocol_fee,
+            fee_bps: ctx.accounts.state.protocol_fee_bps,
+        });
+    }
+
+    let vault_a = VaultAAccounts { a_vault, a_vault_lp, a_vault_lp_mint, a_token_vault };
+    let mut effects = SolEffects {
+        accounts: SolEffectAccounts {
+            user: ctx.accounts.user.key(),
+            user_wallet: ctx.accounts.user_wallet.to_account_info(),
+            payer: ctx.accounts.user_wallet.to_account_info(),
+            referrer: Some((&ctx.accounts.referrer, ctx.accounts.referrer_wallet.to_account_info())),
+            upline_accounts: &upline_accounts,
+            program_sol_vault: ctx.accounts.program_sol_vault.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            event_authority: ctx.accounts.event_authority.to_account_info(),
+            state: &mut ctx.accounts.state,
+        },
+        bumps: SolEffectBumps {
+            program_sol_vault: ctx.bumps.program_sol_vault,
+            event_authority: ctx.bumps.event_authority,
+        },
+        program_id: ctx.program_id,
+        remaining_accounts: ctx.remaining_accounts,
+        deposit: DepositSource::Wallet,
+        burn: BurnRoute::Swap(Box::new(swap_accounts!(ctx.accounts, vault_a))),
+        last_step: true,
+        wsol_closed: false,
+    };
+    execute_sol_effects(&mut effects, &outcome)?;
+    let wsol_closed = effects.wsol_closed;
+
+    // Slot 3 leaves an empty WSOL account behind - return its rent
+    if slot_idx == 2 && !wsol_closed && ctx.accounts.user_wsol_account.data_len() > 0 {
+        close_wsol_account(
+            &ctx.accounts.user_wallet.to_account_info(),
+            &ctx.accounts.user_wsol_account.to_account_info(),
+        )?;
+        debug_msg!("💼 Closed WSOL account successfully");
+    }
+    
+    debug_msg!("🎉 User registration completed successfully!");
+    debug_msg!("👤 New user: {}", ctx.accounts.user.key());
+    debug_msg!("👤 Referrer: {}", ctx.accounts.referrer.key());
+    debug_msg!("💰 Deposit processed: {} lamports", deposit_amount);
+    debug_msg!("📊 Matrix status - Effects: {}, Slot filled: {}", outcome.effects.len(), slot_idx);
+
+    if airdrop_was_active && !ctx.accounts.state.airdrop_active {
+        emit_cpi!(AirdropDeactivated {
+            end_timestamp: ctx.accounts.state.airdrop_end_timestamp,
+            next_chain_id: ctx.accounts.state.next_chain_id,
+        });
+    }
+
+    emit_cpi!(UserRegistered {
+        user: ctx.accounts.user.key(),
+        user_wallet: ctx.accounts.user_wallet.key(),
+        referrer: Some(ctx.accounts.referrer.key()),
+        upline_id,
+        chain_id,
+        upline_depth: ctx.accounts.referrer.load()?.upline.depth + 1,
+        deposit_amount,
+        deposit_token: RESERVE_SOL,
+    });
+    
+    Ok(())
+}
+
+    // Convert a UserAccount written with the old Borsh layout to the zero-copy layout.
+    // Permissionless: the conversion is deterministic and the payer only funds the extra rent.
+    pub fn migrate_user_account(ctx: Context<MigrateUserAccount>) -> Result<()> {
+        let user_info = ctx.accounts.user.to_account_info();
+        let new_len = 8 + UserAccount::SIZE;
+
+        // Decode the legacy layout
+        let legacy = {
+            let data = user_info.try_borrow_data()?;
+            if data.len() < 8 || data[..8] != UserAccount::DISCRIMINATOR {
+                return Err(error!(ErrorCode::InvalidAccountData));
+            }
+            // Legacy accounts were always allocated with the full Borsh size
+            if data.len() != 8 + LegacyUserAccount::SIZE {
+                return Err(error!(ErrorCode::AccountAlreadyMigrated));
+            }
+
+            let mut account_slice = &data[8..];
+            LegacyUserAccount::deserialize(&mut account_slice)?
+        };
+
+        // Must be the canonical user PDA of the stored owner wallet
+        let (expected_pda, _) = Pubkey::find_program_address(
+            &[b"user_account", legacy.owner_wallet.as_ref()],
+            ctx.program_id
+        );
+        require!(
+            expected_pda == user_info.key(),
+            ErrorCode::InvalidAccountOwner
+        );
+
+        if legacy.upline.upline.len() > LEGACY_UPLINE_DEPTH {
+            return Err(error!(ErrorCode::InvalidUplineCount));
+        }
+
+        // Top up rent and grow the account
+        let required_lamports = Rent::get()?.minimum_balance(new_len);
+        if user_info.lamports() < required_lamports {
+            let ix = solana_program::system_instruction::transfer(
+                &ctx.accounts.payer.key(),
+                &user_info.key(),
+                required_lamports - user_info.lamports()
+            );
+
+            solana_program::program::invoke(
+                &ix,
+                &[ctx.accounts.payer.to_account_info(), user_info.clone()],
+            )?;
+        }
+        user_info.realloc(new_len, true)?;
+
+        // Write every field of the new layout
+        let mut data = user_info.try_borrow_mut_data()?;
+        let user: &mut UserAccount = bytemuck::from_bytes_mut(&mut data[8..new_len]);
+
+        user.reserved_sol = legacy.reserved_sol;
+        user.owner_wallet = legacy.owner_wallet;
+        user.referrer = legacy.referrer.unwrap_or_default();
+        user.chain.id = legacy.chain.id;
+        user.chain.filled_slots = legacy.chain.filled_slots;
+        user.chain._padding = [0; 3];
+        for (slot, legacy_slot) in user.chain.slots.iter_mut().zip(legacy.chain.slots.iter()) {
+            *slot = legacy_slot.unwrap_or_default();
+        }
+        user.is_registered = legacy.is_registered as u8;
+        // Referrals made before the migration are unknown - the account stays unclosable
+        user.flags = 0;
+        user.reserve_token = RESERVE_SOL;
+        user._padding = [0; 1];
+        user.referral_count = 0;
+        user.payout_address = Pubkey::default();
+        user.cycles = CycleStats::default();
+        user.upline.id = legacy.upline.id;
+        user.upline.depth = legacy.upline.depth;
+        user.upline.count = legacy.upline.upline.len() as u8;
+        user.upline._padding = [0; 2];
+        for (i, entry) in user.upline.upline.iter_mut().enumerate() {
+            *entry = legacy.upline.upline.get(i).copied().unwrap_or_default();
+        }
+
+        msg!("✅ User account {} migrated to zero-copy layout", user_info.key());
+        Ok(())
+    }
+
+    // Admin: configure how many uplines a registration cascade may walk and what
+    // happens to a deposit that is still unallocated when that depth is reached
+    pub fn set_upline_depth(
+        ctx: Context<SetUplineDepth>,
+        depth: u8,
+        policy: DepthOverflowPolicy
+    ) -> Result<()> {
+        require!(
+            depth >= 1 && depth as usize <= MAX_UPLINE_CAPACITY,
+            ErrorCode::InvalidUplineDepth
+        );
+
+        let state = &mut ctx.accounts.state;
+        state.max_upline_depth = depth;
+        state.depth_overflow_policy = policy;
+
+        msg!("✅ Upline depth set to {} with {:?} overflow policy", depth, policy);
+        Ok(())
+    }
+
+    // Admin: restrict registrations under a referrer to allowlisted wallets. The root
+    // and the signer are kept whatever the mode, so a phase can be reopened and closed
+    // again without resubmitting them.
+    pub fn set_allowlist(
+        ctx: Context<SetAllowlist>,
+        mode: AllowlistMode,
+        root: [u8; 32],
+        invite_signer: Pubkey,
+    ) -> Result<()> {
+        match mode {
+            AllowlistMode::Open => {}
+            AllowlistMode::MerkleRoot => require!(root != [0; 32], ErrorCode::InvalidAllowlist),
+            AllowlistMode::InviteSigner => require!(invite_signer != Pubkey::default(), ErrorCode::InvalidAllowlist),
+        }
+
+        let state = &mut ctx.accounts.state;
+        state.allowlist_mode = mode;
+        state.allowlist_root = root;
+        state.invite_signer = invite_signer;
+
+        emit!(AllowlistUpdated { mode, root, invite_signer });
+
+        msg!("✅ Registration allowlist set to {:?}", mode);
+        Ok(())
+    }
+
+    // Admin: open registrations under a referrer from start_timestamp until
+    // end_timestamp, for at most max_registrations users (0 = no cap). Once a campaign
+    // was scheduled, registrations outside its window fail; a new campaign can be
+    // scheduled after the previous one ended or was closed.
+    pub fn schedule_campaign(
+        ctx: Context<ManageCampaign>,
+        start_timestamp: i64,
+        end_timestamp: i64,
+        max_registrations: u32,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let state = &mut ctx.accounts.state;
+        require!(
+            start_timestamp < end_timestamp && end_timestamp > now,
+            ErrorCode::InvalidCampaignWindow
+        );
+        require!(!state.campaign.is_pending(now), ErrorCode::CampaignInProgress);
+
+        state.campaign = Campaign {
+            id: state.campaign.id + 1,
+            start_timestamp,
+            end_timestamp,
+            first_upline_id: state.next_upline_id,
+            max_registrations,
+            closed: false,
+        };
+
+        emit!(CampaignScheduled {
+            id: state.campaign.id,
+            start_timestamp,
+            end_timestamp,
+            max_registrations,
+            first_upline_id: state.next_upline_id,
+        });
+
+        msg!("✅ Campaign {} scheduled from {} to {}", state.campaign.id, start_timestamp, end_timestamp);
+        Ok(())
+    }
+
+    // Admin: push back the end of the current campaign and/or raise its cap. Neither can
+    // shrink - close the campaign instead.
+    pub fn extend_campaign(
+        ctx: Context<ManageCampaign>,
+        end_timestamp: i64,
+        max_registrations: u32,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let campaign = &mut ctx.accounts.state.campaign;
+        require!(campaign.is_pending(now), ErrorCode::NoCampaignInProgress);
+        require!(end_timestamp >= campaign.end_timestamp, ErrorCode::InvalidCampaignWindow);
+        require!(
+            max_registrations == 0
+                || (campaign.max_registrations != 0 && max_registrations >= campaign.max_registrations),
+            ErrorCode::InvalidCampaignWindow
+        );
+
+        campaign.end_timestamp = end_timestamp;
+        campaign.max_registrations = max_registrations;
+
+        emit!(CampaignExtended { id: campaign.id, end_timestamp, max_registrations });
+
+        msg!("✅ Campaign {} extended to {}", campaign.id, end_timestamp);
+        Ok(())
+    }
+
+    // Admin: end the current campaign now. Registrations stay closed until the next
+    // campaign is scheduled.
+    pub fn close_campaign(ctx: Context<ManageCampaign>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let next_upline_id = ctx.accounts.state.next_upline_id;
+        let campaign = &mut ctx.accounts.state.campaign;
+        require!(campaign.is_pending(now), ErrorCode::NoCampaignInProgress);
+
+        campaign.closed = true;
+        campaign.end_timestamp = campaign.end_timestamp.min(now);
+
+        emit!(CampaignClosed {
+            id: campaign.id,
+            closed_at: now,
+            registrations: campaign.registrations(next_upline_id),
+        });
+
+        msg!("✅ Campaign {} closed", campaign.id);
+        Ok(())
+    }
+
+    // Admin: throttle matrix completions per referrer - at most max_cycles_per_week
+    // cycles per airdrop week counted from week_start, and min_cycle_interval seconds
+    // between two of them. Zero disables a limit.
+    pub fn set_referrer_limits(
+        ctx: Context<SetReferrerLimits>,
+        max_cycles_per_week: u32,
+        min_cycle_interval: i64,
+        week_start: i64,
+    ) -> Result<()> {
+        require!(min_cycle_interval >= 0 && week_start >= 0, ErrorCode::InvalidReferrerLimits);
+
+        let limits = ReferrerLimits { max_cycles_per_week, min_cycle_interval, week_start };
+        ctx.accounts.state.referrer_limits = limits;
+
+        emit!(ReferrerLimitsUpdated { max_cycles_per_week, min_cycle_interval, week_start });
+
+        msg!("✅ Referrer limits: {} cycles per week, {}s between cycles", max_cycles_per_week, min_cycle_interval);
+        Ok(())
+    }
+
+    // Admin: share of each SOL deposit, in basis points, sent to multisig_treasury
+    // before the deposit is routed. 0 disables the fee.
+    pub fn set_protocol_fee(ctx: Context<SetProtocolFee>, fee_bps: u16) -> Result<()> {
+        require!(fee_bps <= MAX_PROTOCOL_FEE_BPS, ErrorCode::InvalidProtocolFee);
+
+        ctx.accounts.state.protocol_fee_bps = fee_bps;
+
+        emit!(ProtocolFeeUpdated { fee_bps });
+
+        msg!("✅ Protocol fee set to {} bps", fee_bps);
+        Ok(())
+    }
+
+    // Admin: hold SOL burns in the burn escrow instead of reverting the registration
+    // when the Meteora pool cannot quote the swap. retry_pending_burns burns them later,
+    // at most `retry_slippage_bps` below the pool's quote.
+    pub fn set_burn_escrow(ctx: Context<SetBurnEscrow>, enabled: bool, retry_slippage_bps: u16) -> Result<()> {
+        require!(retry_slippage_bps <= MAX_BURN_RETRY_SLIPPAGE_BPS, ErrorCode::InvalidBurnRetrySlippage);
+
+        ctx.accounts.state.burn_escrow_enabled = enabled;
+        ctx.accounts.state.burn_retry_slippage_bps = retry_slippage_bps;
+
+        emit!(BurnEscrowUpdated { enabled, retry_slippage_bps });
+
+        msg!("✅ Burn escrow {}, retry slippage {} bps", if enabled { "enabled" } else { "disabled" }, retry_slippage_bps);
+        Ok(())
+    }
+
+    // Permissionless crank: swap up to MAX_BURN_RETRY_LAMPORTS escrowed lamports to DONUT
+    // and burn them once the Meteora pool is healthy again. The swap must return the
+    // pool's own quote less the owner's burn_retry_slippage_bps, and the cap bounds the
+    // price impact of one call. The escrow PDA signs the wrap, swap and burn.
+    pub fn retry_pending_burns<'info>(
+        ctx: Context<'_, '_, 'info, 'info, RetryPendingBurns<'info>>,
+    ) -> Result<()> {
+        let pending = ctx.accounts.state.pending_burn_lamports;
+        require!(pending > 0, ErrorCode::NoPendingBurns);
+        let amount = pending.min(MAX_BURN_RETRY_LAMPORTS);
+
+        let vault_a = extract_and_verify_vault_a_accounts(ctx.remaining_accounts)?;
+        verify_all_fixed_addresses(
+            &ctx.accounts.pool.key(),
+            &ctx.accounts.b_vault.key(),
+            &ctx.accounts.b_token_vault.key(),
+            &ctx.accounts.b_vault_lp_mint.key(),
+            &ctx.accounts.b_vault_lp.key(),
+            &ctx.accounts.token_mint.key(),
+            &verified_addresses::WSOL_MINT,
+        )?;
+        verify_swap_programs(
+            &ctx.accounts.vault_program.key(),
+            &ctx.accounts.amm_program.key(),
+            &ctx.accounts.protocol_token_fee.key(),
+        )?;
+
+        // Quoted before the swap moves the pool
+        let quote = quote_swap_amount_out(
+            &ctx.accounts.pool.to_account_info(),
+            vault_a.a_vault,
+            &ctx.accounts.b_vault.to_account_info(),
+            vault_a.a_vault_lp,
+            &ctx.accounts.b_vault_lp.to_account_info(),
+            vault_a.a_vault_lp_mint,
+            &ctx.accounts.b_vault_lp_mint.to_account_info(),
+            amount,
+        )?;
+        let min_donut_out = ctx.accounts.state.burn_retry_min_out(quote);
+
+        let escrow_bump = [ctx.bumps.burn_escrow];
+        let escrow_seeds: &[&[u8]] = &[b"burn_escrow".as_ref(), &escrow_bump];
+
+        // Wrap the escrowed lamports into the escrow's WSOL account
+        solana_program::program::invoke_signed(
+            &solana_program::system_instruction::transfer(
+                &ctx.accounts.burn_escrow.key(),
+                &ctx.accounts.escrow_wsol_account.key(),
+                amount,
+            ),
+            &[
+                ctx.accounts.burn_escrow.to_account_info(),
+                ctx.accounts.escrow_wsol_account.to_account_info(),
+            ],
+            &[escrow_seeds],
+        ).map_err(|_| error!(ErrorCode::WrapSolFailed))?;
+
+        solana_program::program::invoke(
+            &spl_token::instruction::sync_native(&token::ID, &ctx.accounts.escrow_wsol_account.key())?,
+            &[ctx.accounts.escrow_wsol_account.to_account_info()],
+        ).map_err(|_| error!(ErrorCode::WrapSolFailed))?;
+
+        let donut_burned = process_swap_and_burn(
+            &ctx.accounts.pool.to_account_info(),
+            &ctx.accounts.burn_escrow.to_account_info(),
+            &ctx.accounts.escrow_wsol_account.to_account_info(),
+            &ctx.accounts.escrow_donut_account.to_account_info(),
+            vault_a.a_vault,
+            &ctx.accounts.b_vault.to_account_info(),
+            vault_a.a_token_vault,
+            &ctx.accounts.b_token_vault.to_account_info(),
+            vault_a.a_vault_lp_mint,
+            &ctx.accounts.b_vault_lp_mint.to_account_info(),
+            vault_a.a_vault_lp,
+            &ctx.accounts.b_vault_lp.to_account_info(),
+            &ctx.accounts.token_mint.to_account_info(),
+            &ctx.accounts.protocol_token_fee.to_account_info(),
+            &ctx.accounts.vault_program.to_account_info(),
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.amm_program.to_account_info(),
+            amount,
+            &[escrow_seeds],
+        )?;
+        if donut_burned < min_donut_out {
+            msg!("❌ Swap returned {} DONUT, minimum {}", donut_burned, min_donut_out);
+            return Err(error!(ErrorCode::BurnSlippageExceeded));
+        }
+        let remaining_lamports = pending - amount;
+        ctx.accounts.state.pending_burn_lamports = remaining_lamports;
+
+        emit_cpi!(PendingBurnsRetried {
+            cranker: ctx.accounts.cranker.key(),
+            sol_amount: amount,
+            donut_amount: donut_burned,
+            min_donut_amount: min_donut_out,
+            remaining_lamports,
+        });
+
+        msg!("🔥 Burned {} escrowed lamports as {} DONUT, {} left", amount, donut_burned, remaining_lamports);
+        Ok(())
     }
-    
-    msg!("✅ Referrer is registered, chain ID: {}, filled slots: {}", 
-         ctx.accounts.referrer.chain.id, 
-         ctx.accounts.referrer.chain.filled_slots);
 
-    // ADICIONAR: Verificar se airdrop está ativo antes de validar registro
-    if ctx.accounts.state.airdrop_active {
-        // Check if the user exists in the airdrop program
-        if !user_exists_in_airdrop(ctx.remaining_accounts, &ctx.accounts.referrer_wallet.key()) {
-            msg!("❌ Airdrop está ativo mas referrer não está registrado no airdrop");
-            return Err(error!(ErrorCode::UserNotRegisteredInAirdrop));
+    // Admin: grow ProgramState to the current layout. New fields are zero-filled,
+    // which reads as the default depth, the Burn policy, no reserved lamports (seed
+    // them with reconcile_vault), open registration, no campaign, no referrer limits,
+    // no protocol fee, no burn escrow or retry slippage and unreconciled reserves -
+    // sweep_vault_surplus stays disabled until reconcile_vault runs.
+    pub fn resize_program_state(ctx: Context<ResizeProgramState>) -> Result<()> {
+        let state_info = ctx.accounts.state.to_account_info();
+        let new_len = 8 + ProgramState::SIZE;
+
+        {
+            let data = state_info.try_borrow_data()?;
+            if data.len() < 8 + 32 || data[..8] != ProgramState::DISCRIMINATOR {
+                return Err(error!(ErrorCode::InvalidAccountData));
+            }
+            if data.len() >= new_len {
+                return Err(error!(ErrorCode::AccountAlreadyMigrated));
+            }
+
+            // Owner is the first field in every layout version
+            let stored_owner = Pubkey::try_from(&data[8..40]).map_err(|_| error!(ErrorCode::InvalidAccountData))?;
+            require!(
+                stored_owner == ctx.accounts.owner.key(),
+                ErrorCode::NotAuthorized
+            );
         }
-    } else {
-        msg!("📴 Airdrop não está mais ativo. Pulando verificação de registro.");
+
+        let required_lamports = Rent::get()?.minimum_balance(new_len);
+        if state_info.lamports() < required_lamports {
+            let ix = solana_program::system_instruction::transfer(
+                &ctx.accounts.owner.key(),
+                &state_info.key(),
+                required_lamports - state_info.lamports()
+            );
+
+            solana_program::program::invoke(
+                &ix,
+                &[ctx.accounts.owner.to_account_info(), state_info.clone()],
+            )?;
+        }
+        state_info.realloc(new_len, true)?;
+
+        msg!("✅ Program state resized to {} bytes", new_len);
+        Ok(())
     }
 
-    // Check if we have vault A accounts and Chainlink accounts in remaining_accounts
-    if ctx.remaining_accounts.len() < VAULT_A_ACCOUNTS_COUNT + 2 {
-        return Err(error!(ErrorCode::MissingVaultAAccounts));
+    // Grow a zero-copy UserAccount created with a smaller upline capacity or before
+    // payout_address or cycles existed. The upline array is the last field, so new
+    // entries are simply zero-filled; an account without those fields has its upline
+    // moved up to make room for them, unset.
+    pub fn resize_user_account(ctx: Context<ResizeUserAccount>) -> Result<()> {
+        let user_info = ctx.accounts.user.to_account_info();
+        let old_len = user_info.data_len();
+        let new_len = 8 + UserAccount::SIZE;
+        let min_len = 8 + UserAccount::size_with_capacity(0) - 32 - UserAccount::CYCLE_STATS_SIZE;
+
+        // A whole number of upline entries short of the current layout, plus the size
+        // of the missing fields in front of the upline
+        let missing_fields_offset = {
+            let data = user_info.try_borrow_data()?;
+            if data.len() < min_len || data[..8] != UserAccount::DISCRIMINATOR {
+                return Err(error!(ErrorCode::InvalidAccountData));
+            }
+            if data.len() >= new_len {
+                return Err(error!(ErrorCode::AccountAlreadyMigrated));
+            }
+            let missing_fields_offset = match (new_len - data.len()) % (32 + 32) {
+                0 => None,
+                UserAccount::CYCLE_STATS_SIZE => Some(UserAccount::CYCLE_STATS_OFFSET),
+                n if n == 32 + UserAccount::CYCLE_STATS_SIZE => Some(UserAccount::PAYOUT_ADDRESS_OFFSET),
+                _ => return Err(error!(ErrorCode::InvalidAccountData)),
+            };
+
+            // owner_wallet follows reserved_sol
+            let owner_wallet = Pubkey::try_from(&data[16..48]).map_err(|_| error!(ErrorCode::InvalidAccountData))?;
+            let (expected_pda, _) = Pubkey::find_program_address(
+                &[b"user_account", owner_wallet.as_ref()],
+                ctx.program_id
+            );
+            require!(
+                expected_pda == user_info.key(),
+                ErrorCode::InvalidAccountOwner
+            );
+            missing_fields_offset
+        };
+
+        let required_lamports = Rent::get()?.minimum_balance(new_len);
+        if user_info.lamports() < required_lamports {
+            let ix = solana_program::system_instruction::transfer(
+                &ctx.accounts.payer.key(),
+                &user_info.key(),
+                required_lamports - user_info.lamports()
+            );
+
+            solana_program::program::invoke(
+                &ix,
+                &[ctx.accounts.payer.to_account_info(), user_info.clone()],
+            )?;
+        }
+        user_info.realloc(new_len, true)?;
+
+        if let Some(offset) = missing_fields_offset {
+            let missing = UserAccount::CYCLE_STATS_OFFSET + UserAccount::CYCLE_STATS_SIZE - offset;
+            let mut data = user_info.try_borrow_mut_data()?;
+            data.copy_within(offset..old_len, offset + missing);
+            data[offset..offset + missing].fill(0);
+        }
+
+        msg!("✅ User account {} resized to {} upline entries", user_info.key(), MAX_UPLINE_CAPACITY);
+        Ok(())
     }
 
-    // Extract vault A accounts from the beginning of remaining_accounts
-    let a_vault = &ctx.remaining_accounts[0];
-    let a_vault_lp = &ctx.remaining_accounts[1];
-    let a_vault_lp_mint = &ctx.remaining_accounts[2];
-    let a_token_vault = &ctx.remaining_accounts[3];
+    // Read-only solvency check of program_sol_vault against the tracked reserves.
+    // Simulate it to read the VaultAudit from the return data.
+    pub fn audit_vault(ctx: Context<AuditVault>) -> Result<VaultAudit> {
+        let state = &ctx.accounts.state;
+        let vault_balance = ctx.accounts.program_sol_vault.lamports();
+        let rent_exempt_minimum = Rent::get()?.minimum_balance(0);
+
+        let audit = VaultAudit {
+            vault_balance,
+            rent_exempt_minimum,
+            total_reserved_lamports: state.total_reserved_lamports,
+            surplus: state.vault_surplus(vault_balance, rent_exempt_minimum),
+            deficit: state.total_reserved_lamports.saturating_sub(vault_balance),
+        };
 
-    // Verify Vault A addresses
-    verify_vault_a_addresses(
-        &a_vault.key(),
-        &a_vault_lp.key(),
-        &a_vault_lp_mint.key(),
-        &a_token_vault.key()
-    )?;
+        msg!("📊 Vault: {} lamports, reserved: {}, rent minimum: {}",
+             vault_balance, state.total_reserved_lamports, rent_exempt_minimum);
+        if audit.deficit > 0 {
+            msg!("❌ Vault deficit: {} lamports", audit.deficit);
+        } else if audit.surplus > 0 {
+            msg!("⚠️ Vault surplus: {} lamports", audit.surplus);
+        } else {
+            msg!("✅ Vault covers the reserved lamports");
+        }
 
-    // Extract Chainlink accounts from remaining_accounts
-    let chainlink_feed = &ctx.remaining_accounts[4];
-    let chainlink_program = &ctx.remaining_accounts[5];
+        Ok(audit)
+    }
 
-    // STRICT VERIFICATION OF ALL POOL ADDRESSES
-    verify_all_fixed_addresses(
-        &ctx.accounts.pool.key(),
-        &ctx.accounts.b_vault.key(),
-        &ctx.accounts.b_token_vault.key(),
-        &ctx.accounts.b_vault_lp_mint.key(),
-        &ctx.accounts.b_vault_lp.key(),
-        &ctx.accounts.token_mint.key(),
-        &ctx.accounts.wsol_mint.key(),
-    )?;
-    
-    // CRITICAL: Validate vault program
-    verify_address_strict(
-        &ctx.accounts.vault_program.key(), 
-        &verified_addresses::METEORA_VAULT_PROGRAM, 
-        ErrorCode::InvalidVaultProgram
-    )?;
-    
-    // Validate AMM program
-    verify_address_strict(
-        &ctx.accounts.amm_program.key(),
-        &verified_addresses::METEORA_AMM_PROGRAM,
-        ErrorCode::InvalidAmmProgram
-    )?;
-    
-    // Validate protocol fee account
-    verify_address_strict(
-        &ctx.accounts.protocol_token_fee.key(),
-        &verified_addresses::PROTOCOL_TOKEN_B_FEE,
-        ErrorCode::InvalidProtocolFeeAccount
-    )?;
+    // Admin: move the vault surplus - lamports no reserve accounts for, e.g. direct
+    // transfers - to the multisig treasury. Reserves and the vault rent stay. A resized
+    // state tracks no reserves until reconcile_vault seeds them, so it cannot sweep before.
+    pub fn sweep_vault_surplus(ctx: Context<SweepVaultSurplus>) -> Result<()> {
+        if !ctx.accounts.state.reserves_reconciled {
+            msg!("❌ Reserved total not reconciled since the state was resized - run reconcile_vault");
+            return Err(error!(ErrorCode::ReservesNotReconciled));
+        }
 
-    // Verify Chainlink addresses
-    verify_chainlink_addresses(
-        &chainlink_program.key(),
-        &chainlink_feed.key(),
-    )?;
+        let vault_info = ctx.accounts.program_sol_vault.to_account_info();
+        let treasury_info = ctx.accounts.multisig_treasury.to_account_info();
+        let rent_exempt_minimum = Rent::get()?.minimum_balance(0);
 
-    // Get minimum deposit amount from Chainlink feed
-    let minimum_deposit = calculate_minimum_sol_deposit(
-        chainlink_feed,
-        chainlink_program,
-    )?;
+        let surplus = ctx.accounts.state.vault_surplus(vault_info.lamports(), rent_exempt_minimum);
+        require!(surplus > 0, ErrorCode::NoVaultSurplus);
 
-    // Verify deposit amount meets the minimum requirement
-    if deposit_amount < minimum_deposit {
-        msg!("Deposit amount: {}, minimum required: {}", deposit_amount, minimum_deposit);
-        return Err(error!(ErrorCode::InsufficientDeposit));
+        let ix = solana_program::system_instruction::transfer(
+            &vault_info.key(),
+            &treasury_info.key(),
+            surplus
+        );
+        solana_program::program::invoke_signed(
+            &ix,
+            &[vault_info, treasury_info.clone()],
+            &[&[b"program_sol_vault".as_ref(), &[ctx.bumps.program_sol_vault]]],
+        )?;
+
+        emit!(VaultSurplusSwept {
+            treasury: treasury_info.key(),
+            amount: surplus,
+            total_reserved_lamports: ctx.accounts.state.total_reserved_lamports,
+        });
+
+        msg!("✅ Swept {} lamports of vault surplus to the treasury", surplus);
+        Ok(())
     }
-    
-    // Create the new UplineEntry structure for the referrer
-    let referrer_entry = UplineEntry {
-        pda: ctx.accounts.referrer.key(),
-        wallet: ctx.accounts.referrer_wallet.key(),
-    };
-    
-    // Create the user's upline by copying the referrer's upline and adding the referrer
-    let mut new_upline = Vec::new();
-    
-    // OPTIMIZATION - Try to reserve exact capacity to avoid reallocations
-    if ctx.accounts.referrer.upline.upline.len() >= MAX_UPLINE_DEPTH {
-        new_upline.try_reserve(MAX_UPLINE_DEPTH).ok();
-        let start_idx = ctx.accounts.referrer.upline.upline.len() - (MAX_UPLINE_DEPTH - 1);
-        new_upline.extend_from_slice(&ctx.accounts.referrer.upline.upline[start_idx..]);
-    } else {
-        new_upline.try_reserve(ctx.accounts.referrer.upline.upline.len() + 1).ok();
-        new_upline.extend_from_slice(&ctx.accounts.referrer.upline.upline);
+
+    // Admin: seed total_reserved_lamports from an off-chain sum of every
+    // UserAccount.reserved_sol held in RESERVE_SOL - needed once after resize_program_state, which leaves the
+    // new counter at zero. The total can only be raised, never above the vault balance,
+    // so it cannot release reserves to a sweep.
+    pub fn reconcile_vault(ctx: Context<ReconcileVault>, total_reserved_lamports: u64) -> Result<()> {
+        let vault_balance = ctx.accounts.program_sol_vault.lamports();
+        let state = &mut ctx.accounts.state;
+
+        require!(
+            total_reserved_lamports >= state.total_reserved_lamports && total_reserved_lamports <= vault_balance,
+            ErrorCode::InvalidReservedTotal
+        );
+
+        let previous_total = state.total_reserved_lamports;
+        state.total_reserved_lamports = total_reserved_lamports;
+        state.reserves_reconciled = true;
+
+        emit!(VaultReconciled {
+            previous_total,
+            total_reserved_lamports,
+            vault_balance,
+        });
+
+        msg!("✅ Reserved total reconciled: {} -> {} lamports", previous_total, total_reserved_lamports);
+        Ok(())
     }
-    
-    // Add the current referrer
-    new_upline.push(referrer_entry);
-    
-    // OPTIMIZATION - Reduce capacity to current size
-    new_upline.shrink_to_fit();
 
-    // Get upline ID from global counter and update state in a limited scope
-    let (upline_id, chain_id) = {
-        let state = &mut ctx.accounts.state;
-        let upline_id = state.next_upline_id;
-        let chain_id = state.next_chain_id;
+    // Close a UserAccount nobody depends on: empty matrix, no reserve and no referral
+    // ever registered under it. The account is not deleted but shrunk to a
+    // ClosedUserAccount tombstone - it stays allocated and program-owned, so the wallet
+    // can never register again, with the same or another referrer. Everything above the
+    // tombstone's rent goes back to the wallet.
+    pub fn close_user_account(ctx: Context<CloseUserAccount>) -> Result<()> {
+        require!(
+            ctx.accounts.pending_registration.lamports() == 0,
+            ErrorCode::RegistrationPending
+        );
+
+        let user_info = ctx.accounts.user.to_account_info();
+        let wallet_info = ctx.accounts.owner_wallet.to_account_info();
+        let user = read_user_account(&user_info)?;
+        require!(user.owner_wallet == wallet_info.key(), ErrorCode::InvalidAccountOwner);
+        require!(user.is_closable(), ErrorCode::UserAccountInUse);
+
+        let tombstone = ClosedUserAccount {
+            owner_wallet: user.owner_wallet,
+            referrer: user.referrer,
+            upline_id: user.upline.id,
+            closed_at: Clock::get()?.unix_timestamp,
+        };
+        let reclaimed = replace_with_record(&user_info, &wallet_info, &tombstone, ClosedUserAccount::SIZE)?;
+
+        emit!(UserAccountClosed {
+            user: user_info.key(),
+            owner_wallet: wallet_info.key(),
+            referrer: (tombstone.referrer != Pubkey::default()).then_some(tombstone.referrer),
+            reclaimed,
+        });
+
+        msg!("✅ User account {} closed, {} lamports returned", user_info.key(), reclaimed);
+        Ok(())
+    }
+
+    // Move a position to a new wallet: the new wallet's UserAccount gets a copy of the
+    // matrix, reserve, upline and counters, and the old PDA becomes a redirect to it.
+    // Accounts that reference the old PDA - direct referrals, matrix slots of the
+    // referrer and the stored upline of every descendant - are updated lazily with
+    // refresh_user_references; until then a cascade through a stale upline entry sends
+    // the redirect with the new account and pays the new wallet. Pending registrations
+    // under this user are advanced with the redirect as their pending_referrer.
+    pub fn migrate_user_wallet(ctx: Context<MigrateUserWallet>) -> Result<()> {
+        require!(
+            ctx.accounts.pending_registration.lamports() == 0,
+            ErrorCode::RegistrationPending
+        );
+
+        let new_wallet = ctx.accounts.new_wallet.key();
+        // Matrix completions of the new wallet are notified to the airdrop
+        if ctx.accounts.state.airdrop_active && !user_exists_in_airdrop(ctx.remaining_accounts, &new_wallet) {
+            msg!("❌ New wallet {} is not registered in the airdrop", new_wallet);
+            return Err(error!(ErrorCode::UserNotRegisteredInAirdrop));
+        }
+
+        let user_info = ctx.accounts.user.to_account_info();
+        let wallet_info = ctx.accounts.owner_wallet.to_account_info();
+        let user = read_user_account(&user_info)?;
+        require!(user.owner_wallet == wallet_info.key(), ErrorCode::InvalidAccountOwner);
+        require!(user.is_registered(), ErrorCode::SlotNotRegistered);
+
+        {
+            let mut new_user = ctx.accounts.new_user.load_init()?;
+            *new_user = user;
+            new_user.owner_wallet = new_wallet;
+        }
+
+        let redirect = UserAccountRedirect {
+            owner_wallet: user.owner_wallet,
+            new_wallet,
+            new_user: ctx.accounts.new_user.key(),
+            migrated_at: Clock::get()?.unix_timestamp,
+        };
+        replace_with_record(&user_info, &wallet_info, &redirect, UserAccountRedirect::SIZE)?;
+
+        emit!(UserWalletMigrated {
+            old_user: user_info.key(),
+            new_user: redirect.new_user,
+            old_wallet: redirect.owner_wallet,
+            new_wallet,
+            upline_id: user.upline.id,
+        });
+
+        msg!("✅ User account {} moved to {} (wallet {})", user_info.key(), redirect.new_user, new_wallet);
+        Ok(())
+    }
+
+    // Permissionless: point the user's referrer, matrix slots and stored upline entries
+    // at the new positions of migrated accounts, so their cascades no longer need the
+    // redirect accounts.
+    pub fn refresh_user_references<'info>(
+        ctx: Context<'_, '_, 'info, 'info, RefreshUserReferences<'info>>,
+    ) -> Result<()> {
+        let mut user = ctx.accounts.user.load_mut()?;
+        let mut changed = false;
+
+        for redirect_info in ctx.remaining_accounts {
+            require!(redirect_info.owner == &crate::ID, ErrorCode::InvalidAccountOwner);
+            let redirect = UserAccountRedirect::try_deserialize(&mut &redirect_info.try_borrow_data()?[..])?;
+            if user.redirect(&redirect_info.key(), &redirect.entry()) {
+                msg!("🔀 {} -> {}", redirect_info.key(), redirect.new_user);
+                changed = true;
+            }
+        }
+
+        require!(changed, ErrorCode::NoStaleReferences);
+        msg!("✅ References of {} refreshed", ctx.accounts.user.key());
+        Ok(())
+    }
+
+    // Send this user's reserve payments to another system wallet. The owner wallet
+    // keeps the account and the airdrop notifications.
+    pub fn set_payout_address(ctx: Context<SetPayoutAddress>) -> Result<()> {
+        let owner_wallet = ctx.accounts.owner_wallet.key();
+        let payout_address = Some(ctx.accounts.payout_address.key()).filter(|payout| *payout != owner_wallet);
+
+        let mut user = ctx.accounts.user.load_mut()?;
+        require!(user.owner_wallet == owner_wallet, ErrorCode::InvalidAccountOwner);
+        require!(user.is_registered(), ErrorCode::SlotNotRegistered);
+        user.payout_address = payout_address.unwrap_or_default();
+
+        emit!(PayoutAddressSet {
+            user: ctx.accounts.user.key(),
+            owner_wallet,
+            payout_address,
+        });
+
+        match payout_address {
+            Some(payout) => msg!("✅ Payouts of {} go to {}", ctx.accounts.user.key(), payout),
+            None => msg!("✅ Payouts of {} go to the owner wallet", ctx.accounts.user.key()),
+        }
+        Ok(())
+    }
+
+    // Admin: accept `token` in register_with_token_deposit. min_deposit is the USD minimum in
+    // token base units - no oracle is read for a stablecoin. Calling it again updates the
+    // minimum and the pool; the mint of a configured token cannot change while its vault
+    // may hold reserves.
+    pub fn configure_deposit_token(
+        ctx: Context<ConfigureDepositToken>,
+        token: u8,
+        min_deposit: u64,
+    ) -> Result<()> {
+        require!(token != RESERVE_SOL, ErrorCode::InvalidDepositToken);
+        require!(min_deposit > 0, ErrorCode::InsufficientDeposit);
+
+        let config = &mut ctx.accounts.deposit_config;
+        let mint = ctx.accounts.mint.key();
+        require!(
+            config.mint == Pubkey::default() || config.mint == mint,
+            ErrorCode::InvalidDepositToken
+        );
+
+        config.token = token;
+        config.mint = mint;
+        config.min_deposit = min_deposit;
+        config.pool = ctx.accounts.pool.key();
+        config.b_vault = ctx.accounts.b_vault.key();
+        config.b_token_vault = ctx.accounts.b_token_vault.key();
+        config.b_vault_lp_mint = ctx.accounts.b_vault_lp_mint.key();
+        config.b_vault_lp = ctx.accounts.b_vault_lp.key();
+        config.protocol_token_fee = ctx.accounts.protocol_token_fee.key();
+        config.a_vault_lp = ctx.accounts.a_vault_lp.key();
+        config.bump = ctx.bumps.deposit_config;
+        config.vault_bump = ctx.bumps.token_reserve_vault;
+
+        emit!(DepositTokenConfigured {
+            token,
+            mint,
+            min_deposit,
+            pool: config.pool,
+        });
+
+        msg!("✅ Deposit token {} configured: mint {}, minimum {}", token, mint, min_deposit);
+        Ok(())
+    }
+
+    // Multi-instruction registration - step 1.
+    // Runs the same validation, user creation and direct referrer matrix logic as
+    // register_with_sol_deposit. When the referrer's matrix completes in slot 3 and the
+    // referrer has uplines, the deposit is held in the pending registration PDA and the
+    // upline cascade is processed by one or more advance_registration calls.
+    pub fn begin_registration<'a, 'b, 'info>(
+        ctx: Context<'a, 'b, 'info, 'info, BeginRegistration<'info>>,
+        deposit_amount: u64,
+        allowlist_proof: Vec<[u8; 32]>
+    ) -> Result<()> {
+        debug_msg!("🚀 Beginning multi-step registration");
+        debug_msg!("👤 User wallet: {}", ctx.accounts.user_wallet.key());
+        debug_msg!("💰 Deposit amount: {} lamports", deposit_amount);
+
+        ctx.accounts.state.campaign.check_registration(Clock::get()?.unix_timestamp, ctx.accounts.state.next_upline_id)?;
+        verify_allowlist(&ctx.accounts.state, &ctx.accounts.user_wallet.key(), &allowlist_proof, ctx.remaining_accounts)?;
+
+        let (referrer_registered, referrer_chain_id, is_base_referrer) = {
+            let referrer = ctx.accounts.referrer.load()?;
+            (referrer.is_registered(), referrer.chain.id, referrer.referrer().is_none())
+        };
+        let airdrop_was_active = ctx.accounts.state.airdrop_active;
+
+        // Check if referrer is registered
+        if !referrer_registered {
+            return Err(error!(ErrorCode::ReferrerNotRegistered));
+        }
+
+        if ctx.accounts.state.airdrop_active
+            && !user_exists_in_airdrop(ctx.remaining_accounts, &ctx.accounts.referrer_wallet.key())
+        {
+            return Err(error!(ErrorCode::UserNotRegisteredInAirdrop));
+        }
+
+        // Check if we have vault A accounts and Chainlink accounts in remaining_accounts
+        if ctx.remaining_accounts.len() < VAULT_A_ACCOUNTS_COUNT + 2 {
+            return Err(error!(ErrorCode::MissingVaultAAccounts));
+        }
+
+        let vault_a = extract_and_verify_vault_a_accounts(ctx.remaining_accounts)?;
+        let chainlink_feed = &ctx.remaining_accounts[4];
+        let chainlink_program = &ctx.remaining_accounts[5];
+
+        verify_all_fixed_addresses(
+            &ctx.accounts.pool.key(),
+            &ctx.accounts.b_vault.key(),
+            &ctx.accounts.b_token_vault.key(),
+            &ctx.accounts.b_vault_lp_mint.key(),
+            &ctx.accounts.b_vault_lp.key(),
+            &ctx.accounts.token_mint.key(),
+            &ctx.accounts.wsol_mint.key(),
+        )?;
+
+        verify_swap_programs(
+            &ctx.accounts.vault_program.key(),
+            &ctx.accounts.amm_program.key(),
+            &ctx.accounts.protocol_token_fee.key(),
+        )?;
+
+        verify_chainlink_addresses(
+            &chainlink_program.key(),
+            &chainlink_feed.key(),
+        )?;
+
+        let minimum_deposit = calculate_minimum_sol_deposit(
+            chainlink_feed,
+            chainlink_program,
+        )?;
+
+        if deposit_amount < minimum_deposit {
+            msg!("Deposit amount: {}, minimum required: {}", deposit_amount, minimum_deposit);
+            return Err(error!(ErrorCode::InsufficientDeposit));
+        }
+
+        // Same protocol fee as register_with_sol_deposit, collected once the outcome is known
+        let protocol_fee = ctx.accounts.state.protocol_fee(deposit_amount);
+        let routed_amount = deposit_amount - protocol_fee;
+
+        // Step 1: Create the user under the referrer
+        let referrer_entry = UplineEntry {
+            pda: ctx.accounts.referrer.key(),
+            wallet: ctx.accounts.referrer_wallet.key(),
+        };
+
+        let (upline_id, chain_id) = {
+            let state = &mut ctx.accounts.state;
+            let upline_id = state.next_upline_id;
+            let chain_id = state.next_chain_id;
+            state.next_upline_id += 1;
+            state.next_chain_id += 1;
+            (upline_id, chain_id)
+        };
+
+        initialize_referred_user_data(
+            &mut *ctx.accounts.user.load_init()?,
+            &ctx.accounts.user_wallet.key(),
+            &ctx.accounts.referrer.key(),
+            &*ctx.accounts.referrer.load()?,
+            referrer_entry,
+            upline_id,
+            chain_id,
+            ctx.accounts.state.upline_depth(),
+        )?;
+        {
+            let mut referrer = ctx.accounts.referrer.load_mut()?;
+            referrer.referral_count = referrer.referral_count.saturating_add(1);
+        }
+
+        emit_cpi!(UserRegistered {
+            user: ctx.accounts.user.key(),
+            user_wallet: ctx.accounts.user_wallet.key(),
+            referrer: Some(ctx.accounts.referrer.key()),
+            upline_id,
+            chain_id,
+            upline_depth: ctx.accounts.referrer.load()?.upline.depth + 1,
+            deposit_amount,
+            deposit_token: RESERVE_SOL,
+        });
+
+        // Step 2: Fill the referrer's matrix. When it completes, a base referrer's deposit
+        // is burned; otherwise it is held in the pending PDA for advance_registration.
+        let mut referrer_record = UserRecord::new(
+            ctx.accounts.referrer.key(),
+            ctx.accounts.referrer_wallet.key(),
+            &*ctx.accounts.referrer.load()?,
+        );
+        let mut next_chain_id = ctx.accounts.state.next_chain_id;
+        let mut effects = Vec::new();
+
+        let placement = matrix::route_slot(
+            &mut referrer_record,
+            RecordRef::Referrer,
+            0,
+            ctx.accounts.user.key(),
+            routed_amount,
+            RESERVE_SOL,
+            &mut next_chain_id,
+            &ctx.accounts.state.throttle(Clock::get()?.unix_timestamp),
+            &mut effects,
+        )?;
+
+        if placement.completed && is_base_referrer {
+            effects.push(Effect::Burn {
+                owner: ctx.accounts.referrer.key(),
+                chain_id: referrer_chain_id,
+                amount: routed_amount,
+                depth: 0,
+            });
+        }
+        let cascade_pending = placement.completed && !is_base_referrer;
+
+        // Only the upline cascade can end in a refund - a pending cascade holds the fee
+        // with the deposit until it settles
+        if protocol_fee > 0 && !cascade_pending {
+            collect_protocol_fee(
+                &ctx.accounts.state,
+                DepositSource::Wallet,
+                &ctx.accounts.user_wallet.to_account_info(),
+                ctx.remaining_accounts,
+                protocol_fee,
+            )?;
+            emit_cpi!(ProtocolFeeCollected {
+                user: ctx.accounts.user.key(),
+                treasury: ctx.accounts.state.multisig_treasury,
+                deposit_amount,
+                fee_amount: protocol_fee,
+                fee_bps: ctx.accounts.state.protocol_fee_bps,
+            });
+        }
+
+        // Step 3: Execute the effects. The referrer notification is the last one of the
+        // registration only when no upline cascade follows in advance_registration.
+        let outcome = matrix::Outcome {
+            effects,
+            referrer: Some(referrer_record),
+            uplines: Vec::new(),
+            next_chain_id,
+            current_user: ctx.accounts.referrer.key(),
+            remaining_deposit: if cascade_pending { routed_amount } else { 0 },
+            token: RESERVE_SOL,
+            next_upline_index: 0,
+        };
+        let mut effects = SolEffects {
+            accounts: SolEffectAccounts {
+                user: ctx.accounts.user.key(),
+                user_wallet: ctx.accounts.user_wallet.to_account_info(),
+                payer: ctx.accounts.user_wallet.to_account_info(),
+                referrer: Some((&ctx.accounts.referrer, ctx.accounts.referrer_wallet.to_account_info())),
+                upline_accounts: &[],
+                program_sol_vault: ctx.accounts.program_sol_vault.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                event_authority: ctx.accounts.event_authority.to_account_info(),
+                state: &mut ctx.accounts.state,
+            },
+            bumps: SolEffectBumps {
+                program_sol_vault: ctx.bumps.program_sol_vault,
+                event_authority: ctx.bumps.event_authority,
+            },
+            program_id: ctx.program_id,
+            remaining_accounts: ctx.remaining_accounts,
+            deposit: DepositSource::Wallet,
+            burn: BurnRoute::Swap(Box::new(swap_accounts!(ctx.accounts, vault_a))),
+            last_step: !cascade_pending,
+            wsol_closed: false,
+        };
+        execute_sol_effects(&mut effects, &outcome)?;
+        let wsol_closed = effects.wsol_closed;
+
+        if airdrop_was_active && !ctx.accounts.state.airdrop_active {
+            emit_cpi!(AirdropDeactivated {
+                end_timestamp: ctx.accounts.state.airdrop_end_timestamp,
+                next_chain_id: ctx.accounts.state.next_chain_id,
+            });
+        }
+
+        if placement.completed && is_base_referrer && !wsol_closed && ctx.accounts.user_wsol_account.data_len() > 0 {
+            close_wsol_account(
+                &ctx.accounts.user_wallet.to_account_info(),
+                &ctx.accounts.user_wsol_account.to_account_info(),
+            )?;
+        }
+
+        if cascade_pending {
+            // Hold the deposit and its protocol fee in the pending PDA until the cascade allocates it
+            process_reserve_sol(
+                &ctx.accounts.user_wallet.to_account_info(),
+                &ctx.accounts.pending.to_account_info(),
+                deposit_amount
+            )?;
+
+            let pending = &mut ctx.accounts.pending;
+            pending.user_wallet = ctx.accounts.user_wallet.key();
+            pending.user = ctx.accounts.user.key();
+            pending.referrer = ctx.accounts.referrer.key();
+            pending.current_user = ctx.accounts.referrer.key();
+            pending.remaining_deposit = routed_amount;
+            pending.next_upline_index = 0;
+            pending.bump = ctx.bumps.pending;
+            pending.created_at = Clock::get()?.unix_timestamp;
+            pending.protocol_fee = protocol_fee;
+
+            emit_cpi!(DepositRouted {
+                user: ctx.accounts.user.key(),
+                owner: ctx.accounts.referrer.key(),
+                chain_id: referrer_chain_id,
+                route: DepositRoute::Pending,
+                amount: routed_amount,
+                depth: 0,
+            });
+
+            msg!("⏳ Upline cascade pending - call advance_registration within {}s", PENDING_REGISTRATION_TIMEOUT);
+            return Ok(());
+        }
+
+        // Nothing left to process - return the pending PDA rent to the user
+        ctx.accounts.pending.close(ctx.accounts.user_wallet.to_account_info())?;
+
+        debug_msg!("🎉 User registration completed in a single step");
+        Ok(())
+    }
+
+    // Multi-instruction registration - following steps.
+    // Processes the next pair_count uplines of the referrer's stored upline, in order,
+    // using the deposit held by the pending registration PDA. Once the deposit is burned,
+    // reserved or the upline list is exhausted (swap and burn), the PDA is closed.
+    pub fn advance_registration<'a, 'b, 'info>(
+        ctx: Context<'a, 'b, 'info, 'info, AdvanceRegistration<'info>>,
+        pair_count: u8
+    ) -> Result<()> {
+        debug_msg!("🔁 Advancing registration from upline index {}", ctx.accounts.pending.next_upline_index);
+
+        if ctx.accounts.pending.remaining_deposit == 0 {
+            return Err(error!(ErrorCode::NoPendingCascade));
+        }
+
+        verify_pending_referrer(&ctx.accounts.pending_referrer, &ctx.accounts.referrer.key())?;
+
+        let vault_a = extract_and_verify_vault_a_accounts(ctx.remaining_accounts)?;
 
-        state.next_upline_id += 1;
-        state.next_chain_id += 1;
-        
-        (upline_id, chain_id)
-    };
+        verify_all_fixed_addresses(
+            &ctx.accounts.pool.key(),
+            &ctx.accounts.b_vault.key(),
+            &ctx.accounts.b_token_vault.key(),
+            &ctx.accounts.b_vault_lp_mint.key(),
+            &ctx.accounts.b_vault_lp.key(),
+            &ctx.accounts.token_mint.key(),
+            &ctx.accounts.wsol_mint.key(),
+        )?;
 
-    // Create new user data
-    let user = &mut ctx.accounts.user;
+        verify_swap_programs(
+            &ctx.accounts.vault_program.key(),
+            &ctx.accounts.amm_program.key(),
+            &ctx.accounts.protocol_token_fee.key(),
+        )?;
 
-    user.is_registered = true;
-    user.referrer = Some(ctx.accounts.referrer.key());
-    user.owner_wallet = ctx.accounts.user_wallet.key();
-    user.upline = ReferralUpline {
-        id: upline_id,
-        depth: ctx.accounts.referrer.upline.depth + 1,
-        upline: new_upline,
-    };
-    user.chain = ReferralChain {
-        id: chain_id,
-        slots: [None, None, None],
-        filled_slots: 0,
-    };
-    
-    // Initialize user financial data
-    user.reserved_sol = 0;
+        // The pair_count uplines are the last remaining accounts
+        let pair_count = pair_count as usize;
+        let (upline_accounts, upline_accounts_len) = UplineAccounts::split_back(ctx.remaining_accounts, pair_count)?;
+        if ctx.remaining_accounts.len() < VAULT_A_ACCOUNTS_COUNT + upline_accounts_len {
+            return Err(error!(ErrorCode::MissingUplineAccount));
+        }
 
-    // ===== FINANCIAL LOGIC =====
-    // Determine which slot we're filling in the referrer's matrix
-    let slot_idx = ctx.accounts.referrer.chain.filled_slots as usize;
+        let start_index = ctx.accounts.pending.next_upline_index as usize;
+        let upline_list_len = {
+            let referrer = ctx.accounts.referrer.load()?;
+            let expected_uplines = referrer.upline.entries();
 
-    // LOGIC FOR SLOT 1: Swap and burn tokens
-    if slot_idx == 0 {
-        // Transfer SOL to WSOL (wrap)
-        let transfer_ix = solana_program::system_instruction::transfer(
-            &ctx.accounts.user_wallet.key(),
-            &ctx.accounts.user_wsol_account.key(),
-            deposit_amount
+            require!(
+                start_index + pair_count <= expected_uplines.len(),
+                ErrorCode::InvalidUplineCount
+            );
+
+            // Validate each sent upline against the stored upline, starting at the pending index
+            validate_upline_pairs(&expected_uplines[start_index..], &upline_accounts)?;
+
+            expected_uplines.len()
+        };
+
+        // The cascade settles at the depth limit, so a pending index at or past it means the
+        // depth was lowered since the last step. There is no upline left to route to, so only
+        // finish_pending_registration can settle the deposit, once the timeout has passed.
+        require!(
+            start_index < upline_list_len.min(ctx.accounts.state.upline_depth()),
+            ErrorCode::InvalidPendingRegistration
         );
-        
-        solana_program::program::invoke(
-            &transfer_ix,
-            &[
-                ctx.accounts.user_wallet.to_account_info(),
-                ctx.accounts.user_wsol_account.to_account_info(),
-            ],
-        ).map_err(|_| error!(ErrorCode::WrapSolFailed))?;
-        
-        // Sync the WSOL account
-        let sync_native_ix = spl_token::instruction::sync_native(
-            &token::ID,
-            &ctx.accounts.user_wsol_account.key(),
+
+        let airdrop_was_active = ctx.accounts.state.airdrop_active;
+        let registering_user = ctx.accounts.pending.user;
+        let current_user = pending_current_user(&ctx.accounts.pending, &ctx.accounts.referrer.key());
+
+        let mut processed_uplines = std::collections::HashSet::new();
+        let outcome = matrix::advance(
+            current_user,
+            ctx.accounts.pending.remaining_deposit,
+            RESERVE_SOL,
+            start_index,
+            pair_count,
+            upline_list_len,
+            ctx.accounts.state.upline_depth(),
+            |i| load_upline_record(&upline_accounts[i], &mut processed_uplines),
+            ctx.accounts.state.next_chain_id,
+            ctx.accounts.state.depth_overflow_policy,
+            &ctx.accounts.state.throttle(Clock::get()?.unix_timestamp),
         )?;
-        
-        solana_program::program::invoke(
-            &sync_native_ix,
-            &[ctx.accounts.user_wsol_account.to_account_info()],
-        ).map_err(|_| error!(ErrorCode::WrapSolFailed))?;
 
-        // Clone AccountInfo to avoid lifetime problems
-        let (pool_info, user_wallet_info, user_wsol_account_info, user_donut_account_info, b_vault_info, b_token_vault_info, b_vault_lp_mint_info, b_vault_lp_info, token_mint_info, protocol_token_fee_info, vault_program_info, token_program_info, amm_program_info) = get_matrix_account_info(&ctx)?;
+        // Execute the effects - the deposit is paid out of the pending PDA
+        let pending_info = ctx.accounts.pending.to_account_info();
+        let mut effects = SolEffects {
+            accounts: SolEffectAccounts {
+                user: registering_user,
+                user_wallet: ctx.accounts.user_wallet.to_account_info(),
+                payer: ctx.accounts.user_wallet.to_account_info(),
+                referrer: None,
+                upline_accounts: &upline_accounts,
+                program_sol_vault: ctx.accounts.program_sol_vault.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                event_authority: ctx.accounts.event_authority.to_account_info(),
+                state: &mut ctx.accounts.state,
+            },
+            bumps: SolEffectBumps {
+                program_sol_vault: ctx.bumps.program_sol_vault,
+                event_authority: ctx.bumps.event_authority,
+            },
+            program_id: ctx.program_id,
+            remaining_accounts: ctx.remaining_accounts,
+            deposit: DepositSource::Pending(&pending_info),
+            burn: BurnRoute::Swap(Box::new(swap_accounts!(ctx.accounts, vault_a))),
+            last_step: outcome.remaining_deposit == 0,
+            wsol_closed: false,
+        };
+        execute_sol_effects(&mut effects, &outcome)?;
+        let wsol_closed = effects.wsol_closed;
+
+        if airdrop_was_active && !ctx.accounts.state.airdrop_active {
+            emit_cpi!(AirdropDeactivated {
+                end_timestamp: ctx.accounts.state.airdrop_end_timestamp,
+                next_chain_id: ctx.accounts.state.next_chain_id,
+            });
+        }
 
-        // Process swap and burn with cloned AccountInfo
-        process_swap_and_burn(
-            &pool_info,
-            &user_wallet_info,
-            &user_wsol_account_info,
-            &user_donut_account_info,
-            a_vault,
-            &b_vault_info,
-            a_token_vault,
-            &b_token_vault_info,
-            a_vault_lp_mint,
-            &b_vault_lp_mint_info,
-            a_vault_lp,
-            &b_vault_lp_info,
-            &token_mint_info,
-            &protocol_token_fee_info,
-            &vault_program_info,
-            &token_program_info,
-            &amm_program_info,
-            deposit_amount 
-        )?;
-    } 
-    // LOGIC FOR SLOT 2: Reserve SOL value
-    else if slot_idx == 1 {
-        // Closing the WSOL account transfers the lamports back to the owner
-        let close_ix = spl_token::instruction::close_account(
-            &token::ID,
-            &ctx.accounts.user_wsol_account.key(),
-            &ctx.accounts.user_wallet.key(),
-            &ctx.accounts.user_wallet.key(),
-            &[]
-        )?;
-        
-        let close_accounts = [
-            ctx.accounts.user_wsol_account.to_account_info(),
-            ctx.accounts.user_wallet.to_account_info(),
-            ctx.accounts.user_wallet.to_account_info(),
-        ];
-        
-        solana_program::program::invoke(
-            &close_ix,
-            &close_accounts,
-        ).map_err(|_| error!(ErrorCode::UnwrapSolFailed))?;
-        
-        // Now transfer SOL to reserve
-        process_reserve_sol(
-            &ctx.accounts.user_wallet.to_account_info(),
-            &ctx.accounts.program_sol_vault.to_account_info(),
-            deposit_amount
-        )?;
-        
-        // Update reserved value for the referrer
-        ctx.accounts.referrer.reserved_sol = deposit_amount;
-    }
-    // LOGIC FOR SLOT 3: Pay referrer (SOL) and start recursion
-    else if slot_idx == 2 {
-        // NEW VALIDATION: If not base, MUST have uplines
-        if ctx.accounts.referrer.referrer.is_some() {
-            // Constantes claras
-            const VAULT_A_COUNT: usize = 4;
-            const CHAINLINK_COUNT: usize = 2;
-            const AIRDROP_BASE_COUNT: usize = 7;
-            const MAX_UPLINE_AIRDROP_PDAS: usize = 6;
-            
-            // Sem as 36 week PDAs, upline airdrop PDAs começam em 13
-            let upline_airdrop_start = VAULT_A_COUNT + CHAINLINK_COUNT + AIRDROP_BASE_COUNT; // = 13
-            
-            // Contar PDAs do airdrop (máximo 6)
-            let mut upline_airdrop_pdas_count = 0;
-            for i in 0..MAX_UPLINE_AIRDROP_PDAS {
-                let idx = upline_airdrop_start + i;
-                if idx < ctx.remaining_accounts.len() {
-                    let account = &ctx.remaining_accounts[idx];
-                    // PDAs do airdrop têm owner = AIRDROP_PROGRAM_ID
-                    if account.owner == &airdrop_addresses::AIRDROP_ACCOUNT {
-                        upline_airdrop_pdas_count += 1;
-                    } else {
-                        break;
-                    }
-                }
+        if outcome.remaining_deposit == 0 {
+            if !wsol_closed && ctx.accounts.user_wsol_account.data_len() > 0 {
+                close_wsol_account(
+                    &ctx.accounts.user_wallet.to_account_info(),
+                    &ctx.accounts.user_wsol_account.to_account_info(),
+                )?;
             }
-            
-            // Uplines (pares) começam após PDAs do airdrop
-            let upline_pairs_start = upline_airdrop_start + upline_airdrop_pdas_count;
-            
-            // Validar que temos uplines
-            if ctx.remaining_accounts.len() <= upline_pairs_start {
-                msg!("❌ Error: Slot 3 of non-base user requires uplines!");
-                return Err(error!(ErrorCode::UplineRequiredForNonBase));
+
+            // A refunded fee is returned with the pending PDA rent
+            let protocol_fee = ctx.accounts.pending.protocol_fee;
+            if protocol_fee > 0 && !outcome.is_refunded() {
+                collect_protocol_fee(
+                    &ctx.accounts.state,
+                    DepositSource::Pending(&pending_info),
+                    &ctx.accounts.user_wallet.to_account_info(),
+                    ctx.remaining_accounts,
+                    protocol_fee,
+                )?;
+                emit_cpi!(ProtocolFeeCollected {
+                    user: registering_user,
+                    treasury: ctx.accounts.state.multisig_treasury,
+                    deposit_amount: ctx.accounts.pending.remaining_deposit + protocol_fee,
+                    fee_amount: protocol_fee,
+                    fee_bps: ctx.accounts.state.protocol_fee_bps,
+                });
             }
-            
-            msg!("✅ Slot 3 validation passed");
+
+            ctx.accounts.pending.remaining_deposit = 0;
+            ctx.accounts.pending.close(ctx.accounts.user_wallet.to_account_info())?;
+
+            debug_msg!("🎉 Registration cascade completed");
+            return Ok(());
         }
-        
-        // 1. Transfer the reserved SOL value to the referrer
-        if ctx.accounts.referrer.reserved_sol > 0 {
-            verify_wallet_is_system_account(&ctx.accounts.referrer_wallet.to_account_info())?;
-            
-            process_pay_referrer(
-                &ctx.accounts.program_sol_vault.to_account_info(),
-                &ctx.accounts.referrer_wallet.to_account_info(),
-                ctx.accounts.referrer.reserved_sol,
-                &[&[
-                    b"program_sol_vault".as_ref(),
-                    &[ctx.bumps.program_sol_vault]
-                ]],
-            )?;
-            
-            ctx.accounts.referrer.reserved_sol = 0;
+
+        let pending = &mut ctx.accounts.pending;
+        pending.current_user = outcome.current_user;
+        pending.next_upline_index = outcome.next_upline_index as u8;
+
+        msg!("⏳ Cascade paused at upline index {}", outcome.next_upline_index);
+        Ok(())
+    }
+
+    // Permissionless: settle a pending registration its wallet left unfinished for
+    // PENDING_REGISTRATION_TIMEOUT. The cranker sends the rest of the referrer's stored
+    // upline up to the configured depth, so the cascade always settles - reserves and
+    // payouts as in advance_registration, burns held in the burn escrow for
+    // retry_pending_burns and the overflow policy at the depth limit. The pending PDA
+    // is closed to the registering wallet.
+    pub fn finish_pending_registration<'a, 'b, 'info>(
+        ctx: Context<'a, 'b, 'info, 'info, FinishPendingRegistration<'info>>,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let expires_at = ctx.accounts.pending.created_at.saturating_add(PENDING_REGISTRATION_TIMEOUT);
+        if now < expires_at {
+            msg!("❌ Pending registration is left to its wallet until {}", expires_at);
+            return Err(error!(ErrorCode::PendingRegistrationNotExpired));
         }
-        
-        // 2. ALWAYS wrap SOL to WSOL in slot 3
-        let transfer_ix = solana_program::system_instruction::transfer(
-            &ctx.accounts.user_wallet.key(),
-            &ctx.accounts.user_wsol_account.key(),
-            deposit_amount
-        );
-        
-        solana_program::program::invoke(
-            &transfer_ix,
-            &[
-                ctx.accounts.user_wallet.to_account_info(),
-                ctx.accounts.user_wsol_account.to_account_info(),
-            ],
-        ).map_err(|_| error!(ErrorCode::WrapSolFailed))?;
-        
-        let sync_native_ix = spl_token::instruction::sync_native(
-            &token::ID,
-            &ctx.accounts.user_wsol_account.key(),
+
+        let deposit = ctx.accounts.pending.remaining_deposit;
+        if deposit == 0 {
+            return Err(error!(ErrorCode::NoPendingCascade));
+        }
+
+        verify_pending_referrer(&ctx.accounts.pending_referrer, &ctx.accounts.referrer.key())?;
+
+        // The uplines are the last remaining accounts - every one left up to the depth
+        let start_index = ctx.accounts.pending.next_upline_index as usize;
+        let upline_depth = ctx.accounts.state.upline_depth();
+        let (upline_accounts, upline_list_len) = {
+            let referrer = ctx.accounts.referrer.load()?;
+            let expected_uplines = referrer.upline.entries();
+            let expected_uplines = expected_uplines
+                .get(start_index..expected_uplines.len().min(upline_depth))
+                .unwrap_or_default();
+
+            let (upline_accounts, _) = UplineAccounts::split_back(ctx.remaining_accounts, expected_uplines.len())?;
+            validate_upline_pairs(expected_uplines, &upline_accounts)?;
+
+            (upline_accounts, referrer.upline.entries().len())
+        };
+
+        let airdrop_was_active = ctx.accounts.state.airdrop_active;
+        let registering_user = ctx.accounts.pending.user;
+        let current_user = pending_current_user(&ctx.accounts.pending, &ctx.accounts.referrer.key());
+
+        let mut processed_uplines = std::collections::HashSet::new();
+        let outcome = matrix::advance(
+            current_user,
+            deposit,
+            RESERVE_SOL,
+            start_index,
+            upline_accounts.len(),
+            upline_list_len,
+            upline_depth,
+            |i| load_upline_record(&upline_accounts[i], &mut processed_uplines),
+            ctx.accounts.state.next_chain_id,
+            ctx.accounts.state.depth_overflow_policy,
+            &ctx.accounts.state.throttle(now),
         )?;
-        
-        solana_program::program::invoke(
-            &sync_native_ix,
-            &[ctx.accounts.user_wsol_account.to_account_info()],
-        ).map_err(|_| error!(ErrorCode::WrapSolFailed))?;
-        
-        msg!("✅ SOL wrapped to WSOL for slot 3 processing");
-    }
+        require!(outcome.remaining_deposit == 0, ErrorCode::UnusedDepositDetected);
+
+        let pending_info = ctx.accounts.pending.to_account_info();
+        let mut effects = SolEffects {
+            accounts: SolEffectAccounts {
+                user: registering_user,
+                user_wallet: ctx.accounts.user_wallet.to_account_info(),
+                payer: ctx.accounts.cranker.to_account_info(),
+                referrer: None,
+                upline_accounts: &upline_accounts,
+                program_sol_vault: ctx.accounts.program_sol_vault.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                event_authority: ctx.accounts.event_authority.to_account_info(),
+                state: &mut ctx.accounts.state,
+            },
+            bumps: SolEffectBumps {
+                program_sol_vault: ctx.bumps.program_sol_vault,
+                event_authority: ctx.bumps.event_authority,
+            },
+            program_id: ctx.program_id,
+            remaining_accounts: ctx.remaining_accounts,
+            deposit: DepositSource::Pending(&pending_info),
+            burn: BurnRoute::Escrow(ctx.accounts.burn_escrow.to_account_info()),
+            last_step: true,
+            wsol_closed: false,
+        };
+        execute_sol_effects(&mut effects, &outcome)?;
 
-    // Track if there are uplines to process in slot 3
-    let mut will_have_upline_notifications = false;
-    if slot_idx == 2 && ctx.accounts.referrer.referrer.is_some() {
-        // Use the indices already calculated above
-        const VAULT_A_COUNT: usize = 4;
-        const CHAINLINK_COUNT: usize = 2;
-        const AIRDROP_BASE_COUNT: usize = 7;
-        const MAX_UPLINE_AIRDROP_PDAS: usize = 6;
-        
-        let upline_airdrop_start = VAULT_A_COUNT + CHAINLINK_COUNT + AIRDROP_BASE_COUNT;
-        
-        let mut upline_airdrop_pdas_count = 0;
-        for i in 0..MAX_UPLINE_AIRDROP_PDAS {
-            let idx = upline_airdrop_start + i;
-            if idx < ctx.remaining_accounts.len() {
-                let account = &ctx.remaining_accounts[idx];
-                if account.owner == &airdrop_addresses::AIRDROP_ACCOUNT {
-                    upline_airdrop_pdas_count += 1;
-                } else {
-                    break;
-                }
-            }
+        if airdrop_was_active && !ctx.accounts.state.airdrop_active {
+            emit_cpi!(AirdropDeactivated {
+                end_timestamp: ctx.accounts.state.airdrop_end_timestamp,
+                next_chain_id: ctx.accounts.state.next_chain_id,
+            });
         }
-        
-        let upline_pairs_start = upline_airdrop_start + upline_airdrop_pdas_count;
-        
-        if ctx.remaining_accounts.len() > upline_pairs_start {
-            let upline_accounts = &ctx.remaining_accounts[upline_pairs_start..];
-            will_have_upline_notifications = upline_accounts.len() >= 2;
+
+        // A refunded fee is returned with the pending PDA rent
+        let protocol_fee = ctx.accounts.pending.protocol_fee;
+        if protocol_fee > 0 && !outcome.is_refunded() {
+            collect_protocol_fee(
+                &ctx.accounts.state,
+                DepositSource::Pending(&pending_info),
+                &ctx.accounts.user_wallet.to_account_info(),
+                ctx.remaining_accounts,
+                protocol_fee,
+            )?;
+            emit_cpi!(ProtocolFeeCollected {
+                user: registering_user,
+                treasury: ctx.accounts.state.multisig_treasury,
+                deposit_amount: deposit + protocol_fee,
+                fee_amount: protocol_fee,
+                fee_bps: ctx.accounts.state.protocol_fee_bps,
+            });
         }
+
+        emit_cpi!(PendingRegistrationFinished {
+            user: registering_user,
+            user_wallet: ctx.accounts.user_wallet.key(),
+            cranker: ctx.accounts.cranker.key(),
+            amount: deposit,
+            upline_index: start_index as u8,
+        });
+
+        ctx.accounts.pending.remaining_deposit = 0;
+        ctx.accounts.pending.close(ctx.accounts.user_wallet.to_account_info())?;
+
+        msg!("✅ Pending registration of {} settled from upline index {}", registering_user, start_index);
+        Ok(())
     }
 
-    // Process the referrer's matrix - determine if it's the last notification
-    let is_last_if_no_uplines = slot_idx == 2 && !will_have_upline_notifications;
-    
-    let (chain_completed, upline_pubkey) = process_referrer_chain(
-        &ctx.accounts.user.key(),
-        &mut ctx.accounts.referrer,
-        ctx.accounts.state.next_chain_id,
-        &ctx.accounts.referrer_wallet.key(),
-        &ctx.program_id,
-        &ctx.remaining_accounts,
-        &ctx.accounts.system_program.to_account_info(),
-        &ctx.accounts.user_wallet.to_account_info(),
-        is_last_if_no_uplines,  // If there are no uplines, this is the last notification
-        &mut ctx.accounts.state, // ADICIONAR
-    )?;
+    // Register with referrer, paying the deposit in a token configured by
+    // configure_deposit_token instead of SOL. Same matrix logic as register_with_sol_deposit:
+    // slot 1 swaps the token to DONUT through the token's pool and burns it, slot 2
+    // reserves it in the token vault and slot 3 pays each reserve in the token it was
+    // reserved in. The minimum is a fixed token amount, so no Chainlink accounts are read.
+    pub fn register_with_token_deposit<'a, 'b, 'info>(
+        ctx: Context<'a, 'b, 'info, 'info, RegisterWithTokenDeposit<'info>>,
+        deposit_amount: u64,
+        token: u8,
+        allowlist_proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        debug_msg!("🚀 Starting user registration with token {} deposit", token);
+        debug_msg!("👤 User wallet: {}", ctx.accounts.user_wallet.key());
+        debug_msg!("👤 Referrer wallet: {}", ctx.accounts.referrer_wallet.key());
+        debug_msg!("💰 Deposit amount: {} token units", deposit_amount);
 
-    force_memory_cleanup();
+        ctx.accounts.state.campaign.check_registration(Clock::get()?.unix_timestamp, ctx.accounts.state.next_upline_id)?;
+        verify_allowlist(&ctx.accounts.state, &ctx.accounts.user_wallet.key(), &allowlist_proof, ctx.remaining_accounts)?;
 
-    // If the matrix was completed, increment the global ID for the next one
-    if chain_completed {
-        let state = &mut ctx.accounts.state;
-        state.next_chain_id += 1;
-        msg!("🔄 Matrix was completed, incremented next_chain_id to: {}", state.next_chain_id);
-    } else {
-        msg!("📈 Matrix in progress, not completed yet");
-    }
-
-    // If the referrer's matrix was completed, process recursion
-    if chain_completed && slot_idx == 2 {
-        msg!("🎯 Processing slot 3 completion with recursion logic");
-        let mut current_user_pubkey = upline_pubkey;
-        let mut current_deposit = deposit_amount;
-        let mut wsol_closed = false;
-        let mut deposit_allocated = false;
-        let mut total_notifications = 1;
-
-        // Use the indices calculated for uplines
-        const VAULT_A_COUNT: usize = 4;
-        const CHAINLINK_COUNT: usize = 2;
-        const AIRDROP_BASE_COUNT: usize = 7;
-        const MAX_UPLINE_AIRDROP_PDAS: usize = 6;
-        
-        let upline_airdrop_start = VAULT_A_COUNT + CHAINLINK_COUNT + AIRDROP_BASE_COUNT;
-        
-        let mut upline_airdrop_pdas_count = 0;
-        for i in 0..MAX_UPLINE_AIRDROP_PDAS {
-            let idx = upline_airdrop_start + i;
-            if idx < ctx.remaining_accounts.len() {
-                let account = &ctx.remaining_accounts[idx];
-                if account.owner == &airdrop_addresses::AIRDROP_ACCOUNT {
-                    upline_airdrop_pdas_count += 1;
-                } else {
-                    break;
-                }
-            }
+        let (referrer_registered, referrer_filled_slots, referrer_is_base) = {
+            let referrer = ctx.accounts.referrer.load()?;
+            (referrer.is_registered(), referrer.chain.filled_slots, referrer.referrer().is_none())
+        };
+        let airdrop_was_active = ctx.accounts.state.airdrop_active;
+
+        if !referrer_registered {
+            msg!("❌ Referrer is not registered");
+            return Err(error!(ErrorCode::ReferrerNotRegistered));
         }
-        
-        let upline_start_idx = upline_airdrop_start + upline_airdrop_pdas_count;
-        
-        msg!("💰 Starting recursion with deposit: {} lamports", current_deposit);
 
-        let is_base_user = ctx.accounts.referrer.referrer.is_none();
-        
-        if is_base_user {
-            msg!("👤 Base user detected - executing swap and burn required");
-            
-            if current_deposit > 0 {
-                let (pool_info, user_wallet_info, user_wsol_account_info, user_donut_account_info, b_vault_info, b_token_vault_info, b_vault_lp_mint_info, b_vault_lp_info, token_mint_info, protocol_token_fee_info, vault_program_info, token_program_info, amm_program_info) = get_matrix_account_info(&ctx)?;
-                
-                process_swap_and_burn(
-                    &pool_info,
-                    &user_wallet_info,
-                    &user_wsol_account_info,
-                    &user_donut_account_info,
-                    a_vault,
-                    &b_vault_info,
-                    a_token_vault,
-                    &b_token_vault_info,
-                    a_vault_lp_mint,
-                    &b_vault_lp_mint_info,
-                    a_vault_lp,
-                    &b_vault_lp_info,
-                    &token_mint_info,
-                    &protocol_token_fee_info,
-                    &vault_program_info,
-                    &token_program_info,
-                    &amm_program_info,
-                    current_deposit
-                )?;
-                
-                msg!("✅ Swap and burn executed for base user");
-                deposit_allocated = true;
-                current_deposit = 0;
-            }
-        } else {
-            // Not base - MUST process uplines
-            if ctx.remaining_accounts.len() > upline_start_idx && current_deposit > 0 {
-                let upline_accounts = &ctx.remaining_accounts[upline_start_idx..];
-                
-                if upline_accounts.len() % 2 != 0 {
-                    return Err(error!(ErrorCode::MissingUplineAccount));
-                }
-                
-                let pair_count = upline_accounts.len() / 2;
-     
-                // Validate that the sent uplines correspond to the stored ones
-                let expected_uplines = &ctx.accounts.referrer.upline.upline;
-                let provided_pairs = upline_accounts.len() / 2;
-                
-                // Cannot send more uplines than exist
-                require!(
-                    provided_pairs <= expected_uplines.len(),
-                    ErrorCode::InvalidUplineCount
-                );
-                
-                // Validate each sent pair
-                for (i, chunk) in upline_accounts.chunks(2).enumerate() {
-                    if i >= expected_uplines.len() { break; }
-                    
-                    require!(
-                        chunk[0].key() == expected_uplines[i].pda,
-                        ErrorCode::InvalidUplineOrder
-                    );
-                    
-                    require!(
-                        chunk[1].key() == expected_uplines[i].wallet,
-                        ErrorCode::InvalidUplineWallet
-                    );
-                }
-            
-                // Track total notifications that will be made
-                let mut matrices_that_will_complete = 0;
+        if ctx.accounts.state.airdrop_active
+            && !user_exists_in_airdrop(ctx.remaining_accounts, &ctx.accounts.referrer_wallet.key())
+        {
+            msg!("❌ Airdrop is active but the referrer is not registered in it");
+            return Err(error!(ErrorCode::UserNotRegisteredInAirdrop));
+        }
 
-                // First, count how many matrices will complete
-                for pair_index in 0..pair_count {
-                    if pair_index >= MAX_UPLINE_DEPTH {
-                        break;
-                    }
-                    
-                    let base_idx = pair_index * 2;
-                    let upline_info = &upline_accounts[base_idx];
-                    
-                    if upline_info.owner.eq(&crate::ID) {
-                        let data = upline_info.try_borrow_data()?;
-                        if data.len() > 8 {
-                            let mut account_slice = &data[8..];
-                            if let Ok(upline_data) = UserAccount::deserialize(&mut account_slice) {
-                                if upline_data.is_registered && upline_data.chain.filled_slots == 2 {
-                                    matrices_that_will_complete += 1;
-                                    total_notifications += 1; // Add to existing total
-                                }
-                            }
-                        }
-                    }
-                }
-                
-                msg!("📊 Total number of notifications that will be made: {}", total_notifications);
-                msg!("📊 Total number of matrices that will complete: {}", matrices_that_will_complete);
-
-                const BATCH_SIZE: usize = 1;
-                let batch_count = (pair_count + BATCH_SIZE - 1) / BATCH_SIZE;
-                let mut notifications_made = 1;
-
-                //validation
-                use std::collections::HashSet;
-                let mut processed_uplines = HashSet::new();
-
-                
-                for batch_idx in 0..batch_count {
-                    let start_pair = batch_idx * BATCH_SIZE;
-                    let end_pair = std::cmp::min(start_pair + BATCH_SIZE, pair_count);
-                    
-                    for pair_index in start_pair..end_pair {
-                        if pair_index >= MAX_UPLINE_DEPTH || current_deposit == 0 {
-                            break;
-                        }
+        if ctx.remaining_accounts.len() < VAULT_A_ACCOUNTS_COUNT {
+            return Err(error!(ErrorCode::MissingVaultAAccounts));
+        }
 
-                        let base_idx = pair_index * 2;
-                        
-                        let upline_info = &upline_accounts[base_idx];
-                        let upline_wallet = &upline_accounts[base_idx + 1];
+        let a_vault = &ctx.remaining_accounts[0];
+        let a_vault_lp = &ctx.remaining_accounts[1];
+        let a_vault_lp_mint = &ctx.remaining_accounts[2];
+        let a_token_vault = &ctx.remaining_accounts[3];
 
-                        // Detect exploit attempt with duplicates
-                        let upline_key = upline_info.key();
-                        require!(
-                            processed_uplines.insert(upline_key),
-                            ErrorCode::DuplicateUplineExploit
-                        );
-                        
-                        if upline_wallet.owner != &solana_program::system_program::ID {
-                            return Err(error!(ErrorCode::PaymentWalletInvalid));
-                        }
-                        
-                        if !upline_info.owner.eq(&crate::ID) {
-                            return Err(error!(ErrorCode::InvalidSlotOwner));
-                        }
+        // The pool's LP account of vault A is checked against the config like its vault B accounts
+        verify_shared_vault_a_addresses(
+            &a_vault.key(),
+            &a_vault_lp_mint.key(),
+            &a_token_vault.key()
+        )?;
+        verify_address_strict(
+            &a_vault_lp.key(),
+            &ctx.accounts.deposit_config.a_vault_lp,
+            ErrorCode::InvalidVaultAddress
+        )?;
+
+        // The pool accounts and the fee account are checked against the config by address
+        verify_address_strict(
+            &ctx.accounts.vault_program.key(),
+            &verified_addresses::METEORA_VAULT_PROGRAM,
+            ErrorCode::InvalidVaultProgram
+        )?;
+        verify_address_strict(
+            &ctx.accounts.amm_program.key(),
+            &verified_addresses::METEORA_AMM_PROGRAM,
+            ErrorCode::InvalidAmmProgram
+        )?;
 
-                        let mut upline_account_data;
-                        {
-                            let data = upline_info.try_borrow_data()?;
-                            if data.len() <= 8 {
-                                return Err(ProgramError::InvalidAccountData.into());
-                            }
+        let mint = ctx.accounts.deposit_config.mint;
+        if deposit_amount < ctx.accounts.deposit_config.min_deposit {
+            msg!("Deposit amount: {}, minimum required: {}", deposit_amount, ctx.accounts.deposit_config.min_deposit);
+            return Err(error!(ErrorCode::InsufficientDeposit));
+        }
 
-                            let mut account_slice = &data[8..];
-                            upline_account_data = UserAccount::deserialize(&mut account_slice)?;
+        let balance = read_and_validate_token_account(
+            &ctx.accounts.user_token_account.to_account_info(),
+            &mint,
+            &ctx.accounts.user_wallet.key(),
+        )?;
+        if balance < deposit_amount {
+            msg!("Token balance: {}, deposit: {}", balance, deposit_amount);
+            return Err(error!(ErrorCode::InsufficientDeposit));
+        }
 
-                            if !upline_account_data.is_registered {
-                                return Err(error!(ErrorCode::SlotNotRegistered));
-                            }
-                        }
+        let referrer_entry = UplineEntry {
+            pda: ctx.accounts.referrer.key(),
+            wallet: ctx.accounts.referrer_wallet.key(),
+        };
 
-                        force_memory_cleanup();
-
-                        let upline_slot_idx = upline_account_data.chain.filled_slots as usize;
-                        let upline_key = *upline_info.key;
-                        
-                        upline_account_data.chain.slots[upline_slot_idx] = Some(current_user_pubkey);
-                        
-                        emit!(SlotFilled {
-                            slot_idx: upline_slot_idx as u8,
-                            chain_id: upline_account_data.chain.id,
-                            user: current_user_pubkey,
-                            owner: upline_key,
-                        });
-                        
-                        upline_account_data.chain.filled_slots += 1;
-                        
-                        if upline_slot_idx == 0 {
-                            if !wsol_closed {
-                                let (pool_info, user_wallet_info, user_wsol_account_info, user_donut_account_info, b_vault_info, b_token_vault_info, b_vault_lp_mint_info, b_vault_lp_info, token_mint_info, protocol_token_fee_info, vault_program_info, token_program_info, amm_program_info) = get_matrix_account_info(&ctx)?;
-
-                                process_swap_and_burn(
-                                    &pool_info,
-                                    &user_wallet_info,
-                                    &user_wsol_account_info,
-                                    &user_donut_account_info,
-                                    a_vault,
-                                    &b_vault_info,
-                                    a_token_vault,
-                                    &b_token_vault_info,
-                                    a_vault_lp_mint,
-                                    &b_vault_lp_mint_info,
-                                    a_vault_lp,
-                                    &b_vault_lp_info,
-                                    &token_mint_info,
-                                    &protocol_token_fee_info,
-                                    &vault_program_info,
-                                    &token_program_info,
-                                    &amm_program_info,
-                                    current_deposit
-                                )?;
-                            }
-                            
-                            deposit_allocated = true;
-                            current_deposit = 0;
-                        } 
-                        else if upline_slot_idx == 1 {
-                            if !wsol_closed {
-                                let close_ix = spl_token::instruction::close_account(
-                                    &token::ID,
-                                    &ctx.accounts.user_wsol_account.key(),
-                                    &ctx.accounts.user_wallet.key(),
-                                    &ctx.accounts.user_wallet.key(),
-                                    &[]
-                                )?;
-                                
-                                let close_accounts = [
-                                    ctx.accounts.user_wsol_account.to_account_info(),
-                                    ctx.accounts.user_wallet.to_account_info(),
-                                    ctx.accounts.user_wallet.to_account_info(),
-                                ];
-                                
-                                solana_program::program::invoke(
-                                    &close_ix,
-                                    &close_accounts,
-                                ).map_err(|_| error!(ErrorCode::UnwrapSolFailed))?;
-                                
-                                wsol_closed = true;
-                            }
-                            
-                            process_reserve_sol(
-                                &ctx.accounts.user_wallet.to_account_info(),
-                                &ctx.accounts.program_sol_vault.to_account_info(),
-                                current_deposit
-                            )?;
-                            
-                            upline_account_data.reserved_sol = current_deposit;
-                            
-                            deposit_allocated = true;
-                            current_deposit = 0;
-                        }
-                        else if upline_slot_idx == 2 {
-                            if upline_account_data.reserved_sol > 0 {
-                                let reserved_sol = upline_account_data.reserved_sol;
-                                
-                                if upline_wallet.owner != &solana_program::system_program::ID {
-                                    return Err(error!(ErrorCode::PaymentWalletInvalid));
-                                }
-                                
-                                let ix = solana_program::system_instruction::transfer(
-                                    &ctx.accounts.program_sol_vault.key(),
-                                    &upline_wallet.key(),
-                                    reserved_sol
-                                );
-                                
-                                let mut accounts = Vec::with_capacity(2);
-                                accounts.push(ctx.accounts.program_sol_vault.to_account_info());
-                                accounts.push(upline_wallet.clone());
-                                
-                                solana_program::program::invoke_signed(
-                                    &ix,
-                                    &accounts,
-                                    &[&[
-                                        b"program_sol_vault".as_ref(),
-                                        &[ctx.bumps.program_sol_vault]
-                                    ]],
-                                ).map_err(|_| error!(ErrorCode::ReferrerPaymentFailed))?;
-                                
-                                upline_account_data.reserved_sol = 0;
-                            }
-                        }
-                        
-                        let chain_completed = upline_account_data.chain.filled_slots == 3;
-                        
-                        if chain_completed {
-                            notifications_made += 1;
-                            let is_last_notification = notifications_made == total_notifications;
-                            
-                            msg!("📊 Notification {}/{} (last: {})", 
-                                 notifications_made, total_notifications, is_last_notification);
-                            
-                            notify_airdrop_program(
-                                &upline_wallet.key(),
-                                &ctx.program_id,
-                                ctx.remaining_accounts,
-                                &ctx.accounts.system_program.to_account_info(),
-                                &ctx.accounts.user_wallet.to_account_info(),
-                                is_last_notification,  // calculated flag
-                                &mut ctx.accounts.state, // ADICIONAR
-                            )?;
-                            
-                            let state = &mut ctx.accounts.state;
-                            let next_chain_id_value = state.next_chain_id;
-                            state.next_chain_id += 1;
-                            
-                            upline_account_data.chain.id = next_chain_id_value;
-                            upline_account_data.chain.slots = [None, None, None];
-                            upline_account_data.chain.filled_slots = 0;
-                            
-                            current_user_pubkey = upline_key;
-                        }
-                        
-                        {
-                            let mut data = upline_info.try_borrow_mut_data()?;
-                            let mut write_data = &mut data[8..];
-                            upline_account_data.serialize(&mut write_data)?;
-                        }
+        let (upline_id, chain_id) = {
+            let state = &mut ctx.accounts.state;
+            let upline_id = state.next_upline_id;
+            let chain_id = state.next_chain_id;
 
-                        force_memory_cleanup();
-                        
-                        if !chain_completed {
-                            break;
-                        }
-                        
-                        if deposit_allocated {
-                            break;
-                        }
-                        
-                        if pair_index >= MAX_UPLINE_DEPTH - 1 {
-                            break;
+            state.next_upline_id += 1;
+            state.next_chain_id += 1;
+
+            (upline_id, chain_id)
+        };
+
+        initialize_referred_user_data(
+            &mut *ctx.accounts.user.load_init()?,
+            &ctx.accounts.user_wallet.key(),
+            &ctx.accounts.referrer.key(),
+            &*ctx.accounts.referrer.load()?,
+            referrer_entry,
+            upline_id,
+            chain_id,
+            ctx.accounts.state.upline_depth(),
+        )?;
+        {
+            let mut referrer = ctx.accounts.referrer.load_mut()?;
+            referrer.referral_count = referrer.referral_count.saturating_add(1);
+        }
+
+        let upline_depth = ctx.accounts.state.upline_depth();
+
+        // ===== FINANCIAL LOGIC =====
+        let slot_idx = referrer_filled_slots as usize;
+
+        let cascades = slot_idx == 2 && !referrer_is_base;
+        let upline_accounts = find_upline_pairs_after(ctx.remaining_accounts, VAULT_A_ACCOUNTS_COUNT, upline_depth);
+        let upline_accounts = if cascades {
+            if upline_accounts.is_empty() {
+                msg!("❌ Error: Slot 3 of non-base user requires uplines!");
+                return Err(error!(ErrorCode::UplineRequiredForNonBase));
+            }
+
+            cascade_upline_pairs(ctx.accounts.referrer.load()?.upline.entries(), upline_depth, upline_accounts)?
+        } else {
+            Vec::new()
+        };
+
+        let referrer_record = UserRecord::new(
+            ctx.accounts.referrer.key(),
+            ctx.accounts.referrer_wallet.key(),
+            &*ctx.accounts.referrer.load()?,
+        );
+
+        let mut processed_uplines = std::collections::HashSet::new();
+        let outcome = matrix::register(
+            ctx.accounts.user.key(),
+            deposit_amount,
+            token,
+            referrer_record,
+            upline_accounts.len(),
+            |i| load_upline_record(&upline_accounts[i], &mut processed_uplines),
+            ctx.accounts.state.next_chain_id,
+            ctx.accounts.state.depth_overflow_policy,
+            &ctx.accounts.state.throttle(Clock::get()?.unix_timestamp),
+        )?;
+
+        debug_msg!("📊 Matrix engine returned {} effects", outcome.effects.len());
+
+        let total_notifications = outcome.notification_count();
+        let mut notifications_made = 0;
+        let vault_bump = [ctx.bumps.program_sol_vault];
+        let vault_seeds: &[&[u8]] = &[b"program_sol_vault".as_ref(), &vault_bump];
+        let user_wallet_info = ctx.accounts.user_wallet.to_account_info();
+        let user_token_info = ctx.accounts.user_token_account.to_account_info();
+
+        for effect in outcome.effects.iter() {
+            match *effect {
+                Effect::Write { target, owner, user, slot_idx, chain_id, .. } => {
+                    let record = outcome.record(target).ok_or(error!(ErrorCode::MissingUplineAccount))?;
+                    match target {
+                        RecordRef::Referrer => record.apply(&mut *ctx.accounts.referrer.load_mut()?),
+                        RecordRef::Upline(i) => {
+                            let upline_loader = AccountLoader::<UserAccount>::try_from(upline_accounts[i].user)?;
+                            record.apply(&mut *upline_loader.load_mut()?);
                         }
                     }
-                    
-                    if deposit_allocated {
-                        break;
-                    }
-                }
 
-                // CRITICAL: If all uplines were processed and deposit was not allocated, MUST do swap and burn
-                if !deposit_allocated && current_deposit > 0 {
-                    msg!("⚠️ All uplines processed without allocating deposit");
-                    msg!("💱 Executing swap and burn required");
-                    
-                    if wsol_closed {
-                        let transfer_ix = solana_program::system_instruction::transfer(
-                            &ctx.accounts.user_wallet.key(),
-                            &ctx.accounts.user_wsol_account.key(),
-                            current_deposit
-                        );
-                        
-                        solana_program::program::invoke(
-                            &transfer_ix,
-                            &[
-                                ctx.accounts.user_wallet.to_account_info(),
-                                ctx.accounts.user_wsol_account.to_account_info(),
-                            ],
-                        ).map_err(|_| error!(ErrorCode::WrapSolFailed))?;
-                        
-                        let sync_native_ix = spl_token::instruction::sync_native(
-                            &token::ID,
-                            &ctx.accounts.user_wsol_account.key(),
-                        )?;
-                        
-                        solana_program::program::invoke(
-                            &sync_native_ix,
-                            &[ctx.accounts.user_wsol_account.to_account_info()],
-                        ).map_err(|_| error!(ErrorCode::WrapSolFailed))?;
-                        
-                        wsol_closed = false;
-                    }
-                    
-                    let (pool_info, user_wallet_info, user_wsol_account_info, user_donut_account_info, b_vault_info, b_token_vault_info, b_vault_lp_mint_info, b_vault_lp_info, token_mint_info, protocol_token_fee_info, vault_program_info, token_program_info, amm_program_info) = get_matrix_account_info(&ctx)?;
-                    
-                    process_swap_and_burn(
-                        &pool_info,
+                    emit!(SlotFilled {
+                        slot_idx,
+                        chain_id,
+                        user,
+                        owner,
+                    });
+                }
+                Effect::Burn { owner, chain_id, amount, depth } => {
+                    // The token account is the swap source - no wrapping needed
+                    let donut_burned = process_swap_and_burn(
+                        &ctx.accounts.pool.to_account_info(),
                         &user_wallet_info,
-                        &user_wsol_account_info,
-                        &user_donut_account_info,
+                        &user_token_info,
+                        &ctx.accounts.user_donut_account.to_account_info(),
                         a_vault,
-                        &b_vault_info,
+                        &ctx.accounts.b_vault.to_account_info(),
                         a_token_vault,
-                        &b_token_vault_info,
+                        &ctx.accounts.b_token_vault.to_account_info(),
                         a_vault_lp_mint,
-                        &b_vault_lp_mint_info,
+                        &ctx.accounts.b_vault_lp_mint.to_account_info(),
                         a_vault_lp,
-                        &b_vault_lp_info,
-                        &token_mint_info,
-                        &protocol_token_fee_info,
-                        &vault_program_info,
-                        &token_program_info,
-                        &amm_program_info,
-                        current_deposit
+                        &ctx.accounts.b_vault_lp.to_account_info(),
+                        &ctx.accounts.token_mint.to_account_info(),
+                        &ctx.accounts.protocol_token_fee.to_account_info(),
+                        &ctx.accounts.vault_program.to_account_info(),
+                        &ctx.accounts.token_program.to_account_info(),
+                        &ctx.accounts.amm_program.to_account_info(),
+                        amount,
+                        &[],
                     )?;
-                    
-                    deposit_allocated = true;
-                    current_deposit = 0;
-                    msg!("✅ Swap and burn executed after processing all uplines");
+
+                    emit_cpi!(DonutBurned {
+                        user_wallet: ctx.accounts.user_wallet.key(),
+                        chain_id,
+                        sol_amount: amount,
+                        donut_amount: donut_burned,
+                        depth,
+                        token,
+                    });
+
+                    emit_cpi!(DepositRouted {
+                        user: ctx.accounts.user.key(),
+                        owner,
+                        chain_id,
+                        route: DepositRoute::Burned,
+                        amount,
+                        depth,
+                    });
+                }
+                Effect::Reserve { owner, chain_id, amount, depth, .. } => {
+                    process_transfer_token_deposit(
+                        &user_wallet_info,
+                        &user_token_info,
+                        &ctx.accounts.token_reserve_vault.to_account_info(),
+                        amount,
+                    )?;
+                    ctx.accounts.deposit_config.credit_reserve(amount)?;
+
+                    emit_cpi!(ReserveCredited {
+                        owner,
+                        chain_id,
+                        amount,
+                        depth,
+                        token,
+                    });
+
+                    emit_cpi!(DepositRouted {
+                        user: ctx.accounts.user.key(),
+                        owner,
+                        chain_id,
+                        route: DepositRoute::Reserved,
+                        amount,
+                        depth,
+                    });
+                }
+                Effect::Pay { target, owner, wallet, chain_id, amount, depth, token: reserve_token } => {
+                    if reserve_token == RESERVE_SOL {
+                        let owner_wallet_info = match target {
+                            RecordRef::Referrer => ctx.accounts.referrer_wallet.to_account_info(),
+                            RecordRef::Upline(i) => upline_accounts[i].wallet.clone(),
+                        };
+                        let wallet_info = find_payout_account(&owner_wallet_info, ctx.remaining_accounts, &wallet)?;
+                        verify_wallet_is_system_account(wallet_info)?;
+
+                        process_pay_referrer(
+                            &ctx.accounts.program_sol_vault.to_account_info(),
+                            wallet_info,
+                            amount,
+                            &[vault_seeds],
+                        )?;
+                        ctx.accounts.state.release_reserve(amount);
+                    } else if reserve_token == token {
+                        let destination_key = anchor_spl::associated_token::get_associated_token_address(&wallet, &mint);
+                        let destination = ctx.remaining_accounts
+                            .iter()
+                            .find(|account| account.key() == destination_key)
+                            .ok_or_else(|| error!(ErrorCode::MissingPayoutAccount))?;
+                        let config_info = ctx.accounts.deposit_config.to_account_info();
+
+                        process_pay_token_reserve(
+                            &mut ctx.accounts.deposit_config,
+                            &config_info,
+                            &ctx.accounts.token_reserve_vault.to_account_info(),
+                            destination,
+                            &wallet,
+                            amount,
+                        )?;
+                    } else {
+                        pay_token_reserve_from_remaining(
+                            ctx.remaining_accounts,
+                            reserve_token,
+                            &wallet,
+                            amount,
+                        )?;
+                    }
+
+                    emit_cpi!(ReservePaid {
+                        owner,
+                        wallet,
+                        chain_id,
+                        amount,
+                        depth,
+                        token: reserve_token,
+                    });
+                }
+                Effect::Notify { owner, wallet, chain_id, next_chain_id, depth } => {
+                    notifications_made += 1;
+                    let is_last_notification = notifications_made == total_notifications;
+
+                    let airdrop_notified = notify_airdrop_program(
+                        &wallet,
+                        ctx.program_id,
+                        ctx.remaining_accounts,
+                        &ctx.accounts.system_program.to_account_info(),
+                        &user_wallet_info,
+                        is_last_notification,
+                        &mut ctx.accounts.state,
+                    )?;
+
+                    if airdrop_notified {
+                        emit_cpi!(AirdropNotified {
+                            wallet,
+                            chain_id,
+                            depth,
+                            is_last_notification,
+                        });
+                    }
+
+                    emit_cpi!(MatrixCompleted {
+                        owner,
+                        chain_id,
+                        next_chain_id,
+                        depth,
+                    });
+                }
+                Effect::Overflow { owner, chain_id, amount, depth, policy } => {
+                    msg!("⚠️ Upline depth {} reached - applying {:?} policy", upline_depth, policy);
+
+                    // The deposit is still in the user's token account
+                    let route = if policy == DepthOverflowPolicy::Treasury {
+                        let treasury_key = anchor_spl::associated_token::get_associated_token_address(
+                            &ctx.accounts.state.multisig_treasury,
+                            &mint,
+                        );
+                        let treasury = find_treasury_account(ctx.remaining_accounts, &treasury_key)?;
+
+                        process_transfer_token_deposit(
+                            &user_wallet_info,
+                            &user_token_info,
+                            treasury,
+                            amount,
+                        )?;
+                        DepositRoute::Treasury
+                    } else {
+                        DepositRoute::Refunded
+                    };
+
+                    emit_cpi!(DepositRouted {
+                        user: ctx.accounts.user.key(),
+                        owner,
+                        chain_id,
+                        route,
+                        amount,
+                        depth,
+                    });
                 }
-            } else {
-                return Err(error!(ErrorCode::UplineRequiredForNonBase));
             }
         }
-        
-        // FINAL SECURITY VALIDATION
-        if current_deposit > 0 || !deposit_allocated {
-            msg!("❌ CRITICAL ERROR: Deposit was not allocated!");
-            msg!("  current_deposit: {}", current_deposit);
-            msg!("  deposit_allocated: {}", deposit_allocated);
-            return Err(error!(ErrorCode::UnusedDepositDetected));
-        }
-        
-        msg!("✅ All deposits properly allocated and processed");
-        
-        if !wsol_closed {
-            let account_info = ctx.accounts.user_wsol_account.to_account_info();
-            if account_info.data_len() > 0 {
-                let close_ix = spl_token::instruction::close_account(
-                    &token::ID,
-                    &ctx.accounts.user_wsol_account.key(),
-                    &ctx.accounts.user_wallet.key(),
-                    &ctx.accounts.user_wallet.key(),
-                    &[]
-                )?;
 
-                let close_accounts = [
-                    ctx.accounts.user_wsol_account.to_account_info(),
-                    ctx.accounts.user_wallet.to_account_info(),
-                    ctx.accounts.user_wallet.to_account_info(),
-                ];
-                
-                solana_program::program::invoke(
-                    &close_ix,
-                    &close_accounts,
-                ).map_err(|_| error!(ErrorCode::UnwrapSolFailed))?;
-                
-                msg!("💼 Closed WSOL account successfully");
-            }
+        ctx.accounts.state.next_chain_id = outcome.next_chain_id;
+
+        debug_msg!("🎉 User registration completed successfully!");
+        debug_msg!("📊 Matrix status - Effects: {}, Slot filled: {}", outcome.effects.len(), slot_idx);
+
+        if airdrop_was_active && !ctx.accounts.state.airdrop_active {
+            emit_cpi!(AirdropDeactivated {
+                end_timestamp: ctx.accounts.state.airdrop_end_timestamp,
+                next_chain_id: ctx.accounts.state.next_chain_id,
+            });
         }
-    } else if slot_idx == 2 {
-        msg!("🎯 Slot 3 filled but recursion not triggered (no matrix completion)");
+
+        emit_cpi!(UserRegistered {
+            user: ctx.accounts.user.key(),
+            user_wallet: ctx.accounts.user_wallet.key(),
+            referrer: Some(ctx.accounts.referrer.key()),
+            upline_id,
+            chain_id,
+            upline_depth: ctx.accounts.referrer.load()?.upline.depth + 1,
+            deposit_amount,
+            deposit_token: token,
+        });
+
+        Ok(())
+    }
+
+    // Claim `code` for the signer's UserAccount, first come first served. Clients resolve
+    // the code to the referrer wallet and register through the normal instructions.
+    pub fn claim_referral_code(ctx: Context<ClaimReferralCode>, code: String) -> Result<()> {
+        let owner_wallet = ctx.accounts.owner_wallet.key();
+        let user_key = ctx.accounts.user.key();
+
+        let mut user = ctx.accounts.user.load_mut()?;
+        require!(user.owner_wallet == owner_wallet, ErrorCode::InvalidAccountOwner);
+        require!(user.is_registered(), ErrorCode::SlotNotRegistered);
+        require!(user.flags & USER_FLAG_HAS_REFERRAL_CODE == 0, ErrorCode::ReferralCodeAlreadyClaimed);
+        user.flags |= USER_FLAG_HAS_REFERRAL_CODE;
+
+        let referral_code = &mut ctx.accounts.referral_code;
+        referral_code.code = code.clone();
+        referral_code.user = user_key;
+        referral_code.owner_wallet = owner_wallet;
+        referral_code.claimed_at = Clock::get()?.unix_timestamp;
+        referral_code.bump = ctx.bumps.referral_code;
+
+        emit!(ReferralCodeClaimed {
+            code: code.clone(),
+            referral_code: referral_code.key(),
+            user: user_key,
+            owner_wallet,
+        });
+
+        msg!("✅ Referral code '{}' now resolves to {}", code, user_key);
+        Ok(())
     }
-    
-    msg!("🎉 User registration completed successfully!");
-    msg!("👤 New user: {}", ctx.accounts.user.key());
-    msg!("👤 Referrer: {}", ctx.accounts.referrer.key());
-    msg!("💰 Deposit processed: {} lamports", deposit_amount);
-    msg!("📊 Matrix status - Chain completed: {}, Slot filled: {}", chain_completed, slot_idx);
-    
-    Ok(())
 }
-}
\ No newline at end of file
@@ -0,0 +1,383 @@
+// Matrix engine - slot placement, reset on completion, upline propagation, deposit
+// routing and referrer limits over plain user records. It never touches accounts: it
+// returns the list of effects (burn, reserve, pay, notify, write) that the instruction
+// handlers execute, so the same rules run on-chain, in off-chain simulations and in
+// host tests.
+
+use anchor_lang::prelude::*;
+
+use crate::{CycleStats, DepthOverflowPolicy, ErrorCode, ReferralChain, ReferrerLimits, UserAccount, RESERVE_SOL};
+
+/// Outcome of placing a user in a matrix
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlotPlacement {
+    pub slot_idx: usize,   // Slot the user landed in (0, 1, 2)
+    pub completed: bool,   // Third slot filled - the matrix was reset to the new ID
+}
+
+/// Place `user` in the next free slot of `chain`. Filling the third slot completes the
+/// matrix, which is reset right away with `next_chain_id`; the caller must then advance
+/// `ProgramState::next_chain_id`. Returns None if the matrix has no free slot.
+pub fn place_in_matrix(chain: &mut ReferralChain, user: Pubkey, next_chain_id: u32) -> Option<SlotPlacement> {
+    let slot_idx = chain.filled_slots as usize;
+    if slot_idx >= 3 {
+        return None;
+    }
+
+    chain.slots[slot_idx] = user;
+    chain.filled_slots += 1;
+
+    let completed = chain.filled_slots == 3;
+    if completed {
+        chain.id = next_chain_id;
+        chain.slots = [Pubkey::default(); 3];
+        chain.filled_slots = 0;
+    }
+
+    Some(SlotPlacement { slot_idx, completed })
+}
+
+/// Referrer limits in force and the Clock of the instruction. The default has no
+/// limits; completions are still counted, at timestamp 0.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Throttle {
+    pub limits: ReferrerLimits,
+    pub now: i64,
+}
+
+/// Account a record was read from, used by the handlers to write it back
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordRef {
+    Referrer,      // Direct referrer
+    Upline(usize), // Index among the upline pairs passed to the instruction
+}
+
+/// The part of a UserAccount the engine reads and changes
+#[derive(Clone, Copy)]
+pub struct UserRecord {
+    pub key: Pubkey,        // UserAccount PDA
+    pub wallet: Pubkey,     // Owner wallet - the airdrop is notified for it
+    pub payout: Pubkey,     // Receives reserve payments - payout_address or the wallet
+    pub is_base: bool,      // No referrer - top of its tree
+    pub chain: ReferralChain,
+    pub reserved_sol: u64,
+    pub reserve_token: u8,  // Currency of reserved_sol - RESERVE_SOL or a deposit token
+    pub cycles: CycleStats,
+}
+
+impl UserRecord {
+    pub fn new(key: Pubkey, wallet: Pubkey, account: &UserAccount) -> Self {
+        Self {
+            key,
+            wallet,
+            payout: account.payout_address().unwrap_or(wallet),
+            is_base: account.referrer().is_none(),
+            chain: account.chain,
+            reserved_sol: account.reserved_sol,
+            reserve_token: account.reserve_token,
+            cycles: account.cycles,
+        }
+    }
+
+    // Copy the engine's changes back into the account
+    pub fn apply(&self, account: &mut UserAccount) {
+        account.chain = self.chain;
+        account.reserved_sol = self.reserved_sol;
+        account.reserve_token = self.reserve_token;
+        account.cycles = self.cycles;
+    }
+}
+
+/// Actions the handler performs, in order. `depth` is 0 for the direct referrer and
+/// upline index + 1 for the cascade, as in the emitted events. Amounts are in the
+/// currency named by `token`: RESERVE_SOL (lamports) or a deposit token (base units).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Effect {
+    // Persist the record and log the slot it received
+    Write { target: RecordRef, owner: Pubkey, user: Pubkey, slot_idx: u8, chain_id: u32, depth: u8 },
+    // Swap the deposit to DONUT and burn it
+    Burn { owner: Pubkey, chain_id: u32, amount: u64, depth: u8 },
+    // Move the deposit into the vault of its currency, credited to owner
+    Reserve { owner: Pubkey, chain_id: u32, amount: u64, depth: u8, token: u8 },
+    // Release owner's reserve from the vault of its currency to its payout wallet
+    Pay { target: RecordRef, owner: Pubkey, wallet: Pubkey, chain_id: u32, amount: u64, depth: u8, token: u8 },
+    // Matrix completed - notify the airdrop program for the wallet
+    Notify { owner: Pubkey, wallet: Pubkey, chain_id: u32, next_chain_id: u32, depth: u8 },
+    // Deposit left over at the configured depth - Treasury or Refund policy
+    Overflow { owner: Pubkey, chain_id: u32, amount: u64, depth: u8, policy: DepthOverflowPolicy },
+}
+
+/// Result of running the engine for one instruction
+pub struct Outcome {
+    pub effects: Vec<Effect>,
+    pub referrer: Option<UserRecord>,
+    pub uplines: Vec<UserRecord>,   // Uplines reached, indexed like RecordRef::Upline
+    pub next_chain_id: u32,         // New ProgramState::next_chain_id
+    pub current_user: Pubkey,       // Account to place in the next upline matrix
+    pub remaining_deposit: u64,     // Not allocated yet - only for a paused cascade
+    pub token: u8,                  // Currency of the deposit
+    pub next_upline_index: usize,   // Next index in the referrer's stored upline
+}
+
+impl Outcome {
+    fn new(current_user: Pubkey, deposit: u64, token: u8, next_chain_id: u32, next_upline_index: usize) -> Self {
+        Self {
+            effects: Vec::new(),
+            referrer: None,
+            uplines: Vec::new(),
+            next_chain_id,
+            current_user,
+            remaining_deposit: deposit,
+            token,
+            next_upline_index,
+        }
+    }
+
+    pub fn record(&self, target: RecordRef) -> Option<&UserRecord> {
+        match target {
+            RecordRef::Referrer => self.referrer.as_ref(),
+            RecordRef::Upline(index) => self.uplines.get(index),
+        }
+    }
+
+    pub fn notification_count(&self) -> usize {
+        self.effects.iter().filter(|e| matches!(e, Effect::Notify { .. })).count()
+    }
+
+    // The deposit is returned to the registering wallet by the Refund overflow policy
+    pub fn is_refunded(&self) -> bool {
+        self.effects
+            .iter()
+            .any(|e| matches!(e, Effect::Overflow { policy: DepthOverflowPolicy::Refund, .. }))
+    }
+}
+
+/// Place `user` in `record`'s matrix and route `deposit`, paid in `token`, for the slot it
+/// lands in: slot 1 burns, slot 2 reserves, slot 3 pays out the reserve in the currency it
+/// was reserved in and completes the matrix. On completion the deposit is still
+/// unallocated and moves up the upline. Completing the direct referrer's matrix is
+/// subject to the referrer limits; every completion is counted.
+#[allow(clippy::too_many_arguments)]
+pub fn route_slot(
+    record: &mut UserRecord,
+    target: RecordRef,
+    depth: u8,
+    user: Pubkey,
+    deposit: u64,
+    token: u8,
+    next_chain_id: &mut u32,
+    throttle: &Throttle,
+    effects: &mut Vec<Effect>,
+) -> Result<SlotPlacement> {
+    if target == RecordRef::Referrer && record.chain.filled_slots == 2 {
+        throttle.limits.check_cycle(&record.cycles, throttle.now)?;
+    }
+
+    let chain_id = record.chain.id;
+    let placement = place_in_matrix(&mut record.chain, user, *next_chain_id)
+        .ok_or(error!(ErrorCode::MatrixFull))?;
+
+    let write = Effect::Write {
+        target,
+        owner: record.key,
+        user,
+        slot_idx: placement.slot_idx as u8,
+        chain_id,
+        depth,
+    };
+
+    match placement.slot_idx {
+        0 => {
+            effects.push(write);
+            effects.push(Effect::Burn { owner: record.key, chain_id, amount: deposit, depth });
+        }
+        1 => {
+            record.reserved_sol = deposit;
+            record.reserve_token = token;
+            effects.push(write);
+            effects.push(Effect::Reserve { owner: record.key, chain_id, amount: deposit, depth, token });
+        }
+        _ => {
+            if record.reserved_sol > 0 {
+                effects.push(Effect::Pay {
+                    target,
+                    owner: record.key,
+                    wallet: record.payout,
+                    chain_id,
+                    amount: record.reserved_sol,
+                    depth,
+                    token: record.reserve_token,
+                });
+                record.reserved_sol = 0;
+                record.reserve_token = RESERVE_SOL;
+            }
+            effects.push(write);
+            effects.push(Effect::Notify {
+                owner: record.key,
+                wallet: record.wallet,
+                chain_id,
+                next_chain_id: *next_chain_id,
+                depth,
+            });
+            *next_chain_id += 1;
+            throttle.limits.record_cycle(&mut record.cycles, throttle.now);
+        }
+    }
+
+    Ok(placement)
+}
+
+/// Register `user` under `referrer` with `deposit`, paid in `token`. When the referrer's matrix completes,
+/// the deposit cascades through its stored upline: `load_upline(i)` returns the i-th of the
+/// `upline_count` uplines passed to the instruction and is only called for uplines the
+/// cascade reaches. A deposit still unallocated after the last one follows the overflow
+/// policy, or is burned when that upline is a base user.
+#[allow(clippy::too_many_arguments)]
+pub fn register<F>(
+    user: Pubkey,
+    deposit: u64,
+    token: u8,
+    referrer: UserRecord,
+    upline_count: usize,
+    load_upline: F,
+    next_chain_id: u32,
+    overflow_policy: DepthOverflowPolicy,
+    throttle: &Throttle,
+) -> Result<Outcome>
+where
+    F: FnMut(usize) -> Result<UserRecord>,
+{
+    let mut outcome = Outcome::new(referrer.key, deposit, token, next_chain_id, 0);
+    let mut referrer = referrer;
+    let chain_id = referrer.chain.id;
+
+    let placement = route_slot(
+        &mut referrer,
+        RecordRef::Referrer,
+        0,
+        user,
+        deposit,
+        token,
+        &mut outcome.next_chain_id,
+        throttle,
+        &mut outcome.effects,
+    )?;
+    outcome.referrer = Some(referrer);
+
+    if !placement.completed {
+        outcome.remaining_deposit = 0;
+        return Ok(outcome);
+    }
+
+    // Base users have no upline - the deposit is burned in their name
+    if referrer.is_base {
+        outcome.effects.push(Effect::Burn { owner: referrer.key, chain_id, amount: deposit, depth: 0 });
+        outcome.remaining_deposit = 0;
+        return Ok(outcome);
+    }
+
+    require!(upline_count > 0, ErrorCode::UplineRequiredForNonBase);
+
+    cascade(&mut outcome, upline_count, 0, upline_count, load_upline, overflow_policy, throttle)?;
+
+    require!(outcome.remaining_deposit == 0, ErrorCode::UnusedDepositDetected);
+    Ok(outcome)
+}
+
+/// Continue a paused cascade (advance_registration): route `deposit` through the
+/// `pair_count` uplines starting at `start_index` of the referrer's stored upline.
+/// The overflow policy only applies once the stored upline or the configured depth is
+/// exhausted; otherwise the remaining deposit stays pending for the next step.
+#[allow(clippy::too_many_arguments)]
+pub fn advance<F>(
+    current_user: Pubkey,
+    deposit: u64,
+    token: u8,
+    start_index: usize,
+    pair_count: usize,
+    upline_len: usize,
+    upline_depth: usize,
+    load_upline: F,
+    next_chain_id: u32,
+    overflow_policy: DepthOverflowPolicy,
+    throttle: &Throttle,
+) -> Result<Outcome>
+where
+    F: FnMut(usize) -> Result<UserRecord>,
+{
+    let mut outcome = Outcome::new(current_user, deposit, token, next_chain_id, start_index);
+    let limit = upline_len.min(upline_depth);
+
+    cascade(&mut outcome, limit, start_index, pair_count, load_upline, overflow_policy, throttle)?;
+
+    Ok(outcome)
+}
+
+// Walk up to `pair_count` uplines from `start_index` while the deposit keeps completing
+// matrices; settle the leftover once `limit` uplines have been processed.
+fn cascade<F>(
+    outcome: &mut Outcome,
+    limit: usize,
+    start_index: usize,
+    pair_count: usize,
+    mut load_upline: F,
+    overflow_policy: DepthOverflowPolicy,
+    throttle: &Throttle,
+) -> Result<()>
+where
+    F: FnMut(usize) -> Result<UserRecord>,
+{
+    let deposit = outcome.remaining_deposit;
+    let mut last_chain_id = 0;
+    let mut last_upline_is_base = true;
+
+    for i in 0..pair_count {
+        let index = start_index + i;
+        if index >= limit {
+            break;
+        }
+
+        let mut upline = load_upline(i)?;
+        let depth = (index + 1) as u8;
+        last_chain_id = upline.chain.id;
+        last_upline_is_base = upline.is_base;
+
+        let placement = route_slot(
+            &mut upline,
+            RecordRef::Upline(i),
+            depth,
+            outcome.current_user,
+            deposit,
+            outcome.token,
+            &mut outcome.next_chain_id,
+            throttle,
+            &mut outcome.effects,
+        )?;
+        outcome.uplines.push(upline);
+        outcome.next_upline_index = index + 1;
+
+        if !placement.completed {
+            outcome.remaining_deposit = 0;
+            return Ok(());
+        }
+
+        outcome.current_user = upline.key;
+    }
+
+    if outcome.next_upline_index < limit {
+        return Ok(());
+    }
+
+    // Cascade stopped at the configured depth below the top of the tree - apply the policy
+    let policy = if last_upline_is_base { DepthOverflowPolicy::Burn } else { overflow_policy };
+    let owner = outcome.current_user;
+    let depth = outcome.next_upline_index as u8;
+
+    outcome.effects.push(match policy {
+        DepthOverflowPolicy::Burn => Effect::Burn { owner, chain_id: last_chain_id, amount: deposit, depth },
+        _ => Effect::Overflow { owner, chain_id: last_chain_id, amount: deposit, depth, policy },
+    });
+    outcome.remaining_deposit = 0;
+
+    Ok(())
+}
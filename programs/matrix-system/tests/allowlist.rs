@@ -0,0 +1,76 @@
+// set_allowlist: registrations under a referrer need a Merkle proof against the stored
+// root, or an Ed25519 invite from the invite signer earlier in the same transaction.
+
+mod common;
+
+use common::*;
+use matrix_system::{
+    allowlist::{leaf, node},
+    AllowlistMode,
+};
+use solana_sdk::{pubkey::Pubkey, signature::{Keypair, Signer}};
+
+#[tokio::test]
+async fn merkle_root_mode_needs_a_proof_of_the_wallet() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+
+    let (first, second, outsider) = (env.create_user(), env.create_user(), env.create_user());
+    let (first_leaf, second_leaf) = (leaf(&first.wallet.pubkey()), leaf(&second.wallet.pubkey()));
+    env.set_allowlist(AllowlistMode::MerkleRoot, node(&first_leaf, &second_leaf), &Pubkey::default()).await.unwrap();
+
+    assert!(env.register(&first, &base, DEPOSIT).await.is_err());
+    assert!(env.register_allowlisted(&first, &base, DEPOSIT, vec![first_leaf], None).await.is_err());
+    assert!(env.register_allowlisted(&outsider, &base, DEPOSIT, vec![second_leaf], None).await.is_err());
+    assert!(env.account(&outsider.pda).await.is_none());
+
+    env.register_allowlisted(&first, &base, DEPOSIT, vec![second_leaf], None).await.unwrap();
+    env.register_allowlisted(&second, &base, DEPOSIT, vec![first_leaf], None).await.unwrap();
+    assert_eq!(env.user_account(&base.pda).await.chain.filled_slots, 2);
+
+    // The owner's base registrations are not restricted
+    let other_base = env.create_user();
+    env.register_without_referrer(&other_base, DEPOSIT).await.unwrap();
+
+    env.set_allowlist(AllowlistMode::Open, [0; 32], &Pubkey::default()).await.unwrap();
+    env.register(&outsider, &base, DEPOSIT).await.unwrap();
+}
+
+#[tokio::test]
+async fn invite_signer_mode_needs_an_invite_for_the_wallet() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+
+    let invite_signer = Keypair::new();
+    env.set_allowlist(AllowlistMode::InviteSigner, [0; 32], &invite_signer.pubkey()).await.unwrap();
+    assert_eq!(env.program_state().await.invite_signer, invite_signer.pubkey());
+
+    let user = env.create_user();
+    let other = env.create_user();
+    assert!(env.register(&user, &base, DEPOSIT).await.is_err());
+
+    // Signed by another key, or inviting another wallet
+    let forged = invite_instruction(&Keypair::new(), &user.wallet.pubkey());
+    assert!(env.register_allowlisted(&user, &base, DEPOSIT, Vec::new(), Some(forged)).await.is_err());
+    let misdirected = invite_instruction(&invite_signer, &other.wallet.pubkey());
+    assert!(env.register_allowlisted(&user, &base, DEPOSIT, Vec::new(), Some(misdirected)).await.is_err());
+
+    let invite = invite_instruction(&invite_signer, &user.wallet.pubkey());
+    env.register_allowlisted(&user, &base, DEPOSIT, Vec::new(), Some(invite)).await.unwrap();
+    assert!(env.user_account(&user.pda).await.is_registered());
+}
+
+#[tokio::test]
+async fn allowlist_mode_needs_its_root_or_signer() {
+    let mut env = TestEnv::start().await;
+
+    assert!(env.set_allowlist(AllowlistMode::MerkleRoot, [0; 32], &Pubkey::new_unique()).await.is_err());
+    assert!(env.set_allowlist(AllowlistMode::InviteSigner, [7; 32], &Pubkey::default()).await.is_err());
+
+    env.set_allowlist(AllowlistMode::MerkleRoot, [7; 32], &Pubkey::default()).await.unwrap();
+    let state = env.program_state().await;
+    assert_eq!(state.allowlist_mode, AllowlistMode::MerkleRoot);
+    assert_eq!(state.allowlist_root, [7; 32]);
+}
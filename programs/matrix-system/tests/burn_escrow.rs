@@ -0,0 +1,142 @@
+// Burn escrow fallback: while the Meteora pool cannot quote the swap, slot burns are
+// held in the burn escrow PDA instead of reverting the registration, and the
+// permissionless retry_pending_burns swaps and burns them once the pool is back - at
+// most MAX_BURN_RETRY_LAMPORTS per call and never further below the pool's quote than
+// the owner's retry slippage.
+
+mod common;
+
+use common::*;
+use matrix_system::{ErrorCode, MAX_BURN_RETRY_LAMPORTS, MAX_BURN_RETRY_SLIPPAGE_BPS};
+
+const RETRY_SLIPPAGE_BPS: u16 = 100;
+
+#[tokio::test]
+async fn disabled_pool_reverts_the_registration_without_the_escrow() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+
+    env.set_pool_enabled(false).await;
+    let user = env.create_user();
+    assert!(env.register(&user, &base, DEPOSIT).await.is_err());
+    assert_eq!(env.lamports(&burn_escrow()).await, 0);
+}
+
+#[tokio::test]
+async fn escrowed_burn_is_retried_once_the_pool_is_healthy() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    env.set_burn_escrow(true, RETRY_SLIPPAGE_BPS).await.unwrap();
+
+    env.set_pool_enabled(false).await;
+    let user = env.create_user();
+    env.register(&user, &base, DEPOSIT).await.unwrap();
+
+    assert_eq!(env.lamports(&burn_escrow()).await, DEPOSIT);
+    assert_eq!(env.program_state().await.pending_burn_lamports, DEPOSIT);
+    assert_eq!(env.user_account(&base.pda).await.chain.filled_slots, 1);
+
+    // Nothing can be swapped while the pool is still disabled
+    let cranker = env.create_user();
+    assert!(env.retry_pending_burns(&cranker.wallet).await.is_err());
+
+    // Anyone cranks once the pool is back
+    env.set_pool_enabled(true).await;
+    let supply_before = env.donut_supply().await;
+    env.retry_pending_burns(&cranker.wallet).await.unwrap();
+
+    assert_eq!(env.donut_supply().await, supply_before - DEPOSIT * DONUT_PER_LAMPORT);
+    assert_eq!(env.lamports(&burn_escrow()).await, 0);
+    assert_eq!(env.program_state().await.pending_burn_lamports, 0);
+
+    // Nothing left to burn
+    assert!(env.retry_pending_burns(&cranker.wallet).await.is_err());
+}
+
+#[tokio::test]
+async fn swap_below_the_pool_quote_keeps_the_escrow() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    env.set_burn_escrow(true, RETRY_SLIPPAGE_BPS).await.unwrap();
+
+    env.set_pool_enabled(false).await;
+    let user = env.create_user();
+    env.register(&user, &base, DEPOSIT).await.unwrap();
+    env.set_pool_enabled(true).await;
+
+    // The swap returns 2% less than the pool's quote - past the 1% bound
+    env.set_b_vault_lp_amount(LP_SUPPLY * 98 / 100);
+    let cranker = env.create_user();
+    let supply_before = env.donut_supply().await;
+    let err = env.retry_pending_burns(&cranker.wallet).await.unwrap_err();
+    assert_eq!(error_code(err), Some(ErrorCode::BurnSlippageExceeded.into()));
+
+    assert_eq!(env.donut_supply().await, supply_before);
+    assert_eq!(env.lamports(&burn_escrow()).await, DEPOSIT);
+    assert_eq!(env.program_state().await.pending_burn_lamports, DEPOSIT);
+
+    // Half a percent less is within the bound
+    env.set_b_vault_lp_amount(LP_SUPPLY * 995 / 1000);
+    env.retry_pending_burns(&cranker.wallet).await.unwrap();
+    assert_eq!(env.lamports(&burn_escrow()).await, 0);
+}
+
+#[tokio::test]
+async fn retry_slippage_is_bounded() {
+    let mut env = TestEnv::start().await;
+
+    let err = env.set_burn_escrow(true, MAX_BURN_RETRY_SLIPPAGE_BPS + 1).await.unwrap_err();
+    assert_eq!(error_code(err), Some(ErrorCode::InvalidBurnRetrySlippage.into()));
+
+    env.set_burn_escrow(true, MAX_BURN_RETRY_SLIPPAGE_BPS).await.unwrap();
+    let state = env.program_state().await;
+    assert!(state.burn_escrow_enabled);
+    assert_eq!(state.burn_retry_slippage_bps, MAX_BURN_RETRY_SLIPPAGE_BPS);
+}
+
+#[tokio::test]
+async fn one_retry_burns_at_most_the_cap() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    env.set_burn_escrow(true, RETRY_SLIPPAGE_BPS).await.unwrap();
+
+    env.set_pool_enabled(false).await;
+    let user = env.create_user();
+    env.register(&user, &base, DEPOSIT).await.unwrap();
+    env.set_pool_enabled(true).await;
+
+    // Escrowed burns grown past the cap
+    env.transfer(&burn_escrow(), MAX_BURN_RETRY_LAMPORTS).await.unwrap();
+    let mut state = env.program_state().await;
+    state.pending_burn_lamports += MAX_BURN_RETRY_LAMPORTS;
+    env.set_program_state(&state);
+
+    let cranker = env.create_user();
+    env.retry_pending_burns(&cranker.wallet).await.unwrap();
+    assert_eq!(env.lamports(&burn_escrow()).await, DEPOSIT);
+    assert_eq!(env.program_state().await.pending_burn_lamports, DEPOSIT);
+
+    env.retry_pending_burns(&cranker.wallet).await.unwrap();
+    assert_eq!(env.lamports(&burn_escrow()).await, 0);
+    assert_eq!(env.program_state().await.pending_burn_lamports, 0);
+}
+
+#[tokio::test]
+async fn healthy_pool_burns_directly_with_the_escrow_enabled() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    env.set_burn_escrow(true, RETRY_SLIPPAGE_BPS).await.unwrap();
+
+    let supply_before = env.donut_supply().await;
+    let user = env.create_user();
+    env.register(&user, &base, DEPOSIT).await.unwrap();
+
+    assert!(env.donut_supply().await < supply_before);
+    assert_eq!(env.lamports(&burn_escrow()).await, 0);
+    assert_eq!(env.program_state().await.pending_burn_lamports, 0);
+}
@@ -0,0 +1,93 @@
+// schedule_campaign / extend_campaign / close_campaign: registrations under a referrer
+// are accepted only inside the campaign window and below its registration cap.
+
+mod common;
+
+use common::*;
+
+const HOUR: i64 = 3600;
+
+#[tokio::test]
+async fn registrations_follow_the_campaign_window() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+
+    let now = env.unix_timestamp().await;
+    env.schedule_campaign(now + HOUR, now + 2 * HOUR, 0).await.unwrap();
+    let campaign = env.program_state().await.campaign;
+    assert_eq!(campaign.id, 1);
+    assert_eq!(campaign.first_upline_id, 2);
+
+    let user = env.create_user();
+    assert!(env.register(&user, &base, DEPOSIT).await.is_err());
+    assert!(env.account(&user.pda).await.is_none());
+
+    env.set_unix_timestamp(now + HOUR).await;
+    env.register(&user, &base, DEPOSIT).await.unwrap();
+
+    // The end is exclusive
+    env.set_unix_timestamp(now + 2 * HOUR).await;
+    let late = env.create_user();
+    assert!(env.register(&late, &base, DEPOSIT).await.is_err());
+
+    // The owner's base registrations are not restricted
+    let other_base = env.create_user();
+    env.register_without_referrer(&other_base, DEPOSIT).await.unwrap();
+
+    // An ended campaign can be replaced, a running one cannot
+    env.schedule_campaign(now, now + 3 * HOUR, 0).await.unwrap();
+    assert!(env.schedule_campaign(now, now + 4 * HOUR, 0).await.is_err());
+    env.register(&late, &base, DEPOSIT).await.unwrap();
+    assert_eq!(env.program_state().await.campaign.id, 2);
+}
+
+#[tokio::test]
+async fn campaign_cap_counts_upline_ids() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+
+    let now = env.unix_timestamp().await;
+    env.schedule_campaign(now, now + HOUR, 2).await.unwrap();
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &base, DEPOSIT).await.unwrap();
+    }
+    let user = env.create_user();
+    assert!(env.register(&user, &base, DEPOSIT).await.is_err());
+
+    // Raising the cap reopens it, lowering it or ending earlier is rejected
+    assert!(env.extend_campaign(now + HOUR, 1).await.is_err());
+    assert!(env.extend_campaign(now + HOUR - 1, 3).await.is_err());
+    env.extend_campaign(now + 2 * HOUR, 3).await.unwrap();
+    env.register(&user, &base, DEPOSIT).await.unwrap();
+
+    let campaign = env.program_state().await.campaign;
+    assert_eq!(campaign.end_timestamp, now + 2 * HOUR);
+    assert_eq!(campaign.registrations(env.program_state().await.next_upline_id), 3);
+}
+
+#[tokio::test]
+async fn closed_campaign_rejects_registrations_until_the_next_one() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+
+    let now = env.unix_timestamp().await;
+    assert!(env.close_campaign().await.is_err());
+    assert!(env.schedule_campaign(now + HOUR, now, 0).await.is_err());
+
+    env.schedule_campaign(now, now + HOUR, 0).await.unwrap();
+    env.close_campaign().await.unwrap();
+    let campaign = env.program_state().await.campaign;
+    assert!(campaign.closed);
+    assert_eq!(campaign.end_timestamp, now);
+
+    let user = env.create_user();
+    assert!(env.register(&user, &base, DEPOSIT).await.is_err());
+    assert!(env.extend_campaign(now + 2 * HOUR, 0).await.is_err());
+
+    env.schedule_campaign(now, now + HOUR, 0).await.unwrap();
+    env.register(&user, &base, DEPOSIT).await.unwrap();
+}
@@ -0,0 +1,334 @@
+// Property tests for the matrix placement and upline cascade invariants. Random trees of
+// in-memory UserAccounts register through matrix::register, as register_with_sol_deposit
+// does; the effects it returns are settled against a lamport ledger in order, the way the
+// handlers execute them, and the records are written back on Effect::Write.
+
+use std::collections::{HashMap, HashSet};
+
+use anchor_lang::prelude::Pubkey;
+use bytemuck::Zeroable;
+use matrix_system::matrix::{self, Effect, Throttle, UserRecord};
+use matrix_system::{
+    initialize_base_user_data, initialize_referred_user_data, place_in_matrix, DepthOverflowPolicy, UplineEntry,
+    UserAccount, RESERVE_SOL,
+};
+use proptest::prelude::*;
+
+struct TreeUser {
+    pda: Pubkey,
+    wallet: Pubkey,
+    account: UserAccount,
+}
+
+// Where the deposits went, in lamports
+#[derive(Default)]
+struct Ledger {
+    deposited: u64,
+    vault: u64,
+    burned: u64,
+    paid: HashMap<Pubkey, u64>, // By payout wallet
+    treasury: u64,
+    refunded: u64,
+}
+
+struct Tree {
+    users: Vec<TreeUser>,
+    index: HashMap<Pubkey, usize>,
+    next_upline_id: u32,
+    next_chain_id: u32,
+    upline_depth: usize,
+    policy: DepthOverflowPolicy,
+    ledger: Ledger,
+}
+
+impl Tree {
+    fn new(upline_depth: usize, policy: DepthOverflowPolicy) -> Self {
+        Self {
+            users: Vec::new(),
+            index: HashMap::new(),
+            next_upline_id: 1,
+            next_chain_id: 1,
+            upline_depth,
+            policy,
+            ledger: Ledger::default(),
+        }
+    }
+
+    fn take_ids(&mut self) -> (u32, u32) {
+        let ids = (self.next_upline_id, self.next_chain_id);
+        self.next_upline_id += 1;
+        self.next_chain_id += 1;
+        ids
+    }
+
+    fn push(&mut self, wallet: Pubkey, account: UserAccount) {
+        let pda = Pubkey::new_unique();
+        self.index.insert(pda, self.users.len());
+        self.users.push(TreeUser { pda, wallet, account });
+    }
+
+    fn user(&self, pda: &Pubkey) -> &TreeUser {
+        &self.users[self.index[pda]]
+    }
+
+    // register_without_referrer burns the whole deposit - there is no matrix to route it through
+    fn register_base(&mut self, deposit: u64) {
+        let wallet = Pubkey::new_unique();
+        let (upline_id, chain_id) = self.take_ids();
+        let mut account = UserAccount::zeroed();
+        initialize_base_user_data(&mut account, &wallet, upline_id, chain_id).unwrap();
+        self.push(wallet, account);
+
+        self.ledger.deposited += deposit;
+        self.ledger.burned += deposit;
+    }
+
+    // Register a new user under `referrer_idx` and settle the engine's effects
+    fn register(&mut self, referrer_idx: usize, deposit: u64) -> Vec<Effect> {
+        let wallet = Pubkey::new_unique();
+        let (upline_id, chain_id) = self.take_ids();
+        let referrer = &self.users[referrer_idx];
+        let referrer_entry = UplineEntry { pda: referrer.pda, wallet: referrer.wallet };
+
+        let mut account = UserAccount::zeroed();
+        initialize_referred_user_data(
+            &mut account,
+            &wallet,
+            &referrer.pda,
+            &referrer.account,
+            referrer_entry,
+            upline_id,
+            chain_id,
+            self.upline_depth,
+        )
+        .unwrap();
+        self.push(wallet, account);
+        let user = self.users.last().unwrap().pda;
+        self.ledger.deposited += deposit;
+
+        // The client sends the referrer's stored uplines up to the configured depth
+        let referrer = &self.users[referrer_idx];
+        let referrer_record = UserRecord::new(referrer.pda, referrer.wallet, &referrer.account);
+        let stored: Vec<UplineEntry> = referrer.account.upline.entries().to_vec();
+        let upline_count = stored.len().min(self.upline_depth);
+
+        let mut loaded = Vec::new();
+        let outcome = matrix::register(
+            user,
+            deposit,
+            RESERVE_SOL,
+            referrer_record,
+            upline_count,
+            |i| {
+                loaded.push(i);
+                let upline = self.user(&stored[i].pda);
+                Ok(UserRecord::new(upline.pda, upline.wallet, &upline.account))
+            },
+            self.next_chain_id,
+            self.policy,
+            &Throttle::default(),
+        )
+        .unwrap();
+
+        // Uplines are read in stored order and only while the deposit keeps completing matrices
+        assert_eq!(loaded, (0..outcome.uplines.len()).collect::<Vec<_>>());
+        assert_eq!(outcome.remaining_deposit, 0);
+
+        let mut allocated = Vec::new();
+        let mut next_chain_id = self.next_chain_id;
+        let mut placed_user = user;
+        let mut last_depth = 0;
+
+        for effect in &outcome.effects {
+            match *effect {
+                Effect::Write { target, owner, user: placed, slot_idx, chain_id, depth } => {
+                    let record = outcome.record(target).unwrap();
+                    assert_eq!(record.key, owner);
+                    assert_eq!(placed, placed_user, "each upline receives the matrix completed below it");
+                    assert!(slot_idx < 3);
+                    assert_eq!(chain_id, self.user(&owner).account.chain.id);
+                    assert!(depth >= last_depth);
+                    last_depth = depth;
+
+                    let idx = self.index[&owner];
+                    record.apply(&mut self.users[idx].account);
+                }
+                Effect::Burn { amount, .. } => {
+                    self.ledger.burned += amount;
+                    allocated.push(amount);
+                }
+                Effect::Reserve { owner, amount, token, .. } => {
+                    assert_eq!(token, RESERVE_SOL);
+                    assert_eq!(self.user(&owner).account.reserved_sol, amount, "reserve credited to its owner");
+                    self.ledger.vault += amount;
+                    allocated.push(amount);
+                }
+                Effect::Pay { owner, wallet, amount, token, .. } => {
+                    // Paid before the record is written back - it still holds the reserve
+                    let upline = self.user(&owner);
+                    assert_eq!(token, RESERVE_SOL);
+                    assert_eq!(wallet, upline.wallet);
+                    assert_eq!(amount, upline.account.reserved_sol, "pay exactly the stored reserve");
+                    self.ledger.vault = self.ledger.vault.checked_sub(amount).expect("vault underflow");
+                    *self.ledger.paid.entry(wallet).or_default() += amount;
+                }
+                Effect::Notify { owner, wallet, next_chain_id: new_id, .. } => {
+                    assert_eq!(wallet, self.user(&owner).wallet);
+                    assert_eq!(new_id, next_chain_id, "completed matrices take consecutive ids");
+                    assert_eq!(self.user(&owner).account.chain.id, new_id);
+                    next_chain_id += 1;
+                    placed_user = owner;
+                }
+                Effect::Overflow { amount, policy, .. } => {
+                    assert_eq!(policy, self.policy);
+                    match policy {
+                        DepthOverflowPolicy::Treasury => self.ledger.treasury += amount,
+                        DepthOverflowPolicy::Refund => self.ledger.refunded += amount,
+                        DepthOverflowPolicy::Burn => panic!("a burn overflow is an Effect::Burn"),
+                    }
+                    allocated.push(amount);
+                }
+            }
+        }
+
+        assert_eq!(allocated, vec![deposit], "the deposit is allocated exactly once");
+        assert_eq!(outcome.next_chain_id, next_chain_id);
+        assert_eq!(outcome.notification_count() as u32, next_chain_id - self.next_chain_id);
+        self.next_chain_id = outcome.next_chain_id;
+
+        outcome.effects
+    }
+
+    fn check_invariants(&self) {
+        let mut chain_ids = HashSet::new();
+        let mut reserved_total = 0;
+
+        for user in &self.users {
+            let chain = &user.account.chain;
+            assert!(chain.filled_slots < 3, "filled_slots must reset on completion");
+            assert!(chain_ids.insert(chain.id), "duplicate chain id {}", chain.id);
+            assert!(chain.id < self.next_chain_id);
+            for (i, slot) in chain.slots.iter().enumerate() {
+                assert_eq!(*slot != Pubkey::default(), i < chain.filled_slots as usize);
+            }
+
+            // A reserve only exists while the second slot is the last one filled
+            if user.account.reserved_sol > 0 {
+                assert_eq!(chain.filled_slots, 2);
+            }
+            reserved_total += user.account.reserved_sol;
+
+            assert!(user.account.upline.count as usize <= self.upline_depth);
+        }
+
+        let ledger = &self.ledger;
+        let paid: u64 = ledger.paid.values().sum();
+        assert_eq!(ledger.vault, reserved_total, "vault must hold exactly the reserved SOL");
+        assert_eq!(
+            ledger.deposited,
+            ledger.burned + ledger.vault + paid + ledger.treasury + ledger.refunded,
+            "every deposited lamport is burned, reserved, paid or returned"
+        );
+    }
+}
+
+fn policy_strategy() -> impl Strategy<Value = DepthOverflowPolicy> {
+    prop_oneof![
+        Just(DepthOverflowPolicy::Burn),
+        Just(DepthOverflowPolicy::Treasury),
+        Just(DepthOverflowPolicy::Refund),
+    ]
+}
+
+// A registration: either a new base user, or a user under an existing user picked by index
+fn registration_strategy() -> impl Strategy<Value = (bool, prop::sample::Index, u64)> {
+    (prop::bool::weighted(0.05), any::<prop::sample::Index>(), 66_666_667u64..10_000_000_000)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+
+    #[test]
+    fn cascade_preserves_invariants(
+        upline_depth in 1usize..=6,
+        policy in policy_strategy(),
+        registrations in prop::collection::vec(registration_strategy(), 1..300),
+    ) {
+        let mut tree = Tree::new(upline_depth, policy);
+        tree.register_base(registrations[0].2);
+
+        for (new_base, referrer, deposit) in registrations {
+            if new_base {
+                tree.register_base(deposit);
+            } else {
+                let referrer_idx = referrer.index(tree.users.len());
+                tree.register(referrer_idx, deposit);
+            }
+            tree.check_invariants();
+        }
+    }
+
+    // Chains of single referrals make every slot 3 cascade walk the whole upline
+    #[test]
+    fn deep_lines_reach_every_depth(
+        upline_depth in 1usize..=6,
+        policy in policy_strategy(),
+        line_length in 1usize..12,
+        deposit in 66_666_667u64..1_000_000_000,
+    ) {
+        let mut tree = Tree::new(upline_depth, policy);
+        tree.register_base(deposit);
+
+        let mut line = vec![0];
+        for _ in 0..line_length {
+            tree.register(*line.last().unwrap(), deposit);
+            line.push(tree.users.len() - 1);
+        }
+
+        // Fill every matrix of the line bottom-up, several rounds
+        let mut deepest = 0;
+        for _ in 0..9 {
+            for &idx in line.iter().rev() {
+                let effects = tree.register(idx, deposit);
+                tree.check_invariants();
+
+                for effect in effects {
+                    if let Effect::Overflow { depth, .. } | Effect::Burn { depth, .. } = effect {
+                        deepest = deepest.max(depth as usize);
+                    }
+                }
+            }
+        }
+
+        // The cascade never settles past the top of the line or the configured depth
+        prop_assert!(deepest <= line_length.min(upline_depth));
+    }
+}
+
+#[test]
+fn placement_fills_slots_then_resets() {
+    let mut account = UserAccount::zeroed();
+    initialize_base_user_data(&mut account, &Pubkey::new_unique(), 1, 1).unwrap();
+
+    for expected_slot in 0..3 {
+        let user = Pubkey::new_unique();
+        let placement = place_in_matrix(&mut account.chain, user, 7).unwrap();
+        assert_eq!(placement.slot_idx, expected_slot);
+        assert_eq!(placement.completed, expected_slot == 2);
+        if !placement.completed {
+            assert_eq!(account.chain.slots[expected_slot], user);
+            assert_eq!(account.chain.id, 1);
+        }
+    }
+
+    assert_eq!(account.chain.id, 7);
+    assert_eq!(account.chain.filled_slots, 0);
+    assert_eq!(account.chain.slots, [Pubkey::default(); 3]);
+}
+
+#[test]
+fn placement_rejects_full_matrix() {
+    let mut account = UserAccount::zeroed();
+    account.chain.filled_slots = 3;
+    assert_eq!(place_in_matrix(&mut account.chain, Pubkey::new_unique(), 2), None);
+}
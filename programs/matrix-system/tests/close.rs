@@ -0,0 +1,92 @@
+// close_user_account: only an account nobody depends on can be closed, the rent goes
+// back to the wallet and the tombstone keeps the wallet from registering again.
+
+mod common;
+
+use anchor_lang::{AccountDeserialize, Discriminator};
+use common::*;
+use matrix_system::{ClosedUserAccount, USER_FLAG_COUNTS_REFERRALS};
+use solana_sdk::signature::Signer;
+
+#[tokio::test]
+async fn leaf_closes_and_cannot_register_again() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let leaf = env.create_user();
+    env.register(&leaf, &base, DEPOSIT).await.unwrap();
+
+    assert_eq!(env.user_account(&base.pda).await.referral_count, 1);
+    let leaf_account = env.user_account(&leaf.pda).await;
+    assert_eq!(leaf_account.flags & USER_FLAG_COUNTS_REFERRALS, USER_FLAG_COUNTS_REFERRALS);
+    assert_eq!(leaf_account.referral_count, 0);
+
+    let rent_before = env.lamports(&leaf.pda).await;
+    let wallet_before = env.lamports(&leaf.wallet.pubkey()).await;
+    env.close_user_account(&leaf).await.unwrap();
+
+    let account = env.account(&leaf.pda).await.unwrap();
+    assert_eq!(account.owner, matrix_system::ID);
+    assert_eq!(account.data.len(), 8 + ClosedUserAccount::SIZE);
+    assert_eq!(account.data[..8], ClosedUserAccount::DISCRIMINATOR);
+    let tombstone = ClosedUserAccount::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(tombstone.owner_wallet, leaf.wallet.pubkey());
+    assert_eq!(tombstone.referrer, base.pda);
+
+    // The transaction fee is paid by the test payer
+    let reclaimed = rent_before - account.lamports;
+    assert!(reclaimed > 0);
+    assert_eq!(env.lamports(&leaf.wallet.pubkey()).await, wallet_before + reclaimed);
+
+    // Neither under the old referrer nor under a new one
+    assert!(env.register(&leaf, &base, DEPOSIT).await.is_err());
+    let other = env.create_user();
+    env.register_without_referrer(&other, DEPOSIT).await.unwrap();
+    assert!(env.register(&leaf, &other, DEPOSIT).await.is_err());
+    assert!(env.close_user_account(&leaf).await.is_err());
+}
+
+#[tokio::test]
+async fn referrer_and_filled_matrix_cannot_close() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let referrer = env.create_user();
+    env.register(&referrer, &base, DEPOSIT).await.unwrap();
+    let referred = env.create_user();
+    env.register(&referred, &referrer, DEPOSIT).await.unwrap();
+
+    // base has a filled slot, referrer has a downline
+    assert!(env.close_user_account(&base).await.is_err());
+    assert!(env.close_user_account(&referrer).await.is_err());
+
+    // The referral count stays after the referral closes
+    env.close_user_account(&referred).await.unwrap();
+    assert_eq!(env.user_account(&referrer.pda).await.referral_count, 1);
+    assert!(env.close_user_account(&referrer).await.is_err());
+}
+
+#[tokio::test]
+async fn untracked_account_cannot_close() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let leaf = env.create_user();
+    env.register(&leaf, &base, DEPOSIT).await.unwrap();
+
+    // Migrated accounts do not know their referrals
+    let mut account = env.user_account(&leaf.pda).await;
+    account.flags = 0;
+    env.set_user_account(&leaf.pda, &account).await;
+    assert!(env.close_user_account(&leaf).await.is_err());
+
+    // Someone else's wallet cannot close it either
+    account.flags = USER_FLAG_COUNTS_REFERRALS;
+    env.set_user_account(&leaf.pda, &account).await;
+    let stranger = env.create_user();
+    let mut impostor = env.create_user();
+    impostor.wallet = stranger.wallet;
+    impostor.pda = leaf.pda;
+    assert!(env.close_user_account(&impostor).await.is_err());
+    env.close_user_account(&leaf).await.unwrap();
+}
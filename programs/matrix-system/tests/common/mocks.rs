@@ -0,0 +1,208 @@
+// Stand-in programs loaded into solana-program-test at the addresses hardcoded in
+// matrix_system. They implement just enough of each external interface for the
+// registration flows: the Meteora swap CPI (AMM + vault), the Chainlink store query
+// and the airdrop notify_matrix_completion entrypoint.
+
+use anchor_lang::{prelude::borsh, AnchorDeserialize};
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program::{invoke, invoke_signed, set_return_data},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+// Base key used by Meteora to derive vault PDAs: ["vault", token_mint, base]
+pub const METEORA_VAULT_BASE: Pubkey = solana_program::pubkey!("HWzXGcGHy4tcpYfaRDCyLNzXqBTv3E6BttpCH2vJxArv");
+
+// sha256("global:swap")[0..8] - same discriminator matrix_system sends
+pub const SWAP_DISCRIMINATOR: [u8; 8] = [248, 198, 158, 145, 225, 117, 135, 200];
+
+// Same discriminator matrix_system sends to the airdrop program
+pub const NOTIFY_MATRIX_COMPLETION_DISCRIMINATOR: [u8; 8] = [88, 30, 2, 65, 55, 218, 137, 194];
+
+// Offsets of the Meteora accounts read by matrix_system
+pub const POOL_ENABLED_OFFSET: usize = 8 + 225;
+pub const VAULT_TOTAL_AMOUNT_OFFSET: usize = 11;
+
+// Airdrop program_state layout: fields read by matrix_system plus mock-only counters
+pub const AIRDROP_CURRENT_WEEK_OFFSET: usize = 72;
+pub const AIRDROP_START_TIMESTAMP_OFFSET: usize = 104;
+pub const AIRDROP_NOTIFICATION_COUNT_OFFSET: usize = 112;
+pub const AIRDROP_LAST_NOTIFICATION_COUNT_OFFSET: usize = 120;
+pub const AIRDROP_PROGRAM_STATE_LEN: usize = 128;
+// Length of an airdrop week as matrix_system computes the actual week
+pub const AIRDROP_WEEK_SECONDS: i64 = 1800;
+
+// Chainlink feed layout used by the mock store: decimals, then the Borsh Round
+// (round_id: u32, slot: u64, timestamp: u32, answer: i128)
+pub const CHAINLINK_FEED_LEN: usize = 1 + 4 + 8 + 4 + 16;
+
+// chainlink_solana prefixes every query with this discriminator, then the Borsh Query
+const QUERY_INSTRUCTION_DISCRIMINATOR: [u8; 8] = [0x27, 0xfb, 0x82, 0x9f, 0x2e, 0x88, 0xa4, 0xa9];
+
+// Same variants, in the same order, as the Query enum of chainlink_solana
+#[derive(AnchorDeserialize)]
+enum Query {
+    Version,
+    Decimals,
+    Description,
+    RoundData { round_id: u32 },
+    LatestRoundData,
+    Aggregator,
+}
+
+fn read_u64(account: &AccountInfo, offset: usize) -> Result<u64, ProgramError> {
+    let data = account.try_borrow_data()?;
+    let bytes = data
+        .get(offset..offset + 8)
+        .ok_or(ProgramError::InvalidAccountData)?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+// Meteora dynamic AMM swap: pulls WSOL from the user into the B token vault and asks
+// the vault program to release DONUT from the A token vault at the vault A/B ratio.
+pub fn meteora_amm(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    if data.len() < 24 || data[..8] != SWAP_DISCRIMINATOR {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let amount_in = u64::from_le_bytes(data[8..16].try_into().unwrap());
+    let minimum_out = u64::from_le_bytes(data[16..24].try_into().unwrap());
+
+    let [pool, user_source, user_destination, a_vault, b_vault, a_token_vault, b_token_vault, _a_vault_lp_mint, _b_vault_lp_mint, _a_vault_lp, _b_vault_lp, _protocol_token_fee, user, vault_program, token_program] =
+        accounts
+    else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+
+    if pool.try_borrow_data()?.get(POOL_ENABLED_OFFSET).copied().unwrap_or(0) == 0 {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let a_total = read_u64(a_vault, VAULT_TOTAL_AMOUNT_OFFSET)?;
+    let b_total = read_u64(b_vault, VAULT_TOTAL_AMOUNT_OFFSET)?;
+    let amount_out = (amount_in as u128 * a_total as u128 / b_total as u128) as u64;
+    if amount_out < minimum_out {
+        return Err(ProgramError::Custom(0x1771)); // Meteora ExceededSlippage
+    }
+
+    invoke(
+        &spl_token::instruction::transfer(
+            token_program.key,
+            user_source.key,
+            b_token_vault.key,
+            user.key,
+            &[],
+            amount_in,
+        )?,
+        &[user_source.clone(), b_token_vault.clone(), user.clone(), token_program.clone()],
+    )?;
+
+    invoke(
+        &Instruction {
+            program_id: *vault_program.key,
+            accounts: vec![
+                AccountMeta::new_readonly(*a_vault.key, false),
+                AccountMeta::new(*a_token_vault.key, false),
+                AccountMeta::new(*user_destination.key, false),
+                AccountMeta::new_readonly(*token_program.key, false),
+            ],
+            data: amount_out.to_le_bytes().to_vec(),
+        },
+        &[
+            a_vault.clone(),
+            a_token_vault.clone(),
+            user_destination.clone(),
+            token_program.clone(),
+            vault_program.clone(),
+        ],
+    )
+}
+
+// Meteora vault withdraw: the vault PDA signs a transfer out of its token vault
+pub fn meteora_vault(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let [vault, token_vault, destination, token_program] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+    let amount = u64::from_le_bytes(
+        data.get(..8)
+            .ok_or(ProgramError::InvalidInstructionData)?
+            .try_into()
+            .unwrap(),
+    );
+
+    let token_mint = Pubkey::try_from(&token_vault.try_borrow_data()?[..32])
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+    let (expected_vault, bump) = Pubkey::find_program_address(
+        &[b"vault", token_mint.as_ref(), METEORA_VAULT_BASE.as_ref()],
+        program_id,
+    );
+    if expected_vault != *vault.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            token_program.key,
+            token_vault.key,
+            destination.key,
+            vault.key,
+            &[],
+            amount,
+        )?,
+        &[token_vault.clone(), destination.clone(), vault.clone(), token_program.clone()],
+        &[&[b"vault", token_mint.as_ref(), METEORA_VAULT_BASE.as_ref(), &[bump]]],
+    )
+}
+
+// Chainlink store: answers Decimals and LatestRoundData queries from the feed account
+// through return data, which is what chainlink_solana reads after its CPI.
+pub fn chainlink_store(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let feed = accounts.first().ok_or(ProgramError::NotEnoughAccountKeys)?;
+    if feed.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    let feed_data = feed.try_borrow_data()?;
+    if feed_data.len() < CHAINLINK_FEED_LEN {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let query = data
+        .strip_prefix(&QUERY_INSTRUCTION_DISCRIMINATOR)
+        .and_then(|mut query| Query::deserialize(&mut query).ok())
+        .ok_or(ProgramError::InvalidInstructionData)?;
+    match query {
+        Query::Decimals => set_return_data(&feed_data[..1]),
+        Query::LatestRoundData => set_return_data(&feed_data[1..CHAINLINK_FEED_LEN]),
+        _ => return Err(ProgramError::InvalidInstructionData),
+    }
+    Ok(())
+}
+
+fn increment_u64(account: &AccountInfo, offset: usize) -> ProgramResult {
+    let count = read_u64(account, offset)?;
+    account.try_borrow_mut_data()?[offset..offset + 8].copy_from_slice(&(count + 1).to_le_bytes());
+    Ok(())
+}
+
+// Airdrop notify_matrix_completion: counts notifications, and those flagged as the last
+// of their registration, in program_state
+pub fn airdrop(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    if data.len() < 11 || data[..8] != NOTIFY_MATRIX_COMPLETION_DISCRIMINATOR {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    let program_state = accounts.first().ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let user_account = accounts.get(2).ok_or(ProgramError::NotEnoughAccountKeys)?;
+    if program_state.owner != program_id || user_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    increment_u64(program_state, AIRDROP_NOTIFICATION_COUNT_OFFSET)?;
+    if data[8] == 1 {
+        increment_u64(program_state, AIRDROP_LAST_NOTIFICATION_COUNT_OFFSET)?;
+    }
+    Ok(())
+}
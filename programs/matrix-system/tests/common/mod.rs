@@ -0,0 +1,1260 @@
+// Offline test harness: loads matrix_system and the mock Meteora, Chainlink and
+// airdrop programs into solana-program-test, seeds the fixed accounts the program
+// verifies and builds the registration instructions.
+
+#![allow(dead_code)]
+
+pub mod mocks;
+
+use anchor_lang::{AccountDeserialize, AccountSerialize, AnchorDeserialize, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::get_associated_token_address;
+use matrix_system::{
+    airdrop_addresses::AIRDROP_ACCOUNT, allowlist::invite_message, verified_addresses::*, AllowlistMode, Campaign, DepthOverflowPolicy,
+    PendingRegistration, ProgramState, ReferralCode, ReferrerLimits,
+    TokenDepositConfig, UplineEntry, UserAccount, UserAccountRedirect, VaultAudit, RESERVE_SOL,
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    sysvar,
+};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    clock::Clock,
+    compute_budget::ComputeBudgetInstruction,
+    signature::{Keypair, Signer},
+    system_program,
+    transaction::{Transaction, TransactionError},
+};
+
+// Pool reserves: 1_000_000 DONUT per SOL
+pub const DONUT_RESERVE: u64 = 1_000_000_000_000_000_000;
+pub const SOL_RESERVE: u64 = 1_000_000_000_000;
+pub const LP_SUPPLY: u64 = 1_000_000_000;
+// DONUT the swap returns per lamport at the starting price
+pub const DONUT_PER_LAMPORT: u64 = DONUT_RESERVE / SOL_RESERVE;
+
+// Chainlink answer: $150 with 8 decimals - minimum deposit is 10 / 150 SOL
+pub const SOL_USD_PRICE: i128 = 150_00000000;
+pub const DEPOSIT: u64 = 100_000_000;
+
+// Stablecoin deposit token with 6 decimals, priced at the same $150 per SOL in its pool
+pub const USDC: u8 = 1;
+pub const USDC_RESERVE: u64 = 150_000_000_000;
+pub const USDC_MIN_DEPOSIT: u64 = 10_000_000;
+pub const USDC_DEPOSIT: u64 = 15_000_000;
+
+const WALLET_LAMPORTS: u64 = 10_000_000_000;
+const TOKEN_ACCOUNT_RENT: u64 = 2_039_280;
+const MINT_RENT: u64 = 1_461_600;
+
+// anchor's entry takes a single lifetime for the slice and the accounts. The slice
+// outlives this call, so widening its lifetime for the call only is sound - the same
+// transmute anchor's own program-test examples use.
+fn matrix_entry<'info>(
+    program_id: &Pubkey,
+    accounts: &[solana_program::account_info::AccountInfo<'info>],
+    data: &[u8],
+) -> solana_program::entrypoint::ProgramResult {
+    let accounts = unsafe {
+        std::mem::transmute::<
+            &[solana_program::account_info::AccountInfo<'info>],
+            &'info [solana_program::account_info::AccountInfo<'info>],
+        >(accounts)
+    };
+    matrix_system::entry(program_id, accounts, data)
+}
+
+pub struct TestUser {
+    pub wallet: Keypair,
+    pub pda: Pubkey,
+    pub wsol: Pubkey,
+    pub donut: Pubkey,
+}
+
+// Mint and Meteora DONUT/token pool accounts of a deposit token
+#[derive(Clone, Copy)]
+pub struct DepositToken {
+    pub token: u8,
+    pub mint: Pubkey,
+    pub pool: Pubkey,
+    pub b_vault: Pubkey,
+    pub b_token_vault: Pubkey,
+    pub b_vault_lp_mint: Pubkey,
+    pub b_vault_lp: Pubkey,
+    pub protocol_token_fee: Pubkey,
+    pub a_vault_lp: Pubkey, // The pool's own LP account of the shared DONUT vault A
+}
+
+pub struct TestEnv {
+    pub context: ProgramTestContext,
+    pub state: Pubkey,
+}
+
+pub fn user_pda(wallet: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"user_account", wallet.as_ref()], &matrix_system::ID).0
+}
+
+pub fn program_sol_vault() -> Pubkey {
+    Pubkey::find_program_address(&[b"program_sol_vault"], &matrix_system::ID).0
+}
+
+pub fn burn_escrow() -> Pubkey {
+    Pubkey::find_program_address(&[b"burn_escrow"], &matrix_system::ID).0
+}
+
+pub fn pending_registration(wallet: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"pending_registration", wallet.as_ref()], &matrix_system::ID).0
+}
+
+pub fn event_authority() -> Pubkey {
+    Pubkey::find_program_address(&[b"__event_authority"], &matrix_system::ID).0
+}
+
+pub fn token_deposit_config(token: u8) -> Pubkey {
+    Pubkey::find_program_address(&[b"token_deposit", &[token]], &matrix_system::ID).0
+}
+
+pub fn token_reserve_vault(token: u8) -> Pubkey {
+    Pubkey::find_program_address(&[b"token_reserve_vault", &[token]], &matrix_system::ID).0
+}
+
+pub fn referral_code(code: &str) -> Pubkey {
+    Pubkey::find_program_address(&[b"referral_code", code.as_bytes()], &matrix_system::ID).0
+}
+
+// Ed25519 program instruction carrying the invite of `wallet` signed by `signer`, with
+// every offset pointing into the instruction itself
+pub fn invite_instruction(signer: &Keypair, wallet: &Pubkey) -> Instruction {
+    let message = invite_message(wallet);
+    let signature = signer.sign_message(&message);
+    let (public_key_offset, signature_offset, message_offset) = (16u16, 48u16, 112u16);
+
+    let mut data = vec![1, 0];
+    for value in [signature_offset, u16::MAX, public_key_offset, u16::MAX, message_offset, message.len() as u16, u16::MAX] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(signer.pubkey().as_ref());
+    data.extend_from_slice(signature.as_ref());
+    data.extend_from_slice(&message);
+    Instruction { program_id: solana_sdk::ed25519_program::ID, accounts: vec![], data }
+}
+
+pub fn airdrop_program_state() -> Pubkey {
+    Pubkey::find_program_address(&[b"program_state"], &AIRDROP_ACCOUNT).0
+}
+
+pub fn airdrop_user_pda(wallet: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"user_account", wallet.as_ref()], &AIRDROP_ACCOUNT).0
+}
+
+pub fn airdrop_week_pda(week: u8) -> Pubkey {
+    Pubkey::find_program_address(&[b"weekly_data", &week.to_le_bytes()], &AIRDROP_ACCOUNT).0
+}
+
+// Custom error code of a failed instruction - None for other failures, such as a panic
+pub fn error_code(err: BanksClientError) -> Option<u32> {
+    match err.unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => Some(code),
+        _ => None,
+    }
+}
+
+fn packed<T: Pack>(state: T) -> Vec<u8> {
+    let mut data = vec![0; T::LEN];
+    T::pack(state, &mut data).unwrap();
+    data
+}
+
+fn mint_account(supply: u64) -> Account {
+    Account {
+        lamports: MINT_RENT,
+        data: packed(spl_token::state::Mint {
+            mint_authority: COption::None,
+            supply,
+            decimals: 9,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }),
+        owner: spl_token::ID,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Account {
+    let is_native = mint == WSOL_MINT;
+    Account {
+        lamports: TOKEN_ACCOUNT_RENT + if is_native { amount } else { 0 },
+        data: packed(spl_token::state::Account {
+            mint,
+            owner,
+            amount,
+            delegate: COption::None,
+            state: spl_token::state::AccountState::Initialized,
+            is_native: if is_native { COption::Some(TOKEN_ACCOUNT_RENT) } else { COption::None },
+            delegated_amount: 0,
+            close_authority: COption::None,
+        }),
+        owner: spl_token::ID,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+fn raw_account(owner: Pubkey, data: Vec<u8>) -> Account {
+    Account {
+        lamports: 10_000_000,
+        data,
+        owner,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+fn meteora_vault_account(total_amount: u64) -> Account {
+    let mut data = vec![0; 1232];
+    let offset = mocks::VAULT_TOTAL_AMOUNT_OFFSET;
+    data[offset..offset + 8].copy_from_slice(&total_amount.to_le_bytes());
+    raw_account(METEORA_VAULT_PROGRAM, data)
+}
+
+fn writable(pubkey: Pubkey) -> AccountMeta {
+    AccountMeta::new(pubkey, false)
+}
+
+fn readonly(pubkey: Pubkey) -> AccountMeta {
+    AccountMeta::new_readonly(pubkey, false)
+}
+
+// Vault A accounts expected at remaining_accounts[0..4]
+fn vault_a_accounts() -> Vec<AccountMeta> {
+    vec![
+        writable(A_VAULT),
+        writable(A_VAULT_LP),
+        writable(A_VAULT_LP_MINT),
+        writable(A_TOKEN_VAULT),
+    ]
+}
+
+impl TestEnv {
+    pub async fn start() -> Self {
+        let mut program_test = ProgramTest::new("matrix_system", matrix_system::ID, processor!(matrix_entry));
+        program_test.prefer_bpf(false);
+        program_test.add_program("meteora_amm", METEORA_AMM_PROGRAM, processor!(mocks::meteora_amm));
+        program_test.add_program("meteora_vault", METEORA_VAULT_PROGRAM, processor!(mocks::meteora_vault));
+        program_test.add_program("chainlink_store", CHAINLINK_PROGRAM, processor!(mocks::chainlink_store));
+        program_test.add_program("airdrop", AIRDROP_ACCOUNT, processor!(mocks::airdrop));
+
+        // Meteora pool: DONUT in vault A, SOL in vault B, LP fully owned by the pool
+        let mut pool_data = vec![0; 944];
+        pool_data[mocks::POOL_ENABLED_OFFSET] = 1;
+        program_test.add_account(POOL_ADDRESS, raw_account(METEORA_AMM_PROGRAM, pool_data));
+        program_test.add_account(TOKEN_MINT, mint_account(DONUT_RESERVE));
+        program_test.add_account(WSOL_MINT, mint_account(0));
+        program_test.add_account(A_VAULT, meteora_vault_account(DONUT_RESERVE));
+        program_test.add_account(B_VAULT, meteora_vault_account(SOL_RESERVE));
+        program_test.add_account(A_TOKEN_VAULT, token_account(TOKEN_MINT, A_VAULT, DONUT_RESERVE));
+        program_test.add_account(B_TOKEN_VAULT, token_account(WSOL_MINT, B_VAULT, 0));
+        program_test.add_account(A_VAULT_LP_MINT, mint_account(LP_SUPPLY));
+        program_test.add_account(B_VAULT_LP_MINT, mint_account(LP_SUPPLY));
+        program_test.add_account(A_VAULT_LP, token_account(A_VAULT_LP_MINT, POOL_ADDRESS, LP_SUPPLY));
+        program_test.add_account(B_VAULT_LP, token_account(B_VAULT_LP_MINT, POOL_ADDRESS, LP_SUPPLY));
+        program_test.add_account(PROTOCOL_TOKEN_B_FEE, token_account(TOKEN_MINT, POOL_ADDRESS, 0));
+
+        let mut context = program_test.start_with_context().await;
+        let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+
+        // Fresh Chainlink round
+        let mut feed = vec![0; mocks::CHAINLINK_FEED_LEN];
+        feed[0] = 8;
+        feed[1..5].copy_from_slice(&1u32.to_le_bytes());
+        feed[5..13].copy_from_slice(&clock.slot.to_le_bytes());
+        feed[13..17].copy_from_slice(&(clock.unix_timestamp as u32).to_le_bytes());
+        feed[17..33].copy_from_slice(&SOL_USD_PRICE.to_le_bytes());
+        context.set_account(&SOL_USD_FEED, &raw_account(CHAINLINK_PROGRAM, feed).into());
+
+        // Airdrop started now, week 1
+        let mut airdrop_state = vec![0; mocks::AIRDROP_PROGRAM_STATE_LEN];
+        airdrop_state[mocks::AIRDROP_CURRENT_WEEK_OFFSET] = 1;
+        let offset = mocks::AIRDROP_START_TIMESTAMP_OFFSET;
+        airdrop_state[offset..offset + 8].copy_from_slice(&clock.unix_timestamp.to_le_bytes());
+        context.set_account(&airdrop_program_state(), &raw_account(AIRDROP_ACCOUNT, airdrop_state).into());
+        context.set_account(&airdrop_week_pda(1), &raw_account(AIRDROP_ACCOUNT, vec![1; 64]).into());
+
+        // Program state - the test payer acts as owner and multisig treasury
+        let state = Pubkey::new_unique();
+        let program_state = ProgramState {
+            owner: context.payer.pubkey(),
+            multisig_treasury: context.payer.pubkey(),
+            next_upline_id: 1,
+            next_chain_id: 1,
+            airdrop_active: true,
+            airdrop_end_timestamp: 0,
+            max_upline_depth: 6,
+            depth_overflow_policy: DepthOverflowPolicy::Burn,
+            total_reserved_lamports: 0,
+            allowlist_mode: AllowlistMode::Open,
+            allowlist_root: [0; 32],
+            invite_signer: Pubkey::default(),
+            campaign: Campaign::default(),
+            referrer_limits: ReferrerLimits::default(),
+            protocol_fee_bps: 0,
+            burn_escrow_enabled: false,
+            pending_burn_lamports: 0,
+            reserves_reconciled: true,
+            burn_retry_slippage_bps: 0,
+        };
+        let mut state_data = Vec::new();
+        program_state.try_serialize(&mut state_data).unwrap();
+        state_data.resize(8 + ProgramState::SIZE, 0);
+        context.set_account(&state, &raw_account(matrix_system::ID, state_data).into());
+
+        Self { context, state }
+    }
+
+    // Funded wallet with empty WSOL and DONUT token accounts, registered in the airdrop
+    pub fn create_user(&mut self) -> TestUser {
+        let wallet = Keypair::new();
+        let owner = wallet.pubkey();
+        let wsol = get_associated_token_address(&owner, &WSOL_MINT);
+        let donut = get_associated_token_address(&owner, &TOKEN_MINT);
+
+        self.context.set_account(
+            &owner,
+            &AccountSharedData::new(WALLET_LAMPORTS, 0, &system_program::ID),
+        );
+        self.context.set_account(&wsol, &token_account(WSOL_MINT, owner, 0).into());
+        self.context.set_account(&donut, &token_account(TOKEN_MINT, owner, 0).into());
+        self.context.set_account(
+            &airdrop_user_pda(&owner),
+            &raw_account(AIRDROP_ACCOUNT, vec![1; 64]).into(),
+        );
+
+        TestUser { pda: user_pda(&owner), wallet, wsol, donut }
+    }
+
+    async fn send(&mut self, instruction: Instruction, signers: &[&Keypair]) -> Result<(), BanksClientError> {
+        self.send_all(vec![instruction], signers).await
+    }
+
+    async fn send_all(&mut self, instructions: Vec<Instruction>, signers: &[&Keypair]) -> Result<(), BanksClientError> {
+        let blockhash = self.context.get_new_latest_blockhash().await.unwrap();
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend_from_slice(signers);
+
+        let mut all_instructions = vec![ComputeBudgetInstruction::set_compute_unit_limit(1_400_000)];
+        all_instructions.extend(instructions);
+        let transaction = Transaction::new_signed_with_payer(
+            &all_instructions,
+            Some(&self.context.payer.pubkey()),
+            &all_signers,
+            blockhash,
+        );
+        self.context.banks_client.process_transaction(transaction).await
+    }
+
+    pub async fn register_without_referrer(&mut self, user: &TestUser, deposit_amount: u64) -> Result<(), BanksClientError> {
+        let mut accounts = matrix_system::accounts::RegisterWithoutReferrerDeposit {
+            state: self.state,
+            owner: self.context.payer.pubkey(),
+            user_wallet: user.wallet.pubkey(),
+            user: user.pda,
+            user_wsol_account: user.wsol,
+            user_donut_account: user.donut,
+            wsol_mint: WSOL_MINT,
+            pool: POOL_ADDRESS,
+            b_vault: B_VAULT,
+            b_token_vault: B_TOKEN_VAULT,
+            b_vault_lp_mint: B_VAULT_LP_MINT,
+            b_vault_lp: B_VAULT_LP,
+            vault_program: METEORA_VAULT_PROGRAM,
+            token_mint: TOKEN_MINT,
+            protocol_token_fee: PROTOCOL_TOKEN_B_FEE,
+            amm_program: METEORA_AMM_PROGRAM,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            rent: sysvar::rent::ID,
+            event_authority: event_authority(),
+            program: matrix_system::ID,
+        }
+        .to_account_metas(None);
+        accounts.extend(vault_a_accounts());
+
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts,
+            data: matrix_system::instruction::RegisterWithoutReferrer { deposit_amount }.data(),
+        };
+        let wallet = user.wallet.insecure_clone();
+        self.send(instruction, &[&wallet]).await
+    }
+
+    // Builds the remaining accounts from the referrer's on-chain state, including the
+    // upline airdrop PDAs and upline pairs when this registration fills slot 3.
+    pub async fn register(&mut self, user: &TestUser, referrer: &TestUser, deposit_amount: u64) -> Result<(), BanksClientError> {
+        self.register_allowlisted(user, referrer, deposit_amount, Vec::new(), None).await
+    }
+
+    // register_with_sol_deposit with a Merkle proof and an Ed25519 invite sent before it
+    pub async fn register_allowlisted(
+        &mut self,
+        user: &TestUser,
+        referrer: &TestUser,
+        deposit_amount: u64,
+        allowlist_proof: Vec<[u8; 32]>,
+        invite: Option<Instruction>,
+    ) -> Result<(), BanksClientError> {
+        let instruction = self.sol_registration(user, referrer, deposit_amount, allowlist_proof).await;
+        let wallet = user.wallet.insecure_clone();
+        self.send_all(invite.into_iter().chain([instruction]).collect(), &[&wallet]).await
+    }
+
+    // register_with_sol_deposit with the account list rewritten by `edit` - a client
+    // sending tampered remaining accounts
+    pub async fn register_edited(
+        &mut self,
+        user: &TestUser,
+        referrer: &TestUser,
+        deposit_amount: u64,
+        edit: impl FnOnce(&mut Vec<AccountMeta>),
+    ) -> Result<(), BanksClientError> {
+        let mut instruction = self.sol_registration(user, referrer, deposit_amount, Vec::new()).await;
+        edit(&mut instruction.accounts);
+        let wallet = user.wallet.insecure_clone();
+        self.send(instruction, &[&wallet]).await
+    }
+
+    // register_with_sol_deposit built from the state before `update` changes it - a
+    // client acting on accounts that changed before its transaction landed
+    pub async fn register_after(
+        &mut self,
+        user: &TestUser,
+        referrer: &TestUser,
+        deposit_amount: u64,
+        update: impl FnOnce(&mut Self),
+    ) -> Result<(), BanksClientError> {
+        let instruction = self.sol_registration(user, referrer, deposit_amount, Vec::new()).await;
+        update(self);
+        let wallet = user.wallet.insecure_clone();
+        self.send(instruction, &[&wallet]).await
+    }
+
+    async fn sol_registration(
+        &mut self,
+        user: &TestUser,
+        referrer: &TestUser,
+        deposit_amount: u64,
+        allowlist_proof: Vec<[u8; 32]>,
+    ) -> Instruction {
+        let referrer_wallet = referrer.wallet.pubkey();
+        let mut accounts = matrix_system::accounts::RegisterWithSolDeposit {
+            state: self.state,
+            user_wallet: user.wallet.pubkey(),
+            referrer: referrer.pda,
+            referrer_wallet,
+            user: user.pda,
+            user_wsol_account: user.wsol,
+            user_donut_account: user.donut,
+            wsol_mint: WSOL_MINT,
+            pool: POOL_ADDRESS,
+            b_vault: B_VAULT,
+            b_token_vault: B_TOKEN_VAULT,
+            b_vault_lp_mint: B_VAULT_LP_MINT,
+            b_vault_lp: B_VAULT_LP,
+            vault_program: METEORA_VAULT_PROGRAM,
+            program_sol_vault: program_sol_vault(),
+            token_mint: TOKEN_MINT,
+            protocol_token_fee: PROTOCOL_TOKEN_B_FEE,
+            amm_program: METEORA_AMM_PROGRAM,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            rent: sysvar::rent::ID,
+            event_authority: event_authority(),
+            program: matrix_system::ID,
+        }
+        .to_account_metas(None);
+
+        accounts.extend(self.registration_remaining_accounts(referrer, true).await);
+        // The protocol fee goes to the treasury before the deposit is routed
+        let state = self.program_state().await;
+        if state.protocol_fee_bps > 0 {
+            accounts.push(writable(state.multisig_treasury));
+        }
+        if state.burn_escrow_enabled {
+            accounts.push(writable(burn_escrow()));
+        }
+
+        Instruction {
+            program_id: matrix_system::ID,
+            accounts,
+            data: matrix_system::instruction::RegisterWithSolDeposit { deposit_amount, allowlist_proof }.data(),
+        }
+    }
+
+    // begin_registration - the accounts of register_with_sol_deposit plus the pending PDA
+    pub async fn begin_registration(&mut self, user: &TestUser, referrer: &TestUser, deposit_amount: u64) -> Result<(), BanksClientError> {
+        let wallet = user.wallet.pubkey();
+        let mut accounts = matrix_system::accounts::BeginRegistration {
+            state: self.state,
+            user_wallet: wallet,
+            referrer: referrer.pda,
+            referrer_wallet: referrer.wallet.pubkey(),
+            user: user.pda,
+            pending: pending_registration(&wallet),
+            user_wsol_account: user.wsol,
+            user_donut_account: user.donut,
+            wsol_mint: WSOL_MINT,
+            pool: POOL_ADDRESS,
+            b_vault: B_VAULT,
+            b_token_vault: B_TOKEN_VAULT,
+            b_vault_lp_mint: B_VAULT_LP_MINT,
+            b_vault_lp: B_VAULT_LP,
+            vault_program: METEORA_VAULT_PROGRAM,
+            program_sol_vault: program_sol_vault(),
+            token_mint: TOKEN_MINT,
+            protocol_token_fee: PROTOCOL_TOKEN_B_FEE,
+            amm_program: METEORA_AMM_PROGRAM,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            rent: sysvar::rent::ID,
+            event_authority: event_authority(),
+            program: matrix_system::ID,
+        }
+        .to_account_metas(None);
+
+        accounts.extend(self.registration_remaining_accounts(referrer, true).await);
+        let state = self.program_state().await;
+        if state.protocol_fee_bps > 0 {
+            accounts.push(writable(state.multisig_treasury));
+        }
+        if state.burn_escrow_enabled {
+            accounts.push(writable(burn_escrow()));
+        }
+
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts,
+            data: matrix_system::instruction::BeginRegistration { deposit_amount, allowlist_proof: Vec::new() }.data(),
+        };
+        let wallet = user.wallet.insecure_clone();
+        self.send(instruction, &[&wallet]).await
+    }
+
+    // advance_registration over the next `pair_count` uplines of the pending cascade.
+    // `referrer` holds the stored upline - the new position of a migrated referrer.
+    pub async fn advance_registration(&mut self, user: &TestUser, referrer: &TestUser, pair_count: u8) -> Result<(), BanksClientError> {
+        let wallet = user.wallet.pubkey();
+        let pending = self.pending_registration(user).await.expect("no pending registration");
+        let mut accounts = matrix_system::accounts::AdvanceRegistration {
+            state: self.state,
+            user_wallet: wallet,
+            pending: pending_registration(&wallet),
+            pending_referrer: pending.referrer,
+            referrer: referrer.pda,
+            user_wsol_account: user.wsol,
+            user_donut_account: user.donut,
+            wsol_mint: WSOL_MINT,
+            pool: POOL_ADDRESS,
+            b_vault: B_VAULT,
+            b_token_vault: B_TOKEN_VAULT,
+            b_vault_lp_mint: B_VAULT_LP_MINT,
+            b_vault_lp: B_VAULT_LP,
+            vault_program: METEORA_VAULT_PROGRAM,
+            program_sol_vault: program_sol_vault(),
+            token_mint: TOKEN_MINT,
+            protocol_token_fee: PROTOCOL_TOKEN_B_FEE,
+            amm_program: METEORA_AMM_PROGRAM,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+            event_authority: event_authority(),
+            program: matrix_system::ID,
+        }
+        .to_account_metas(None);
+
+        let start_index = pending.next_upline_index as usize;
+        accounts.extend(vault_a_accounts());
+        accounts.extend(self.cascade_remaining_accounts(referrer, start_index, pair_count as usize).await);
+
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts,
+            data: matrix_system::instruction::AdvanceRegistration { pair_count }.data(),
+        };
+        let wallet = user.wallet.insecure_clone();
+        self.send(instruction, &[&wallet]).await
+    }
+
+    // finish_pending_registration sent by `cranker` with the rest of the stored upline
+    pub async fn finish_pending_registration(&mut self, cranker: &Keypair, user: &TestUser, referrer: &TestUser) -> Result<(), BanksClientError> {
+        let wallet = user.wallet.pubkey();
+        let pending = self.pending_registration(user).await.expect("no pending registration");
+        let mut accounts = matrix_system::accounts::FinishPendingRegistration {
+            state: self.state,
+            cranker: cranker.pubkey(),
+            user_wallet: wallet,
+            pending: pending_registration(&wallet),
+            pending_referrer: pending.referrer,
+            referrer: referrer.pda,
+            program_sol_vault: program_sol_vault(),
+            burn_escrow: burn_escrow(),
+            system_program: system_program::ID,
+            event_authority: event_authority(),
+            program: matrix_system::ID,
+        }
+        .to_account_metas(None);
+
+        let start_index = pending.next_upline_index as usize;
+        let depth = self.program_state().await.upline_depth();
+        let stored = self.user_account(&referrer.pda).await.upline.entries().len();
+        let pair_count = stored.min(depth).saturating_sub(start_index);
+        accounts.extend(self.cascade_remaining_accounts(referrer, start_index, pair_count).await);
+
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts,
+            data: matrix_system::instruction::FinishPendingRegistration {}.data(),
+        };
+        self.send(instruction, &[cranker]).await
+    }
+
+    pub async fn pending_registration(&mut self, user: &TestUser) -> Option<PendingRegistration> {
+        let account = self.account(&pending_registration(&user.wallet.pubkey())).await?;
+        Some(PendingRegistration::try_deserialize(&mut account.data.as_slice()).unwrap())
+    }
+
+    // Remaining accounts of a cascade step over `pair_count` uplines of `referrer`'s stored
+    // upline from `start_index`: the airdrop accounts, the payout accounts and the
+    // treasury, then the upline pairs last
+    async fn cascade_remaining_accounts(&mut self, referrer: &TestUser, start_index: usize, pair_count: usize) -> Vec<AccountMeta> {
+        let mut uplines = Vec::new();
+        let referrer_account = self.user_account(&referrer.pda).await;
+        for entry in &referrer_account.upline.entries()[start_index..start_index + pair_count] {
+            uplines.push(self.upline_entry_accounts(entry).await);
+        }
+
+        // The stored week and the week of the current Clock
+        let week = self.airdrop_week().await;
+        let mut accounts = vec![writable(airdrop_program_state()), writable(airdrop_week_pda(1)), writable(airdrop_week_pda(week))];
+        let mut payouts = Vec::new();
+        for (_, wallet, upline) in &uplines {
+            accounts.push(writable(airdrop_user_pda(wallet)));
+            if let Some(upline) = upline {
+                payouts.extend(self.payout_accounts(upline).await);
+            }
+        }
+        accounts.push(readonly(AIRDROP_ACCOUNT));
+        accounts.push(readonly(sysvar::instructions::ID));
+        accounts.extend(payouts.into_iter().map(writable));
+        accounts.push(writable(self.program_state().await.multisig_treasury));
+
+        for (entry_accounts, _, _) in &uplines {
+            accounts.extend(entry_accounts.iter().copied().map(writable));
+        }
+        accounts
+    }
+
+    // Accounts of a stored upline entry: its (pda, wallet) pair, or (redirect, new_user,
+    // new_wallet) while it still points at a migrated user. Returned with the wallet the
+    // cascade pays and the UserAccount it reads, if there is one.
+    async fn upline_entry_accounts(&mut self, entry: &UplineEntry) -> (Vec<Pubkey>, Pubkey, Option<UserAccount>) {
+        let data = self.account(&entry.pda).await.map(|account| account.data).unwrap_or_default();
+        let (accounts, user, wallet) = match UserAccountRedirect::try_deserialize(&mut data.as_slice()) {
+            Ok(redirect) => (vec![entry.pda, redirect.new_user, redirect.new_wallet], redirect.new_user, redirect.new_wallet),
+            Err(_) => (vec![entry.pda, entry.wallet], entry.pda, entry.wallet),
+        };
+        let upline = self
+            .account(&user)
+            .await
+            .filter(|account| account.data.len() == 8 + UserAccount::SIZE)
+            .map(|account| bytemuck::pod_read_unaligned(&account.data[8..]));
+        (accounts, wallet, upline)
+    }
+
+    // Airdrop week of the current Clock, as notify_airdrop_program computes it
+    async fn airdrop_week(&mut self) -> u8 {
+        let state = self.account(&airdrop_program_state()).await.unwrap();
+        let offset = mocks::AIRDROP_START_TIMESTAMP_OFFSET;
+        let start = i64::from_le_bytes(state.data[offset..offset + 8].try_into().unwrap());
+        let elapsed = self.unix_timestamp().await - start;
+        (elapsed / mocks::AIRDROP_WEEK_SECONDS + 1).min(36) as u8
+    }
+
+    // Remaining accounts of a registration under `referrer`: vault A, the Chainlink
+    // accounts for SOL deposits, the airdrop accounts, the upline airdrop PDAs and upline
+    // pairs when this registration fills slot 3, then the payout accounts.
+    async fn registration_remaining_accounts(&mut self, referrer: &TestUser, chainlink: bool) -> Vec<AccountMeta> {
+        let referrer_wallet = referrer.wallet.pubkey();
+        let mut accounts = vault_a_accounts();
+        if chainlink {
+            accounts.push(readonly(SOL_USD_FEED));
+            accounts.push(readonly(CHAINLINK_PROGRAM));
+        }
+
+        // Airdrop accounts for the referrer's notification
+        accounts.push(writable(airdrop_program_state()));
+        accounts.push(writable(airdrop_user_pda(&referrer_wallet)));
+        accounts.push(writable(airdrop_week_pda(1)));
+        accounts.push(writable(airdrop_week_pda(1)));
+        accounts.push(writable(referrer_wallet));
+        accounts.push(readonly(AIRDROP_ACCOUNT));
+        accounts.push(readonly(sysvar::instructions::ID));
+
+        let referrer_account = self.user_account(&referrer.pda).await;
+        let mut payouts = self.payout_accounts(&referrer_account).await;
+        if referrer_account.chain.filled_slots == 2 && referrer_account.referrer().is_some() {
+            let mut uplines = Vec::new();
+            for entry in referrer_account.upline.entries() {
+                uplines.push(self.upline_entry_accounts(entry).await);
+            }
+            for (_, wallet, _) in &uplines {
+                accounts.push(writable(airdrop_user_pda(wallet)));
+            }
+            for (entry_accounts, _, upline) in &uplines {
+                accounts.extend(entry_accounts.iter().copied().map(writable));
+                if let Some(upline) = upline {
+                    payouts.extend(self.payout_accounts(upline).await);
+                }
+            }
+        }
+        // Payout accounts follow the upline pairs
+        for payout in payouts {
+            if !accounts.iter().any(|meta| meta.pubkey == payout) {
+                accounts.push(writable(payout));
+            }
+        }
+        accounts
+    }
+
+    // Accounts a reserve payment to `user` needs besides its owner wallet: the payout
+    // address, and for a token reserve the token's config, vault and the payout's token account
+    async fn payout_accounts(&mut self, user: &UserAccount) -> Vec<Pubkey> {
+        let mut accounts: Vec<Pubkey> = user.payout_address().into_iter().collect();
+        if user.reserve_token != RESERVE_SOL {
+            let config = self.deposit_config(user.reserve_token).await;
+            let payout = user.payout_address().unwrap_or(user.owner_wallet);
+            accounts.push(token_deposit_config(user.reserve_token));
+            accounts.push(token_reserve_vault(user.reserve_token));
+            accounts.push(get_associated_token_address(&payout, &config.mint));
+        }
+        accounts
+    }
+
+    // Mint and DONUT/token pool for deposit token `token`, priced like the SOL pool
+    pub fn add_deposit_token_pool(&mut self, token: u8) -> DepositToken {
+        let deposit_token = DepositToken {
+            token,
+            mint: Pubkey::new_unique(),
+            pool: Pubkey::new_unique(),
+            b_vault: Pubkey::new_unique(),
+            b_token_vault: Pubkey::new_unique(),
+            b_vault_lp_mint: Pubkey::new_unique(),
+            b_vault_lp: Pubkey::new_unique(),
+            protocol_token_fee: Pubkey::new_unique(),
+            a_vault_lp: Pubkey::new_unique(),
+        };
+
+        let mut pool_data = vec![0; 944];
+        pool_data[mocks::POOL_ENABLED_OFFSET] = 1;
+        self.context.set_account(&deposit_token.pool, &raw_account(METEORA_AMM_PROGRAM, pool_data).into());
+        self.context.set_account(&deposit_token.mint, &mint_account(0).into());
+        self.context.set_account(&deposit_token.b_vault, &meteora_vault_account(USDC_RESERVE).into());
+        self.context.set_account(
+            &deposit_token.b_token_vault,
+            &token_account(deposit_token.mint, deposit_token.b_vault, 0).into(),
+        );
+        self.context.set_account(&deposit_token.b_vault_lp_mint, &mint_account(LP_SUPPLY).into());
+        self.context.set_account(
+            &deposit_token.b_vault_lp,
+            &token_account(deposit_token.b_vault_lp_mint, deposit_token.pool, LP_SUPPLY).into(),
+        );
+        self.context.set_account(
+            &deposit_token.protocol_token_fee,
+            &token_account(deposit_token.mint, deposit_token.pool, 0).into(),
+        );
+        self.context.set_account(
+            &deposit_token.a_vault_lp,
+            &token_account(A_VAULT_LP_MINT, deposit_token.pool, LP_SUPPLY).into(),
+        );
+        deposit_token
+    }
+
+    // Signed by the test payer, which is the program owner
+    pub async fn configure_deposit_token(&mut self, deposit_token: &DepositToken, min_deposit: u64) -> Result<(), BanksClientError> {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::ConfigureDepositToken {
+                state: self.state,
+                owner: self.context.payer.pubkey(),
+                deposit_config: token_deposit_config(deposit_token.token),
+                token_reserve_vault: token_reserve_vault(deposit_token.token),
+                mint: deposit_token.mint,
+                pool: deposit_token.pool,
+                b_vault: deposit_token.b_vault,
+                b_token_vault: deposit_token.b_token_vault,
+                b_vault_lp_mint: deposit_token.b_vault_lp_mint,
+                b_vault_lp: deposit_token.b_vault_lp,
+                protocol_token_fee: deposit_token.protocol_token_fee,
+                a_vault_lp: deposit_token.a_vault_lp,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            }
+            .to_account_metas(None),
+            data: matrix_system::instruction::ConfigureDepositToken { token: deposit_token.token, min_deposit }.data(),
+        };
+        self.send(instruction, &[]).await
+    }
+
+    // Associated token account of `wallet` for `mint` holding `amount`
+    pub fn set_token_account(&mut self, wallet: &Pubkey, mint: &Pubkey, amount: u64) -> Pubkey {
+        let address = get_associated_token_address(wallet, mint);
+        self.context.set_account(&address, &token_account(*mint, *wallet, amount).into());
+        address
+    }
+
+    // Pays the deposit from the user's associated token account of the deposit token
+    pub async fn register_with_token(
+        &mut self,
+        user: &TestUser,
+        referrer: &TestUser,
+        deposit_token: &DepositToken,
+        deposit_amount: u64,
+    ) -> Result<(), BanksClientError> {
+        let mut accounts = matrix_system::accounts::RegisterWithTokenDeposit {
+            state: self.state,
+            user_wallet: user.wallet.pubkey(),
+            referrer: referrer.pda,
+            referrer_wallet: referrer.wallet.pubkey(),
+            user: user.pda,
+            deposit_config: token_deposit_config(deposit_token.token),
+            user_token_account: get_associated_token_address(&user.wallet.pubkey(), &deposit_token.mint),
+            token_reserve_vault: token_reserve_vault(deposit_token.token),
+            user_donut_account: user.donut,
+            pool: deposit_token.pool,
+            b_vault: deposit_token.b_vault,
+            b_token_vault: deposit_token.b_token_vault,
+            b_vault_lp_mint: deposit_token.b_vault_lp_mint,
+            b_vault_lp: deposit_token.b_vault_lp,
+            vault_program: METEORA_VAULT_PROGRAM,
+            program_sol_vault: program_sol_vault(),
+            token_mint: TOKEN_MINT,
+            protocol_token_fee: deposit_token.protocol_token_fee,
+            amm_program: METEORA_AMM_PROGRAM,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+            event_authority: event_authority(),
+            program: matrix_system::ID,
+        }
+        .to_account_metas(None);
+        let mut remaining_accounts = self.registration_remaining_accounts(referrer, false).await;
+        remaining_accounts[1] = writable(deposit_token.a_vault_lp);
+        accounts.extend(remaining_accounts);
+
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts,
+            data: matrix_system::instruction::RegisterWithTokenDeposit {
+                deposit_amount,
+                token: deposit_token.token,
+                allowlist_proof: Vec::new(),
+            }
+            .data(),
+        };
+        let wallet = user.wallet.insecure_clone();
+        self.send(instruction, &[&wallet]).await
+    }
+
+    pub async fn set_allowlist(&mut self, mode: AllowlistMode, root: [u8; 32], invite_signer: &Pubkey) -> Result<(), BanksClientError> {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::SetAllowlist { state: self.state, owner: self.context.payer.pubkey() }
+                .to_account_metas(None),
+            data: matrix_system::instruction::SetAllowlist { mode, root, invite_signer: *invite_signer }.data(),
+        };
+        self.send(instruction, &[]).await
+    }
+
+    pub async fn schedule_campaign(&mut self, start: i64, end: i64, max_registrations: u32) -> Result<(), BanksClientError> {
+        let data = matrix_system::instruction::ScheduleCampaign { start_timestamp: start, end_timestamp: end, max_registrations }.data();
+        self.manage_campaign(data).await
+    }
+
+    pub async fn extend_campaign(&mut self, end: i64, max_registrations: u32) -> Result<(), BanksClientError> {
+        let data = matrix_system::instruction::ExtendCampaign { end_timestamp: end, max_registrations }.data();
+        self.manage_campaign(data).await
+    }
+
+    pub async fn close_campaign(&mut self) -> Result<(), BanksClientError> {
+        self.manage_campaign(matrix_system::instruction::CloseCampaign {}.data()).await
+    }
+
+    async fn manage_campaign(&mut self, data: Vec<u8>) -> Result<(), BanksClientError> {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::ManageCampaign { state: self.state, owner: self.context.payer.pubkey() }
+                .to_account_metas(None),
+            data,
+        };
+        self.send(instruction, &[]).await
+    }
+
+    pub async fn set_referrer_limits(&mut self, limits: ReferrerLimits) -> Result<(), BanksClientError> {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::SetReferrerLimits { state: self.state, owner: self.context.payer.pubkey() }
+                .to_account_metas(None),
+            data: matrix_system::instruction::SetReferrerLimits {
+                max_cycles_per_week: limits.max_cycles_per_week,
+                min_cycle_interval: limits.min_cycle_interval,
+                week_start: limits.week_start,
+            }
+            .data(),
+        };
+        self.send(instruction, &[]).await
+    }
+
+    pub async fn set_protocol_fee(&mut self, fee_bps: u16) -> Result<(), BanksClientError> {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::SetProtocolFee { state: self.state, owner: self.context.payer.pubkey() }
+                .to_account_metas(None),
+            data: matrix_system::instruction::SetProtocolFee { fee_bps }.data(),
+        };
+        self.send(instruction, &[]).await
+    }
+
+    pub async fn set_burn_escrow(&mut self, enabled: bool, retry_slippage_bps: u16) -> Result<(), BanksClientError> {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::SetBurnEscrow { state: self.state, owner: self.context.payer.pubkey() }
+                .to_account_metas(None),
+            data: matrix_system::instruction::SetBurnEscrow { enabled, retry_slippage_bps }.data(),
+        };
+        self.send(instruction, &[]).await
+    }
+
+    // Swaps and burns the escrowed deposits, signed by `cranker` - the escrow's WSOL and
+    // DONUT token accounts are created empty when missing
+    pub async fn retry_pending_burns(&mut self, cranker: &Keypair) -> Result<(), BanksClientError> {
+        for mint in [WSOL_MINT, TOKEN_MINT] {
+            if self.account(&get_associated_token_address(&burn_escrow(), &mint)).await.is_none() {
+                self.set_token_account(&burn_escrow(), &mint, 0);
+            }
+        }
+
+        let mut accounts = matrix_system::accounts::RetryPendingBurns {
+            state: self.state,
+            cranker: cranker.pubkey(),
+            burn_escrow: burn_escrow(),
+            escrow_wsol_account: get_associated_token_address(&burn_escrow(), &WSOL_MINT),
+            escrow_donut_account: get_associated_token_address(&burn_escrow(), &TOKEN_MINT),
+            pool: POOL_ADDRESS,
+            b_vault: B_VAULT,
+            b_token_vault: B_TOKEN_VAULT,
+            b_vault_lp_mint: B_VAULT_LP_MINT,
+            b_vault_lp: B_VAULT_LP,
+            vault_program: METEORA_VAULT_PROGRAM,
+            token_mint: TOKEN_MINT,
+            protocol_token_fee: PROTOCOL_TOKEN_B_FEE,
+            amm_program: METEORA_AMM_PROGRAM,
+            token_program: spl_token::ID,
+            system_program: system_program::ID,
+            event_authority: event_authority(),
+            program: matrix_system::ID,
+        }
+        .to_account_metas(None);
+        accounts.extend(vault_a_accounts());
+
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts,
+            data: matrix_system::instruction::RetryPendingBurns {}.data(),
+        };
+        self.send(instruction, &[cranker]).await
+    }
+
+    // Flips the enabled flag of the SOL Meteora pool read by the swap quote
+    pub async fn set_pool_enabled(&mut self, enabled: bool) {
+        let mut pool = self.account(&POOL_ADDRESS).await.expect("pool missing");
+        pool.data[mocks::POOL_ENABLED_OFFSET] = enabled as u8;
+        self.context.set_account(&POOL_ADDRESS, &pool.into());
+    }
+
+    // The SOL pool's LP share of vault B, LP_SUPPLY at the start. Only the swap quote reads
+    // it - the mock swap pays at the vault ratio, so a smaller share quotes above the swap.
+    pub fn set_b_vault_lp_amount(&mut self, amount: u64) {
+        self.context.set_account(&B_VAULT_LP, &token_account(B_VAULT_LP_MINT, POOL_ADDRESS, amount).into());
+    }
+
+    pub async fn unix_timestamp(&mut self) -> i64 {
+        self.context.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp
+    }
+
+    pub async fn set_unix_timestamp(&mut self, unix_timestamp: i64) {
+        let mut clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp = unix_timestamp;
+        self.context.set_sysvar(&clock);
+    }
+
+    pub async fn transfer(&mut self, to: &Pubkey, lamports: u64) -> Result<(), BanksClientError> {
+        let instruction = solana_sdk::system_instruction::transfer(&self.context.payer.pubkey(), to, lamports);
+        self.send(instruction, &[]).await
+    }
+
+    // Simulates audit_vault and decodes its return data
+    pub async fn audit_vault(&mut self) -> VaultAudit {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::AuditVault { state: self.state, program_sol_vault: program_sol_vault() }
+                .to_account_metas(None),
+            data: matrix_system::instruction::AuditVault {}.data(),
+        };
+        let blockhash = self.context.get_new_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&self.context.payer.pubkey()),
+            &[&self.context.payer],
+            blockhash,
+        );
+
+        let simulation = self.context.banks_client.simulate_transaction(transaction).await.unwrap();
+        simulation.result.unwrap().unwrap();
+        let return_data = simulation.simulation_details.unwrap().return_data.expect("audit_vault returns data");
+        VaultAudit::try_from_slice(&return_data.data).unwrap()
+    }
+
+    // Signed by the test payer, which is the program owner and the treasury
+    pub async fn sweep_vault_surplus(&mut self) -> Result<(), BanksClientError> {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::SweepVaultSurplus {
+                state: self.state,
+                owner: self.context.payer.pubkey(),
+                program_sol_vault: program_sol_vault(),
+                multisig_treasury: self.context.payer.pubkey(),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: matrix_system::instruction::SweepVaultSurplus {}.data(),
+        };
+        self.send(instruction, &[]).await
+    }
+
+    pub async fn resize_program_state(&mut self) -> Result<(), BanksClientError> {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::ResizeProgramState {
+                state: self.state,
+                owner: self.context.payer.pubkey(),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: matrix_system::instruction::ResizeProgramState {}.data(),
+        };
+        self.send(instruction, &[]).await
+    }
+
+    pub async fn reconcile_vault(&mut self, total_reserved_lamports: u64) -> Result<(), BanksClientError> {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::ReconcileVault {
+                state: self.state,
+                owner: self.context.payer.pubkey(),
+                program_sol_vault: program_sol_vault(),
+            }
+            .to_account_metas(None),
+            data: matrix_system::instruction::ReconcileVault { total_reserved_lamports }.data(),
+        };
+        self.send(instruction, &[]).await
+    }
+
+    pub async fn close_user_account(&mut self, user: &TestUser) -> Result<(), BanksClientError> {
+        let wallet = user.wallet.pubkey();
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::CloseUserAccount {
+                user: user.pda,
+                owner_wallet: wallet,
+                pending_registration: Pubkey::find_program_address(&[b"pending_registration", wallet.as_ref()], &matrix_system::ID).0,
+            }
+            .to_account_metas(None),
+            data: matrix_system::instruction::CloseUserAccount {}.data(),
+        };
+        let wallet = user.wallet.insecure_clone();
+        self.send(instruction, &[&wallet]).await
+    }
+
+    // Moves `user`'s position to the wallet of `new_user`, which must not be registered
+    pub async fn migrate_user_wallet(&mut self, user: &TestUser, new_user: &TestUser) -> Result<(), BanksClientError> {
+        let wallet = user.wallet.pubkey();
+        let new_wallet = new_user.wallet.pubkey();
+        let mut accounts = matrix_system::accounts::MigrateUserWallet {
+            state: self.state,
+            user: user.pda,
+            owner_wallet: wallet,
+            new_wallet,
+            new_user: new_user.pda,
+            pending_registration: Pubkey::find_program_address(&[b"pending_registration", wallet.as_ref()], &matrix_system::ID).0,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None);
+        accounts.push(readonly(airdrop_user_pda(&new_wallet)));
+
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts,
+            data: matrix_system::instruction::MigrateUserWallet {}.data(),
+        };
+        let (wallet, new_wallet) = (user.wallet.insecure_clone(), new_user.wallet.insecure_clone());
+        self.send(instruction, &[&wallet, &new_wallet]).await
+    }
+
+    pub async fn set_payout_address(&mut self, user: &TestUser, payout_address: &Pubkey) -> Result<(), BanksClientError> {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::SetPayoutAddress {
+                user: user.pda,
+                owner_wallet: user.wallet.pubkey(),
+                payout_address: *payout_address,
+            }
+            .to_account_metas(None),
+            data: matrix_system::instruction::SetPayoutAddress {}.data(),
+        };
+        let wallet = user.wallet.insecure_clone();
+        self.send(instruction, &[&wallet]).await
+    }
+
+    pub async fn resize_user_account(&mut self, address: &Pubkey) -> Result<(), BanksClientError> {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::ResizeUserAccount {
+                user: *address,
+                payer: self.context.payer.pubkey(),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: matrix_system::instruction::ResizeUserAccount {}.data(),
+        };
+        self.send(instruction, &[]).await
+    }
+
+    pub async fn migrate_user_account(&mut self, address: &Pubkey) -> Result<(), BanksClientError> {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::MigrateUserAccount {
+                user: *address,
+                payer: self.context.payer.pubkey(),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: matrix_system::instruction::MigrateUserAccount {}.data(),
+        };
+        self.send(instruction, &[]).await
+    }
+
+    pub async fn refresh_user_references(&mut self, user: &Pubkey, redirects: &[Pubkey]) -> Result<(), BanksClientError> {
+        let mut accounts = matrix_system::accounts::RefreshUserReferences { user: *user }.to_account_metas(None);
+        accounts.extend(redirects.iter().map(|redirect| readonly(*redirect)));
+
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts,
+            data: matrix_system::instruction::RefreshUserReferences {}.data(),
+        };
+        self.send(instruction, &[]).await
+    }
+
+    pub async fn account(&mut self, address: &Pubkey) -> Option<Account> {
+        self.context.banks_client.get_account(*address).await.unwrap()
+    }
+
+    pub async fn lamports(&mut self, address: &Pubkey) -> u64 {
+        self.account(address).await.map(|a| a.lamports).unwrap_or(0)
+    }
+
+    pub async fn user_account(&mut self, address: &Pubkey) -> UserAccount {
+        let account = self.account(address).await.expect("user account missing");
+        bytemuck::pod_read_unaligned(&account.data[8..8 + std::mem::size_of::<UserAccount>()])
+    }
+
+    pub async fn claim_referral_code(&mut self, user: &TestUser, code: &str) -> Result<(), BanksClientError> {
+        let instruction = Instruction {
+            program_id: matrix_system::ID,
+            accounts: matrix_system::accounts::ClaimReferralCode {
+                user: user.pda,
+                owner_wallet: user.wallet.pubkey(),
+                referral_code: referral_code(code),
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: matrix_system::instruction::ClaimReferralCode { code: code.to_string() }.data(),
+        };
+        let wallet = user.wallet.insecure_clone();
+        self.send(instruction, &[&wallet]).await
+    }
+
+    pub async fn referral_code(&mut self, code: &str) -> Option<ReferralCode> {
+        let account = self.account(&referral_code(code)).await?;
+        Some(ReferralCode::try_deserialize(&mut account.data.as_slice()).unwrap())
+    }
+
+    pub async fn deposit_config(&mut self, token: u8) -> TokenDepositConfig {
+        let account = self.account(&token_deposit_config(token)).await.expect("deposit token not configured");
+        TokenDepositConfig::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    pub async fn program_state(&mut self) -> ProgramState {
+        let account = self.account(&self.state.clone()).await.unwrap();
+        ProgramState::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    // Overwrite the program state, e.g. to reproduce a state resized from an older layout
+    pub fn set_program_state(&mut self, state: &ProgramState) {
+        let mut data = Vec::new();
+        state.try_serialize(&mut data).unwrap();
+        data.resize(8 + ProgramState::SIZE, 0);
+        self.context.set_account(&self.state, &raw_account(matrix_system::ID, data).into());
+    }
+
+    // Overwrite a UserAccount in place, keeping its discriminator and lamports
+    pub async fn set_user_account(&mut self, address: &Pubkey, user: &UserAccount) {
+        let mut account = self.account(address).await.expect("user account missing");
+        account.data[8..8 + std::mem::size_of::<UserAccount>()].copy_from_slice(bytemuck::bytes_of(user));
+        self.context.set_account(address, &account.into());
+    }
+
+    pub fn set_raw_account(&mut self, address: &Pubkey, data: Vec<u8>) {
+        self.context.set_account(address, &raw_account(matrix_system::ID, data).into());
+    }
+
+    pub async fn token_amount(&mut self, address: &Pubkey) -> u64 {
+        let account = self.account(address).await.expect("token account missing");
+        spl_token::state::Account::unpack(&account.data).unwrap().amount
+    }
+
+    pub async fn donut_supply(&mut self) -> u64 {
+        let account = self.account(&TOKEN_MINT).await.unwrap();
+        spl_token::state::Mint::unpack(&account.data).unwrap().supply
+    }
+
+    pub async fn airdrop_notifications(&mut self) -> u64 {
+        self.airdrop_counter(mocks::AIRDROP_NOTIFICATION_COUNT_OFFSET).await
+    }
+
+    // Notifications sent with the is_last_notification flag
+    pub async fn airdrop_last_notifications(&mut self) -> u64 {
+        self.airdrop_counter(mocks::AIRDROP_LAST_NOTIFICATION_COUNT_OFFSET).await
+    }
+
+    async fn airdrop_counter(&mut self, offset: usize) -> u64 {
+        let account = self.account(&airdrop_program_state()).await.unwrap();
+        u64::from_le_bytes(account.data[offset..offset + 8].try_into().unwrap())
+    }
+}
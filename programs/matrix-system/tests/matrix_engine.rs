@@ -0,0 +1,285 @@
+// Matrix engine effects for each slot, the base burn, the upline cascade, the depth
+// overflow policies, a paused advance_registration cascade and the referrer limits.
+
+use anchor_lang::prelude::Pubkey;
+use matrix_system::matrix::{self, Effect, RecordRef, Throttle, UserRecord};
+use matrix_system::{CycleStats, DepthOverflowPolicy, ReferralChain, ReferrerLimits, RESERVE_SOL};
+
+const DEPOSIT: u64 = 100_000_000;
+
+fn record(chain_id: u32, filled_slots: u8, reserved_sol: u64, is_base: bool) -> UserRecord {
+    let mut slots = [Pubkey::default(); 3];
+    for slot in slots.iter_mut().take(filled_slots as usize) {
+        *slot = Pubkey::new_unique();
+    }
+
+    let wallet = Pubkey::new_unique();
+    UserRecord {
+        key: Pubkey::new_unique(),
+        wallet,
+        payout: wallet,
+        is_base,
+        chain: ReferralChain { id: chain_id, slots, filled_slots, _padding: [0; 3] },
+        reserved_sol,
+        reserve_token: RESERVE_SOL,
+        cycles: CycleStats::default(),
+    }
+}
+
+fn no_uplines(_: usize) -> anchor_lang::Result<UserRecord> {
+    panic!("the cascade must not load uplines")
+}
+
+#[test]
+fn first_slot_burns_and_second_reserves() {
+    let user = Pubkey::new_unique();
+    let referrer = record(1, 0, 0, false);
+
+    let outcome = matrix::register(user, DEPOSIT, RESERVE_SOL, referrer, 0, no_uplines, 10, DepthOverflowPolicy::Burn, &Throttle::default()).unwrap();
+    assert_eq!(
+        outcome.effects,
+        vec![
+            Effect::Write { target: RecordRef::Referrer, owner: referrer.key, user, slot_idx: 0, chain_id: 1, depth: 0 },
+            Effect::Burn { owner: referrer.key, chain_id: 1, amount: DEPOSIT, depth: 0 },
+        ]
+    );
+    assert_eq!(outcome.next_chain_id, 10);
+    assert_eq!(outcome.remaining_deposit, 0);
+
+    let referrer = outcome.referrer.unwrap();
+    let outcome = matrix::register(user, DEPOSIT, RESERVE_SOL, referrer, 0, no_uplines, 10, DepthOverflowPolicy::Burn, &Throttle::default()).unwrap();
+    assert_eq!(
+        outcome.effects[1],
+        Effect::Reserve { owner: referrer.key, chain_id: 1, amount: DEPOSIT, depth: 0, token: RESERVE_SOL }
+    );
+    assert_eq!(outcome.referrer.unwrap().reserved_sol, DEPOSIT);
+    assert_eq!(outcome.referrer.unwrap().chain.filled_slots, 2);
+}
+
+#[test]
+fn third_slot_of_base_referrer_pays_notifies_and_burns() {
+    let user = Pubkey::new_unique();
+    let referrer = record(3, 2, DEPOSIT, true);
+
+    let outcome = matrix::register(user, DEPOSIT, RESERVE_SOL, referrer, 0, no_uplines, 10, DepthOverflowPolicy::Refund, &Throttle::default()).unwrap();
+    assert_eq!(
+        outcome.effects,
+        vec![
+            Effect::Pay {
+                target: RecordRef::Referrer,
+                owner: referrer.key,
+                wallet: referrer.wallet,
+                chain_id: 3,
+                amount: DEPOSIT,
+                depth: 0,
+                token: RESERVE_SOL,
+            },
+            Effect::Write { target: RecordRef::Referrer, owner: referrer.key, user, slot_idx: 2, chain_id: 3, depth: 0 },
+            Effect::Notify { owner: referrer.key, wallet: referrer.wallet, chain_id: 3, next_chain_id: 10, depth: 0 },
+            Effect::Burn { owner: referrer.key, chain_id: 3, amount: DEPOSIT, depth: 0 },
+        ]
+    );
+
+    let referrer = outcome.referrer.unwrap();
+    assert_eq!(referrer.chain.id, 10);
+    assert_eq!(referrer.chain.filled_slots, 0);
+    assert_eq!(referrer.reserved_sol, 0);
+    assert_eq!(outcome.next_chain_id, 11);
+}
+
+#[test]
+fn payout_address_receives_the_reserve_and_owner_wallet_is_notified() {
+    let user = Pubkey::new_unique();
+    let mut referrer = record(3, 2, DEPOSIT, true);
+    referrer.payout = Pubkey::new_unique();
+
+    let outcome = matrix::register(user, DEPOSIT, RESERVE_SOL, referrer, 0, no_uplines, 10, DepthOverflowPolicy::Burn, &Throttle::default()).unwrap();
+    assert!(outcome.effects.contains(&Effect::Pay {
+        target: RecordRef::Referrer,
+        owner: referrer.key,
+        wallet: referrer.payout,
+        chain_id: 3,
+        amount: DEPOSIT,
+        depth: 0,
+        token: RESERVE_SOL,
+    }));
+    assert!(outcome.effects.contains(&Effect::Notify {
+        owner: referrer.key,
+        wallet: referrer.wallet,
+        chain_id: 3,
+        next_chain_id: 10,
+        depth: 0,
+    }));
+}
+
+#[test]
+fn non_base_completion_requires_uplines() {
+    let referrer = record(3, 2, DEPOSIT, false);
+    assert!(matrix::register(Pubkey::new_unique(), DEPOSIT, RESERVE_SOL, referrer, 0, no_uplines, 10, DepthOverflowPolicy::Burn, &Throttle::default()).is_err());
+}
+
+#[test]
+fn cascade_stops_at_first_open_upline() {
+    let referrer = record(3, 2, 0, false);
+    let uplines = [record(4, 2, DEPOSIT, false), record(5, 1, 0, true), record(6, 0, 0, true)];
+    let mut loaded = Vec::new();
+
+    let outcome = matrix::register(
+        Pubkey::new_unique(),
+        DEPOSIT,
+        RESERVE_SOL,
+        referrer,
+        uplines.len(),
+        |i| {
+            loaded.push(i);
+            Ok(uplines[i])
+        },
+        10,
+        DepthOverflowPolicy::Treasury,
+        &Throttle::default(),
+    )
+    .unwrap();
+
+    // The third upline is never reached
+    assert_eq!(loaded, vec![0, 1]);
+    assert_eq!(outcome.uplines.len(), 2);
+    assert_eq!(outcome.notification_count(), 2);
+    assert_eq!(outcome.next_chain_id, 12);
+
+    // The first upline completes with the referrer, the second reserves the deposit
+    assert_eq!(
+        outcome.effects.last(),
+        Some(&Effect::Reserve { owner: uplines[1].key, chain_id: 5, amount: DEPOSIT, depth: 2, token: RESERVE_SOL })
+    );
+    assert_eq!(
+        outcome.effects.iter().find(|e| matches!(e, Effect::Write { target: RecordRef::Upline(1), .. })),
+        Some(&Effect::Write {
+            target: RecordRef::Upline(1),
+            owner: uplines[1].key,
+            user: uplines[0].key,
+            slot_idx: 1,
+            chain_id: 5,
+            depth: 2,
+        })
+    );
+    assert_eq!(outcome.record(RecordRef::Upline(1)).unwrap().reserved_sol, DEPOSIT);
+}
+
+#[test]
+fn depth_overflow_follows_policy_unless_last_upline_is_base() {
+    for (policy, last_is_base, expected_burn) in [
+        (DepthOverflowPolicy::Treasury, false, false),
+        (DepthOverflowPolicy::Refund, false, false),
+        (DepthOverflowPolicy::Burn, false, true),
+        (DepthOverflowPolicy::Refund, true, true),
+    ] {
+        let referrer = record(3, 2, 0, false);
+        let upline = record(4, 2, 0, last_is_base);
+
+        let outcome = matrix::register(Pubkey::new_unique(), DEPOSIT, RESERVE_SOL, referrer, 1, |_| Ok(upline), 10, policy, &Throttle::default()).unwrap();
+        let settled = *outcome.effects.last().unwrap();
+
+        if expected_burn {
+            assert_eq!(settled, Effect::Burn { owner: upline.key, chain_id: 4, amount: DEPOSIT, depth: 1 });
+        } else {
+            assert_eq!(settled, Effect::Overflow { owner: upline.key, chain_id: 4, amount: DEPOSIT, depth: 1, policy });
+        }
+        assert_eq!(outcome.remaining_deposit, 0);
+    }
+}
+
+#[test]
+fn advance_keeps_deposit_pending_until_upline_is_exhausted() {
+    let referrer = Pubkey::new_unique();
+    let uplines = [record(4, 2, 0, false), record(5, 2, 0, false), record(6, 2, 0, true)];
+
+    // First step: one pair, all three uplines stored
+    let outcome = matrix::advance(referrer, DEPOSIT, RESERVE_SOL, 0, 1, 3, 6, |i| Ok(uplines[i]), 10, DepthOverflowPolicy::Refund, &Throttle::default()).unwrap();
+    assert_eq!(outcome.remaining_deposit, DEPOSIT);
+    assert_eq!(outcome.next_upline_index, 1);
+    assert_eq!(outcome.current_user, uplines[0].key);
+    assert!(!outcome.effects.iter().any(|e| matches!(e, Effect::Burn { .. } | Effect::Overflow { .. })));
+
+    // Last step: the top upline is a base user - the deposit is burned
+    let outcome = matrix::advance(
+        outcome.current_user,
+        outcome.remaining_deposit,
+        RESERVE_SOL,
+        outcome.next_upline_index,
+        2,
+        3,
+        6,
+        |i| Ok(uplines[i + 1]),
+        outcome.next_chain_id,
+        DepthOverflowPolicy::Refund,
+        &Throttle::default(),
+    )
+    .unwrap();
+    assert_eq!(outcome.remaining_deposit, 0);
+    assert_eq!(outcome.next_upline_index, 3);
+    assert_eq!(outcome.next_chain_id, 13);
+    assert_eq!(
+        outcome.effects.last(),
+        Some(&Effect::Burn { owner: uplines[2].key, chain_id: 6, amount: DEPOSIT, depth: 3 })
+    );
+}
+
+#[test]
+fn reserve_is_paid_in_the_token_it_was_deposited_in() {
+    const TOKEN: u8 = 1;
+    const TOKEN_DEPOSIT: u64 = 20_000_000;
+    let referrer = record(1, 1, 0, true);
+
+    let outcome = matrix::register(Pubkey::new_unique(), TOKEN_DEPOSIT, TOKEN, referrer, 0, no_uplines, 10, DepthOverflowPolicy::Burn, &Throttle::default()).unwrap();
+    assert_eq!(
+        outcome.effects[1],
+        Effect::Reserve { owner: referrer.key, chain_id: 1, amount: TOKEN_DEPOSIT, depth: 0, token: TOKEN }
+    );
+    let referrer = outcome.referrer.unwrap();
+    assert_eq!(referrer.reserve_token, TOKEN);
+
+    // A SOL deposit completes the matrix - the reserve still leaves in the token
+    let outcome = matrix::register(Pubkey::new_unique(), DEPOSIT, RESERVE_SOL, referrer, 0, no_uplines, 10, DepthOverflowPolicy::Burn, &Throttle::default()).unwrap();
+    assert_eq!(
+        outcome.effects[0],
+        Effect::Pay {
+            target: RecordRef::Referrer,
+            owner: referrer.key,
+            wallet: referrer.wallet,
+            chain_id: 1,
+            amount: TOKEN_DEPOSIT,
+            depth: 0,
+            token: TOKEN,
+        }
+    );
+    assert_eq!(outcome.effects.last(), Some(&Effect::Burn { owner: referrer.key, chain_id: 1, amount: DEPOSIT, depth: 0 }));
+    assert_eq!(outcome.referrer.unwrap().reserve_token, RESERVE_SOL);
+}
+
+#[test]
+fn referrer_limits_throttle_direct_completions_only() {
+    const WEEK: i64 = 1800;
+    let limits = ReferrerLimits { max_cycles_per_week: 2, min_cycle_interval: 600, week_start: 0 };
+    let now = 10 * WEEK + 900;
+    let throttle = Throttle { limits, now };
+    let mut referrer = record(1, 2, 0, true);
+
+    // Too soon after the last completion, then the weekly cap
+    referrer.cycles = CycleStats { last_cycle_at: now - 599, week: 11, count: 1 };
+    assert!(matrix::register(Pubkey::new_unique(), DEPOSIT, RESERVE_SOL, referrer, 0, no_uplines, 10, DepthOverflowPolicy::Burn, &throttle).is_err());
+    referrer.cycles = CycleStats { last_cycle_at: now - 600, week: 11, count: 2 };
+    assert!(matrix::register(Pubkey::new_unique(), DEPOSIT, RESERVE_SOL, referrer, 0, no_uplines, 10, DepthOverflowPolicy::Burn, &throttle).is_err());
+
+    // The count restarts with the week; filling the first two slots is never limited
+    referrer.cycles = CycleStats { last_cycle_at: now - 600, week: 10, count: 2 };
+    let outcome = matrix::register(Pubkey::new_unique(), DEPOSIT, RESERVE_SOL, referrer, 0, no_uplines, 10, DepthOverflowPolicy::Burn, &throttle).unwrap();
+    let completed = outcome.referrer.unwrap();
+    assert_eq!(completed.cycles, CycleStats { last_cycle_at: now, week: 11, count: 1 });
+    assert!(matrix::register(Pubkey::new_unique(), DEPOSIT, RESERVE_SOL, completed, 0, no_uplines, 11, DepthOverflowPolicy::Burn, &throttle).is_ok());
+
+    // A cascade completes a throttled upline and counts it
+    let mut upline = record(4, 2, 0, true);
+    upline.cycles = CycleStats { last_cycle_at: now, week: 11, count: 2 };
+    let outcome = matrix::register(Pubkey::new_unique(), DEPOSIT, RESERVE_SOL, record(3, 2, 0, false), 1, |_| Ok(upline), 10, DepthOverflowPolicy::Burn, &throttle).unwrap();
+    assert_eq!(outcome.uplines[0].cycles.count, 3);
+}
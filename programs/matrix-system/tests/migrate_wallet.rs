@@ -0,0 +1,271 @@
+// migrate_user_wallet: the position moves to the new wallet's PDA and the old PDA becomes
+// a redirect. Cascades through a stale upline entry follow the redirect to the new
+// position and pay the new wallet; refresh_user_references points the entries at it.
+
+mod common;
+
+use anchor_lang::AccountDeserialize;
+use common::*;
+use matrix_system::{ErrorCode, UserAccountRedirect, PENDING_REGISTRATION_TIMEOUT};
+use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey, signature::Signer};
+
+#[tokio::test]
+async fn migrated_position_keeps_its_reserve_and_payout() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let child = env.create_user();
+    env.register(&child, &base, DEPOSIT).await.unwrap();
+    let second = env.create_user();
+    env.register(&second, &base, DEPOSIT).await.unwrap();
+
+    let before = env.user_account(&base.pda).await;
+    let moved = env.create_user();
+    env.migrate_user_wallet(&base, &moved).await.unwrap();
+
+    // Same position under the new wallet
+    let after = env.user_account(&moved.pda).await;
+    assert_eq!(after.owner_wallet, moved.wallet.pubkey());
+    assert_eq!(after.reserved_sol, DEPOSIT);
+    assert_eq!(after.chain.id, before.chain.id);
+    assert_eq!(after.chain.slots, before.chain.slots);
+    assert_eq!(after.upline.id, before.upline.id);
+    assert_eq!(after.referral_count, 2);
+
+    let data = env.account(&base.pda).await.unwrap().data;
+    let redirect = UserAccountRedirect::try_deserialize(&mut data.as_slice()).unwrap();
+    assert_eq!(redirect.owner_wallet, base.wallet.pubkey());
+    assert_eq!(redirect.new_user, moved.pda);
+
+    // Slot 3 of the child cascades through its stale upline entry
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &child, DEPOSIT).await.unwrap();
+    }
+    let last = env.create_user();
+    let old_wallet = env.lamports(&base.wallet.pubkey()).await;
+    let new_wallet = env.lamports(&moved.wallet.pubkey()).await;
+    env.register(&last, &child, DEPOSIT).await.unwrap();
+    assert_eq!(env.lamports(&moved.wallet.pubkey()).await, new_wallet + DEPOSIT);
+    assert_eq!(env.lamports(&base.wallet.pubkey()).await, old_wallet);
+    assert_eq!(env.user_account(&moved.pda).await.reserved_sol, 0);
+}
+
+#[tokio::test]
+async fn migration_guards() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let child = env.create_user();
+    env.register(&child, &base, DEPOSIT).await.unwrap();
+
+    // The new wallet must not be registered
+    assert!(env.migrate_user_wallet(&child, &base).await.is_err());
+    // Nothing references a migration yet
+    assert!(env.refresh_user_references(&child.pda, &[base.pda]).await.is_err());
+
+    let moved = env.create_user();
+    env.migrate_user_wallet(&child, &moved).await.unwrap();
+    env.refresh_user_references(&base.pda, &[child.pda]).await.unwrap();
+    assert_eq!(env.user_account(&base.pda).await.chain.slots[0], moved.pda);
+    assert!(env.refresh_user_references(&base.pda, &[child.pda]).await.is_err());
+
+    // The old wallet cannot register again nor migrate twice
+    assert!(env.register(&child, &base, DEPOSIT).await.is_err());
+    let other = env.create_user();
+    assert!(env.migrate_user_wallet(&child, &other).await.is_err());
+
+    // The migrated position can move again
+    env.migrate_user_wallet(&moved, &other).await.unwrap();
+    assert_eq!(env.user_account(&other.pda).await.referrer, base.pda);
+}
+
+#[tokio::test]
+async fn refreshed_references_point_at_the_new_position() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let child = env.create_user();
+    env.register(&child, &base, DEPOSIT).await.unwrap();
+    let second = env.create_user();
+    env.register(&second, &base, DEPOSIT).await.unwrap();
+
+    let moved = env.create_user();
+    env.migrate_user_wallet(&base, &moved).await.unwrap();
+    env.refresh_user_references(&child.pda, &[base.pda]).await.unwrap();
+
+    let child_account = env.user_account(&child.pda).await;
+    assert_eq!(child_account.referrer, moved.pda);
+    let entries: Vec<(Pubkey, Pubkey)> = child_account.upline.entries().iter().map(|entry| (entry.pda, entry.wallet)).collect();
+    assert_eq!(entries, vec![(moved.pda, moved.wallet.pubkey())]);
+
+    // Plain pairs again - the redirect is no longer needed
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &child, DEPOSIT).await.unwrap();
+    }
+    let new_wallet = env.lamports(&moved.wallet.pubkey()).await;
+    let last = env.create_user();
+    env.register(&last, &child, DEPOSIT).await.unwrap();
+    assert_eq!(env.lamports(&moved.wallet.pubkey()).await, new_wallet + DEPOSIT);
+}
+
+// base -> middle -> leaf with base's and middle's reserves held, then middle migrates
+// while leaf still stores its old PDA. Returns (base, middle, moved, leaf).
+async fn stale_leaf(env: &mut TestEnv) -> (TestUser, TestUser, TestUser, TestUser) {
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let middle = env.create_user();
+    env.register(&middle, &base, DEPOSIT).await.unwrap();
+    let sibling = env.create_user();
+    env.register(&sibling, &base, DEPOSIT).await.unwrap();
+    let leaf = env.create_user();
+    env.register(&leaf, &middle, DEPOSIT).await.unwrap();
+    let other = env.create_user();
+    env.register(&other, &middle, DEPOSIT).await.unwrap();
+
+    let moved = env.create_user();
+    env.migrate_user_wallet(&middle, &moved).await.unwrap();
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &leaf, DEPOSIT).await.unwrap();
+    }
+    (base, middle, moved, leaf)
+}
+
+#[tokio::test]
+async fn cascade_through_an_unrefreshed_descendant_completes_the_new_position() {
+    let mut env = TestEnv::start().await;
+    let (base, middle, moved, leaf) = stale_leaf(&mut env).await;
+    let stale: Vec<Pubkey> = env.user_account(&leaf.pda).await.upline.entries().iter().map(|entry| entry.pda).collect();
+    assert_eq!(stale, vec![base.pda, middle.pda]);
+
+    let base_wallet = env.lamports(&base.wallet.pubkey()).await;
+    let old_wallet = env.lamports(&middle.wallet.pubkey()).await;
+    let new_wallet = env.lamports(&moved.wallet.pubkey()).await;
+    let user = env.create_user();
+    env.register(&user, &leaf, DEPOSIT).await.unwrap();
+
+    // Base completes, then the moved position completes and its reserve goes to the new wallet
+    assert_eq!(env.lamports(&base.wallet.pubkey()).await, base_wallet + DEPOSIT);
+    assert_eq!(env.lamports(&moved.wallet.pubkey()).await, new_wallet + DEPOSIT);
+    assert_eq!(env.lamports(&middle.wallet.pubkey()).await, old_wallet);
+    let moved_account = env.user_account(&moved.pda).await;
+    assert_eq!(moved_account.chain.filled_slots, 0);
+    assert_eq!(moved_account.reserved_sol, 0);
+    assert_eq!(env.lamports(&program_sol_vault()).await, 0);
+
+    // The redirect is left as is and the leaf still stores the old PDA
+    let data = env.account(&middle.pda).await.unwrap().data;
+    assert_eq!(UserAccountRedirect::try_deserialize(&mut data.as_slice()).unwrap().new_user, moved.pda);
+    assert_eq!(env.user_account(&leaf.pda).await.upline.entries()[1].pda, middle.pda);
+}
+
+#[tokio::test]
+async fn stale_upline_entry_must_name_its_new_position() {
+    let mut env = TestEnv::start().await;
+    let (base, middle, moved, leaf) = stale_leaf(&mut env).await;
+    let (old_pda, old_wallet, new_pda, new_wallet) = (middle.pda, middle.wallet.pubkey(), moved.pda, moved.wallet.pubkey());
+
+    // The old pair without the new position
+    let user = env.create_user();
+    let stale_pair = env
+        .register_edited(&user, &leaf, DEPOSIT, |accounts| {
+            let at = accounts.iter().position(|meta| meta.pubkey == old_pda).unwrap();
+            accounts.drain(at + 1..at + 3);
+            accounts.insert(at + 1, AccountMeta::new(old_wallet, false));
+        })
+        .await;
+    assert!(stale_pair.is_err());
+
+    // Another position behind the redirect
+    let (base_pda, base_wallet) = (base.pda, base.wallet.pubkey());
+    let other_position = env
+        .register_edited(&user, &leaf, DEPOSIT, |accounts| {
+            let at = accounts.iter().position(|meta| meta.pubkey == new_pda).unwrap();
+            accounts[at] = AccountMeta::new(base_pda, false);
+            accounts[at + 1] = AccountMeta::new(base_wallet, false);
+        })
+        .await;
+    assert!(other_position.is_err());
+
+    // A redirect whose new wallet is swapped for the old one
+    let old_wallet_paid = env
+        .register_edited(&user, &leaf, DEPOSIT, |accounts| {
+            let at = accounts.iter().position(|meta| meta.pubkey == new_wallet).unwrap();
+            accounts[at] = AccountMeta::new(old_wallet, false);
+        })
+        .await;
+    assert!(old_wallet_paid.is_err());
+
+    assert_eq!(env.user_account(&leaf.pda).await.chain.filled_slots, 2);
+    env.register(&user, &leaf, DEPOSIT).await.unwrap();
+    assert_eq!(env.user_account(&leaf.pda).await.chain.filled_slots, 0);
+}
+
+#[tokio::test]
+async fn pending_registration_follows_its_migrated_referrer() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let middle = env.create_user();
+    env.register(&middle, &base, DEPOSIT).await.unwrap();
+    let sibling = env.create_user();
+    env.register(&sibling, &base, DEPOSIT).await.unwrap();
+    let leaf = env.create_user();
+    env.register(&leaf, &middle, DEPOSIT).await.unwrap();
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &leaf, DEPOSIT).await.unwrap();
+    }
+
+    // The leaf completes and moves to a new wallet while the cascade is pending
+    let user = env.create_user();
+    env.begin_registration(&user, &leaf, DEPOSIT).await.unwrap();
+    let moved = env.create_user();
+    env.migrate_user_wallet(&leaf, &moved).await.unwrap();
+
+    let now = env.unix_timestamp().await;
+    env.set_unix_timestamp(now + PENDING_REGISTRATION_TIMEOUT).await;
+    let cranker = env.create_user();
+
+    // Only the new position of the pending referrer is accepted
+    let err = env.finish_pending_registration(&cranker.wallet, &user, &base).await.unwrap_err();
+    assert_eq!(error_code(err), Some(ErrorCode::InvalidPendingRegistration.into()));
+
+    let base_wallet_before = env.lamports(&base.wallet.pubkey()).await;
+    env.finish_pending_registration(&cranker.wallet, &user, &moved).await.unwrap();
+
+    assert_eq!(env.lamports(&base.wallet.pubkey()).await, base_wallet_before + DEPOSIT);
+    let middle_account = env.user_account(&middle.pda).await;
+    assert_eq!(middle_account.reserved_sol, DEPOSIT);
+    assert_eq!(middle_account.chain.slots[1], base.pda);
+    assert!(env.pending_registration(&user).await.is_none());
+    assert_eq!(env.lamports(&program_sol_vault()).await, DEPOSIT);
+}
+
+#[tokio::test]
+async fn advance_places_the_migrated_referrer_position() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let leaf = env.create_user();
+    env.register(&leaf, &base, DEPOSIT).await.unwrap();
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &leaf, DEPOSIT).await.unwrap();
+    }
+
+    let user = env.create_user();
+    env.begin_registration(&user, &leaf, DEPOSIT).await.unwrap();
+    let moved = env.create_user();
+    env.migrate_user_wallet(&leaf, &moved).await.unwrap();
+
+    // Base's slot 2 receives the leaf's new position, not the redirect
+    env.advance_registration(&user, &moved, 1).await.unwrap();
+
+    let base_account = env.user_account(&base.pda).await;
+    assert_eq!(base_account.chain.slots[1], moved.pda);
+    assert_eq!(base_account.reserved_sol, DEPOSIT);
+    assert!(env.pending_registration(&user).await.is_none());
+}
@@ -0,0 +1,137 @@
+// set_payout_address: reserve payments of the referrer and of cascaded uplines go to the
+// payout address while the owner wallet keeps the airdrop notifications.
+
+mod common;
+
+use common::*;
+use matrix_system::{ErrorCode, UserAccount};
+use solana_sdk::{pubkey::Pubkey, signature::Signer};
+
+#[tokio::test]
+async fn referrer_reserve_is_paid_to_payout_address() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+
+    let payout = Pubkey::new_unique();
+    env.set_payout_address(&base, &payout).await.unwrap();
+    assert_eq!(env.user_account(&base.pda).await.payout_address(), Some(payout));
+
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &base, DEPOSIT).await.unwrap();
+    }
+    let wallet_before = env.lamports(&base.wallet.pubkey()).await;
+    let notifications_before = env.airdrop_notifications().await;
+    let third = env.create_user();
+    env.register(&third, &base, DEPOSIT).await.unwrap();
+
+    assert_eq!(env.lamports(&payout).await, DEPOSIT);
+    assert_eq!(env.lamports(&base.wallet.pubkey()).await, wallet_before);
+    assert_eq!(env.airdrop_notifications().await, notifications_before + 1);
+
+    // The owner wallet clears it
+    env.set_payout_address(&base, &base.wallet.pubkey()).await.unwrap();
+    assert_eq!(env.user_account(&base.pda).await.payout_address(), None);
+}
+
+#[tokio::test]
+async fn referrer_wallet_must_own_the_referrer() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &base, DEPOSIT).await.unwrap();
+    }
+
+    // Without a payout address the reserve would go to the wallet the registrant sends
+    let third = env.create_user();
+    let (base_wallet, third_wallet) = (base.wallet.pubkey(), third.wallet.pubkey());
+    let err = env
+        .register_edited(&third, &base, DEPOSIT, |accounts| {
+            let at = accounts.iter().position(|meta| meta.pubkey == base_wallet).unwrap();
+            accounts[at].pubkey = third_wallet;
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), Some(ErrorCode::InvalidAccountOwner.into()));
+
+    let wallet_before = env.lamports(&base_wallet).await;
+    env.register(&third, &base, DEPOSIT).await.unwrap();
+    assert_eq!(env.lamports(&base_wallet).await, wallet_before + DEPOSIT);
+}
+
+#[tokio::test]
+async fn cascade_pays_upline_payout_address() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let middle = env.create_user();
+    env.register(&middle, &base, DEPOSIT).await.unwrap();
+
+    let base_payout = Pubkey::new_unique();
+    let middle_payout = Pubkey::new_unique();
+    env.set_payout_address(&base, &base_payout).await.unwrap();
+    env.set_payout_address(&middle, &middle_payout).await.unwrap();
+
+    // Two completed matrices of middle land on base slots 2 and 3
+    let wallet_before = env.lamports(&base.wallet.pubkey()).await;
+    for _ in 0..6 {
+        let user = env.create_user();
+        env.register(&user, &middle, DEPOSIT).await.unwrap();
+    }
+
+    assert_eq!(env.user_account(&base.pda).await.chain.filled_slots, 0);
+    assert_eq!(env.lamports(&base_payout).await, DEPOSIT);
+    assert_eq!(env.lamports(&middle_payout).await, 2 * DEPOSIT);
+    assert_eq!(env.lamports(&base.wallet.pubkey()).await, wallet_before);
+}
+
+#[tokio::test]
+async fn payout_address_must_be_a_system_account() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let other = env.create_user();
+    env.register_without_referrer(&other, DEPOSIT).await.unwrap();
+
+    assert!(env.set_payout_address(&base, &other.pda).await.is_err());
+
+    // A payout address that stopped being a system account blocks the payment
+    let mut account = env.user_account(&base.pda).await;
+    account.payout_address = other.pda;
+    env.set_user_account(&base.pda, &account).await;
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &base, DEPOSIT).await.unwrap();
+    }
+    let third = env.create_user();
+    assert!(env.register(&third, &base, DEPOSIT).await.is_err());
+}
+
+#[tokio::test]
+async fn account_without_payout_address_is_resized() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let leaf = env.create_user();
+    env.register(&leaf, &base, DEPOSIT).await.unwrap();
+
+    // Same account in the layout before payout_address, which also predates cycles
+    let before = env.user_account(&leaf.pda).await;
+    let mut data = env.account(&leaf.pda).await.unwrap().data;
+    data.drain(UserAccount::PAYOUT_ADDRESS_OFFSET..UserAccount::CYCLE_STATS_OFFSET + UserAccount::CYCLE_STATS_SIZE);
+    env.set_raw_account(&leaf.pda, data);
+    assert!(env.set_payout_address(&leaf, &Pubkey::new_unique()).await.is_err());
+
+    env.resize_user_account(&leaf.pda).await.unwrap();
+
+    assert_eq!(env.account(&leaf.pda).await.unwrap().data.len(), 8 + UserAccount::SIZE);
+    let after = env.user_account(&leaf.pda).await;
+    assert_eq!(after.payout_address(), None);
+    assert_eq!(after.upline.count, before.upline.count);
+    assert_eq!(after.upline.upline[0].pda, before.upline.upline[0].pda);
+    assert_eq!(after.upline.upline[0].wallet, before.upline.upline[0].wallet);
+    env.set_payout_address(&leaf, &Pubkey::new_unique()).await.unwrap();
+}
@@ -0,0 +1,174 @@
+// Multi-instruction registration: begin_registration holds a slot 3 deposit in the
+// pending PDA, advance_registration walks the upline in batches, and once the timeout
+// has passed anyone can settle the cascade with finish_pending_registration.
+
+mod common;
+
+use common::*;
+use matrix_system::{ErrorCode, PENDING_REGISTRATION_TIMEOUT};
+use solana_sdk::signature::Signer;
+
+// Tree where the next registration under `leaf` completes the leaf, then walks its
+// stored upline top-down: base -> middle -> leaf, with base's slot 2 reserved so the
+// deposit completes base and lands in middle's slot 2, and leaf's slots 1 and 2 filled
+async fn pending_tree(env: &mut TestEnv) -> (TestUser, TestUser, TestUser) {
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let middle = env.create_user();
+    env.register(&middle, &base, DEPOSIT).await.unwrap();
+    let sibling = env.create_user();
+    env.register(&sibling, &base, DEPOSIT).await.unwrap();
+    let leaf = env.create_user();
+    env.register(&leaf, &middle, DEPOSIT).await.unwrap();
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &leaf, DEPOSIT).await.unwrap();
+    }
+    (base, middle, leaf)
+}
+
+#[tokio::test]
+async fn begin_then_advance_in_batches_settles_the_cascade() {
+    let mut env = TestEnv::start().await;
+    let (base, middle, leaf) = pending_tree(&mut env).await;
+
+    // The leaf's matrix completes - its reserve is paid and the deposit is held
+    let user = env.create_user();
+    let leaf_wallet_before = env.lamports(&leaf.wallet.pubkey()).await;
+    env.begin_registration(&user, &leaf, DEPOSIT).await.unwrap();
+
+    assert_eq!(env.user_account(&leaf.pda).await.chain.filled_slots, 0);
+    assert_eq!(env.lamports(&leaf.wallet.pubkey()).await, leaf_wallet_before + DEPOSIT);
+    let pending = env.pending_registration(&user).await.unwrap();
+    assert_eq!(pending.remaining_deposit, DEPOSIT);
+    assert_eq!(pending.next_upline_index, 0);
+    assert_eq!(pending.current_user, leaf.pda);
+
+    // First batch: base completes and is paid its reserve, the deposit moves on
+    let base_wallet_before = env.lamports(&base.wallet.pubkey()).await;
+    env.advance_registration(&user, &leaf, 1).await.unwrap();
+
+    assert_eq!(env.user_account(&base.pda).await.chain.filled_slots, 0);
+    assert_eq!(env.lamports(&base.wallet.pubkey()).await, base_wallet_before + DEPOSIT);
+    let pending = env.pending_registration(&user).await.unwrap();
+    assert_eq!(pending.remaining_deposit, DEPOSIT);
+    assert_eq!(pending.next_upline_index, 1);
+    assert_eq!(pending.current_user, base.pda);
+
+    // Second batch: middle's slot 2 reserves the deposit and the pending PDA is closed
+    env.advance_registration(&user, &leaf, 1).await.unwrap();
+
+    let middle_account = env.user_account(&middle.pda).await;
+    assert_eq!(middle_account.chain.filled_slots, 2);
+    assert_eq!(middle_account.reserved_sol, DEPOSIT);
+    assert!(env.pending_registration(&user).await.is_none());
+    assert_eq!(env.program_state().await.total_reserved_lamports, DEPOSIT);
+    assert_eq!(env.lamports(&program_sol_vault()).await, DEPOSIT);
+}
+
+#[tokio::test]
+async fn only_the_final_step_flags_the_last_notification() {
+    let mut env = TestEnv::start().await;
+    let (_, _, leaf) = pending_tree(&mut env).await;
+
+    // The leaf completes, but the cascade goes on in advance_registration
+    let user = env.create_user();
+    let notifications_before = env.airdrop_notifications().await;
+    let last_before = env.airdrop_last_notifications().await;
+    env.begin_registration(&user, &leaf, DEPOSIT).await.unwrap();
+
+    assert_eq!(env.airdrop_notifications().await, notifications_before + 1);
+    assert_eq!(env.airdrop_last_notifications().await, last_before);
+
+    // Base completes and middle reserves the deposit - base's is the last notification
+    env.advance_registration(&user, &leaf, 2).await.unwrap();
+
+    assert!(env.pending_registration(&user).await.is_none());
+    assert_eq!(env.airdrop_notifications().await, notifications_before + 2);
+    assert_eq!(env.airdrop_last_notifications().await, last_before + 1);
+}
+
+#[tokio::test]
+async fn advance_past_the_depth_limit_is_rejected() {
+    let mut env = TestEnv::start().await;
+    let (base, _, leaf) = pending_tree(&mut env).await;
+
+    let user = env.create_user();
+    env.begin_registration(&user, &leaf, DEPOSIT).await.unwrap();
+    env.advance_registration(&user, &leaf, 1).await.unwrap();
+
+    // Depth lowered to the base user, already processed
+    let mut state = env.program_state().await;
+    state.max_upline_depth = 1;
+    env.set_program_state(&state);
+
+    let chain_id_before = env.program_state().await.next_chain_id;
+    let err = env.advance_registration(&user, &leaf, 1).await.unwrap_err();
+    assert_eq!(error_code(err), Some(ErrorCode::InvalidPendingRegistration.into()));
+
+    let pending = env.pending_registration(&user).await.unwrap();
+    assert_eq!(pending.remaining_deposit, DEPOSIT);
+    assert_eq!(pending.current_user, base.pda);
+    assert_eq!(env.program_state().await.next_chain_id, chain_id_before);
+    assert_eq!(env.lamports(&burn_escrow()).await, 0);
+}
+
+#[tokio::test]
+async fn expired_registration_is_finished_by_anyone() {
+    let mut env = TestEnv::start().await;
+    let (base, middle, leaf) = pending_tree(&mut env).await;
+
+    let user = env.create_user();
+    env.begin_registration(&user, &leaf, DEPOSIT).await.unwrap();
+
+    // The wallet keeps the cascade until the timeout
+    let cranker = env.create_user();
+    assert!(env.finish_pending_registration(&cranker.wallet, &user, &leaf).await.is_err());
+
+    let now = env.unix_timestamp().await;
+    env.set_unix_timestamp(now + PENDING_REGISTRATION_TIMEOUT).await;
+
+    let pending_rent = env.lamports(&pending_registration(&user.wallet.pubkey())).await - DEPOSIT;
+    let user_wallet_before = env.lamports(&user.wallet.pubkey()).await;
+    let base_wallet_before = env.lamports(&base.wallet.pubkey()).await;
+    env.finish_pending_registration(&cranker.wallet, &user, &leaf).await.unwrap();
+
+    assert_eq!(env.lamports(&base.wallet.pubkey()).await, base_wallet_before + DEPOSIT);
+    assert_eq!(env.user_account(&middle.pda).await.reserved_sol, DEPOSIT);
+    assert!(env.pending_registration(&user).await.is_none());
+    assert_eq!(env.lamports(&user.wallet.pubkey()).await, user_wallet_before + pending_rent);
+    assert_eq!(env.lamports(&program_sol_vault()).await, DEPOSIT);
+}
+
+#[tokio::test]
+async fn finished_registration_escrows_the_burn_at_the_depth_limit() {
+    let mut env = TestEnv::start().await;
+    let (base, middle, leaf) = pending_tree(&mut env).await;
+
+    let user = env.create_user();
+    env.begin_registration(&user, &leaf, DEPOSIT).await.unwrap();
+
+    // Depth lowered to the base user - its deposit is burned once it completes
+    let mut state = env.program_state().await;
+    state.max_upline_depth = 1;
+    env.set_program_state(&state);
+
+    let now = env.unix_timestamp().await;
+    env.set_unix_timestamp(now + PENDING_REGISTRATION_TIMEOUT).await;
+
+    let cranker = env.create_user();
+    let base_wallet_before = env.lamports(&base.wallet.pubkey()).await;
+    env.finish_pending_registration(&cranker.wallet, &user, &leaf).await.unwrap();
+
+    assert_eq!(env.lamports(&base.wallet.pubkey()).await, base_wallet_before + DEPOSIT);
+    assert_eq!(env.user_account(&middle.pda).await.chain.filled_slots, 1);
+    assert_eq!(env.lamports(&burn_escrow()).await, DEPOSIT);
+    assert_eq!(env.program_state().await.pending_burn_lamports, DEPOSIT);
+    assert!(env.pending_registration(&user).await.is_none());
+
+    // Burned like any escrowed burn
+    let supply_before = env.donut_supply().await;
+    env.retry_pending_burns(&cranker.wallet).await.unwrap();
+    assert!(env.donut_supply().await < supply_before);
+    assert_eq!(env.lamports(&burn_escrow()).await, 0);
+}
@@ -0,0 +1,138 @@
+// Protocol fee: a share of each SOL deposit goes to the multisig treasury before the
+// matrix routes the rest, and the oracle minimum applies to the gross deposit. A deposit
+// refunded by the depth overflow policy pays no fee.
+
+mod common;
+
+use common::*;
+use matrix_system::{DepthOverflowPolicy, MAX_PROTOCOL_FEE_BPS};
+use solana_sdk::{pubkey::Pubkey, signature::Signer};
+
+const FEE_BPS: u16 = 250;
+
+async fn env_with_fee() -> (TestEnv, TestUser, Pubkey) {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    env.set_protocol_fee(FEE_BPS).await.unwrap();
+
+    // A treasury apart from the test payer, so its balance only moves with the fee
+    let treasury = Pubkey::new_unique();
+    let mut state = env.program_state().await;
+    state.multisig_treasury = treasury;
+    env.set_program_state(&state);
+    (env, base, treasury)
+}
+
+// Tree under `base` where the next registration under the returned leaf completes the
+// leaf, base and middle - the last upline stored, with a referrer above it - so the
+// cascade ends at the depth limit with the Refund policy
+async fn refund_tree(env: &mut TestEnv, base: &TestUser) -> TestUser {
+    let middle = env.create_user();
+    env.register(&middle, base, DEPOSIT).await.unwrap();
+    let sibling = env.create_user();
+    env.register(&sibling, base, DEPOSIT).await.unwrap();
+    let leaf = env.create_user();
+    env.register(&leaf, &middle, DEPOSIT).await.unwrap();
+    let leaf_sibling = env.create_user();
+    env.register(&leaf_sibling, &middle, DEPOSIT).await.unwrap();
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &leaf, DEPOSIT).await.unwrap();
+    }
+
+    let mut state = env.program_state().await;
+    state.max_upline_depth = 2;
+    state.depth_overflow_policy = DepthOverflowPolicy::Refund;
+    env.set_program_state(&state);
+    leaf
+}
+
+#[tokio::test]
+async fn fee_goes_to_the_treasury_before_routing() {
+    let (mut env, base, treasury) = env_with_fee().await;
+    let fee = DEPOSIT * FEE_BPS as u64 / 10_000;
+
+    let first = env.create_user();
+    env.register(&first, &base, DEPOSIT).await.unwrap();
+    assert_eq!(env.lamports(&treasury).await, fee);
+
+    // The second slot reserves the deposit net of the fee
+    let second = env.create_user();
+    env.register(&second, &base, DEPOSIT).await.unwrap();
+    assert_eq!(env.lamports(&treasury).await, 2 * fee);
+    assert_eq!(env.user_account(&base.pda).await.reserved_sol, DEPOSIT - fee);
+    assert_eq!(env.program_state().await.total_reserved_lamports, DEPOSIT - fee);
+}
+
+#[tokio::test]
+async fn minimum_applies_to_the_gross_deposit() {
+    let (mut env, base, _) = env_with_fee().await;
+    env.set_protocol_fee(MAX_PROTOCOL_FEE_BPS).await.unwrap();
+
+    // 0.07 SOL at $150 clears the $10 minimum, 90% of it would not
+    let user = env.create_user();
+    env.register(&user, &base, 70_000_000).await.unwrap();
+
+    assert!(env.set_protocol_fee(MAX_PROTOCOL_FEE_BPS + 1).await.is_err());
+    assert_eq!(env.program_state().await.protocol_fee_bps, MAX_PROTOCOL_FEE_BPS);
+}
+
+#[tokio::test]
+async fn refunded_deposit_pays_no_fee() {
+    let (mut env, base, treasury) = env_with_fee().await;
+    let leaf = refund_tree(&mut env, &base).await;
+
+    let user = env.create_user();
+    let treasury_before = env.lamports(&treasury).await;
+    let wallet_before = env.lamports(&user.wallet.pubkey()).await + env.lamports(&user.wsol).await;
+    env.register(&user, &leaf, DEPOSIT).await.unwrap();
+
+    // Only the new account's rent leaves the wallet, the WSOL account's comes back
+    assert_eq!(env.lamports(&treasury).await, treasury_before);
+    assert_eq!(env.lamports(&user.wallet.pubkey()).await, wallet_before - env.lamports(&user.pda).await);
+}
+
+#[tokio::test]
+async fn pending_cascade_holds_the_fee_until_it_settles() {
+    let (mut env, base, treasury) = env_with_fee().await;
+    let fee = DEPOSIT * FEE_BPS as u64 / 10_000;
+    let leaf = refund_tree(&mut env, &base).await;
+
+    // Under the Treasury policy the fee is taken with the deposit it routes
+    let mut state = env.program_state().await;
+    state.depth_overflow_policy = DepthOverflowPolicy::Treasury;
+    env.set_program_state(&state);
+
+    let user = env.create_user();
+    let treasury_before = env.lamports(&treasury).await;
+    env.begin_registration(&user, &leaf, DEPOSIT).await.unwrap();
+
+    let pending = env.pending_registration(&user).await.unwrap();
+    assert_eq!(pending.protocol_fee, fee);
+    assert_eq!(pending.remaining_deposit, DEPOSIT - fee);
+    assert_eq!(env.lamports(&treasury).await, treasury_before);
+
+    env.advance_registration(&user, &leaf, 2).await.unwrap();
+    assert!(env.pending_registration(&user).await.is_none());
+    assert_eq!(env.lamports(&treasury).await, treasury_before + DEPOSIT);
+}
+
+#[tokio::test]
+async fn refunded_pending_cascade_returns_the_fee() {
+    let (mut env, base, treasury) = env_with_fee().await;
+    let leaf = refund_tree(&mut env, &base).await;
+
+    let user = env.create_user();
+    let treasury_before = env.lamports(&treasury).await;
+    env.begin_registration(&user, &leaf, DEPOSIT).await.unwrap();
+
+    // The whole deposit and the rent of the pending PDA and the WSOL account go back to the wallet
+    let pending_rent = env.lamports(&pending_registration(&user.wallet.pubkey())).await - DEPOSIT;
+    let wallet_before = env.lamports(&user.wallet.pubkey()).await + env.lamports(&user.wsol).await;
+    env.advance_registration(&user, &leaf, 2).await.unwrap();
+
+    assert!(env.pending_registration(&user).await.is_none());
+    assert_eq!(env.lamports(&treasury).await, treasury_before);
+    assert_eq!(env.lamports(&user.wallet.pubkey()).await, wallet_before + DEPOSIT + pending_rent);
+}
@@ -0,0 +1,54 @@
+// claim_referral_code: a registered user maps one short code to its UserAccount, first
+// come first served.
+
+mod common;
+
+use common::*;
+use matrix_system::USER_FLAG_HAS_REFERRAL_CODE;
+use solana_sdk::signature::Signer;
+
+#[tokio::test]
+async fn registered_user_claims_one_code() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+
+    env.claim_referral_code(&base, "donut-42").await.unwrap();
+    let code = env.referral_code("donut-42").await.unwrap();
+    assert_eq!(code.code, "donut-42");
+    assert_eq!(code.user, base.pda);
+    assert_eq!(code.owner_wallet, base.wallet.pubkey());
+    assert_ne!(env.user_account(&base.pda).await.flags & USER_FLAG_HAS_REFERRAL_CODE, 0);
+
+    // One code per user
+    assert!(env.claim_referral_code(&base, "second").await.is_err());
+    assert!(env.referral_code("second").await.is_none());
+
+    // The flag leaves registrations under the user unchanged
+    let user = env.create_user();
+    env.register(&user, &base, DEPOSIT).await.unwrap();
+    assert_eq!(env.user_account(&base.pda).await.chain.filled_slots, 1);
+}
+
+#[tokio::test]
+async fn taken_invalid_or_unregistered_claims_are_rejected() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let other = env.create_user();
+    env.register(&other, &base, DEPOSIT).await.unwrap();
+
+    env.claim_referral_code(&base, "taken").await.unwrap();
+    assert!(env.claim_referral_code(&other, "taken").await.is_err());
+
+    for code in ["ab", "UPPER", "with space", "seventeen-chars-x"] {
+        assert!(env.claim_referral_code(&other, code).await.is_err(), "{}", code);
+    }
+
+    // A wallet without a UserAccount cannot claim
+    let unregistered = env.create_user();
+    assert!(env.claim_referral_code(&unregistered, "nobody").await.is_err());
+
+    env.claim_referral_code(&other, "other_1").await.unwrap();
+    assert_eq!(env.referral_code("other_1").await.unwrap().user, other.pda);
+}
@@ -0,0 +1,96 @@
+// Referrer limits: completions of a referrer's matrix are throttled by the minimum
+// interval and the weekly cap against the Clock, and accounts created before the cycle
+// counters are resized.
+
+mod common;
+
+use common::*;
+use matrix_system::{CycleStats, ReferrerLimits, UserAccount};
+use solana_sdk::pubkey::Pubkey;
+
+const WEEK: i64 = 1800;
+
+// Fill the first two slots of `referrer` - never limited
+async fn fill_two_slots(env: &mut TestEnv, referrer: &TestUser) {
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, referrer, DEPOSIT).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn completions_wait_for_the_minimum_interval() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let now = env.unix_timestamp().await;
+    env.set_referrer_limits(ReferrerLimits { max_cycles_per_week: 0, min_cycle_interval: 600, week_start: now }).await.unwrap();
+
+    fill_two_slots(&mut env, &base).await;
+    let third = env.create_user();
+    env.register(&third, &base, DEPOSIT).await.unwrap();
+    assert_eq!(env.user_account(&base.pda).await.cycles.last_cycle_at, now);
+
+    fill_two_slots(&mut env, &base).await;
+    let late = env.create_user();
+    assert!(env.register(&late, &base, DEPOSIT).await.is_err());
+
+    env.set_unix_timestamp(now + 600).await;
+    env.register(&late, &base, DEPOSIT).await.unwrap();
+    assert_eq!(env.user_account(&base.pda).await.cycles, CycleStats { last_cycle_at: now + 600, week: 1, count: 2 });
+}
+
+#[tokio::test]
+async fn weekly_cap_resets_with_the_airdrop_week() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    // The next week starts in a minute - the airdrop itself stays in its first week
+    let now = env.unix_timestamp().await;
+    let week_start = now - WEEK + 60;
+    env.set_referrer_limits(ReferrerLimits { max_cycles_per_week: 1, min_cycle_interval: 0, week_start }).await.unwrap();
+
+    fill_two_slots(&mut env, &base).await;
+    let third = env.create_user();
+    env.register(&third, &base, DEPOSIT).await.unwrap();
+    assert_eq!(env.user_account(&base.pda).await.cycles, CycleStats { last_cycle_at: now, week: 1, count: 1 });
+
+    fill_two_slots(&mut env, &base).await;
+    let next = env.create_user();
+    assert!(env.register(&next, &base, DEPOSIT).await.is_err());
+
+    env.set_unix_timestamp(now + 60).await;
+    env.register(&next, &base, DEPOSIT).await.unwrap();
+    assert_eq!(env.user_account(&base.pda).await.cycles, CycleStats { last_cycle_at: now + 60, week: 2, count: 1 });
+
+    // Negative limits are rejected
+    let invalid = ReferrerLimits { max_cycles_per_week: 1, min_cycle_interval: -1, week_start };
+    assert!(env.set_referrer_limits(invalid).await.is_err());
+}
+
+#[tokio::test]
+async fn account_without_cycles_is_resized() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    fill_two_slots(&mut env, &base).await;
+    let leaf = env.create_user();
+    env.register(&leaf, &base, DEPOSIT).await.unwrap();
+
+    // Same account in the layout before cycles
+    let before = env.user_account(&base.pda).await;
+    assert_eq!(before.cycles.count, 1);
+    let mut data = env.account(&base.pda).await.unwrap().data;
+    data.drain(UserAccount::CYCLE_STATS_OFFSET..UserAccount::CYCLE_STATS_OFFSET + UserAccount::CYCLE_STATS_SIZE);
+    env.set_raw_account(&base.pda, data);
+    assert!(env.set_payout_address(&base, &Pubkey::new_unique()).await.is_err());
+
+    env.resize_user_account(&base.pda).await.unwrap();
+
+    let after = env.user_account(&base.pda).await;
+    assert_eq!(after.cycles, CycleStats::default());
+    assert_eq!(after.chain.id, before.chain.id);
+    assert_eq!(after.upline.depth, before.upline.depth);
+    let user = env.create_user();
+    env.register(&user, &base, DEPOSIT).await.unwrap();
+}
@@ -0,0 +1,222 @@
+// Registration flows against the mock Meteora, Chainlink and airdrop programs.
+// Every slot of the referrer's matrix is exercised, including the slot 3 cascade
+// into a base upline at each of its three slots.
+
+mod common;
+
+use anchor_lang::{AnchorSerialize, Discriminator};
+use common::*;
+use matrix_system::{ErrorCode, LegacyReferralChain, LegacyReferralUpline, LegacyUserAccount, UserAccount};
+use solana_sdk::signature::Signer;
+
+#[tokio::test]
+async fn base_registration_burns_deposit() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    let supply_before = env.donut_supply().await;
+
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+
+    let account = env.user_account(&base.pda).await;
+    assert!(account.is_registered());
+    assert_eq!(account.referrer(), None);
+    assert_eq!(account.owner_wallet, base.wallet.pubkey());
+    assert_eq!(account.upline.count, 0);
+    assert_eq!(account.chain.filled_slots, 0);
+
+    // The swapped DONUT leaves the user's account and the supply
+    let donut_out = DEPOSIT * (DONUT_RESERVE / SOL_RESERVE);
+    assert_eq!(env.donut_supply().await, supply_before - donut_out);
+    assert_eq!(env.token_amount(&base.donut).await, 0);
+
+    let state = env.program_state().await;
+    assert_eq!(state.next_upline_id, 2);
+    assert_eq!(state.next_chain_id, 2);
+}
+
+#[tokio::test]
+async fn referred_registration_fills_each_slot() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+
+    // Slot 1: swap and burn
+    let first = env.create_user();
+    let supply_before = env.donut_supply().await;
+    env.register(&first, &base, DEPOSIT).await.unwrap();
+
+    let referrer = env.user_account(&base.pda).await;
+    assert_eq!(referrer.chain.filled_slots, 1);
+    assert_eq!(referrer.chain.slots[0], first.pda);
+    assert!(env.donut_supply().await < supply_before);
+
+    let user = env.user_account(&first.pda).await;
+    assert_eq!(user.referrer(), Some(base.pda));
+    assert_eq!(user.upline.count, 1);
+    assert_eq!(user.upline.upline[0].wallet, base.wallet.pubkey());
+
+    // Slot 2: deposit reserved in the program vault
+    let second = env.create_user();
+    let vault_before = env.lamports(&program_sol_vault()).await;
+    let supply_before = env.donut_supply().await;
+    env.register(&second, &base, DEPOSIT).await.unwrap();
+
+    let referrer = env.user_account(&base.pda).await;
+    assert_eq!(referrer.chain.filled_slots, 2);
+    assert_eq!(referrer.reserved_sol, DEPOSIT);
+    assert_eq!(env.lamports(&program_sol_vault()).await, vault_before + DEPOSIT);
+    assert_eq!(env.donut_supply().await, supply_before);
+
+    // Slot 3 of a base referrer: reserve paid out, airdrop notified, deposit burned
+    let third = env.create_user();
+    let wallet_before = env.lamports(&base.wallet.pubkey()).await;
+    let notifications_before = env.airdrop_notifications().await;
+    let supply_before = env.donut_supply().await;
+    env.register(&third, &base, DEPOSIT).await.unwrap();
+
+    let referrer = env.user_account(&base.pda).await;
+    assert_eq!(referrer.chain.filled_slots, 0);
+    assert_eq!(referrer.reserved_sol, 0);
+    assert_eq!(env.lamports(&base.wallet.pubkey()).await, wallet_before + DEPOSIT);
+    assert_eq!(env.lamports(&program_sol_vault()).await, vault_before);
+    assert_eq!(env.airdrop_notifications().await, notifications_before + 1);
+    assert!(env.donut_supply().await < supply_before);
+}
+
+#[tokio::test]
+async fn slot_three_cascades_through_base_upline() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+
+    let middle = env.create_user();
+    env.register(&middle, &base, DEPOSIT).await.unwrap();
+    assert_eq!(env.user_account(&base.pda).await.chain.filled_slots, 1);
+
+    // Completes the middle user's matrix three times, landing on each slot of the base
+    async fn complete_matrix(env: &mut TestEnv, referrer: &TestUser) {
+        for _ in 0..3 {
+            let user = env.create_user();
+            env.register(&user, referrer, DEPOSIT).await.unwrap();
+        }
+        assert_eq!(env.user_account(&referrer.pda).await.chain.filled_slots, 0);
+    }
+
+    // Base slot 2: the cascaded deposit is reserved for the base upline
+    let vault_before = env.lamports(&program_sol_vault()).await;
+    complete_matrix(&mut env, &middle).await;
+
+    let upline = env.user_account(&base.pda).await;
+    assert_eq!(upline.chain.filled_slots, 2);
+    assert_eq!(upline.reserved_sol, DEPOSIT);
+    // Middle's own slot 2 reserve was paid out on slot 3; only the cascade remains
+    assert_eq!(env.lamports(&program_sol_vault()).await, vault_before + DEPOSIT);
+
+    // Base slot 3: reserve paid to the base wallet, matrix reset, deposit burned
+    let wallet_before = env.lamports(&base.wallet.pubkey()).await;
+    let notifications_before = env.airdrop_notifications().await;
+    let supply_before = env.donut_supply().await;
+    complete_matrix(&mut env, &middle).await;
+
+    let upline = env.user_account(&base.pda).await;
+    assert_eq!(upline.chain.filled_slots, 0);
+    assert_eq!(upline.reserved_sol, 0);
+    assert_eq!(env.lamports(&base.wallet.pubkey()).await, wallet_before + DEPOSIT);
+    assert_eq!(env.lamports(&program_sol_vault()).await, vault_before);
+    // Middle completed and base completed
+    assert_eq!(env.airdrop_notifications().await, notifications_before + 2);
+    assert!(env.donut_supply().await < supply_before);
+
+    // Base slot 1: the cascaded deposit is swapped and burned
+    let supply_before = env.donut_supply().await;
+    complete_matrix(&mut env, &middle).await;
+
+    let upline = env.user_account(&base.pda).await;
+    assert_eq!(upline.chain.filled_slots, 1);
+    assert_eq!(upline.reserved_sol, 0);
+    let burned_per_deposit = DEPOSIT * (DONUT_RESERVE / SOL_RESERVE);
+    // Slot 1 of the middle user and the cascaded slot 3 deposit
+    assert_eq!(env.donut_supply().await, supply_before - 2 * burned_per_deposit);
+}
+
+#[tokio::test]
+async fn slot_three_with_a_truncated_upline_is_rejected() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    let middle = env.create_user();
+    env.register(&middle, &base, DEPOSIT).await.unwrap();
+    let leaf = env.create_user();
+    env.register(&leaf, &middle, DEPOSIT).await.unwrap();
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &leaf, DEPOSIT).await.unwrap();
+    }
+
+    // Dropping the base pair would settle the cascade at middle as if the depth were reached
+    let user = env.create_user();
+    let (base_pda, base_wallet) = (base.pda, base.wallet.pubkey());
+    let truncated = env
+        .register_edited(&user, &leaf, DEPOSIT, |accounts| {
+            accounts.retain(|meta| meta.pubkey != base_pda && meta.pubkey != base_wallet)
+        })
+        .await;
+    assert!(truncated.is_err());
+    assert!(env.account(&user.pda).await.is_none());
+    assert_eq!(env.user_account(&leaf.pda).await.chain.filled_slots, 2);
+
+    env.register(&user, &leaf, DEPOSIT).await.unwrap();
+    assert_eq!(env.user_account(&leaf.pda).await.chain.filled_slots, 0);
+}
+
+#[tokio::test]
+async fn legacy_referrer_must_be_migrated_first() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+
+    // Same referrer still in the Borsh layout written before the zero-copy conversion
+    let account = env.user_account(&base.pda).await;
+    let legacy = LegacyUserAccount {
+        is_registered: true,
+        referrer: None,
+        owner_wallet: base.wallet.pubkey(),
+        upline: LegacyReferralUpline { id: account.upline.id, depth: account.upline.depth, upline: Vec::new() },
+        chain: LegacyReferralChain { id: account.chain.id, slots: [None; 3], filled_slots: 0 },
+        reserved_sol: 0,
+    };
+    let mut data = UserAccount::DISCRIMINATOR.to_vec();
+    legacy.serialize(&mut data).unwrap();
+    data.resize(8 + LegacyUserAccount::SIZE, 0);
+
+    // Rejected by the length check instead of panicking in the zero-copy load
+    let user = env.create_user();
+    let base_pda = base.pda;
+    let err = env
+        .register_after(&user, &base, DEPOSIT, |env| env.set_raw_account(&base_pda, data))
+        .await
+        .unwrap_err();
+    assert_eq!(error_code(err), Some(ErrorCode::UserAccountNeedsResize.into()));
+    assert!(env.account(&user.pda).await.is_none());
+
+    env.migrate_user_account(&base.pda).await.unwrap();
+    env.register(&user, &base, DEPOSIT).await.unwrap();
+
+    let migrated = env.user_account(&base.pda).await;
+    assert_eq!(migrated.owner_wallet, base.wallet.pubkey());
+    assert_eq!(migrated.chain.id, account.chain.id);
+    assert_eq!(migrated.chain.filled_slots, 1);
+    assert_eq!(env.user_account(&user.pda).await.referrer(), Some(base.pda));
+}
+
+#[tokio::test]
+async fn deposit_below_minimum_is_rejected() {
+    let mut env = TestEnv::start().await;
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+
+    // 0.01 SOL at $150 is below the $10 minimum
+    let user = env.create_user();
+    assert!(env.register(&user, &base, 10_000_000).await.is_err());
+    assert!(env.account(&user.pda).await.is_none());
+}
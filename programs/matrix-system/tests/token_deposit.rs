@@ -0,0 +1,129 @@
+// register_with_token_deposit: a configured stablecoin is burned through its own pool in
+// slot 1, reserved in the token vault in slot 2 and paid out in the token it was
+// reserved in, whatever currency completes the matrix.
+
+mod common;
+
+use common::*;
+use matrix_system::{verified_addresses::A_VAULT_LP, ErrorCode, RESERVE_SOL};
+use solana_sdk::signature::Signer;
+
+async fn env_with_usdc() -> (TestEnv, DepositToken, TestUser) {
+    let mut env = TestEnv::start().await;
+    let usdc = env.add_deposit_token_pool(USDC);
+    env.configure_deposit_token(&usdc, USDC_MIN_DEPOSIT).await.unwrap();
+
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    (env, usdc, base)
+}
+
+fn usdc_user(env: &mut TestEnv, usdc: &DepositToken) -> TestUser {
+    let user = env.create_user();
+    env.set_token_account(&user.wallet.pubkey(), &usdc.mint, USDC_DEPOSIT);
+    user
+}
+
+#[tokio::test]
+async fn token_deposits_burn_then_reserve_in_the_token_vault() {
+    let (mut env, usdc, base) = env_with_usdc().await;
+
+    let first = usdc_user(&mut env, &usdc);
+    let supply_before = env.donut_supply().await;
+    env.register_with_token(&first, &base, &usdc, USDC_DEPOSIT).await.unwrap();
+
+    assert!(env.donut_supply().await < supply_before);
+    assert_eq!(env.token_amount(&usdc.b_token_vault).await, USDC_DEPOSIT);
+    assert_eq!(env.user_account(&first.pda).await.reserve_token, RESERVE_SOL);
+
+    let second = usdc_user(&mut env, &usdc);
+    env.register_with_token(&second, &base, &usdc, USDC_DEPOSIT).await.unwrap();
+
+    assert_eq!(env.token_amount(&token_reserve_vault(USDC)).await, USDC_DEPOSIT);
+    let account = env.user_account(&base.pda).await;
+    assert_eq!(account.reserved_sol, USDC_DEPOSIT);
+    assert_eq!(account.reserve_token, USDC);
+    assert_eq!(env.deposit_config(USDC).await.total_reserved, USDC_DEPOSIT);
+    assert_eq!(env.program_state().await.total_reserved_lamports, 0);
+}
+
+#[tokio::test]
+async fn token_reserve_is_paid_in_the_token_when_sol_completes_the_matrix() {
+    let (mut env, usdc, base) = env_with_usdc().await;
+    for _ in 0..2 {
+        let user = usdc_user(&mut env, &usdc);
+        env.register_with_token(&user, &base, &usdc, USDC_DEPOSIT).await.unwrap();
+    }
+    let base_usdc = env.set_token_account(&base.wallet.pubkey(), &usdc.mint, 0);
+    let wallet_before = env.lamports(&base.wallet.pubkey()).await;
+    let notifications_before = env.airdrop_notifications().await;
+
+    let third = env.create_user();
+    env.register(&third, &base, DEPOSIT).await.unwrap();
+
+    assert_eq!(env.token_amount(&base_usdc).await, USDC_DEPOSIT);
+    assert_eq!(env.token_amount(&token_reserve_vault(USDC)).await, 0);
+    assert_eq!(env.deposit_config(USDC).await.total_reserved, 0);
+    assert_eq!(env.lamports(&base.wallet.pubkey()).await, wallet_before);
+    assert_eq!(env.airdrop_notifications().await, notifications_before + 1);
+
+    let account = env.user_account(&base.pda).await;
+    assert_eq!(account.reserved_sol, 0);
+    assert_eq!(account.reserve_token, RESERVE_SOL);
+}
+
+#[tokio::test]
+async fn sol_reserve_is_paid_in_sol_when_a_token_completes_the_matrix() {
+    let (mut env, usdc, base) = env_with_usdc().await;
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &base, DEPOSIT).await.unwrap();
+    }
+    let wallet_before = env.lamports(&base.wallet.pubkey()).await;
+
+    let third = usdc_user(&mut env, &usdc);
+    env.register_with_token(&third, &base, &usdc, USDC_DEPOSIT).await.unwrap();
+
+    // The base referrer's completion burns the token deposit
+    assert_eq!(env.lamports(&base.wallet.pubkey()).await, wallet_before + DEPOSIT);
+    assert_eq!(env.token_amount(&usdc.b_token_vault).await, USDC_DEPOSIT);
+    assert_eq!(env.program_state().await.total_reserved_lamports, 0);
+}
+
+#[tokio::test]
+async fn deposit_below_minimum_or_unconfigured_token_is_rejected() {
+    let (mut env, usdc, base) = env_with_usdc().await;
+
+    let user = usdc_user(&mut env, &usdc);
+    assert!(env.register_with_token(&user, &base, &usdc, USDC_MIN_DEPOSIT - 1).await.is_err());
+
+    let other = env.add_deposit_token_pool(2);
+    env.set_token_account(&user.wallet.pubkey(), &other.mint, USDC_DEPOSIT);
+    assert!(env.register_with_token(&user, &base, &other, USDC_DEPOSIT).await.is_err());
+
+    // RESERVE_SOL cannot be configured and a configured mint cannot change
+    let sol = DepositToken { token: RESERVE_SOL, ..other };
+    assert!(env.configure_deposit_token(&sol, USDC_MIN_DEPOSIT).await.is_err());
+    let swapped = DepositToken { token: USDC, ..other };
+    assert!(env.configure_deposit_token(&swapped, USDC_MIN_DEPOSIT).await.is_err());
+
+    env.register_with_token(&user, &base, &usdc, USDC_DEPOSIT).await.unwrap();
+}
+
+#[tokio::test]
+async fn token_pool_uses_its_own_vault_a_lp_account() {
+    let (mut env, usdc, base) = env_with_usdc().await;
+    assert_eq!(env.deposit_config(USDC).await.a_vault_lp, usdc.a_vault_lp);
+
+    // The SOL pool's LP account of vault A prices and swaps through another pool
+    let user = usdc_user(&mut env, &usdc);
+    let sol_pool_lp = DepositToken { a_vault_lp: A_VAULT_LP, ..usdc };
+    let err = env.register_with_token(&user, &base, &sol_pool_lp, USDC_DEPOSIT).await.unwrap_err();
+    assert_eq!(error_code(err), Some(ErrorCode::InvalidVaultAddress.into()));
+
+    // ...and cannot be configured for a pool that does not own it
+    let err = env.configure_deposit_token(&sol_pool_lp, USDC_MIN_DEPOSIT).await.unwrap_err();
+    assert_eq!(error_code(err), Some(ErrorCode::InvalidVaultAddress.into()));
+
+    env.register_with_token(&user, &base, &usdc, USDC_DEPOSIT).await.unwrap();
+}
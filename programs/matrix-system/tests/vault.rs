@@ -0,0 +1,125 @@
+// Vault solvency: total_reserved_lamports follows every reserve and payout,
+// audit_vault reports the surplus, sweep_vault_surplus only moves the surplus and
+// reconcile_vault can only raise the tracked total. A resized state cannot sweep until
+// it was reconciled.
+
+mod common;
+
+use common::*;
+use solana_sdk::signature::Signer;
+
+const DIRECT_TRANSFER: u64 = 2_000_000_000;
+
+// ProgramState layout before total_reserved_lamports: owner, multisig_treasury,
+// next_upline_id, next_chain_id, airdrop_active, airdrop_end_timestamp,
+// max_upline_depth and depth_overflow_policy
+const LEGACY_STATE_LEN: usize = 8 + 32 + 32 + 4 + 4 + 1 + 8 + 1 + 1;
+
+// Base user with two referrals - its slot 2 deposit sits in the vault
+async fn reserve_one_deposit(env: &mut TestEnv) -> TestUser {
+    let base = env.create_user();
+    env.register_without_referrer(&base, DEPOSIT).await.unwrap();
+    for _ in 0..2 {
+        let user = env.create_user();
+        env.register(&user, &base, DEPOSIT).await.unwrap();
+    }
+    base
+}
+
+#[tokio::test]
+async fn reserved_total_follows_reserves_and_payouts() {
+    let mut env = TestEnv::start().await;
+    let base = reserve_one_deposit(&mut env).await;
+
+    assert_eq!(env.program_state().await.total_reserved_lamports, DEPOSIT);
+    let audit = env.audit_vault().await;
+    assert_eq!(audit.vault_balance, DEPOSIT);
+    assert_eq!(audit.total_reserved_lamports, DEPOSIT);
+    assert_eq!(audit.surplus, 0);
+    assert_eq!(audit.deficit, 0);
+
+    // Slot 3 pays the reserve out
+    let third = env.create_user();
+    env.register(&third, &base, DEPOSIT).await.unwrap();
+    assert_eq!(env.program_state().await.total_reserved_lamports, 0);
+    assert_eq!(env.lamports(&program_sol_vault()).await, 0);
+}
+
+#[tokio::test]
+async fn sweep_moves_only_the_surplus() {
+    let mut env = TestEnv::start().await;
+    let base = reserve_one_deposit(&mut env).await;
+
+    // Nothing above the reserve and the rent minimum yet
+    assert!(env.sweep_vault_surplus().await.is_err());
+
+    env.transfer(&program_sol_vault(), DIRECT_TRANSFER).await.unwrap();
+    let audit = env.audit_vault().await;
+    assert_eq!(audit.vault_balance, DEPOSIT + DIRECT_TRANSFER);
+    assert_eq!(audit.surplus, DIRECT_TRANSFER - audit.rent_exempt_minimum);
+
+    env.sweep_vault_surplus().await.unwrap();
+    assert_eq!(env.lamports(&program_sol_vault()).await, DEPOSIT + audit.rent_exempt_minimum);
+    assert_eq!(env.audit_vault().await.surplus, 0);
+    assert!(env.sweep_vault_surplus().await.is_err());
+
+    // The reserve is still paid in full
+    let wallet_before = env.lamports(&base.wallet.pubkey()).await;
+    let third = env.create_user();
+    env.register(&third, &base, DEPOSIT).await.unwrap();
+    assert_eq!(env.lamports(&base.wallet.pubkey()).await, wallet_before + DEPOSIT);
+    assert_eq!(env.lamports(&program_sol_vault()).await, audit.rent_exempt_minimum);
+}
+
+#[tokio::test]
+async fn reconcile_seeds_a_resized_state() {
+    let mut env = TestEnv::start().await;
+    reserve_one_deposit(&mut env).await;
+
+    // A state resized from the layout without the counter reads zero
+    let mut state = env.program_state().await;
+    state.total_reserved_lamports = 0;
+    env.set_program_state(&state);
+
+    let audit = env.audit_vault().await;
+    assert_eq!(audit.surplus, DEPOSIT - audit.rent_exempt_minimum);
+
+    // Never above the vault balance
+    assert!(env.reconcile_vault(DEPOSIT + 1).await.is_err());
+    env.reconcile_vault(DEPOSIT).await.unwrap();
+    assert_eq!(env.program_state().await.total_reserved_lamports, DEPOSIT);
+    assert_eq!(env.audit_vault().await.surplus, 0);
+
+    // Never lowered
+    assert!(env.reconcile_vault(DEPOSIT - 1).await.is_err());
+}
+
+#[tokio::test]
+async fn resized_state_cannot_sweep_before_reconcile() {
+    let mut env = TestEnv::start().await;
+    reserve_one_deposit(&mut env).await;
+
+    // State still on the layout without the counter
+    let state = env.state;
+    let mut data = env.account(&state).await.unwrap().data;
+    data.truncate(LEGACY_STATE_LEN);
+    env.set_raw_account(&state, data);
+    env.resize_program_state().await.unwrap();
+
+    let state = env.program_state().await;
+    assert_eq!(state.total_reserved_lamports, 0);
+    assert!(!state.reserves_reconciled);
+
+    // The untracked reserve would read as surplus
+    assert!(env.sweep_vault_surplus().await.is_err());
+    assert_eq!(env.lamports(&program_sol_vault()).await, DEPOSIT);
+
+    env.reconcile_vault(DEPOSIT).await.unwrap();
+    assert!(env.program_state().await.reserves_reconciled);
+    assert!(env.sweep_vault_surplus().await.is_err());
+
+    env.transfer(&program_sol_vault(), DIRECT_TRANSFER).await.unwrap();
+    env.sweep_vault_surplus().await.unwrap();
+    let rent_exempt_minimum = env.audit_vault().await.rent_exempt_minimum;
+    assert_eq!(env.lamports(&program_sol_vault()).await, DEPOSIT + rent_exempt_minimum);
+}
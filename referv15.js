@@ -775,9 +775,9 @@ const {
         console.log(`\n🔍 Tipo de usuário: ${isBaseUser ? 'BASE' : 'NÃO-BASE'}`);
         
         // Usar uplines do referrerInfo
-        if (referrerInfo.upline?.upline?.length > 0) {
-          console.log(`\n📊 Uplines encontrados no referrer: ${referrerInfo.upline.upline.length}`);
-          const uplines = referrerInfo.upline.upline.map(entry => entry.pda);
+        if (referrerInfo.upline?.count > 0) {
+          console.log(`\n📊 Uplines encontrados no referrer: ${referrerInfo.upline.count}`);
+          const uplines = referrerInfo.upline.upline.slice(0, referrerInfo.upline.count).map(entry => entry.pda);
           uplineAccounts = [];
           
           for (let i = 0; i < Math.min(uplines.length, 6); i++) {
@@ -1120,6 +1120,11 @@ const {
           systemProgram: SystemProgram.programId,
           associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
           rent: SYSVAR_RENT_PUBKEY,
+          eventAuthority: PublicKey.findProgramAddressSync(
+            [Buffer.from("__event_authority")],
+            program.programId
+          )[0],
+          program: program.programId,
         })
         .remainingAccounts(mainRemainingAccounts)
         .instruction();
@@ -0,0 +1,11 @@
+// Offline economic simulator for matrix-system. Synthetic registrations go through the
+// program's own matrix engine, so burn, reserve, payout and airdrop notification counts
+// follow register_with_sol_deposit without touching a cluster.
+
+pub mod model;
+pub mod report;
+pub mod simulation;
+
+pub use model::{ArrivalModel, ReferrerModel};
+pub use report::{Bucket, CycleStats, Report, Summary};
+pub use simulation::{Config, Simulation};
@@ -0,0 +1,174 @@
+// matrix-sim - runs synthetic registrations through the matrix rules and reports SOL
+// burned versus paid, the vault balance over time, cycles per user and airdrop
+// notifications per week.
+
+use std::fs;
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use matrix_sim::{ArrivalModel, Config, ReferrerModel, Simulation};
+use matrix_system::DepthOverflowPolicy;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Arrivals {
+    Constant,
+    Growth,
+    Viral,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Referrers {
+    Uniform,
+    Preferential,
+    Recent,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Policy {
+    Burn,
+    Treasury,
+    Refund,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Output {
+    Text,
+    Json,
+}
+
+#[derive(Parser)]
+#[command(name = "matrix-sim", about = "Economic simulator for the matrix-system registration rules")]
+struct Cli {
+    /// Referred registrations to simulate
+    #[arg(long, short = 'n', default_value_t = 1_000_000)]
+    registrations: u64,
+
+    /// Stop after this many days even if registrations are left
+    #[arg(long)]
+    days: Option<f64>,
+
+    /// Base users registered before the first arrival
+    #[arg(long, default_value_t = 1)]
+    base_users: u32,
+
+    /// Deposit of each referred registration in lamports
+    #[arg(long, default_value_t = 100_000_000)]
+    deposit: u64,
+
+    /// Deposit of each base user in lamports
+    #[arg(long, default_value_t = 100_000_000)]
+    base_deposit: u64,
+
+    /// Upline depth (ProgramState::max_upline_depth)
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u8).range(1..=16))]
+    upline_depth: u8,
+
+    /// Routing of a deposit left over at the upline depth
+    #[arg(long, value_enum, default_value = "burn")]
+    overflow_policy: Policy,
+
+    /// Registration arrival model
+    #[arg(long, value_enum, default_value = "constant")]
+    arrivals: Arrivals,
+
+    /// Registrations per hour (constant, and growth at the start)
+    #[arg(long, default_value_t = 100.0)]
+    rate: f64,
+
+    /// Daily rate growth for --arrivals growth, e.g. 0.05 or -0.02
+    #[arg(long, default_value_t = 0.05)]
+    growth: f64,
+
+    /// Registrations each user brings per day for --arrivals viral
+    #[arg(long, default_value_t = 0.1)]
+    viral_rate: f64,
+
+    /// How new registrations pick their referrer
+    #[arg(long, value_enum, default_value = "uniform")]
+    referrers: Referrers,
+
+    /// Recent registrations a referrer is picked from for --referrers recent
+    #[arg(long, default_value_t = 1_000)]
+    window: usize,
+
+    /// Time series resolution in hours
+    #[arg(long, default_value_t = 24.0)]
+    bucket_hours: f64,
+
+    /// Length of an airdrop week in hours
+    #[arg(long, default_value_t = 168.0)]
+    airdrop_week_hours: f64,
+
+    /// Airdrop weeks - completions after the last one are not notified
+    #[arg(long, default_value_t = 36)]
+    airdrop_weeks: u32,
+
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+
+    /// Write report.json, timeseries.csv and airdrop_weeks.csv to this directory
+    #[arg(long)]
+    out_dir: Option<PathBuf>,
+
+    /// Output format of the summary
+    #[arg(long, short = 'o', value_enum, default_value = "text")]
+    output: Output,
+}
+
+impl Cli {
+    fn config(&self) -> Config {
+        Config {
+            registrations: self.registrations,
+            duration_days: self.days,
+            base_users: self.base_users,
+            deposit: self.deposit,
+            base_deposit: self.base_deposit,
+            upline_depth: self.upline_depth as usize,
+            overflow_policy: match self.overflow_policy {
+                Policy::Burn => DepthOverflowPolicy::Burn,
+                Policy::Treasury => DepthOverflowPolicy::Treasury,
+                Policy::Refund => DepthOverflowPolicy::Refund,
+            },
+            arrivals: match self.arrivals {
+                Arrivals::Constant => ArrivalModel::Constant { per_hour: self.rate },
+                Arrivals::Growth => ArrivalModel::Growth { per_hour: self.rate, daily_growth: self.growth },
+                Arrivals::Viral => ArrivalModel::Viral { per_user_per_day: self.viral_rate },
+            },
+            referrers: match self.referrers {
+                Referrers::Uniform => ReferrerModel::Uniform,
+                Referrers::Preferential => ReferrerModel::Preferential,
+                Referrers::Recent => ReferrerModel::Recent { window: self.window },
+            },
+            bucket_seconds: (self.bucket_hours * 3600.0) as u64,
+            airdrop_week_seconds: (self.airdrop_week_hours * 3600.0) as u64,
+            airdrop_weeks: self.airdrop_weeks,
+            seed: self.seed,
+        }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(err) = run(&cli) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let report = Simulation::new(cli.config()).run().map_err(|err| format!("matrix engine rejected a registration: {}", err))?;
+
+    if let Some(dir) = &cli.out_dir {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("report.json"), report.to_json()?)?;
+        fs::write(dir.join("timeseries.csv"), report.buckets_csv())?;
+        fs::write(dir.join("airdrop_weeks.csv"), report.airdrop_weeks_csv())?;
+        eprintln!("report written to {}", dir.display());
+    }
+
+    match cli.output {
+        Output::Text => println!("{}", report.summary),
+        Output::Json => println!("{}", serde_json::to_string_pretty(&report.summary)?),
+    }
+    Ok(())
+}
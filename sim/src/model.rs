@@ -0,0 +1,90 @@
+// Input models of a simulation: when registrations arrive and which registered user
+// each one picks as referrer.
+
+use rand::Rng;
+use serde::Serialize;
+
+const SECONDS_PER_HOUR: f64 = 3600.0;
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// Registration arrivals as a Poisson process. Rates that change over time are sampled
+/// at the previous arrival, which is accurate while the gaps are short against the rate
+/// change.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum ArrivalModel {
+    // Fixed registrations per hour
+    Constant { per_hour: f64 },
+    // per_hour at the start, multiplied by (1 + daily_growth) every day - negative decays
+    Growth { per_hour: f64, daily_growth: f64 },
+    // Every registered user brings per_user_per_day new registrations
+    Viral { per_user_per_day: f64 },
+}
+
+impl ArrivalModel {
+    /// Registrations per second at `elapsed` seconds with `users` registered
+    pub fn rate(&self, elapsed: f64, users: usize) -> f64 {
+        match *self {
+            ArrivalModel::Constant { per_hour } => per_hour / SECONDS_PER_HOUR,
+            ArrivalModel::Growth { per_hour, daily_growth } => {
+                per_hour / SECONDS_PER_HOUR * (1.0 + daily_growth).powf(elapsed / SECONDS_PER_DAY)
+            }
+            ArrivalModel::Viral { per_user_per_day } => per_user_per_day * users as f64 / SECONDS_PER_DAY,
+        }
+    }
+
+    /// Seconds until the next registration, None once the rate has dropped to zero
+    pub fn next_gap<R: Rng>(&self, rng: &mut R, elapsed: f64, users: usize) -> Option<f64> {
+        let rate = self.rate(elapsed, users);
+        if !(rate > 0.0 && rate.is_finite()) {
+            return None;
+        }
+        // Exponential gap by inversion; 1 - u keeps ln away from zero
+        let u: f64 = rng.gen();
+        Some(-(1.0 - u).ln() / rate)
+    }
+}
+
+/// How a new registration picks its referrer among the registered users
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum ReferrerModel {
+    // Every registered user is equally likely
+    Uniform,
+    // Proportional to 1 + referrals made - a few users bring most of the network
+    Preferential,
+    // Uniform among the `window` most recent registrations - viral waves
+    Recent { window: usize },
+}
+
+/// Referrer sampling state for a ReferrerModel
+pub struct ReferrerPicker {
+    model: ReferrerModel,
+    tickets: Vec<u32>, // Preferential: each user once, plus once per referral made
+}
+
+impl ReferrerPicker {
+    pub fn new(model: ReferrerModel) -> Self {
+        Self { model, tickets: Vec::new() }
+    }
+
+    /// Record a registration: `user` joined under `referrer`
+    pub fn registered(&mut self, user: u32, referrer: Option<u32>) {
+        if let ReferrerModel::Preferential = self.model {
+            self.tickets.push(user);
+            self.tickets.extend(referrer);
+        }
+    }
+
+    /// Referrer among `users` registered users, indexed in registration order
+    pub fn pick<R: Rng>(&self, rng: &mut R, users: usize) -> u32 {
+        match self.model {
+            ReferrerModel::Uniform => rng.gen_range(0..users) as u32,
+            ReferrerModel::Preferential => self.tickets[rng.gen_range(0..self.tickets.len())],
+            ReferrerModel::Recent { window } => {
+                let first = users.saturating_sub(window.max(1));
+                rng.gen_range(first..users) as u32
+            }
+        }
+    }
+}
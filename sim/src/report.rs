@@ -0,0 +1,165 @@
+// Simulation output: totals, per-bucket time series and airdrop notifications per week,
+// as JSON, CSV or a text summary.
+
+use std::fmt::{self, Write};
+
+use serde::Serialize;
+
+use crate::simulation::Config;
+
+// Lamports as SOL with every decimal
+fn sol(lamports: u64) -> String {
+    format!("{}.{:09}", lamports / 1_000_000_000, lamports % 1_000_000_000)
+}
+
+fn share(part: u64, total: u64) -> f64 {
+    if total == 0 { 0.0 } else { part as f64 / total as f64 }
+}
+
+/// Flows of one time bucket; balances are taken at the end of the bucket
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Bucket {
+    pub start: u64, // Seconds since the start of the simulation
+    pub registrations: u64,
+    pub deposited: u64,
+    pub burned: u64,
+    pub reserved: u64, // Moved into program_sol_vault
+    pub paid: u64,     // Released from program_sol_vault to users
+    pub overflow: u64, // Treasury or refund, depending on the policy
+    pub notifications: u64,
+    pub vault_balance: u64,
+    pub users: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CycleStats {
+    pub mean: f64,
+    pub p50: u32,
+    pub p90: u32,
+    pub p99: u32,
+    pub max: u32,
+    pub users_with_cycle: f64, // Share of users who completed at least one matrix
+}
+
+impl CycleStats {
+    /// From a histogram: `counts[n]` users completed n matrices
+    pub fn from_histogram(counts: &[u64]) -> Self {
+        let users: u64 = counts.iter().sum();
+        if users == 0 {
+            return Self::default();
+        }
+        let total: u64 = counts.iter().enumerate().map(|(cycles, count)| cycles as u64 * count).sum();
+
+        let percentile = |p: f64| {
+            let rank = ((users as f64 * p).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (cycles, count) in counts.iter().enumerate() {
+                seen += count;
+                if seen >= rank {
+                    return cycles as u32;
+                }
+            }
+            counts.len().saturating_sub(1) as u32
+        };
+
+        Self {
+            mean: total as f64 / users as f64,
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            max: counts.iter().rposition(|count| *count > 0).unwrap_or(0) as u32,
+            users_with_cycle: share(users - counts[0], users),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Summary {
+    pub registrations: u64,
+    pub base_users: u64,
+    pub simulated_days: f64,
+    pub deposited: u64,      // Deposits of referred registrations
+    pub base_deposited: u64, // Deposits of base users - go to the liquidity pool
+    pub burned: u64,
+    pub paid: u64,
+    pub treasury: u64,
+    pub refunded: u64,
+    pub vault_balance: u64, // Reserves still held at the end
+    pub peak_vault_balance: u64,
+    pub burned_share: f64, // Of the referred deposits
+    pub paid_share: f64,
+    pub matrices_completed: u64,
+    pub notifications_during_airdrop: u64,
+    pub cycles: CycleStats,
+    pub cascade_depths: Vec<u64>, // [n] registrations whose deposit reached n uplines
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+    pub config: Config,
+    pub summary: Summary,
+    pub buckets: Vec<Bucket>,
+    pub airdrop_weeks: Vec<u64>, // Notifications in airdrop week index + 1
+}
+
+impl Report {
+    pub(crate) fn finish(mut self) -> Self {
+        let summary = &mut self.summary;
+        summary.burned_share = share(summary.burned, summary.deposited);
+        summary.paid_share = share(summary.paid, summary.deposited);
+        summary.notifications_during_airdrop = self.airdrop_weeks.iter().sum();
+        self
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn buckets_csv(&self) -> String {
+        let mut csv = String::from("start,registrations,deposited,burned,reserved,paid,overflow,notifications,vault_balance,users\n");
+        for b in &self.buckets {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{},{},{}",
+                b.start, b.registrations, b.deposited, b.burned, b.reserved, b.paid, b.overflow, b.notifications, b.vault_balance, b.users
+            );
+        }
+        csv
+    }
+
+    pub fn airdrop_weeks_csv(&self) -> String {
+        let mut csv = String::from("week,notifications\n");
+        for (week, notifications) in self.airdrop_weeks.iter().enumerate() {
+            let _ = writeln!(csv, "{},{}", week + 1, notifications);
+        }
+        csv
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Registrations      {} referred + {} base over {:.1} days", self.registrations, self.base_users, self.simulated_days)?;
+        writeln!(f, "  deposited        {} SOL ({} SOL from base users)", sol(self.deposited), sol(self.base_deposited))?;
+        writeln!(f, "  burned           {} SOL ({:.1}%)", sol(self.burned), self.burned_share * 100.0)?;
+        writeln!(f, "  paid out         {} SOL ({:.1}%)", sol(self.paid), self.paid_share * 100.0)?;
+        if self.treasury > 0 {
+            writeln!(f, "  treasury         {} SOL", sol(self.treasury))?;
+        }
+        if self.refunded > 0 {
+            writeln!(f, "  refunded         {} SOL", sol(self.refunded))?;
+        }
+        writeln!(f, "  vault            {} SOL (peak {} SOL)", sol(self.vault_balance), sol(self.peak_vault_balance))?;
+        writeln!(f, "Matrices completed {} ({} notified during the airdrop)", self.matrices_completed, self.notifications_during_airdrop)?;
+        writeln!(
+            f,
+            "  cycles per user  mean {:.2}, p50 {}, p90 {}, p99 {}, max {}",
+            self.cycles.mean, self.cycles.p50, self.cycles.p90, self.cycles.p99, self.cycles.max
+        )?;
+        writeln!(f, "  users cycled     {:.1}%", self.cycles.users_with_cycle * 100.0)?;
+        write!(f, "Cascade depth      ")?;
+        for (depth, count) in self.cascade_depths.iter().enumerate() {
+            write!(f, "{}{}: {}", if depth == 0 { "" } else { ", " }, depth, count)?;
+        }
+        Ok(())
+    }
+}
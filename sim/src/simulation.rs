@@ -0,0 +1,278 @@
+// Simulation state and the registration step. Each synthetic registration runs through
+// matrix::register exactly like register_with_sol_deposit: the new user takes the next
+// chain ID, the referrer's stored upline is passed when its third slot is filled, and
+// the effects are tallied instead of executed. Users are kept as compact records - the
+// stored upline is rebuilt from the referrer links, which gives the same entries as
+// initialize_referred_user_data as long as the upline depth does not change.
+
+use anchor_lang::prelude::*;
+use matrix_system::{
+    matrix::{self, Effect, Throttle, UserRecord},
+    DepthOverflowPolicy, ReferralChain, RESERVE_SOL,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Serialize, Serializer};
+
+use crate::model::{ArrivalModel, ReferrerModel, ReferrerPicker};
+use crate::report::{Bucket, CycleStats, Report, Summary};
+
+const NO_REFERRER: u32 = u32::MAX;
+
+fn debug<T: std::fmt::Debug, S: Serializer>(value: &T, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_args!("{:?}", value))
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Config {
+    pub registrations: u64,       // Referred registrations to simulate
+    pub duration_days: Option<f64>, // Stop earlier once this much time has passed
+    pub base_users: u32,          // Registered without referrer before the first arrival
+    pub deposit: u64,             // Lamports per referred registration
+    pub base_deposit: u64,        // Lamports per base user
+    pub upline_depth: usize,      // ProgramState::upline_depth()
+    #[serde(serialize_with = "debug")]
+    pub overflow_policy: DepthOverflowPolicy,
+    pub arrivals: ArrivalModel,
+    pub referrers: ReferrerModel,
+    pub bucket_seconds: u64,      // Time series resolution
+    pub airdrop_week_seconds: u64,
+    pub airdrop_weeks: u32,       // Notifications after the last week are not sent
+    pub seed: u64,
+}
+
+// Compact UserAccount - only what the engine reads plus counters
+#[derive(Clone, Copy)]
+struct User {
+    referrer: u32,
+    chain_id: u32,
+    filled_slots: u8,
+    reserved_sol: u64,
+    cycles: u32,
+}
+
+// Synthetic UserAccount key of user `index`, never the default pubkey
+fn key(index: u32) -> Pubkey {
+    let mut bytes = [0u8; 32];
+    bytes[..8].copy_from_slice(&(index as u64 + 1).to_le_bytes());
+    Pubkey::new_from_array(bytes)
+}
+
+fn index(key: &Pubkey) -> usize {
+    u64::from_le_bytes(key.to_bytes()[..8].try_into().unwrap()) as usize - 1
+}
+
+pub struct Simulation {
+    config: Config,
+    rng: StdRng,
+    picker: ReferrerPicker,
+    users: Vec<User>,
+    next_chain_id: u32,
+    elapsed: f64,
+    vault_balance: u64,
+    summary: Summary,
+    buckets: Vec<Bucket>,
+    airdrop_weeks: Vec<u64>,
+}
+
+impl Simulation {
+    pub fn new(config: Config) -> Self {
+        let mut simulation = Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            picker: ReferrerPicker::new(config.referrers),
+            users: Vec::new(),
+            next_chain_id: 1,
+            elapsed: 0.0,
+            vault_balance: 0,
+            summary: Summary::default(),
+            buckets: vec![Bucket::default()],
+            airdrop_weeks: vec![0; config.airdrop_weeks as usize],
+            config,
+        };
+
+        // register_without_referrer - the deposit goes to the liquidity pool
+        for _ in 0..simulation.config.base_users.max(1) {
+            simulation.add_user(NO_REFERRER);
+            simulation.summary.base_users += 1;
+            simulation.summary.base_deposited += simulation.config.base_deposit;
+        }
+        simulation.buckets[0].users = simulation.users.len() as u64;
+        simulation
+    }
+
+    fn add_user(&mut self, referrer: u32) -> u32 {
+        let user = self.users.len() as u32;
+        self.users.push(User { referrer, chain_id: self.next_chain_id, filled_slots: 0, reserved_sol: 0, cycles: 0 });
+        self.next_chain_id += 1;
+        self.picker.registered(user, (referrer != NO_REFERRER).then_some(referrer));
+        user
+    }
+
+    fn record(&self, user: u32) -> UserRecord {
+        let account = &self.users[user as usize];
+        UserRecord {
+            key: key(user),
+            wallet: key(user),
+            payout: key(user),
+            is_base: account.referrer == NO_REFERRER,
+            chain: ReferralChain { id: account.chain_id, slots: [Pubkey::default(); 3], filled_slots: account.filled_slots, _padding: [0; 3] },
+            reserved_sol: account.reserved_sol,
+            reserve_token: RESERVE_SOL,
+            cycles: Default::default(),
+        }
+    }
+
+    fn store(&mut self, record: &UserRecord) {
+        let account = &mut self.users[index(&record.key)];
+        account.chain_id = record.chain.id;
+        account.filled_slots = record.chain.filled_slots;
+        account.reserved_sol = record.reserved_sol;
+    }
+
+    /// Stored upline of `user`: its nearest `upline_depth` ancestors, root first
+    pub fn upline(&self, user: u32) -> Vec<u32> {
+        let mut upline = Vec::with_capacity(self.config.upline_depth);
+        let mut ancestor = self.users[user as usize].referrer;
+        while ancestor != NO_REFERRER && upline.len() < self.config.upline_depth {
+            upline.push(ancestor);
+            ancestor = self.users[ancestor as usize].referrer;
+        }
+        upline.reverse();
+        upline
+    }
+
+    /// Sum of reserved_sol over every user - always equal to the vault balance
+    pub fn reserved_total(&self) -> u64 {
+        self.users.iter().map(|user| user.reserved_sol).sum()
+    }
+
+    fn bucket(&mut self) -> &mut Bucket {
+        let index = (self.elapsed as u64 / self.config.bucket_seconds.max(1)) as usize;
+        while self.buckets.len() <= index {
+            let start = self.buckets.len() as u64 * self.config.bucket_seconds.max(1);
+            let carried = Bucket { start, vault_balance: self.vault_balance, users: self.users.len() as u64, ..Bucket::default() };
+            self.buckets.push(carried);
+        }
+        &mut self.buckets[index]
+    }
+
+    /// Simulate the next registration. Returns false once the arrivals stop, the
+    /// registration count is reached or the duration has passed.
+    pub fn step(&mut self) -> Result<bool> {
+        if self.summary.registrations >= self.config.registrations {
+            return Ok(false);
+        }
+        let Some(gap) = self.config.arrivals.next_gap(&mut self.rng, self.elapsed, self.users.len()) else {
+            return Ok(false);
+        };
+        if self.config.duration_days.is_some_and(|days| self.elapsed + gap > days * 86_400.0) {
+            return Ok(false);
+        }
+        self.elapsed += gap;
+
+        let referrer = self.picker.pick(&mut self.rng, self.users.len());
+        let referrer_record = self.record(referrer);
+        let upline = if referrer_record.chain.filled_slots == 2 && !referrer_record.is_base {
+            self.upline(referrer)
+        } else {
+            Vec::new()
+        };
+
+        // The new user's matrix takes the current chain ID before the engine runs
+        let user = self.add_user(referrer);
+        let deposit = self.config.deposit;
+        let outcome = matrix::register(
+            key(user),
+            deposit,
+            RESERVE_SOL,
+            referrer_record,
+            upline.len().min(self.config.upline_depth),
+            |i| Ok(self.record(upline[i])),
+            self.next_chain_id,
+            self.config.overflow_policy,
+            &Throttle::default(),
+        )?;
+
+        self.next_chain_id = outcome.next_chain_id;
+        for record in outcome.referrer.iter().chain(outcome.uplines.iter()) {
+            self.store(record);
+        }
+
+        let week = (self.elapsed as u64 / self.config.airdrop_week_seconds.max(1)) as usize;
+        let mut flows = Bucket { registrations: 1, deposited: deposit, ..Bucket::default() };
+        for effect in &outcome.effects {
+            match *effect {
+                Effect::Write { .. } => {}
+                Effect::Burn { amount, .. } => flows.burned += amount,
+                Effect::Reserve { amount, .. } => flows.reserved += amount,
+                Effect::Pay { amount, .. } => flows.paid += amount,
+                Effect::Notify { owner, .. } => {
+                    flows.notifications += 1;
+                    self.users[index(&owner)].cycles += 1;
+                    if let Some(notifications) = self.airdrop_weeks.get_mut(week) {
+                        *notifications += 1;
+                    }
+                }
+                Effect::Overflow { amount, policy, .. } => {
+                    flows.overflow += amount;
+                    match policy {
+                        DepthOverflowPolicy::Treasury => self.summary.treasury += amount,
+                        _ => self.summary.refunded += amount,
+                    }
+                }
+            }
+        }
+
+        self.vault_balance = self.vault_balance + flows.reserved - flows.paid;
+        let summary = &mut self.summary;
+        summary.registrations += 1;
+        summary.deposited += flows.deposited;
+        summary.burned += flows.burned;
+        summary.paid += flows.paid;
+        summary.matrices_completed += flows.notifications;
+        summary.peak_vault_balance = summary.peak_vault_balance.max(self.vault_balance);
+        let depth = outcome.uplines.len();
+        if summary.cascade_depths.len() <= depth {
+            summary.cascade_depths.resize(depth + 1, 0);
+        }
+        summary.cascade_depths[depth] += 1;
+
+        let (vault_balance, users) = (self.vault_balance, self.users.len() as u64);
+        let bucket = self.bucket();
+        bucket.registrations += flows.registrations;
+        bucket.deposited += flows.deposited;
+        bucket.burned += flows.burned;
+        bucket.reserved += flows.reserved;
+        bucket.paid += flows.paid;
+        bucket.overflow += flows.overflow;
+        bucket.notifications += flows.notifications;
+        bucket.vault_balance = vault_balance;
+        bucket.users = users;
+
+        Ok(true)
+    }
+
+    /// Run until `step` stops and build the report
+    pub fn run(mut self) -> Result<Report> {
+        while self.step()? {}
+        Ok(self.report())
+    }
+
+    pub fn report(&self) -> Report {
+        let mut counts = Vec::new();
+        for user in &self.users {
+            let cycles = user.cycles as usize;
+            if counts.len() <= cycles {
+                counts.resize(cycles + 1, 0);
+            }
+            counts[cycles] += 1;
+        }
+
+        let mut summary = self.summary.clone();
+        summary.simulated_days = self.elapsed / 86_400.0;
+        summary.vault_balance = self.vault_balance;
+        summary.cycles = CycleStats::from_histogram(&counts);
+
+        Report { config: self.config.clone(), summary, buckets: self.buckets.clone(), airdrop_weeks: self.airdrop_weeks.clone() }
+            .finish()
+    }
+}
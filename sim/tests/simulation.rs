@@ -0,0 +1,110 @@
+// Simulator invariants: every lamport deposited is accounted for, the vault matches the
+// users' reserves and the rebuilt uplines match the program's initializer.
+
+use anchor_lang::prelude::Pubkey;
+use bytemuck::Zeroable;
+use matrix_sim::{ArrivalModel, Config, ReferrerModel, Simulation};
+use matrix_system::{initialize_base_user_data, initialize_referred_user_data, DepthOverflowPolicy, UplineEntry, UserAccount};
+
+fn config(registrations: u64, referrers: ReferrerModel, upline_depth: usize, overflow_policy: DepthOverflowPolicy) -> Config {
+    Config {
+        registrations,
+        duration_days: None,
+        base_users: 3,
+        deposit: 100_000_000,
+        base_deposit: 100_000_000,
+        upline_depth,
+        overflow_policy,
+        arrivals: ArrivalModel::Constant { per_hour: 100.0 },
+        referrers,
+        bucket_seconds: 86_400,
+        airdrop_week_seconds: 604_800,
+        airdrop_weeks: 36,
+        seed: 7,
+    }
+}
+
+#[test]
+fn deposits_are_burned_paid_or_held() {
+    for referrers in [ReferrerModel::Uniform, ReferrerModel::Preferential, ReferrerModel::Recent { window: 50 }] {
+        let mut simulation = Simulation::new(config(20_000, referrers, 6, DepthOverflowPolicy::Burn));
+        while simulation.step().unwrap() {}
+        let report = simulation.report();
+        let summary = &report.summary;
+
+        assert_eq!(summary.registrations, 20_000);
+        assert_eq!(summary.deposited, summary.burned + summary.paid + summary.vault_balance);
+        assert_eq!(summary.vault_balance, simulation.reserved_total());
+        assert_eq!(summary.treasury + summary.refunded, 0);
+
+        // Time series adds up to the totals
+        assert_eq!(report.buckets.iter().map(|b| b.registrations).sum::<u64>(), 20_000);
+        assert_eq!(report.buckets.iter().map(|b| b.burned).sum::<u64>(), summary.burned);
+        assert_eq!(report.buckets.last().unwrap().vault_balance, summary.vault_balance);
+        assert_eq!(summary.cascade_depths.iter().sum::<u64>(), 20_000);
+    }
+}
+
+#[test]
+fn overflow_policy_routes_leftovers() {
+    // A line keeps completing matrices up to the depth limit
+    let treasury = Simulation::new(config(30_000, ReferrerModel::Recent { window: 3 }, 1, DepthOverflowPolicy::Treasury))
+        .run()
+        .unwrap()
+        .summary;
+    assert!(treasury.treasury > 0);
+    assert_eq!(treasury.deposited, treasury.burned + treasury.paid + treasury.vault_balance + treasury.treasury);
+
+    let burn = Simulation::new(config(30_000, ReferrerModel::Recent { window: 3 }, 1, DepthOverflowPolicy::Burn))
+        .run()
+        .unwrap()
+        .summary;
+    assert_eq!(burn.treasury, 0);
+    assert_eq!(burn.burned, treasury.burned + treasury.treasury);
+}
+
+#[test]
+fn same_seed_same_report() {
+    let run = || Simulation::new(config(5_000, ReferrerModel::Preferential, 6, DepthOverflowPolicy::Burn)).run().unwrap().to_json().unwrap();
+    assert_eq!(run(), run());
+}
+
+#[test]
+fn duration_and_viral_arrivals() {
+    let mut config = config(u64::MAX, ReferrerModel::Uniform, 6, DepthOverflowPolicy::Burn);
+    config.arrivals = ArrivalModel::Viral { per_user_per_day: 0.5 };
+    config.duration_days = Some(10.0);
+
+    let report = Simulation::new(config).run().unwrap();
+    assert!(report.summary.simulated_days <= 10.0);
+    assert!(report.buckets.len() <= 10);
+    // Grows roughly by e^(0.5 * 10) from the 3 base users
+    assert!(report.summary.registrations > 100);
+}
+
+#[test]
+fn upline_matches_the_program_initializer() {
+    const DEPTH: usize = 4;
+
+    // window 1 always picks the newest user: a single line below base user 0
+    let mut config = config(9, ReferrerModel::Recent { window: 1 }, DEPTH, DepthOverflowPolicy::Burn);
+    config.base_users = 1;
+    let mut simulation = Simulation::new(config);
+    while simulation.step().unwrap() {}
+
+    let wallets: Vec<Pubkey> = (0..10).map(|_| Pubkey::new_unique()).collect();
+    let mut accounts = vec![UserAccount::zeroed()];
+    initialize_base_user_data(&mut accounts[0], &wallets[0], 1, 1).unwrap();
+    for i in 1..10 {
+        let entry = UplineEntry { pda: wallets[i - 1], wallet: wallets[i - 1] };
+        let mut account = UserAccount::zeroed();
+        initialize_referred_user_data(&mut account, &wallets[i], &wallets[i - 1], &accounts[i - 1], entry, 1, 1, DEPTH).unwrap();
+        accounts.push(account);
+    }
+
+    for (i, account) in accounts.iter().enumerate() {
+        let expected: Vec<Pubkey> = account.upline.entries().iter().map(|entry| entry.wallet).collect();
+        let rebuilt: Vec<Pubkey> = simulation.upline(i as u32).iter().map(|&user| wallets[user as usize]).collect();
+        assert_eq!(rebuilt, expected, "user {}", i);
+    }
+}
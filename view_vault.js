@@ -1,4 +1,35 @@
 const { Connection, PublicKey } = require('@solana/web3.js');
+const crypto = require('crypto');
+
+// Discriminator das contas UserAccount (sha256("account:UserAccount")[0..8])
+const USER_ACCOUNT_DISCRIMINATOR = crypto.createHash('sha256').update('account:UserAccount').digest().subarray(0, 8);
+
+// Tamanho das contas ainda no layout Borsh antigo (8 + LegacyUserAccount::SIZE)
+const LEGACY_USER_ACCOUNT_SIZE = 579;
+
+// Soma o reserved_sol de todas as contas de usuário. No layout zero-copy ele é o
+// primeiro campo, logo após o discriminator; no layout Borsh antigo são os últimos 8 bytes
+async function sumReservedSol(connection, programId) {
+    const accounts = await connection.getProgramAccounts(programId);
+    let total = 0n;
+    let users = 0;
+    let legacy = 0;
+
+    for (const { account } of accounts) {
+        const data = account.data;
+        if (data.length < 16 || !data.subarray(0, 8).equals(USER_ACCOUNT_DISCRIMINATOR)) continue;
+
+        users++;
+        if (data.length === LEGACY_USER_ACCOUNT_SIZE) {
+            legacy++;
+            total += data.readBigUInt64LE(data.length - 8);
+        } else {
+            total += data.readBigUInt64LE(8);
+        }
+    }
+
+    return { total: Number(total), users, legacy };
+}
 
 async function analyzeVaultTransactions() {
     console.log("🔍 ANÁLISE DETALHADA DO VAULT DO PROGRAMA 🔍");
@@ -14,8 +45,15 @@ async function analyzeVaultTransactions() {
             PROGRAM_ID
         );
         
+        const vaultBalance = await connection.getBalance(programSolVault);
         console.log(`\n🏦 Vault: ${programSolVault.toString()}`);
-        console.log(`💰 Saldo Atual: 0 SOL`);
+        console.log(`💰 Saldo Atual: ${vaultBalance / 1e9} SOL`);
+
+        const reserved = await sumReservedSol(connection, PROGRAM_ID);
+        console.log(`🔒 SOL reservado em ${reserved.users} contas de usuário: ${reserved.total / 1e9} SOL`);
+        if (reserved.legacy > 0) {
+            console.log(`⚠️ ${reserved.legacy} contas ainda no layout Borsh antigo - execute migrate_user_account`);
+        }
         
         // Buscar TODAS as transações do vault
         console.log(`\n📜 HISTÓRICO COMPLETO DE TRANSAÇÕES:`);
@@ -101,10 +139,15 @@ async function analyzeVaultTransactions() {
         console.log(`   Total Sacado: ${totalWithdrawn / 1e9} SOL`);
         console.log(`   Diferença: ${(totalDeposited - totalWithdrawn) / 1e9} SOL`);
         console.log(`   Saldo Esperado: ${(totalDeposited - totalWithdrawn) / 1e9} SOL`);
-        console.log(`   Saldo Real: 0 SOL`);
+        console.log(`   Saldo Real: ${vaultBalance / 1e9} SOL`);
+        console.log(`   SOL Reservado: ${reserved.total / 1e9} SOL`);
         
-        if (totalDeposited - totalWithdrawn > 0) {
-            console.log(`\n❌ DISCREPÂNCIA DETECTADA: ${(totalDeposited - totalWithdrawn) / 1e9} SOL DESAPARECIDOS!`);
+        const missing = totalDeposited - totalWithdrawn - vaultBalance;
+        if (missing > 0) {
+            console.log(`\n❌ DISCREPÂNCIA DETECTADA: ${missing / 1e9} SOL DESAPARECIDOS!`);
+        }
+        if (reserved.total > vaultBalance) {
+            console.log(`\n❌ RESERVAS DESCOBERTAS: ${(reserved.total - vaultBalance) / 1e9} SOL reservados além do saldo do vault!`);
         }
         
         // Mostrar depósitos
@@ -150,8 +193,8 @@ async function analyzeVaultTransactions() {
         console.log("Isso indica que são transações FALHADAS ou apenas leituras");
         
         console.log("\n🚨 CONCLUSÃO:");
-        console.log("1. O vault está vazio (0 SOL)");
-        console.log("2. Usuários têm SOL marcado como reservado");
+        console.log(`1. O vault tem ${vaultBalance / 1e9} SOL`);
+        console.log(`2. Usuários têm ${reserved.total / 1e9} SOL marcado como reservado`);
         console.log("3. As transações recentes são apenas consultas/falhas");
         console.log("4. O SOL pode ter sido sacado indevidamente ou nunca foi depositado");
         